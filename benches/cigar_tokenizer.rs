@@ -0,0 +1,50 @@
+// Throughput of the CIGAR tokenizer with and without SIMD on a synthetic CIGAR of 50 MB, checking first that both give
+// the same operations. Run with `cargo bench --bench cigar_tokenizer`; criterion compares each run with the previous
+// one (or with a baseline saved with --save-baseline) and reports regressions. The size of the CIGAR (in MB) can be
+// changed with FLR_BENCH_CIGAR_MB.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use feature_level_report::cigar::{simd_kernel, CigarOps};
+
+// Runs of 1 to 5 digits of the operations of a long alignment, mostly matches with mismatches and short indels
+fn synthetic_cigar(bytes: usize) -> String {
+    let mut cigar = String::with_capacity(bytes + 16);
+    let mut state: u64 = 42;
+    while cigar.len() < bytes {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let draw = state >> 33;
+        let op = match draw % 16 {
+            0..=9 => '=',
+            10..=12 => 'X',
+            13 => 'I',
+            14 => 'D',
+            _ => 'M',
+        };
+        let length = 1 + (draw >> 4) % 10u64.pow(1 + (draw >> 8) as u32 % 5);
+        cigar.push_str(&length.to_string());
+        cigar.push(op);
+    }
+    cigar
+}
+
+// Number of operations and sum of their lengths
+fn tokenize(cigar: &str, simd: bool) -> (usize, i64) {
+    CigarOps::new(cigar, simd).fold((0, 0), |(ops, bases), (length, _)| (ops + 1, bases + length))
+}
+
+fn cigar_tokenizer(c: &mut Criterion) {
+    let megabytes: usize = std::env::var("FLR_BENCH_CIGAR_MB").ok().and_then(|mb| mb.parse().ok()).unwrap_or(50);
+    let cigar = synthetic_cigar(megabytes << 20);
+    assert_eq!(tokenize(&cigar, false), tokenize(&cigar, true), "SIMD and scalar tokenizers differ");
+
+    let mut group = c.benchmark_group(format!("cigar_tokenizer_{}MB", megabytes));
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(cigar.len() as u64));
+    group.bench_function("scalar", |b| b.iter(|| tokenize(std::hint::black_box(&cigar), false)));
+    group.bench_function(format!("simd_{}", simd_kernel().unwrap_or("none")), |b| b.iter(|| tokenize(std::hint::black_box(&cigar), true)));
+    group.finish();
+}
+
+criterion_group!(benches, cigar_tokenizer);
+criterion_main!(benches);
@@ -0,0 +1,69 @@
+// End-to-end throughput of the report on the same generated input, plain, gzip-compressed, and zstd-compressed (with
+// the zstd command line tool, which also decompresses zstd input). Run with `cargo bench`; the number of lines can be
+// changed with FLR_BENCH_LINES.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+const ITERATIONS: usize = 3;
+
+// Writes `lines` alignments over features, alternating strands and CIGARs with indels
+fn write_fixture(writer: &mut impl Write, lines: usize) -> std::io::Result<()> {
+    for i in 0..lines {
+        let offset = (i % 1000) as i64 * 1000;
+        let (strand, feature_strand) = if i % 2 == 0 { ('+', '+') } else { ('-', '-') };
+        writeln!(writer, "q{}\t2000000\t{}\t{}\t{}\tt{}\t2000000\t{}\t{}\t280\t300\t60\ttp:A:P\tcg:Z:100M10I50M10D140M\tq{}\t{}\t{}\tgene{}\t0\t+\tgene\tt{}\t{}\t{}\tgene{}\t0\t{}\tgene",
+            i % 10, offset + 100, offset + 400, strand, i % 10, offset + 200, offset + 500,
+            i % 10, offset + 150, offset + 250, i, i % 10, offset + 250, offset + 350, i, feature_strand)?;
+    }
+    Ok(())
+}
+
+// Best wall-clock time of running the report on `input`, discarding its output
+fn time_report(input: &Path) -> Duration {
+    (0..ITERATIONS).map(|_| {
+        let start = Instant::now();
+        let status = Command::new(env!("CARGO_BIN_EXE_feature_level_report"))
+            .arg("-i").arg(input)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .expect("Failed to run feature_level_report");
+        assert!(status.success(), "feature_level_report failed on {}", input.display());
+        start.elapsed()
+    }).min().unwrap()
+}
+
+fn main() -> std::io::Result<()> {
+    let lines: usize = std::env::var("FLR_BENCH_LINES").ok().map(|s| s.parse().expect("Invalid FLR_BENCH_LINES")).unwrap_or(50_000);
+    let dir = std::env::temp_dir().join(format!("feature_level_report_bench.{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    let plain: PathBuf = dir.join("input.tsv");
+    let mut writer = BufWriter::new(File::create(&plain)?);
+    write_fixture(&mut writer, lines)?;
+    writer.flush()?;
+
+    let gzip: PathBuf = dir.join("input.tsv.gz");
+    let mut writer = GzEncoder::new(BufWriter::new(File::create(&gzip)?), Compression::default());
+    write_fixture(&mut writer, lines)?;
+    writer.finish()?.flush()?;
+
+    let zstd: PathBuf = dir.join("input.tsv.zst");
+    let status = Command::new("zstd").arg("-q").arg(&plain).arg("-o").arg(&zstd).status()?;
+    assert!(status.success(), "zstd failed to compress {}", plain.display());
+
+    for (format, path) in [("plain", &plain), ("gzip", &gzip), ("zstd", &zstd)] {
+        let elapsed = time_report(path);
+        let size = std::fs::metadata(path)?.len();
+        println!("{:<6} {:>12.0} lines/s  ({} lines, {} bytes, best of {} runs: {:.3} s)", format, lines as f64 / elapsed.as_secs_f64(), lines, size, ITERATIONS, elapsed.as_secs_f64());
+    }
+
+    std::fs::remove_dir_all(&dir)
+}
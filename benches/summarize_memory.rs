@@ -0,0 +1,64 @@
+// Peak memory of `merge --summarize` on a generated report with one group per row, i.e. as many distinct
+// (feature, query, target) keys as rows. Run with `cargo bench --bench summarize_memory`; the number of groups can be
+// changed with FLR_BENCH_GROUPS. The peak resident set size is sampled from /proc, so this only runs on Linux.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+// Writes a report whose rows all have different feature names, over a few query and target sequences
+fn write_report(writer: &mut impl Write, groups: usize) -> std::io::Result<()> {
+    writeln!(writer, "feature.name\tquery\tquery.feature.start\tquery.feature.end\tquery.strand\ttarget\ttarget.feature.start\ttarget.feature.end\taligned.bp\tnot.aligned.in.query.bp\tnot.aligned.in.target.bp\tindels.in.query.bp\tindels.in.target\tignored.in.query.bp\tignored.in.target.bp")?;
+    for i in 0..groups {
+        let offset = (i % 1000) * 1000;
+        writeln!(writer, "gene{}\tHG002#1#chr{}\t{}\t{}\t+\tCHM13#0#chr{}\t{}\t{}\t90\t0\t0\t10\t0\t0\t10",
+            i, i % 24 + 1, offset + 150, offset + 250, i % 24 + 1, offset + 250, offset + 350)?;
+    }
+    Ok(())
+}
+
+// Peak resident set size (VmHWM) of a process, in bytes, while it runs
+fn peak_rss(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse::<u64>().ok().map(|kb| kb * 1024)
+}
+
+fn measure(input: &Path, output: &Path) -> u64 {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_feature_level_report"))
+        .arg("merge").arg(output).arg(input).arg("--summarize")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to run feature_level_report");
+    let mut peak = 0;
+    loop {
+        // The last sample before the exit is the peak of the whole run
+        if let Some(rss) = peak_rss(child.id()) {
+            peak = rss;
+        }
+        if let Some(status) = child.try_wait().expect("Failed to wait for feature_level_report") {
+            assert!(status.success(), "feature_level_report failed on {}", input.display());
+            return peak;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let groups: usize = std::env::var("FLR_BENCH_GROUPS").ok().map(|s| s.parse().expect("Invalid FLR_BENCH_GROUPS")).unwrap_or(1_000_000);
+    let dir = std::env::temp_dir().join(format!("feature_level_report_bench.{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    let input = dir.join("report.tsv");
+    let mut writer = BufWriter::new(File::create(&input)?);
+    write_report(&mut writer, groups)?;
+    writer.flush()?;
+
+    let peak = measure(&input, &dir.join("summary.tsv"));
+    println!("summarize {:>10} groups: peak RSS {:.1} MiB, {:.0} bytes/group", groups, peak as f64 / (1024.0 * 1024.0), peak as f64 / groups as f64);
+
+    std::fs::remove_dir_all(&dir)
+}
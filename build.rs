@@ -0,0 +1,38 @@
+use std::path::Path;
+use std::process::Command;
+
+// With the capi feature:
+// - generates the header of src/capi.rs with cbindgen, when it is installed, into OUT_DIR, where the capi tests
+//   compare it with include/feature_level_report.h (the header of the repository is never written);
+// - compiles capi/check_abi.c against include/feature_level_report.h into a static library of OUT_DIR, linked and
+//   run by the capi tests
+fn main() {
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=include/feature_level_report.h");
+    println!("cargo:rerun-if-changed=capi/check_abi.c");
+    if std::env::var_os("CARGO_FEATURE_CAPI").is_none() {
+        return;
+    }
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+
+    let header = out_dir.join("feature_level_report.h");
+    let generated = Command::new("cbindgen").args(["--config", "cbindgen.toml", "--output"]).arg(&header).arg("src/capi.rs").status();
+    if matches!(generated, Ok(status) if status.success()) {
+        println!("cargo:rustc-env=FLR_GENERATED_HEADER={}", header.display());
+    }
+
+    // main is renamed so that the tests can call it
+    let object = out_dir.join("check_abi.o");
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let compiled = Command::new(&compiler).args(["-c", "-Wall", "-Wextra", "-Werror", "-Iinclude", "-Dmain=flr_check_abi_main", "capi/check_abi.c", "-o"]).arg(&object).status();
+    if !matches!(compiled, Ok(status) if status.success()) {
+        panic!("failed to compile capi/check_abi.c with {} ({:?}), needed by the capi feature; set CC to a C compiler", compiler, compiled);
+    }
+    let archived = Command::new("ar").arg("crs").arg(out_dir.join("libcheck_abi.a")).arg(&object).status();
+    if !matches!(archived, Ok(status) if status.success()) {
+        panic!("failed to archive capi/check_abi.c with ar ({:?})", archived);
+    }
+    println!("cargo:rustc-link-search=native={}", out_dir.display());
+}
@@ -0,0 +1,85 @@
+// Checks the C API (capi feature) through its header against counts of the report, both from a CIGAR string and from
+// a parsed CIGAR; exits with status 1 after reporting the differences. Run by cargo test --features capi (compiled by
+// build.rs), or built and run on its own from the repository root with:
+//
+//   cargo build --release --features capi
+//   cc -Wall -Wextra -Iinclude capi/check_abi.c target/release/libfeature_level_report.a -lpthread -ldl -lm -o check_abi
+//   ./check_abi
+#include <stdio.h>
+#include <string.h>
+
+#include "feature_level_report.h"
+
+static int failures = 0;
+
+static void expect(const char *what, int64_t value, int64_t expected) {
+    if (value != expected) {
+        fprintf(stderr, "FAIL: %s: %lld, expected %lld\n", what, (long long)value, (long long)expected);
+        failures++;
+    }
+}
+
+// Columns of report: aligned.bp, not.aligned.in.query.bp, not.aligned.in.target.bp, indels.in.query.bp,
+// indels.in.target, ignored.in.query.bp, ignored.in.target.bp
+static void expect_counts(const char *what, const FlrCounts *counts, const int64_t expected[7]) {
+    const int64_t values[7] = {counts->aligned_bp, counts->not_aligned_in_query_bp, counts->not_aligned_in_target_bp, counts->indels_in_query_bp, counts->indels_in_target_bp, counts->ignored_in_query_bp, counts->ignored_in_target_bp};
+    for (int i = 0; i < 7; i++) {
+        expect(what, values[i], expected[i]);
+    }
+}
+
+int main(void) {
+    FlrOptions options = {.max_indel_size = -1, .max_gap_merge = 0};
+    FlrCounts counts;
+    const char *cigar = "100M5D100M3I95M";
+    const char *eqx_cigar = "5=1X2=1X10=1X35=1X4=1X2=1X7=1X3=1X24=5D6=1X1=1X14=2X1=2X17=1X4=1X2=1X10=1X5=1X2=1X6=4X15=1X3I2=1X1=2X1=2X38=1X1=2X1=1X2=1X6=1X24=1X7=";
+    FlrAlignment forward = {.query_start = 20, .query_end = 318, .query_strand = '+', .target_start = 50, .target_end = 350,
+                            .feature_in_query_start = 60, .feature_in_query_end = 200, .feature_in_target_start = 100, .feature_in_target_end = 230};
+    const int64_t forward_expected[7] = {125, 0, 0, 0, 5, 15, 0};
+
+    expect("flr_count status", flr_count(&forward, (const uint8_t *)cigar, strlen(cigar), &options, &counts), FLR_OK);
+    expect_counts("flr_count", &counts, forward_expected);
+    expect("flr_count mismatches", counts.mismatches_bp, 0);
+    expect("flr_count eqx status", flr_count(&forward, (const uint8_t *)eqx_cigar, strlen(eqx_cigar), &options, &counts), FLR_OK);
+    expect_counts("flr_count eqx", &counts, forward_expected);
+    expect("flr_count eqx mismatches", counts.mismatches_bp, 17);
+
+    // A CIGAR parsed once for several features of its alignment
+    FlrCigar *parsed = flr_cigar_new((const uint8_t *)cigar, strlen(cigar));
+    expect("flr_cigar_new", parsed != NULL, 1);
+    expect("flr_count_parsed status", flr_count_parsed(&forward, parsed, &options, &counts), FLR_OK);
+    expect_counts("flr_count_parsed", &counts, forward_expected);
+    FlrAlignment inside = forward;
+    inside.feature_in_query_start = 30;
+    inside.feature_in_query_end = 80;
+    inside.feature_in_target_start = 60;
+    inside.feature_in_target_end = 110;
+    const int64_t inside_expected[7] = {50, 0, 0, 0, 0, 0, 0};
+    expect("flr_count_parsed inside status", flr_count_parsed(&inside, parsed, &options, &counts), FLR_OK);
+    expect_counts("flr_count_parsed inside", &counts, inside_expected);
+    expect("flr_count_parsed inside blocks", counts.aligned_blocks, 1);
+    // The 5D is not aligned rather than an indel above --max-indel-size
+    options.max_indel_size = 4;
+    const int64_t large_indel_expected[7] = {125, 0, 5, 0, 0, 15, 0};
+    expect("flr_count_parsed max indel size status", flr_count_parsed(&forward, parsed, &options, &counts), FLR_OK);
+    expect_counts("flr_count_parsed max indel size", &counts, large_indel_expected);
+    flr_cigar_free(parsed);
+    flr_cigar_free(NULL);
+    options.max_indel_size = -1;
+
+    // Invalid arguments
+    const char *invalid_cigar = "100M5Q";
+    expect("flr_cigar_new invalid", flr_cigar_new((const uint8_t *)invalid_cigar, strlen(invalid_cigar)) == NULL, 1);
+    expect("flr_count invalid CIGAR", flr_count(&forward, (const uint8_t *)invalid_cigar, strlen(invalid_cigar), &options, &counts), FLR_INVALID_ARGUMENT);
+    FlrAlignment unstranded = forward;
+    unstranded.query_strand = '.';
+    expect("flr_count invalid strand", flr_count(&unstranded, (const uint8_t *)cigar, strlen(cigar), &options, &counts), FLR_INVALID_ARGUMENT);
+    expect("flr_count null result", flr_count(&forward, (const uint8_t *)cigar, strlen(cigar), &options, NULL), FLR_INVALID_ARGUMENT);
+    expect("flr_count_parsed null CIGAR", flr_count_parsed(&forward, NULL, &options, &counts), FLR_INVALID_ARGUMENT);
+
+    if (failures > 0) {
+        return 1;
+    }
+    printf("OK: C API checks passed\n");
+    return 0;
+}
@@ -0,0 +1,107 @@
+#ifndef FEATURE_LEVEL_REPORT_H
+#define FEATURE_LEVEL_REPORT_H
+
+/* Generated from src/capi.rs by cbindgen (cbindgen --config cbindgen.toml --output include/feature_level_report.h src/capi.rs), do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define FLR_OK 0
+
+#define FLR_INVALID_ARGUMENT 1
+
+#define FLR_INTERNAL_ERROR 2
+
+typedef struct FlrCigar FlrCigar;
+
+typedef struct FlrAlignment {
+  int64_t query_start;
+  int64_t query_end;
+  char query_strand;
+  int64_t target_start;
+  int64_t target_end;
+  int64_t feature_in_query_start;
+  int64_t feature_in_query_end;
+  int64_t feature_in_target_start;
+  int64_t feature_in_target_end;
+} FlrAlignment;
+
+typedef struct FlrOptions {
+  int64_t max_indel_size;
+  int64_t max_gap_merge;
+} FlrOptions;
+
+typedef struct FlrCounts {
+  int64_t aligned_bp;
+  int64_t not_aligned_in_query_bp;
+  int64_t not_aligned_in_target_bp;
+  int64_t indels_in_query_bp;
+  int64_t indels_in_target_bp;
+  int64_t ignored_in_query_bp;
+  int64_t ignored_in_target_bp;
+  int64_t mismatches_bp;
+  int64_t indel_events_in_query;
+  int64_t indel_events_in_target;
+  int64_t edge_adjustment_in_query_bp;
+  int64_t edge_adjustment_in_target_bp;
+  int64_t aligned_blocks;
+  int64_t largest_aligned_block;
+} FlrCounts;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Counts the bases of a feature pair in an alignment, from its CIGAR (`cigar_length` bytes, not NUL-terminated).
+
+ # Safety
+
+ `cigar` points to `cigar_length` readable bytes; the other pointers point to valid structs (or are null, which
+ is reported as FLR_INVALID_ARGUMENT).
+ */
+int32_t flr_count(const struct FlrAlignment *alignment,
+                  const uint8_t *cigar,
+                  uintptr_t cigar_length,
+                  const struct FlrOptions *options,
+                  struct FlrCounts *result);
+
+/*
+ Parses a CIGAR (`cigar_length` bytes, not NUL-terminated) once for the features of its alignment; null if it is
+ not a valid CIGAR. Free it with flr_cigar_free.
+
+ # Safety
+
+ `cigar` points to `cigar_length` readable bytes.
+ */
+struct FlrCigar *flr_cigar_new(const uint8_t *cigar, uintptr_t cigar_length);
+
+/*
+ Frees a CIGAR parsed with flr_cigar_new (nothing for null).
+
+ # Safety
+
+ `cigar` comes from flr_cigar_new and is not used after.
+ */
+void flr_cigar_free(struct FlrCigar *cigar);
+
+/*
+ Counts the bases of a feature pair in an alignment, from its CIGAR parsed with flr_cigar_new.
+
+ # Safety
+
+ `cigar` comes from flr_cigar_new; the other pointers point to valid structs (or are null, which is reported as
+ FLR_INVALID_ARGUMENT).
+ */
+int32_t flr_count_parsed(const struct FlrAlignment *alignment,
+                         const struct FlrCigar *cigar,
+                         const struct FlrOptions *options,
+                         struct FlrCounts *result);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* FEATURE_LEVEL_REPORT_H */
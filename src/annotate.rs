@@ -0,0 +1,553 @@
+use clap::ArgMatches;
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+use std::iter::Peekable;
+use std::path::PathBuf;
+
+use crate::spill::{ExternalSorter, MergedRows, RowStream, TempFile};
+use crate::{create_file, load_reference_lengths, open_file, read_report_header, reference_length};
+
+// Columns identifying the feature on each side of a report row, in (sequence, start, end) order
+const QUERY_FEATURE_COLUMNS: [&str; 3] = ["query", "query.feature.start", "query.feature.end"];
+pub const TARGET_FEATURE_COLUMNS: [&str; 3] = ["target", "target.feature.start", "target.feature.end"];
+
+// Feature identified by sequence, start, end, and name
+pub type FeatureKey = (String, i64, i64, String);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    // Aligned bases of the alignment covering the feature the most
+    Best,
+    // Aligned bases of all the alignments, capped at the feature length
+    UniqueCoverage,
+}
+
+// Aligned bases over a feature across the report rows it appears in. Both aggregations keep only these two counters,
+// not the aligned intervals of the rows (which the report does not have), so the memory used grows with the number of
+// features and not with the number of rows of each feature
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct FeatureAlignment {
+    best: i64,
+    total: i64,
+}
+
+impl FeatureAlignment {
+    // Aligned bases of a feature of the given length
+    pub fn aligned_bases(&self, aggregation: Aggregation, length: i64) -> i64 {
+        match aggregation {
+            Aggregation::Best => self.best,
+            Aggregation::UniqueCoverage => self.total.min(length.max(0)),
+        }
+    }
+
+    // Adds the aligned bases of a row, or of the rows combined in another aggregate; None if they overflow
+    fn combine(&mut self, other: FeatureAlignment) -> Option<()> {
+        self.best = self.best.max(other.best);
+        self.total = self.total.checked_add(other.total)?;
+        Some(())
+    }
+}
+
+// Features and their aligned bases as spilled (sequence, start, end, name, best, total) rows
+fn feature_row(key: &FeatureKey, feature: &FeatureAlignment) -> Vec<String> {
+    vec![key.0.clone(), key.1.to_string(), key.2.to_string(), key.3.clone(), feature.best.to_string(), feature.total.to_string()]
+}
+
+fn parse_spilled(value: &str) -> i64 {
+    value.parse::<i64>().expect("Invalid value in temporary file")
+}
+
+fn parse_feature_key(row: &[String]) -> FeatureKey {
+    (row[0].clone(), parse_spilled(&row[1]), parse_spilled(&row[2]), row[3].clone())
+}
+
+fn parse_feature_row(row: &[String]) -> (FeatureKey, FeatureAlignment) {
+    (parse_feature_key(row), FeatureAlignment { best: parse_spilled(&row[4]), total: parse_spilled(&row[5]) })
+}
+
+// Orders rows starting with a feature (sequence, start, end, name) as FeatureKey does
+fn compare_feature_rows(a: &[String], b: &[String]) -> Ordering {
+    let coordinate = |value: &String| value.parse::<i64>().unwrap_or(0);
+    a[0].cmp(&b[0])
+        .then_with(|| coordinate(&a[1]).cmp(&coordinate(&b[1])))
+        .then_with(|| coordinate(&a[2]).cmp(&coordinate(&b[2])))
+        .then_with(|| a[3].cmp(&b[3]))
+}
+
+// Aggregates of the report rows by feature. Beyond the memory limit, the aggregates are spilled to temporary files
+// sorted by feature, and those of a feature spilled to several files are combined when the files are merged
+pub struct FeatureAlignments {
+    features: HashMap<FeatureKey, FeatureAlignment>,
+    memory_limit: Option<(usize, PathBuf)>,
+    memory_bytes: usize,
+    spills: Vec<TempFile>,
+}
+
+pub type SortedFeatures = Box<dyn Iterator<Item = io::Result<(FeatureKey, FeatureAlignment)>>>;
+
+impl FeatureAlignments {
+    pub fn new(memory_limit: Option<(usize, PathBuf)>) -> FeatureAlignments {
+        FeatureAlignments { features: HashMap::new(), memory_limit, memory_bytes: 0, spills: Vec::new() }
+    }
+
+    // Adds the aligned bases of a row of the feature; None if its aggregate overflows
+    fn add(&mut self, key: FeatureKey, aligned_bases: i64) -> io::Result<Option<()>> {
+        let key_bytes = key.0.len() + key.3.len();
+        let feature = match self.features.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                self.memory_bytes += std::mem::size_of::<(FeatureKey, FeatureAlignment)>() + key_bytes;
+                entry.insert(FeatureAlignment::default())
+            }
+        };
+        if feature.combine(FeatureAlignment { best: aligned_bases, total: aligned_bases }).is_none() {
+            return Ok(None);
+        }
+        if let Some((memory_limit, _)) = &self.memory_limit {
+            if self.memory_bytes > *memory_limit {
+                self.spill()?;
+            }
+        }
+        Ok(Some(()))
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let temp_dir = &self.memory_limit.as_ref().unwrap().1;
+        let mut features: Vec<(FeatureKey, FeatureAlignment)> = self.features.drain().collect();
+        features.sort_by(|a, b| a.0.cmp(&b.0));
+        let rows: Vec<Vec<String>> = features.iter().map(|(key, feature)| feature_row(key, feature)).collect();
+        let spill = TempFile::new(temp_dir);
+        spill.write_rows(rows.iter())?;
+        self.spills.push(spill);
+        self.memory_bytes = 0;
+        Ok(())
+    }
+
+    pub fn spilled_chunks(&self) -> usize {
+        self.spills.len()
+    }
+
+    // Returns the features sorted by key, each one once; temporary files are removed once the stream is dropped
+    pub fn into_sorted(self) -> io::Result<SortedFeatures> {
+        let mut features: Vec<(FeatureKey, FeatureAlignment)> = self.features.into_iter().collect();
+        features.sort_by(|a, b| a.0.cmp(&b.0));
+        if self.spills.is_empty() {
+            return Ok(Box::new(features.into_iter().map(Ok)));
+        }
+
+        let mut streams: Vec<RowStream> = Vec::with_capacity(self.spills.len() + 1);
+        for spill in &self.spills {
+            streams.push(Box::new(spill.read_rows()?));
+        }
+        streams.push(Box::new(features.into_iter().map(|(key, feature)| Ok(feature_row(&key, &feature)))));
+        let merged: RowStream = Box::new(MergedRows::new(compare_feature_rows, streams, self.spills)?);
+        Ok(Box::new(CombinedFeatures { rows: merged.peekable() }))
+    }
+}
+
+// Combines the adjacent aggregates of the same feature of merged spills
+struct CombinedFeatures {
+    rows: Peekable<RowStream>,
+}
+
+impl Iterator for CombinedFeatures {
+    type Item = io::Result<(FeatureKey, FeatureAlignment)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = match self.rows.next()? {
+            Ok(row) => row,
+            Err(e) => return Some(Err(e)),
+        };
+        let (key, mut feature) = parse_feature_row(&row);
+        while let Some(Ok(next)) = self.rows.peek() {
+            if compare_feature_rows(&row, next) != Ordering::Equal {
+                break;
+            }
+            let (_, next_feature) = parse_feature_row(next);
+            if feature.combine(next_feature).is_none() {
+                return Some(Err(io::Error::new(io::ErrorKind::InvalidData, format!("the aligned bases of feature {} overflow a 64-bit integer", key.3))));
+            }
+            self.rows.next();
+        }
+        Some(Ok((key, feature)))
+    }
+}
+
+pub fn parse_aggregation(value: Option<&str>) -> Aggregation {
+    match value.unwrap_or("best") {
+        "best" => Aggregation::Best,
+        _ => Aggregation::UniqueCoverage,
+    }
+}
+
+// Which feature the bases shared by overlapping features of the BED file are credited to
+pub enum OverlapPolicy {
+    // Every feature covering them
+    CountBoth,
+    // The feature listed first in the BED file
+    First,
+    // The longest feature (the first one among equally long features)
+    Longest,
+    // The feature whose class (7th BED column) comes first in the list, the unlisted classes coming last (the first
+    // feature in the BED file among features of equal priority)
+    Priority(Vec<String>),
+}
+
+pub fn parse_overlap_policy(value: Option<&str>, priority_file: Option<&str>) -> OverlapPolicy {
+    match value.unwrap_or("count-both") {
+        "first" => OverlapPolicy::First,
+        "longest" => OverlapPolicy::Longest,
+        "priority-file" => {
+            let priority_file = priority_file.unwrap_or_else(|| {
+                eprintln!("ERROR: --overlap-policy priority-file needs --priority-file with the feature classes by decreasing priority");
+                std::process::exit(1);
+            });
+            let classes = open_file(priority_file).lines()
+                .map(|line| line.expect("Failed to read priority file").trim().to_string())
+                .filter(|class| !class.is_empty() && !class.starts_with('#'))
+                .collect();
+            OverlapPolicy::Priority(classes)
+        }
+        _ => OverlapPolicy::CountBoth,
+    }
+}
+
+// Bases of each feature line of the BED file (by 0-based line number) not shared with a feature that takes precedence
+// under the overlap policy, resolved per sequence before annotating
+fn owned_lengths(bed_file: &str, policy: &OverlapPolicy) -> io::Result<HashMap<usize, i64>> {
+    // (rank, line number, start, end) of the features of each sequence
+    let mut features: HashMap<String, Vec<(usize, usize, i64, i64)>> = HashMap::new();
+    for (line_number, line) in open_file(bed_file).lines().enumerate() {
+        let line = line?;
+        if !is_feature_line(&line) {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            // Reported by the annotation pass
+            continue;
+        }
+        let start = parse_coordinate(fields[1], "start", bed_file, line_number + 1);
+        let end = parse_coordinate(fields[2], "end", bed_file, line_number + 1);
+        let rank = match policy {
+            OverlapPolicy::Priority(classes) => fields.get(6).and_then(|class| classes.iter().position(|listed| listed == class)).unwrap_or(classes.len()),
+            _ => 0,
+        };
+        features.entry(fields[0].to_string()).or_default().push((rank, line_number, start, end));
+    }
+    let mut owned = HashMap::new();
+    for mut chrom_features in features.into_values() {
+        match policy {
+            OverlapPolicy::Longest => chrom_features.sort_by_key(|&(_, line_number, start, end)| (std::cmp::Reverse(end - start), line_number)),
+            _ => chrom_features.sort_by_key(|&(rank, line_number, _, _)| (rank, line_number)),
+        }
+        // Disjoint intervals of the bases already credited, by start
+        let mut claimed: BTreeMap<i64, i64> = BTreeMap::new();
+        for (_, line_number, start, end) in chrom_features {
+            let (mut merged_start, mut merged_end, mut shared) = (start, end, 0);
+            let overlapping: Vec<(i64, i64)> = claimed.range(..end).rev()
+                .take_while(|(_, &claimed_end)| claimed_end >= start)
+                .map(|(&claimed_start, &claimed_end)| (claimed_start, claimed_end))
+                .collect();
+            for (claimed_start, claimed_end) in overlapping {
+                shared += std::cmp::max(0, std::cmp::min(end, claimed_end) - std::cmp::max(start, claimed_start));
+                merged_start = std::cmp::min(merged_start, claimed_start);
+                merged_end = std::cmp::max(merged_end, claimed_end);
+                claimed.remove(&claimed_start);
+            }
+            if start < end {
+                claimed.insert(merged_start, merged_end);
+            }
+            owned.insert(line_number, std::cmp::max(0, end - start) - shared);
+        }
+    }
+    Ok(owned)
+}
+
+fn column_indices(header: &[&str], names: &[&str]) -> Option<Vec<usize>> {
+    names.iter().map(|name| header.iter().position(|column| column == name)).collect()
+}
+
+fn parse_coordinate(value: &str, name: &str, file: &str, line_number: usize) -> i64 {
+    value.parse::<i64>().unwrap_or_else(|_| {
+        eprintln!("ERROR: {}: line {}: invalid {} '{}'", file, line_number, name, value);
+        std::process::exit(1);
+    })
+}
+
+// Aggregates the aligned bases of the report rows by feature, on the sides given by their (sequence, start, end)
+// columns, spilling the aggregates beyond the memory limit
+pub fn load_report(report_file: &str, side_columns: &[&[&str; 3]], memory_limit: Option<(usize, PathBuf)>) -> io::Result<FeatureAlignments> {
+    let mut lines = open_file(report_file).lines();
+    let (header_line_number, header) = match read_report_header(&mut lines)? {
+        Some(header) => header,
+        None => {
+            eprintln!("WARNING: {} is empty, all features will be reported as absent", report_file);
+            return Ok(FeatureAlignments::new(memory_limit));
+        }
+    };
+    let header: Vec<&str> = header.split('\t').collect();
+    let name_index = header.iter().position(|column| *column == "feature.name");
+    let aligned_index = header.iter().position(|column| *column == "aligned.bp");
+    let sides: Vec<Vec<usize>> = side_columns.iter()
+        .filter_map(|names| column_indices(&header, &names[..]))
+        .collect();
+    let (name_index, aligned_index) = match (name_index, aligned_index) {
+        (Some(name_index), Some(aligned_index)) if !sides.is_empty() => (name_index, aligned_index),
+        _ => {
+            let sides: Vec<String> = side_columns.iter().map(|names| names.join(", ")).collect();
+            eprintln!("ERROR: {} needs the feature.name and aligned.bp columns, and the {} columns", report_file, sides.join(" or "));
+            std::process::exit(1);
+        }
+    };
+
+    let mut features = FeatureAlignments::new(memory_limit);
+    for (line_number, line) in lines.enumerate() {
+        let line = line?;
+        let line_number = header_line_number + line_number + 1;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != header.len() {
+            eprintln!("ERROR: {}: line {}: expected {} columns, found {}", report_file, line_number, header.len(), fields.len());
+            std::process::exit(1);
+        }
+        let aligned_bases = parse_coordinate(fields[aligned_index], "aligned.bp", report_file, line_number);
+        let mut keys: Vec<FeatureKey> = Vec::with_capacity(2);
+        for side in &sides {
+            let key = (
+                fields[side[0]].to_string(),
+                parse_coordinate(fields[side[1]], header[side[1]], report_file, line_number),
+                parse_coordinate(fields[side[2]], header[side[2]], report_file, line_number),
+                fields[name_index].to_string(),
+            );
+            // A self-alignment row counts once for a feature that is both its query and target feature
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        for key in keys {
+            if features.add(key, aligned_bases)?.is_none() {
+                eprintln!("ERROR: {}: line {}: the aligned bases of feature {} overflow a 64-bit integer", report_file, line_number, fields[name_index]);
+                std::process::exit(1);
+            }
+        }
+    }
+    Ok(features)
+}
+
+fn is_feature_line(line: &str) -> bool {
+    !(line.is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser"))
+}
+
+// Aggregates of the features of the BED file: looked up by feature when they all fit in memory, otherwise joined with
+// the lines of the BED file by sorting both by feature, and read back in the order of the lines
+enum BedFeatures {
+    InMemory(HashMap<FeatureKey, FeatureAlignment>),
+    // (line number, best, total) rows of the lines whose feature is in the report, by line number
+    Joined(Peekable<RowStream>),
+}
+
+impl BedFeatures {
+    // Also returns the number of chunks spilled, by the aggregates and by the join
+    fn new(bed_file: &str, features: FeatureAlignments) -> io::Result<(BedFeatures, usize)> {
+        if features.spills.is_empty() {
+            return Ok((BedFeatures::InMemory(features.features), 0));
+        }
+        let (memory_limit, temp_dir) = features.memory_limit.clone().unwrap();
+        let mut spilled_chunks = features.spilled_chunks();
+
+        // (sequence, start, end, name, line number) of the features of the BED file
+        let mut lines = ExternalSorter::new(compare_feature_rows, memory_limit, &temp_dir);
+        for (line_number, line) in open_file(bed_file).lines().enumerate() {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            if !is_feature_line(&line) || fields.len() < 4 {
+                // Reported by the annotation pass
+                continue;
+            }
+            let start = parse_coordinate(fields[1], "start", bed_file, line_number + 1);
+            let end = parse_coordinate(fields[2], "end", bed_file, line_number + 1);
+            lines.push(vec![fields[0].to_string(), start.to_string(), end.to_string(), fields[3].to_string(), line_number.to_string()])?;
+        }
+        spilled_chunks += lines.spilled_chunks();
+
+        let by_line_number = |a: &[String], b: &[String]| a[0].parse::<usize>().unwrap_or(0).cmp(&b[0].parse::<usize>().unwrap_or(0));
+        let mut joined = ExternalSorter::new(by_line_number, memory_limit, &temp_dir);
+        let mut features = features.into_sorted()?.peekable();
+        for line in lines.finish()? {
+            let line = line?;
+            let key = parse_feature_key(&line);
+            // Both are sorted by feature, and several lines may have the same feature
+            while let Some(Ok((feature_key, _))) = features.peek() {
+                if *feature_key >= key {
+                    break;
+                }
+                features.next();
+            }
+            match features.peek() {
+                Some(Ok((feature_key, feature))) if *feature_key == key => {
+                    joined.push(vec![line[4].clone(), feature.best.to_string(), feature.total.to_string()])?;
+                }
+                Some(Err(_)) => return Err(features.next().unwrap().unwrap_err()),
+                _ => {}
+            }
+        }
+        spilled_chunks += joined.spilled_chunks();
+        Ok((BedFeatures::Joined(joined.finish()?.peekable()), spilled_chunks))
+    }
+
+    // Aggregate of the feature of a line, the lines being read in order
+    fn get(&mut self, line_number: usize, key: &FeatureKey) -> io::Result<Option<FeatureAlignment>> {
+        match self {
+            BedFeatures::InMemory(features) => Ok(features.get(key).copied()),
+            BedFeatures::Joined(rows) => match rows.peek() {
+                Some(Ok(row)) if row[0].parse::<usize>().ok() == Some(line_number) => {
+                    let row = rows.next().unwrap()?;
+                    Ok(Some(FeatureAlignment { best: parse_spilled(&row[1]), total: parse_spilled(&row[2]) }))
+                }
+                Some(Err(_)) => Err(rows.next().unwrap().unwrap_err()),
+                _ => Ok(None),
+            },
+        }
+    }
+}
+
+// Appends the aggregates of the features to the lines of the BED file, and returns the number of spilled chunks
+fn write_annotated(bed_file: &str, features: FeatureAlignments, aggregation: Aggregation, owned: Option<&HashMap<usize, i64>>, reference_lengths: &HashMap<String, i64>, writer: &mut impl Write) -> io::Result<usize> {
+    let (mut features, spilled_chunks) = BedFeatures::new(bed_file, features)?;
+    for (line_number, line) in open_file(bed_file).lines().enumerate() {
+        let line = line?;
+        // Header, comment, and empty lines are kept as they are
+        if !is_feature_line(&line) {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            eprintln!("ERROR: {}: line {}: expected at least 4 columns (chrom, start, end, name), found {}", bed_file, line_number + 1, fields.len());
+            std::process::exit(1);
+        }
+        let start = parse_coordinate(fields[1], "start", bed_file, line_number + 1);
+        let end = parse_coordinate(fields[2], "end", bed_file, line_number + 1);
+        let length = reference_length(reference_lengths, fields[3], start, end);
+        let key = (fields[0].to_string(), start, end, fields[3].to_string());
+        let (aligned_bases, fraction, classification) = match features.get(line_number, &key)? {
+            Some(feature) => {
+                // The shared bases lost to another feature are not credited: the aligned bases are capped at those
+                // the feature owns
+                let aligned_bases = feature.aligned_bases(aggregation, length).min(owned.map_or(i64::MAX, |owned| owned[&line_number]));
+                let fraction = if length > 0 { format!("{:.4}", aligned_bases as f64 / length as f64) } else { "NA".to_string() };
+                let classification = if aligned_bases == 0 {
+                    "unaligned"
+                } else if aligned_bases >= length {
+                    "complete"
+                } else {
+                    "partial"
+                };
+                (aligned_bases, fraction, classification)
+            }
+            None => (0, "NA".to_string(), "absent"),
+        };
+        writeln!(writer, "{}\t{}\t{}\t{}", line, aligned_bases, fraction, classification)?;
+    }
+    Ok(spilled_chunks)
+}
+
+pub fn run(matches: &ArgMatches) -> io::Result<()> {
+    let bed_file = matches.value_of("bed").unwrap();
+    let report_file = matches.value_of("report").unwrap();
+    let output_file = matches.value_of("output").unwrap();
+    let aggregation = parse_aggregation(matches.value_of("aggregate"));
+    let overlap_policy = parse_overlap_policy(matches.value_of("overlap_policy"), matches.value_of("priority_file"));
+    let owned = match overlap_policy {
+        OverlapPolicy::CountBoth => None,
+        _ if aggregation != Aggregation::UniqueCoverage => {
+            eprintln!("ERROR: --overlap-policy needs --aggregate unique-coverage");
+            std::process::exit(1);
+        }
+        _ => Some(owned_lengths(bed_file, &overlap_policy)?),
+    };
+    let memory_limit = matches.value_of("limit_memory")
+        .map(|s| s.parse::<usize>().expect("Invalid value for memory limit"))
+        .unwrap_or(usize::MAX);
+    let temp_dir = matches.value_of("temp_dir").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+
+    let reference_lengths = matches.value_of("reference_lengths").map(load_reference_lengths).unwrap_or_default();
+
+    let features = load_report(report_file, &[&QUERY_FEATURE_COLUMNS, &TARGET_FEATURE_COLUMNS], Some((memory_limit, temp_dir.clone())))?;
+    let mut writer = create_file(output_file);
+    let spilled_chunks = write_annotated(bed_file, features, aggregation, owned.as_ref(), &reference_lengths, &mut writer)?;
+    if spilled_chunks > 0 {
+        eprintln!("INFO: the memory limit was exceeded, {} chunks were spilled to {}", spilled_chunks, temp_dir.display());
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("feature_level_report.{}.{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn annotate(bed_file: &str, report_file: &str, memory_limit: usize) -> (String, usize, usize) {
+        let features = load_report(report_file, &[&QUERY_FEATURE_COLUMNS, &TARGET_FEATURE_COLUMNS], Some((memory_limit, std::env::temp_dir()))).unwrap();
+        let aggregate_chunks = features.spilled_chunks();
+        let mut output = Vec::new();
+        let spilled_chunks = write_annotated(bed_file, features, Aggregation::UniqueCoverage, None, &HashMap::new(), &mut output).unwrap();
+        (String::from_utf8(output).unwrap(), aggregate_chunks, spilled_chunks)
+    }
+
+    #[test]
+    fn spilled_aggregates_match_in_memory_ones() {
+        // Fragments of the same features are spread over the report, so that their aggregates are spilled to several
+        // chunks and combined when merged
+        let mut report = "feature.name\tquery\tquery.feature.start\tquery.feature.end\ttarget\ttarget.feature.start\ttarget.feature.end\taligned.bp\n".to_string();
+        for fragment in 0..5 {
+            for feature in 0..20 {
+                let start = feature * 100;
+                report.push_str(&format!("f{}\tq1\t{}\t{}\tt{}\t{}\t{}\t{}\n", feature, start, start + 100, feature % 3, start, start + 100, fragment * 5 + feature));
+            }
+        }
+        let report_file = write_file("spill.report.tsv", &report);
+        // Features absent from the report, repeated, and on both sides of the rows
+        let mut bed = "track name=features\n".to_string();
+        for feature in (0..25).rev() {
+            bed.push_str(&format!("q1\t{}\t{}\tf{}\tx\n", feature * 100, feature * 100 + 100, feature));
+            bed.push_str(&format!("t{}\t{}\t{}\tf{}\n", feature % 3, feature * 100, feature * 100 + 100, feature));
+        }
+        bed.push_str("q1\t0\t100\tf0\trepeated\n");
+        let bed_file = write_file("spill.bed", &bed);
+
+        let (in_memory, aggregate_chunks, spilled_chunks) = annotate(&bed_file, &report_file, usize::MAX);
+        assert_eq!((aggregate_chunks, spilled_chunks), (0, 0));
+        assert!(in_memory.starts_with("track name=features\nq1\t2400\t2500\tf24\tx\t0\tNA\tabsent\n"), "{}", in_memory);
+        assert!(in_memory.contains("q1\t1900\t2000\tf19\tx\t100\t1.0000\tcomplete\n"), "{}", in_memory);
+        assert!(in_memory.ends_with("t0\t0\t100\tf0\t50\t0.5000\tpartial\nq1\t0\t100\tf0\trepeated\t50\t0.5000\tpartial\n"), "{}", in_memory);
+
+        // Each aggregate, BED line, and joined row is spilled on its own
+        let (spilled, aggregate_chunks, spilled_chunks) = annotate(&bed_file, &report_file, 0);
+        assert!(aggregate_chunks > 1 && spilled_chunks > aggregate_chunks, "{} {}", aggregate_chunks, spilled_chunks);
+        assert_eq!(spilled, in_memory);
+        let _ = std::fs::remove_file(report_file);
+        let _ = std::fs::remove_file(bed_file);
+    }
+
+    #[test]
+    fn spilled_aggregates_are_combined_by_feature() {
+        let mut features = FeatureAlignments::new(Some((0, std::env::temp_dir())));
+        for (name, aligned_bases) in [("b", 5), ("a", 3), ("b", 7), ("a", 1)] {
+            features.add(("t1".to_string(), 0, 10, name.to_string()), aligned_bases).unwrap().unwrap();
+        }
+        assert_eq!(features.spilled_chunks(), 4);
+        let sorted: Vec<(FeatureKey, FeatureAlignment)> = features.into_sorted().unwrap().collect::<io::Result<_>>().unwrap();
+        assert_eq!(sorted, vec![
+            (("t1".to_string(), 0, 10, "a".to_string()), FeatureAlignment { best: 3, total: 4 }),
+            (("t1".to_string(), 0, 10, "b".to_string()), FeatureAlignment { best: 7, total: 12 }),
+        ]);
+    }
+}
@@ -0,0 +1,276 @@
+use clap::{Arg, ArgMatches};
+use std::io::{self, BufRead, BufWriter, Write};
+
+use crate::{aligned_blocks_in_features, cigar, count_aligned_bases, features, input_arg, open_file, parse_record, Interval, Record, UNLIMITED_INDEL_SIZE};
+
+// Options of the bin subcommand
+pub fn args() -> Vec<Arg<'static>> {
+    vec![
+        input_arg()
+        .required(true),
+        Arg::with_name("max_indel_size")
+        .short('m')
+        .long("max-indel-size")
+        .value_name("INT")
+        .help("Maximum size of indels to consider in feature intervals")
+        .takes_value(true),
+        Arg::with_name("bin_size")
+        .long("bin-size")
+        .value_name("BP")
+        .help("Length of the windows along the target feature; the last window of a feature keeps its actual, shorter length")
+        .takes_value(true)
+        .required_unless_present_any(["bins", "alignment_windows"])
+        .conflicts_with("bins"),
+        Arg::with_name("bins")
+        .long("bins")
+        .value_name("INT")
+        .help("Number of windows of (almost) equal length per feature, fewer for features shorter than that")
+        .takes_value(true),
+        Arg::with_name("alignment_windows")
+        .long("alignment-windows")
+        .value_name("BP")
+        .help("Instead of the feature windows, read plain PAF and report the aligned bases in windows of BP along the target range of each alignment, whatever the features; the last window of an alignment ends with it")
+        .takes_value(true)
+        .conflicts_with_all(&["bin_size", "bins", "rescue_strand_mismatch", "independent_strands"]),
+        Arg::with_name("window_step")
+        .long("window-step")
+        .value_name("BP")
+        .help("With --alignment-windows, distance between the starts of consecutive windows, for overlapping (sliding) windows [default: the window length]")
+        .takes_value(true)
+        .requires("alignment_windows"),
+        Arg::with_name("rescue_strand_mismatch")
+        .long("rescue-strand-mismatch")
+        .help("Process lines whose feature strands disagree while query and target are in the same orientation instead of skipping them"),
+        Arg::with_name("independent_strands")
+        .long("independent-strands")
+        .help("Do not check the feature strands against the alignment strand, as report --independent-strands")
+        .conflicts_with_all(&["rescue_strand_mismatch", "alignment_windows"]),
+    ]
+}
+
+// Boundaries of the bins tiling [start, end) in feature orientation (from the end for features on the - strand),
+// the last bin being shorter when the length is not a multiple of the bin size
+fn bin_boundaries(start: i64, end: i64, reverse: bool, bin_size: Option<i64>, bins: Option<i64>) -> Vec<i64> {
+    let length = end - start;
+    let offsets: Vec<i64> = match (bin_size, bins) {
+        (Some(bin_size), _) => (0..).map(|i| std::cmp::min(i * bin_size, length)).take(((length + bin_size - 1) / bin_size).max(1) as usize + 1).collect(),
+        (None, Some(bins)) => {
+            let bins = std::cmp::min(bins, length).max(1);
+            (0..=bins).map(|i| i * length / bins).collect()
+        }
+        (None, None) => vec![0, length],
+    };
+    offsets.into_iter().map(|offset| if reverse { end - offset } else { start + offset }).collect()
+}
+
+// Query position aligned to a target position, walking the CIGAR (positions in deletions map to where the query
+// resumes); positions outside the alignment map to the nearest alignment end
+fn project_to_query(query_start: i64, query_end: i64, query_rev: bool, target_start: i64, cigar: &str, position: i64) -> i64 {
+    let mut query_pos = if query_rev { query_end } else { query_start };
+    let mut target_pos = target_start;
+    if position <= target_start {
+        return query_pos;
+    }
+    for (length, op) in cigar::ops(cigar) {
+        let (query_length, target_length) = match op {
+            'M' | '=' | 'X' => (length, length),
+            'D' => (0, length),
+            'I' => (length, 0),
+            _ => (0, 0),
+        };
+        if position < target_pos + target_length {
+            let query_offset = if query_length > 0 { position - target_pos } else { 0 };
+            return if query_rev { query_pos - query_offset } else { query_pos + query_offset };
+        }
+        query_pos += if query_rev { -query_length } else { query_length };
+        target_pos += target_length;
+    }
+    query_pos
+}
+
+// Aligned bases of the windows of window_size bp starting every step bp along [start, end), from the aligned blocks
+// (sorted and not overlapping) in that range; the last window ends at `end` and may be shorter
+fn window_aligned_bases(start: i64, end: i64, blocks: &[Interval], window_size: i64, step: i64) -> Vec<(i64, i64, i64)> {
+    let mut cumulative = vec![0];
+    for (block_start, block_end) in blocks {
+        cumulative.push(cumulative[cumulative.len() - 1] + block_end - block_start);
+    }
+    // Aligned bases before a position
+    let aligned_before = |position: i64| {
+        let index = blocks.partition_point(|&(_, block_end)| block_end <= position);
+        cumulative[index] + blocks.get(index).map_or(0, |&(block_start, _)| std::cmp::max(0, position - block_start))
+    };
+    let mut windows = Vec::new();
+    let mut window_start = start;
+    while window_start < end {
+        let window_end = std::cmp::min(window_start + window_size, end);
+        windows.push((window_start, window_end, aligned_before(window_end) - aligned_before(window_start)));
+        if window_end == end {
+            break;
+        }
+        window_start += step;
+    }
+    windows
+}
+
+// Profile of the aligned bases in fixed-size windows along the target range of each alignment of a plain PAF
+// (--alignment-windows), whatever the features
+fn write_alignment_windows(matches: &ArgMatches, window_size: i64, out: &mut impl Write) -> io::Result<()> {
+    let input_file = matches.value_of("input").unwrap();
+    let step = matches.value_of("window_step").map_or(window_size, |s| s.parse::<i64>().ok().filter(|step| *step > 0).expect("Invalid value for window step"));
+
+    writeln!(out, "query	query.start	query.end	strand	target	target.start	target.end	window.index	window.start	window.end	aligned.bp")?;
+
+    for (line_number, line) in open_file(input_file).lines().enumerate() {
+        let line = line?;
+        let joined = features::whole_alignment(&line);
+        let parts: Vec<&str> = joined.split('\t').collect();
+        let Record { query_name, query_start, query_end, query_strand, target_name, target_start, target_end, cigar, .. } = match parse_record(&parts) {
+            Ok(record) => record,
+            Err(reason) => {
+                eprintln!("WARNING: line {}: {}. Skip this line", line_number + 1, reason);
+                continue;
+            }
+        };
+        let (_, target_blocks) = aligned_blocks_in_features(query_start, query_end, query_strand.chars().next().unwrap(), target_start, cigar, query_start, query_end, target_start, target_end);
+        for (index, (window_start, window_end, aligned_bases)) in window_aligned_bases(target_start, target_end, &target_blocks, window_size, step).into_iter().enumerate() {
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", query_name, query_start, query_end, query_strand, target_name, target_start, target_end, index, window_start, window_end, aligned_bases)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn run(matches: &ArgMatches) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match matches.value_of("alignment_windows") {
+        Some(window_size) => {
+            let window_size = window_size.parse::<i64>().ok().filter(|size| *size > 0).expect("Invalid value for alignment windows");
+            write_alignment_windows(matches, window_size, &mut out)?;
+        }
+        None => write_feature_bins(matches, &mut out)?,
+    }
+    out.flush()
+}
+
+// Counts of the bins of the features of each line of a paf-feature input
+fn write_feature_bins(matches: &ArgMatches, out: &mut impl Write) -> io::Result<()> {
+    let input_file = matches.value_of("input").unwrap();
+    let max_indel_size = matches.value_of("max_indel_size")
+        .map(|s| s.parse::<i64>().expect("Invalid value for max indel size"))
+        .unwrap_or(UNLIMITED_INDEL_SIZE);
+    let bin_size = matches.value_of("bin_size").map(|s| s.parse::<i64>().ok().filter(|size| *size > 0).expect("Invalid value for bin size"));
+    let bins = matches.value_of("bins").map(|s| s.parse::<i64>().ok().filter(|bins| *bins > 0).expect("Invalid value for bins"));
+    let rescue_strand_mismatch = matches.is_present("rescue_strand_mismatch");
+    let independent_strands = matches.is_present("independent_strands");
+
+    writeln!(out, "feature.name\tquery\tquery.feature.start\tquery.feature.end\tquery.strand\ttarget\ttarget.feature.start\ttarget.feature.end\tbin.index\tbin.query.start\tbin.query.end\tbin.target.start\tbin.target.end\taligned.bp\tnot.aligned.in.query.bp\tnot.aligned.in.target.bp\tindels.in.query.bp\tindels.in.target\tignored.in.query.bp\tignored.in.target.bp")?;
+
+    for (line_number, line) in open_file(input_file).lines().enumerate() {
+        let line = line?;
+        let parts: Vec<&str> = line.split('\t').collect();
+        let Record { query_name, query_start, query_end, query_strand, target_name, target_start, target_end, cigar, query_name_2, feature_in_query_start, feature_in_query_end, feature_in_query_name, feature_in_query_strand, target_name_2, feature_in_target_start, feature_in_target_end, feature_in_target_name, feature_in_target_strand, .. } = match parse_record(&parts) {
+            Ok(record) => record,
+            Err(reason) => {
+                eprintln!("WARNING: line {}: {}. Skip this line", line_number + 1, reason);
+                continue;
+            }
+        };
+        if query_name != query_name_2 || target_name != target_name_2 || feature_in_query_name != feature_in_target_name {
+            eprintln!("WARNING: query, target, and/or feature name do not match! Skip this line: {}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", feature_in_query_name, query_name, feature_in_query_start, feature_in_query_end, query_strand, target_name, feature_in_target_start, feature_in_target_end);
+            continue;
+        }
+        if feature_in_query_strand != feature_in_target_strand && query_strand == "+" && !rescue_strand_mismatch && !independent_strands {
+            eprintln!("WARNING: the feature is on different strands in query and target, but query and target are in the same orientation! Skip this line:  {}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", feature_in_query_name, query_name, feature_in_query_start, feature_in_query_end, query_strand, target_name, feature_in_target_start, feature_in_target_end);
+            continue;
+        }
+
+        let query_rev = query_strand == "-";
+        // Bins tile the target feature; their query counterparts tile the query feature at the projected boundaries,
+        // so that each base of both features falls in exactly one bin
+        let target_boundaries = bin_boundaries(feature_in_target_start, feature_in_target_end, feature_in_target_strand == "-", bin_size, bins);
+        let query_boundaries: Vec<i64> = target_boundaries.iter().map(|&boundary| {
+            if boundary == feature_in_target_start {
+                if query_rev { feature_in_query_end } else { feature_in_query_start }
+            } else if boundary == feature_in_target_end {
+                if query_rev { feature_in_query_start } else { feature_in_query_end }
+            } else {
+                project_to_query(query_start, query_end, query_rev, target_start, cigar, boundary).clamp(feature_in_query_start, feature_in_query_end)
+            }
+        }).collect();
+
+        // The bins of a line with an invalid CIGAR are all skipped
+        let bin_counts: Result<Vec<_>, String> = (0..target_boundaries.len() - 1).map(|bin| {
+            let (bin_target_start, bin_target_end) = (target_boundaries[bin].min(target_boundaries[bin + 1]), target_boundaries[bin].max(target_boundaries[bin + 1]));
+            let (bin_query_start, bin_query_end) = (query_boundaries[bin].min(query_boundaries[bin + 1]), query_boundaries[bin].max(query_boundaries[bin + 1]));
+            let counts = count_aligned_bases(
+                query_start, query_end, query_strand.chars().next().unwrap(), target_start, target_end, cigar, bin_query_start, bin_query_end, bin_target_start, bin_target_end, max_indel_size
+            )?;
+            Ok((bin, bin_query_start, bin_query_end, bin_target_start, bin_target_end, counts))
+        }).collect();
+        let bin_counts = match bin_counts {
+            Ok(bin_counts) => bin_counts,
+            Err(reason) => {
+                eprintln!("WARNING: line {}: invalid CIGAR: {}. Skip this line", line_number + 1, reason);
+                continue;
+            }
+        };
+        for (bin, bin_query_start, bin_query_end, bin_target_start, bin_target_end, counts) in bin_counts {
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                feature_in_query_name, query_name, feature_in_query_start, feature_in_query_end, query_strand, target_name, feature_in_target_start, feature_in_target_end,
+                bin, bin_query_start, bin_query_end, bin_target_start, bin_target_end,
+                counts.aligned_bases, counts.not_aligned_bases_in_query, counts.not_aligned_bases_in_target, counts.indels_in_query, counts.indels_in_target, counts.ignored_bases_in_query, counts.ignored_bases_in_target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spill::TempFile;
+    use clap::App;
+    use std::collections::BTreeMap;
+
+    // Columns of the counts, from aligned.bp to ignored.in.target.bp
+    const COUNT_COLUMNS: std::ops::Range<usize> = 13..20;
+
+    #[test]
+    fn bin_counts_add_up_to_the_feature_counts() {
+        let temp_dir = std::env::temp_dir();
+        let input = TempFile::new(&temp_dir);
+        let lines = [
+            "q1\t100\t0\t100\t+\tt1\t100\t0\t100\t90\t100\t60\tcg:Z:10=2X3I20=6D10=1X14=12I8=15D20=\tq1\t5\t95\tf1\t0\t+\tgene\tt1\t3\t97\tf1\t0\t+\tgene",
+            "q1\t100\t0\t100\t-\tt1\t100\t0\t100\t90\t100\t60\tcg:Z:10=2X3I20=6D10=1X14=12I8=15D20=\tq1\t20\t80\tf2\t0\t-\tgene\tt1\t25\t85\tf2\t0\t+\tgene",
+            "q2\t50\t0\t50\t+\tt2\t60\t10\t60\t40\t50\t60\tcg:Z:10M3D20M3I17M\tq2\t0\t50\tf3\t0\t-\tgene\tt2\t0\t60\tf3\t0\t-\tgene",
+        ];
+        std::fs::write(input.path(), lines.map(|line| format!("{}\n", line)).concat()).unwrap();
+        for binning in [["--bin-size", "7"], ["--bins", "3"], ["--bin-size", "1000"]] {
+            for max_indel_size in ["5", "1000"] {
+                let matches = App::new("bin").args(args()).get_matches_from([["bin", "-i", input.path().to_str().unwrap(), "-m", max_indel_size].as_slice(), &binning].concat());
+                let mut report = Vec::new();
+                write_feature_bins(&matches, &mut report).unwrap();
+                let report = String::from_utf8(report).unwrap();
+                let mut sums: BTreeMap<&str, Vec<i64>> = BTreeMap::new();
+                for row in report.lines().skip(1) {
+                    let fields: Vec<&str> = row.split('\t').collect();
+                    let sum = sums.entry(fields[0]).or_insert_with(|| vec![0; COUNT_COLUMNS.len()]);
+                    for (total, value) in sum.iter_mut().zip(&fields[COUNT_COLUMNS]) {
+                        *total += value.parse::<i64>().unwrap();
+                    }
+                }
+                assert_eq!(sums.len(), lines.len());
+                for line in lines {
+                    let parts: Vec<&str> = line.split('\t').collect();
+                    let record = parse_record(&parts).unwrap();
+                    let counts = count_aligned_bases(
+                        record.query_start, record.query_end, record.query_strand.chars().next().unwrap(), record.target_start, record.target_end, record.cigar,
+                        record.feature_in_query_start, record.feature_in_query_end, record.feature_in_target_start, record.feature_in_target_end, max_indel_size.parse().unwrap()
+                    ).unwrap();
+                    let totals = vec![counts.aligned_bases, counts.not_aligned_bases_in_query, counts.not_aligned_bases_in_target, counts.indels_in_query, counts.indels_in_target, counts.ignored_bases_in_query, counts.ignored_bases_in_target];
+                    assert_eq!(sums[record.feature_in_query_name], totals, "{} with {:?} -m {}", record.feature_in_query_name, binning, max_indel_size);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use clap::ArgMatches;
+use std::io::{self, BufWriter, Write};
+
+use crate::annotate::{load_report, parse_aggregation, FeatureKey, TARGET_FEATURE_COLUMNS};
+
+// Run of adjacent target features whose aligned fraction reaches the threshold
+struct Block {
+    target: String,
+    start: i64,
+    end: i64,
+    features: u64,
+    aligned_bases: i64,
+    first_feature: String,
+    last_feature: String,
+}
+
+impl Block {
+    fn new(key: &FeatureKey, aligned_bases: i64) -> Block {
+        let (target, start, end, name) = key;
+        Block { target: target.clone(), start: *start, end: *end, features: 1, aligned_bases, first_feature: name.clone(), last_feature: name.clone() }
+    }
+
+    fn write(&self, writer: &mut impl Write, block_type: &str) -> io::Result<()> {
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", self.target, self.start, self.end, block_type, self.features, self.aligned_bases, self.first_feature, self.last_feature)
+    }
+}
+
+pub fn run(matches: &ArgMatches) -> io::Result<()> {
+    let report_file = matches.value_of("report").unwrap();
+    let aggregation = parse_aggregation(matches.value_of("aggregate"));
+    let threshold = matches.value_of("block_threshold")
+        .map(|s| s.parse::<f64>().expect("Invalid value for block threshold"))
+        .unwrap_or(0.9);
+    let max_feature_gap = matches.value_of("max_feature_gap")
+        .map(|s| s.parse::<i64>().expect("Invalid value for max feature gap"))
+        .unwrap_or(100_000);
+
+    // Target features in target order, with their aligned bases over all the report rows
+    let features: Vec<(FeatureKey, i64)> = load_report(report_file, &[&TARGET_FEATURE_COLUMNS], None)?.into_sorted()?
+        .map(|feature| feature.map(|(key, feature)| {
+            let aligned_bases = feature.aligned_bases(aggregation, key.2 - key.1);
+            (key, aligned_bases)
+        }))
+        .collect::<io::Result<_>>()?;
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    writeln!(out, "target\tblock.start\tblock.end\tblock.type\tfeatures\taligned.bp\tfirst.feature\tlast.feature")?;
+    let mut current: Option<Block> = None;
+    for (key, aligned_bases) in &features {
+        let length = key.2 - key.1;
+        let conserved = length > 0 && *aligned_bases as f64 / length as f64 >= threshold;
+        // A feature below the threshold, on another sequence, or too far from the previous one ends the block
+        if let Some(block) = current.as_mut() {
+            if conserved && block.target == key.0 && key.1 - block.end <= max_feature_gap {
+                block.end = std::cmp::max(block.end, key.2);
+                block.features += 1;
+                block.aligned_bases = block.aligned_bases.checked_add(*aligned_bases).unwrap_or_else(|| {
+                    eprintln!("ERROR: the aligned bases of the block of {} starting at {} overflow a 64-bit integer", block.target, block.start);
+                    std::process::exit(1);
+                });
+                block.last_feature = key.3.clone();
+                continue;
+            }
+            block.write(&mut out, "block")?;
+            current = None;
+        }
+        if conserved {
+            current = Some(Block::new(key, *aligned_bases));
+        } else {
+            Block::new(key, *aligned_bases).write(&mut out, "break")?;
+        }
+    }
+    if let Some(block) = current {
+        block.write(&mut out, "block")?;
+    }
+    out.flush()
+}
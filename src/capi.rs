@@ -0,0 +1,217 @@
+// C API of the counting kernel (capi feature), declared in include/feature_level_report.h: the counts of a feature
+// pair in an alignment, from the CIGAR as a string or parsed once with flr_cigar_new for several features
+use std::os::raw::c_char;
+
+use crate::counting::{count_aligned_bases_by_threshold, count_ops_by_threshold, FeatureCounts, UNLIMITED_INDEL_SIZE};
+
+// Status codes of the counting functions
+pub const FLR_OK: i32 = 0;
+// Null pointer, strand other than '+' or '-', or invalid CIGAR
+pub const FLR_INVALID_ARGUMENT: i32 = 1;
+// Panic caught in the kernel, the result being left untouched
+pub const FLR_INTERNAL_ERROR: i32 = 2;
+
+// Options of the counts, as report --max-indel-size and --max-gap-merge
+#[repr(C)]
+pub struct FlrOptions {
+    // Largest indel counted as an indel rather than as not aligned bases, negative for no limit
+    pub max_indel_size: i64,
+    // Largest gap merged into the aligned block around it
+    pub max_gap_merge: i64,
+}
+
+// Coordinates of an alignment and of the features in its query and target, 0-based half-open
+#[repr(C)]
+pub struct FlrAlignment {
+    pub query_start: i64,
+    pub query_end: i64,
+    // '+' or '-'
+    pub query_strand: c_char,
+    pub target_start: i64,
+    pub target_end: i64,
+    pub feature_in_query_start: i64,
+    pub feature_in_query_end: i64,
+    pub feature_in_target_start: i64,
+    pub feature_in_target_end: i64,
+}
+
+// Counts of a feature pair, as the columns of the report with the same names
+#[repr(C)]
+#[derive(Default)]
+pub struct FlrCounts {
+    pub aligned_bp: i64,
+    pub not_aligned_in_query_bp: i64,
+    pub not_aligned_in_target_bp: i64,
+    pub indels_in_query_bp: i64,
+    pub indels_in_target_bp: i64,
+    pub ignored_in_query_bp: i64,
+    pub ignored_in_target_bp: i64,
+    pub mismatches_bp: i64,
+    pub indel_events_in_query: i64,
+    pub indel_events_in_target: i64,
+    pub edge_adjustment_in_query_bp: i64,
+    pub edge_adjustment_in_target_bp: i64,
+    pub aligned_blocks: i64,
+    pub largest_aligned_block: i64,
+}
+
+impl From<&FeatureCounts> for FlrCounts {
+    fn from(counts: &FeatureCounts) -> FlrCounts {
+        FlrCounts {
+            aligned_bp: counts.aligned_bases,
+            not_aligned_in_query_bp: counts.not_aligned_bases_in_query,
+            not_aligned_in_target_bp: counts.not_aligned_bases_in_target,
+            indels_in_query_bp: counts.indels_in_query,
+            indels_in_target_bp: counts.indels_in_target,
+            ignored_in_query_bp: counts.ignored_bases_in_query,
+            ignored_in_target_bp: counts.ignored_bases_in_target,
+            mismatches_bp: counts.mismatches,
+            indel_events_in_query: counts.indel_events_in_query,
+            indel_events_in_target: counts.indel_events_in_target,
+            edge_adjustment_in_query_bp: counts.edge_adjustment_in_query,
+            edge_adjustment_in_target_bp: counts.edge_adjustment_in_target,
+            aligned_blocks: counts.aligned_blocks,
+            largest_aligned_block: counts.largest_aligned_block,
+        }
+    }
+}
+
+// CIGAR parsed into its (length, type) operations, opaque to C
+pub struct FlrCigar {
+    ops: Vec<(i64, char)>,
+}
+
+// Operations of a CIGAR, None unless it is only length and type pairs of MIDNSHP=X operations
+fn parse_cigar(cigar: &[u8]) -> Option<Vec<(i64, char)>> {
+    let mut ops = Vec::new();
+    let mut length: Option<i64> = None;
+    for &byte in cigar {
+        if byte.is_ascii_digit() {
+            length = Some(length.unwrap_or(0).checked_mul(10)?.checked_add((byte - b'0') as i64)?);
+        } else if b"MIDNSHP=X".contains(&byte) {
+            ops.push((length.take()?, byte as char));
+        } else {
+            return None;
+        }
+    }
+    length.is_none().then_some(ops)
+}
+
+// Counts of an alignment with the options, writing them to `result`; the CIGAR as a string or parsed
+fn count(alignment: *const FlrAlignment, options: *const FlrOptions, result: *mut FlrCounts, walk: impl FnOnce(&FlrAlignment, char, &[i64], i64) -> Result<Vec<FeatureCounts>, String>) -> i32 {
+    // SAFETY: the caller passes pointers to valid structs or null, checked here
+    let (alignment, options, result) = match unsafe { (alignment.as_ref(), options.as_ref(), result.as_mut()) } {
+        (Some(alignment), Some(options), Some(result)) => (alignment, options, result),
+        _ => return FLR_INVALID_ARGUMENT,
+    };
+    let query_strand = match alignment.query_strand as u8 {
+        b'+' => '+',
+        b'-' => '-',
+        _ => return FLR_INVALID_ARGUMENT,
+    };
+    if options.max_gap_merge < 0 {
+        return FLR_INVALID_ARGUMENT;
+    }
+    let max_indel_size = if options.max_indel_size < 0 { UNLIMITED_INDEL_SIZE } else { options.max_indel_size };
+    // Panics must not unwind into C
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| walk(alignment, query_strand, &[max_indel_size], options.max_gap_merge))) {
+        Ok(Ok(counts)) => {
+            *result = FlrCounts::from(&counts[0]);
+            FLR_OK
+        }
+        // A zero-length operation
+        Ok(Err(_)) => FLR_INVALID_ARGUMENT,
+        Err(_) => FLR_INTERNAL_ERROR,
+    }
+}
+
+/// Counts the bases of a feature pair in an alignment, from its CIGAR (`cigar_length` bytes, not NUL-terminated).
+///
+/// # Safety
+///
+/// `cigar` points to `cigar_length` readable bytes; the other pointers point to valid structs (or are null, which
+/// is reported as FLR_INVALID_ARGUMENT).
+#[no_mangle]
+pub unsafe extern "C" fn flr_count(alignment: *const FlrAlignment, cigar: *const u8, cigar_length: usize, options: *const FlrOptions, result: *mut FlrCounts) -> i32 {
+    if cigar.is_null() {
+        return FLR_INVALID_ARGUMENT;
+    }
+    let cigar = std::slice::from_raw_parts(cigar, cigar_length);
+    let cigar = match std::str::from_utf8(cigar).ok().filter(|cigar| parse_cigar(cigar.as_bytes()).is_some()) {
+        Some(cigar) => cigar,
+        None => return FLR_INVALID_ARGUMENT,
+    };
+    count(alignment, options, result, |a, query_strand, max_indel_sizes, max_gap_merge| {
+        count_aligned_bases_by_threshold(a.query_start, a.query_end, query_strand, a.target_start, a.target_end, cigar, a.feature_in_query_start, a.feature_in_query_end, a.feature_in_target_start, a.feature_in_target_end, max_indel_sizes, max_gap_merge)
+    })
+}
+
+/// Parses a CIGAR (`cigar_length` bytes, not NUL-terminated) once for the features of its alignment; null if it is
+/// not a valid CIGAR. Free it with flr_cigar_free.
+///
+/// # Safety
+///
+/// `cigar` points to `cigar_length` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn flr_cigar_new(cigar: *const u8, cigar_length: usize) -> *mut FlrCigar {
+    if cigar.is_null() {
+        return std::ptr::null_mut();
+    }
+    match parse_cigar(std::slice::from_raw_parts(cigar, cigar_length)) {
+        Some(ops) => Box::into_raw(Box::new(FlrCigar { ops })),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Frees a CIGAR parsed with flr_cigar_new (nothing for null).
+///
+/// # Safety
+///
+/// `cigar` comes from flr_cigar_new and is not used after.
+#[no_mangle]
+pub unsafe extern "C" fn flr_cigar_free(cigar: *mut FlrCigar) {
+    if !cigar.is_null() {
+        drop(Box::from_raw(cigar));
+    }
+}
+
+/// Counts the bases of a feature pair in an alignment, from its CIGAR parsed with flr_cigar_new.
+///
+/// # Safety
+///
+/// `cigar` comes from flr_cigar_new; the other pointers point to valid structs (or are null, which is reported as
+/// FLR_INVALID_ARGUMENT).
+#[no_mangle]
+pub unsafe extern "C" fn flr_count_parsed(alignment: *const FlrAlignment, cigar: *const FlrCigar, options: *const FlrOptions, result: *mut FlrCounts) -> i32 {
+    let cigar = match cigar.as_ref() {
+        Some(cigar) => cigar,
+        None => return FLR_INVALID_ARGUMENT,
+    };
+    count(alignment, options, result, |a, query_strand, max_indel_sizes, max_gap_merge| {
+        Ok(count_ops_by_threshold(a.query_start, a.query_end, query_strand, a.target_start, a.target_end, cigar.ops.iter().copied(), a.feature_in_query_start, a.feature_in_query_end, a.feature_in_target_start, a.feature_in_target_end, max_indel_sizes, max_gap_merge))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    // capi/check_abi.c, compiled by build.rs against include/feature_level_report.h
+    #[link(name = "check_abi", kind = "static")]
+    extern "C" {
+        fn flr_check_abi_main() -> std::os::raw::c_int;
+    }
+
+    #[test]
+    fn c_program_checks_pass_through_the_header() {
+        assert_eq!(unsafe { flr_check_abi_main() }, 0, "capi/check_abi.c reported differences");
+    }
+
+    #[test]
+    fn header_is_up_to_date() {
+        let Some(generated) = option_env!("FLR_GENERATED_HEADER") else {
+            eprintln!("skipped: cbindgen is not installed");
+            return;
+        };
+        let committed = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/include/feature_level_report.h")).unwrap();
+        assert!(std::fs::read_to_string(generated).unwrap() == committed, "include/feature_level_report.h is out of date with src/capi.rs; regenerate it with cbindgen --config cbindgen.toml --output include/feature_level_report.h src/capi.rs");
+    }
+}
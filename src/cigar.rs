@@ -0,0 +1,366 @@
+// Tokenizer of CIGAR strings into their (length, type) operations, reading them like the regex (\d+)([MIDNSHP=X]):
+// operations without a length and bytes that are neither digits nor operations are skipped. The scalar tokenizer reads
+// the CIGAR byte by byte; the SIMD one finds the bytes that are not digits 64 at a time, with the widest kernel the CPU
+// running the tool has (AVX2 or SSE2 on x86_64, NEON on aarch64, detected at run time; the scalar tokenizer elsewhere),
+// and converts the digits of each length at once. A length overflowing a 64-bit integer ends the operations; it and the
+// zero-length operations (like the 0M of 0M5I) are reported by CigarOps::error once the operations are read
+use std::sync::OnceLock;
+
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::{vaddv_u8, vandq_u8, vcleq_u8, vdupq_n_u8, vget_high_u8, vget_low_u8, vld1q_u8, vsubq_u8};
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{__m128i, __m256i, _mm256_cmpeq_epi8, _mm256_loadu_si256, _mm256_min_epu8, _mm256_movemask_epi8, _mm256_set1_epi8, _mm256_sub_epi8, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_min_epu8, _mm_movemask_epi8, _mm_set1_epi8, _mm_sub_epi8};
+
+// Bytes whose non-digits are found at once
+const CHUNK: usize = 64;
+
+// Kernel finding the bytes of a chunk (of at most CHUNK bytes) that are not ASCII digits, as a bit mask
+type NonDigits = fn(&[u8]) -> u64;
+
+// SIMD kernels available on the CPU running the tool, the widest first
+fn simd_kernels() -> Vec<(&'static str, NonDigits)> {
+    #[allow(unused_mut)]
+    let mut kernels: Vec<(&'static str, NonDigits)> = Vec::new();
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            kernels.push(("avx2", avx2_non_digits));
+        }
+        if is_x86_feature_detected!("sse2") {
+            kernels.push(("sse2", sse2_non_digits));
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            kernels.push(("neon", neon_non_digits));
+        }
+    }
+    kernels
+}
+
+// Widest SIMD kernel of the CPU running the tool, detected once
+fn simd_kernel_of_cpu() -> Option<(&'static str, NonDigits)> {
+    static KERNEL: OnceLock<Option<(&'static str, NonDigits)>> = OnceLock::new();
+    *KERNEL.get_or_init(|| simd_kernels().first().copied())
+}
+
+// Name of the SIMD kernel of the tokenizer on this CPU, None if it has none and the scalar tokenizer is used (for the
+// benchmarks)
+#[allow(dead_code)]
+pub fn simd_kernel() -> Option<&'static str> {
+    simd_kernel_of_cpu().map(|(name, _)| name)
+}
+
+fn is_op(byte: u8) -> bool {
+    matches!(byte, b'M' | b'I' | b'D' | b'N' | b'S' | b'H' | b'P' | b'=' | b'X')
+}
+
+// Length of digits, None if it overflows
+fn parse_length(digits: &[u8]) -> Option<i64> {
+    digits.iter().try_fold(0i64, |length, digit| length.checked_mul(10)?.checked_add((digit - b'0') as i64))
+}
+
+// Length of the 1 to 8 digits ending at `end`, from the 8 bytes before it: the bytes before the digits are cleared (as
+// leading zeros), and the digits are combined in pairs, then in quadruplets, then all 8
+fn parse_short_length(cigar: &[u8], end: usize, digits: usize) -> i64 {
+    let bytes: [u8; 8] = cigar[end - 8..end].try_into().unwrap();
+    let mut value = u64::from_le_bytes(bytes) & (u64::MAX << (8 * (8 - digits))) & 0x0f0f0f0f0f0f0f0f;
+    value = value.wrapping_mul(2561) >> 8;
+    value = (value & 0x00ff00ff00ff00ff).wrapping_mul(6553601) >> 16;
+    value = (value & 0x0000ffff0000ffff).wrapping_mul(42949672960001) >> 32;
+    value as i64
+}
+
+// Bit mask of the bytes of a chunk (of at most 64 bytes) that are not ASCII digits
+fn scalar_non_digits(chunk: &[u8]) -> u64 {
+    chunk.iter().enumerate().filter(|(_, byte)| !byte.is_ascii_digit()).fold(0, |mask, (index, _)| mask | (1 << index))
+}
+
+#[cfg(target_arch = "x86_64")]
+fn sse2_non_digits(chunk: &[u8]) -> u64 {
+    if chunk.len() < CHUNK {
+        return scalar_non_digits(chunk);
+    }
+    // SAFETY: only used when SSE2 is detected, and the four unaligned loads of 16 bytes stay within the 64 bytes of
+    // the chunk
+    unsafe { sse2_chunk_non_digits(chunk) }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn sse2_chunk_non_digits(chunk: &[u8]) -> u64 {
+    let zero = _mm_set1_epi8(b'0' as i8);
+    let nine = _mm_set1_epi8(9);
+    let mut digits = 0u64;
+    for part in 0..CHUNK / 16 {
+        let bytes = _mm_loadu_si128(chunk.as_ptr().add(part * 16) as *const __m128i);
+        // Digits are the bytes at most 9 above '0' as unsigned bytes, min(byte - '0', 9) being byte - '0'
+        let offsets = _mm_sub_epi8(bytes, zero);
+        let is_digit = _mm_cmpeq_epi8(_mm_min_epu8(offsets, nine), offsets);
+        digits |= (_mm_movemask_epi8(is_digit) as u16 as u64) << (part * 16);
+    }
+    !digits
+}
+
+#[cfg(target_arch = "x86_64")]
+fn avx2_non_digits(chunk: &[u8]) -> u64 {
+    if chunk.len() < CHUNK {
+        return scalar_non_digits(chunk);
+    }
+    // SAFETY: only used when AVX2 is detected, and the two unaligned loads of 32 bytes stay within the 64 bytes of
+    // the chunk
+    unsafe { avx2_chunk_non_digits(chunk) }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn avx2_chunk_non_digits(chunk: &[u8]) -> u64 {
+    let zero = _mm256_set1_epi8(b'0' as i8);
+    let nine = _mm256_set1_epi8(9);
+    let mut digits = 0u64;
+    for part in 0..CHUNK / 32 {
+        let bytes = _mm256_loadu_si256(chunk.as_ptr().add(part * 32) as *const __m256i);
+        // As with SSE2, 32 bytes at a time
+        let offsets = _mm256_sub_epi8(bytes, zero);
+        let is_digit = _mm256_cmpeq_epi8(_mm256_min_epu8(offsets, nine), offsets);
+        digits |= (_mm256_movemask_epi8(is_digit) as u32 as u64) << (part * 32);
+    }
+    !digits
+}
+
+#[cfg(target_arch = "aarch64")]
+fn neon_non_digits(chunk: &[u8]) -> u64 {
+    if chunk.len() < CHUNK {
+        return scalar_non_digits(chunk);
+    }
+    // SAFETY: only used when NEON is detected, and the four loads of 16 bytes stay within the 64 bytes of the chunk
+    unsafe { neon_chunk_non_digits(chunk) }
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn neon_chunk_non_digits(chunk: &[u8]) -> u64 {
+    // NEON has no movemask: the bit of each byte is its weight in its half of 8 bytes, added across the half
+    const WEIGHTS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+    let weights = vld1q_u8(WEIGHTS.as_ptr());
+    let zero = vdupq_n_u8(b'0');
+    let nine = vdupq_n_u8(9);
+    let mut digits = 0u64;
+    for part in 0..CHUNK / 16 {
+        let bytes = vld1q_u8(chunk.as_ptr().add(part * 16));
+        let is_digit = vandq_u8(vcleq_u8(vsubq_u8(bytes, zero), nine), weights);
+        let mask = vaddv_u8(vget_low_u8(is_digit)) as u64 | (vaddv_u8(vget_high_u8(is_digit)) as u64) << 8;
+        digits |= mask << (part * 16);
+    }
+    !digits
+}
+
+// Operations of a CIGAR, as (length, type)
+pub struct CigarOps<'a> {
+    cigar: &'a [u8],
+    // SIMD kernel, None for the scalar tokenizer
+    kernel: Option<NonDigits>,
+    // Next byte read by the scalar tokenizer, or start of the chunk whose non-digits are in `non_digits` for the SIMD
+    // one, and start of the digits of the next length
+    chunk_start: usize,
+    non_digits: u64,
+    length_start: usize,
+    // Operations read, and the number and byte offset of the first one of length 0 and of the length that overflowed,
+    // ending the operations
+    ops_read: usize,
+    zero_length: Option<(usize, usize)>,
+    overflow: Option<(usize, usize)>,
+}
+
+impl<'a> CigarOps<'a> {
+    // Operations of a CIGAR, with the SIMD tokenizer when `simd` is set and the CPU has it
+    pub fn new(cigar: &'a str, simd: bool) -> CigarOps<'a> {
+        CigarOps::with_kernel(cigar, simd_kernel_of_cpu().filter(|_| simd).map(|(_, kernel)| kernel))
+    }
+
+    fn with_kernel(cigar: &'a str, kernel: Option<NonDigits>) -> CigarOps<'a> {
+        let cigar = cigar.as_bytes();
+        let mut ops = CigarOps { cigar, kernel, chunk_start: 0, non_digits: 0, length_start: 0, ops_read: 0, zero_length: None, overflow: None };
+        if let Some(kernel) = kernel {
+            ops.non_digits = kernel(ops.chunk());
+        }
+        ops
+    }
+
+    fn chunk(&self) -> &'a [u8] {
+        &self.cigar[self.chunk_start..std::cmp::min(self.chunk_start + CHUNK, self.cigar.len())]
+    }
+
+    // Operation of the length starting at `length_start`, ending the operations if it overflowed
+    fn op(&mut self, length: Option<i64>, op: u8, length_start: usize) -> Option<(i64, char)> {
+        self.ops_read += 1;
+        let Some(length) = length else {
+            self.overflow = Some((self.ops_read, length_start));
+            self.chunk_start = self.cigar.len();
+            self.non_digits = 0;
+            return None;
+        };
+        if length == 0 && self.zero_length.is_none() {
+            self.zero_length = Some((self.ops_read, length_start));
+        }
+        Some((length, op as char))
+    }
+
+    // Why the CIGAR is invalid, from the operations read so far: the first zero-length operation, or the length that
+    // overflowed
+    pub fn error(&self) -> Option<String> {
+        if let Some((op, offset)) = self.zero_length {
+            return Some(format!("operation {} (at byte {}) has a length of 0", op, offset));
+        }
+        self.overflow.map(|(op, offset)| format!("the length of operation {} (at byte {}) overflows a 64-bit integer", op, offset))
+    }
+
+    fn next_scalar(&mut self) -> Option<(i64, char)> {
+        for (position, &byte) in self.cigar.iter().enumerate().skip(self.chunk_start) {
+            if byte.is_ascii_digit() {
+                continue;
+            }
+            let length_start = std::mem::replace(&mut self.length_start, position + 1);
+            let digits = &self.cigar[length_start..position];
+            if !digits.is_empty() && is_op(byte) {
+                self.chunk_start = position + 1;
+                return self.op(parse_length(digits), byte, length_start);
+            }
+        }
+        self.chunk_start = self.cigar.len();
+        None
+    }
+}
+
+// Operations of a CIGAR, as (length, type)
+pub fn ops(cigar: &str) -> CigarOps<'_> {
+    CigarOps::new(cigar, true)
+}
+
+impl Iterator for CigarOps<'_> {
+    type Item = (i64, char);
+
+    fn next(&mut self) -> Option<(i64, char)> {
+        let Some(kernel) = self.kernel else {
+            return self.next_scalar();
+        };
+        loop {
+            while self.non_digits == 0 {
+                self.chunk_start += CHUNK;
+                if self.chunk_start >= self.cigar.len() {
+                    return None;
+                }
+                self.non_digits = kernel(self.chunk());
+            }
+            let position = self.chunk_start + self.non_digits.trailing_zeros() as usize;
+            self.non_digits &= self.non_digits - 1;
+            let digits = position - self.length_start;
+            let length_start = std::mem::replace(&mut self.length_start, position + 1);
+            let op = self.cigar[position];
+            if digits > 0 && is_op(op) {
+                let length = if digits <= 8 && position >= 8 { Some(parse_short_length(self.cigar, position, digits)) } else { parse_length(&self.cigar[length_start..position]) };
+                return self.op(length, op, length_start);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    // Operations of the CIGAR as read by the regex the tokenizers replace, up to the first length overflowing
+    fn regex_ops(cigar_re: &Regex, cigar: &str) -> (Vec<(i64, char)>, bool) {
+        let mut ops = Vec::new();
+        for cap in cigar_re.captures_iter(cigar) {
+            match cap[1].parse::<i64>() {
+                Ok(length) => ops.push((length, cap[2].chars().next().unwrap())),
+                Err(_) => return (ops, true),
+            }
+        }
+        (ops, false)
+    }
+
+    fn tokenized(cigar: &str, kernel: Option<NonDigits>) -> (Vec<(i64, char)>, Option<String>) {
+        let mut ops = CigarOps::with_kernel(cigar, kernel);
+        let tokens: Vec<(i64, char)> = ops.by_ref().collect();
+        // The operations stay ended
+        assert_eq!(ops.next(), None);
+        (tokens, ops.error())
+    }
+
+    // Random CIGAR-like strings: mostly lengths and operations, with other bytes (including multibyte characters),
+    // operations without a length, and lengths of up to 25 digits, over several chunks
+    fn fuzz_corpus() -> Vec<String> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |bound: u64| -> u64 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % bound
+        };
+        let mut corpus = vec![String::new(), "M".to_string(), "0M".to_string(), "9".to_string(), "12345678901234567890M".to_string(), "9223372036854775807M1I".to_string(), "9223372036854775808M1I".to_string()];
+        for _ in 0..3000 {
+            let mut cigar = String::new();
+            let target_length = next(400) as usize;
+            while cigar.len() < target_length {
+                match next(20) {
+                    0 => cigar.push(['A', '*', ' ', 'é', 'm', '\t'][next(6) as usize]),
+                    1 => cigar.push_str(&"7".repeat(1 + next(25) as usize)),
+                    2 => cigar.push("MIDNSHP=X".chars().nth(next(9) as usize).unwrap()),
+                    _ => {
+                        let digits = 1 + next(9) as u32;
+                        cigar.push_str(&next(10u64.pow(digits)).to_string());
+                        cigar.push("MIDNSHP=X=M".chars().nth(next(11) as usize).unwrap());
+                    }
+                }
+            }
+            corpus.push(cigar);
+        }
+        corpus
+    }
+
+    #[test]
+    fn simd_scalar_and_regex_tokenizers_agree() {
+        let cigar_re = Regex::new(r"(\d+)([MIDNSHP=X])").unwrap();
+        let kernels = simd_kernels();
+        eprintln!("SIMD kernels: {:?}", kernels.iter().map(|(name, _)| *name).collect::<Vec<_>>());
+        let mut overflows = 0;
+        for cigar in fuzz_corpus() {
+            let (regex_tokens, regex_overflow) = regex_ops(&cigar_re, &cigar);
+            let (scalar_tokens, scalar_error) = tokenized(&cigar, None);
+            let zero_length = regex_tokens.iter().any(|&(length, _)| length == 0);
+            assert_eq!((&scalar_tokens, scalar_error.is_some()), (&regex_tokens, regex_overflow || zero_length), "scalar: {}", cigar);
+            for (name, kernel) in &kernels {
+                assert_eq!(tokenized(&cigar, Some(*kernel)), (scalar_tokens.clone(), scalar_error.clone()), "{}: {}", name, cigar);
+            }
+            overflows += regex_overflow as usize;
+        }
+        assert!(overflows > 100, "only {} CIGARs overflow", overflows);
+    }
+
+    fn error(cigar: &str) -> Option<String> {
+        let mut ops = ops(cigar);
+        ops.by_ref().count();
+        ops.error()
+    }
+
+    #[test]
+    fn overflowing_length_is_reported() {
+        let cigar = format!("{}10M5I{}=3M", "0".repeat(70), "1".repeat(20));
+        assert_eq!(ops(&cigar).collect::<Vec<_>>(), vec![(10, 'M'), (5, 'I')]);
+        assert_eq!(error(&cigar).unwrap(), "the length of operation 3 (at byte 75) overflows a 64-bit integer");
+        assert_eq!(error("10M5I9223372036854775807="), None);
+    }
+
+    #[test]
+    fn zero_length_ops_are_reported() {
+        for (cigar, expected) in [("0M5I", "operation 1 (at byte 0)"), ("10=0I5X", "operation 2 (at byte 3)"), ("10M00D0I5M", "operation 2 (at byte 3)")] {
+            assert_eq!(error(cigar).unwrap(), format!("{} has a length of 0", expected), "{}", cigar);
+        }
+        // The operations are still read, as no-ops for the walk
+        assert_eq!(ops("10=0I5X").collect::<Vec<_>>(), vec![(10, '='), (0, 'I'), (5, 'X')]);
+    }
+}
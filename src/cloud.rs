@@ -0,0 +1,278 @@
+use std::io::{self, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// Attempts to stream an object before giving up, each one resuming where the previous one stopped
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+// Bytes of the end of the error output of the client kept for the error messages
+const STDERR_TAIL: usize = 64 * 1024;
+
+pub fn is_object_url(file_path: &str) -> bool {
+    file_path.starts_with("s3://") || file_path.starts_with("gs://")
+}
+
+// Command streaming the object from a byte offset through the provider command line client, which resolves
+// credentials from the standard environment/instance metadata chain (and honors AWS_ENDPOINT_URL for S3-compatible
+// endpoints). Both read a range of the object, so a retried attempt downloads only the bytes not read yet: aws s3 cp
+// cannot read a range, so S3 objects are read with aws s3api get-object, whose body goes to the output through file
+// descriptor 3 while its JSON response goes to /dev/null
+fn client_command(url: &str, offset: u64) -> Command {
+    if let Some(path) = url.strip_prefix("s3://") {
+        let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+        let mut command = Command::new("sh");
+        command.args(["-c", "aws s3api get-object --bucket \"$1\" --key \"$2\" ${3:+--range \"$3\"} /dev/fd/3 3>&1 >/dev/null", "sh", bucket, key]);
+        // An empty object has no range, so the first attempt reads it whole
+        command.arg(if offset > 0 { format!("bytes={}-", offset) } else { String::new() });
+        command
+    } else {
+        let mut command = Command::new("gcloud");
+        command.args(["storage", "cat", url]);
+        if offset > 0 {
+            command.arg(format!("--range={}-", offset));
+        }
+        command
+    }
+}
+
+// Whether a failed attempt only asked for a range starting at the end of the object, after all of it was read by the
+// previous attempts (e.g. when the client failed after its last byte)
+fn is_range_past_the_end(message: &str) -> bool {
+    message.contains("InvalidRange") || message.to_lowercase().contains("not satisfiable")
+}
+
+// Reads the error output of the client on its own thread, so that a client writing more of it than the pipe holds
+// does not block before its output is read; only the end of it is kept
+fn drain_stderr(mut stderr: impl Read + Send + 'static) -> JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut tail = Vec::new();
+        let mut buffer = [0; 8192];
+        while let Ok(read) = stderr.read(&mut buffer) {
+            if read == 0 {
+                break;
+            }
+            tail.extend_from_slice(&buffer[..read]);
+            if tail.len() > 2 * STDERR_TAIL {
+                tail.drain(..tail.len() - STDERR_TAIL);
+            }
+        }
+        let start = tail.len().saturating_sub(STDERR_TAIL);
+        String::from_utf8_lossy(&tail[start..]).trim().to_string()
+    })
+}
+
+// Running client of an attempt
+struct Attempt {
+    child: Child,
+    stdout: ChildStdout,
+    stderr: Option<JoinHandle<String>>,
+}
+
+// Reader over an object in cloud storage. Any failure of the client, before or after the first byte is received, is
+// retried with exponential backoff, the next attempt reading the object from the first byte not read yet; after the last attempt
+// the failure is reported with the provider's message.
+pub struct ObjectReader {
+    url: String,
+    command: fn(&str, u64) -> Command,
+    backoff: Duration,
+    attempt: Attempt,
+    attempts: u32,
+    bytes_read: u64,
+}
+
+impl ObjectReader {
+    pub fn open(url: &str) -> io::Result<ObjectReader> {
+        ObjectReader::with_client(url, client_command, INITIAL_BACKOFF)
+    }
+
+    fn with_client(url: &str, command: fn(&str, u64) -> Command, backoff: Duration) -> io::Result<ObjectReader> {
+        Ok(ObjectReader {
+            url: url.to_string(),
+            command,
+            backoff,
+            attempt: ObjectReader::spawn(url, command, 0)?,
+            attempts: 1,
+            bytes_read: 0,
+        })
+    }
+
+    fn spawn(url: &str, command: fn(&str, u64) -> Command, offset: u64) -> io::Result<Attempt> {
+        let mut child = command(url, offset).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        let stderr = Some(drain_stderr(child.stderr.take().unwrap()));
+        Ok(Attempt { child, stdout, stderr })
+    }
+
+    // Waits for the client of the current attempt, returning its error output if it failed
+    fn finish_attempt(&mut self) -> io::Result<Option<String>> {
+        let status = self.attempt.child.wait()?;
+        let message = self.attempt.stderr.take().map(|stderr| stderr.join().unwrap_or_default()).unwrap_or_default();
+        Ok(if status.success() || (self.bytes_read > 0 && is_range_past_the_end(&message)) {
+            None
+        } else if message.is_empty() {
+            Some(status.to_string())
+        } else {
+            Some(message)
+        })
+    }
+
+    fn retry(&mut self, message: &str) -> io::Result<()> {
+        eprintln!("WARNING: failed to read {} after {} bytes (attempt {} of {}), retrying: {}", self.url, self.bytes_read, self.attempts, MAX_ATTEMPTS, message);
+        std::thread::sleep(self.backoff * 2u32.pow(self.attempts - 1));
+        self.attempts += 1;
+        self.attempt = ObjectReader::spawn(&self.url, self.command, self.bytes_read)?;
+        Ok(())
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = match self.attempt.stdout.read(buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n > 0 {
+                self.bytes_read += n as u64;
+                return Ok(n);
+            }
+
+            // End of the stream: it is complete only if the client succeeded
+            let message = match self.finish_attempt()? {
+                None => return Ok(0),
+                Some(message) => message,
+            };
+            if self.attempts < MAX_ATTEMPTS {
+                self.retry(&message)?;
+                continue;
+            }
+            return Err(io::Error::other(format!("failed to read {} after {} bytes and {} attempts: {}", self.url, self.bytes_read, self.attempts, message)));
+        }
+    }
+}
+
+impl Drop for ObjectReader {
+    // A client still streaming when the reader is dropped early (e.g. with --head) is stopped
+    fn drop(&mut self) {
+        let _ = self.attempt.child.kill();
+        let _ = self.attempt.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const OBJECT: &str = "q1\t100\t0\t100\t+\tt1\t100\t0\t100\t100\t100\t60\tcg:Z:100M\n";
+
+    fn read_all(command: fn(&str, u64) -> Command) -> io::Result<String> {
+        let mut reader = ObjectReader::with_client("s3://bucket/object.paf", command, Duration::from_millis(1))?;
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    fn shell(script: String) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", &script]);
+        command
+    }
+
+    // File telling the fake clients of a test whether they already failed once
+    fn state_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("feature_level_report.{}.{}.state", std::process::id(), name))
+    }
+
+    #[test]
+    fn client_flooding_stderr_does_not_block() {
+        // Far more error output than a pipe holds is written before the object
+        let command = |_: &str, _: u64| shell(format!("head -c 4000000 /dev/zero | tr '\\0' w >&2; printf '{}'", OBJECT.replace('\t', "\\t").replace('\n', "\\n")));
+        assert_eq!(read_all(command).unwrap(), OBJECT);
+    }
+
+    #[test]
+    fn ranged_read_failing_mid_stream_resumes() {
+        let command = |_: &str, offset: u64| {
+            let state = state_file("ranged");
+            let object = OBJECT.replace('\t', "\\t").replace('\n', "\\n");
+            // The first attempt fails after 20 bytes, the next ones read from the offset
+            shell(format!("if [ -e {0} ]; then printf '{1}' | tail -c +{2}; rm {0}; else printf '{1}' | head -c 20; touch {0}; echo 'Connection reset' >&2; exit 1; fi", state.display(), object, offset + 1))
+        };
+        let _ = std::fs::remove_file(state_file("ranged"));
+        assert_eq!(read_all(command).unwrap(), OBJECT);
+    }
+
+    #[test]
+    fn each_retry_reads_from_the_first_byte_not_read_yet() {
+        // Each attempt records its offset and fails after 10 more bytes, until the object is read
+        let command = |_: &str, offset: u64| {
+            let object = OBJECT.replace('\t', "\\t").replace('\n', "\\n");
+            shell(format!("echo {2} >> {0}; printf '{1}' | tail -c +{3} | head -c 10; [ {2} -ge 40 ] || exit 1", state_file("offsets").display(), object, offset, offset + 1))
+        };
+        let _ = std::fs::remove_file(state_file("offsets"));
+        assert_eq!(read_all(command).unwrap(), OBJECT);
+        let offsets = std::fs::read_to_string(state_file("offsets")).unwrap();
+        std::fs::remove_file(state_file("offsets")).unwrap();
+        assert_eq!(offsets, "0\n10\n20\n30\n40\n");
+    }
+
+    #[test]
+    fn range_past_the_end_after_the_whole_object_ends_the_read() {
+        // The first attempt fails after the last byte, so the next one asks for a range past the end of the object
+        let command = |_: &str, offset: u64| {
+            let object = OBJECT.replace('\t', "\\t").replace('\n', "\\n");
+            if offset == 0 {
+                shell(format!("printf '{}'; exit 1", object))
+            } else {
+                shell("echo 'An error occurred (InvalidRange) when calling the GetObject operation' >&2; exit 254".to_string())
+            }
+        };
+        assert_eq!(read_all(command).unwrap(), OBJECT);
+        // Before any byte is read it is an error
+        let command = |_: &str, _: u64| shell("echo 'An error occurred (InvalidRange)' >&2; exit 254".to_string());
+        assert!(read_all(command).unwrap_err().to_string().contains("after 0 bytes and 5 attempts"));
+    }
+
+    #[test]
+    fn client_commands_read_from_the_offset() {
+        let args = |url: &str, offset: u64| client_command(url, offset).get_args().map(|arg| arg.to_string_lossy().into_owned()).collect::<Vec<_>>();
+        let s3 = args("s3://bucket/dir/object.paf", 1024);
+        assert_eq!(s3[2..], ["sh", "bucket", "dir/object.paf", "bytes=1024-"]);
+        assert!(s3[1].starts_with("aws s3api get-object "), "{}", s3[1]);
+        assert_eq!(args("s3://bucket/dir/object.paf", 0)[5], "");
+        assert_eq!(args("gs://bucket/object.paf", 1024), ["storage", "cat", "gs://bucket/object.paf", "--range=1024-"]);
+        assert_eq!(args("gs://bucket/object.paf", 0), ["storage", "cat", "gs://bucket/object.paf"]);
+    }
+
+    #[test]
+    fn persistent_failure_is_reported_after_the_last_attempt() {
+        let command = |_: &str, _: u64| shell("echo 'An error occurred (NoSuchKey)' >&2; exit 1".to_string());
+        let error = read_all(command).unwrap_err().to_string();
+        assert!(error.contains("after 0 bytes and 5 attempts: An error occurred (NoSuchKey)"), "{}", error);
+    }
+
+    // Round trip through an S3-compatible server (e.g. minio or localstack), run when FLR_TEST_S3_BUCKET names a
+    // writable bucket of the server at AWS_ENDPOINT_URL
+    #[test]
+    fn s3_compatible_endpoint() {
+        let Ok(bucket) = std::env::var("FLR_TEST_S3_BUCKET") else {
+            eprintln!("skipped: FLR_TEST_S3_BUCKET is not set");
+            return;
+        };
+        let path = std::env::temp_dir().join(format!("feature_level_report.{}.s3.paf", std::process::id()));
+        std::fs::write(&path, OBJECT.repeat(10000)).unwrap();
+        let url = format!("s3://{}/feature_level_report.{}.paf", bucket, std::process::id());
+        let status = Command::new("aws").args(["s3", "cp", path.to_str().unwrap(), &url]).status().unwrap();
+        assert!(status.success());
+
+        let mut content = String::new();
+        ObjectReader::open(&url).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, OBJECT.repeat(10000));
+        let _ = Command::new("aws").args(["s3", "rm", &url]).status();
+        let _ = std::fs::remove_file(&path);
+    }
+}
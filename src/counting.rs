@@ -0,0 +1,693 @@
+use crate::cigar;
+
+// Half-open [start, end) interval on a sequence
+pub type Interval = (i64, i64);
+
+// Per-feature counters computed by walking the CIGAR
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FeatureCounts {
+    pub aligned_bases: i64,
+    pub not_aligned_bases_in_query: i64,
+    pub not_aligned_bases_in_target: i64,
+    pub indels_in_query: i64,
+    pub indels_in_target: i64,
+    pub ignored_bases_in_query: i64,
+    pub ignored_bases_in_target: i64,
+    // Aligned bases falling in `X` operations
+    pub mismatches: i64,
+    // Indel operations overlapping the feature that are counted as indels (not larger than max_indel_size)
+    pub indel_events_in_query: i64,
+    pub indel_events_in_target: i64,
+    // Indel operations overlapping the feature that are larger than max_indel_size
+    pub large_indel_events_in_query: i64,
+    pub large_indel_events_in_target: i64,
+    // Feature bases in aligned operations that are not counted as aligned because the opposite base falls
+    // outside the feature on the other sequence (included in the ignored bases)
+    pub edge_adjustment_in_query: i64,
+    pub edge_adjustment_in_target: i64,
+    // Feature bases outside the alignment range (included in the ignored bases)
+    pub outside_alignment_in_query: i64,
+    pub outside_alignment_in_target: i64,
+    // Feature bases in the alignment range that no operation reaches, when the CIGAR is shorter than the alignment
+    // or uses operations not counted (included in the ignored bases)
+    pub unconsumed_in_query: i64,
+    pub unconsumed_in_target: i64,
+    // Part of the target feature between its first and last aligned bases (with the opposite base in the query feature)
+    pub aligned_span_in_target: Option<Interval>,
+    // Span of the target bases aligned to the query feature, wherever they fall
+    pub query_feature_span_in_target: Option<Interval>,
+    // Runs of aligned bases not interrupted by indels, and the longest one
+    pub aligned_blocks: i64,
+    pub largest_aligned_block: i64,
+    // Types of the operations seen within the features, one bit per type of CIGAR_OP_TYPES
+    pub op_types: u8,
+}
+
+// CIGAR operation types reported by --cigar-op-types, in the order they are listed
+const CIGAR_OP_TYPES: &str = "M=XIDNS";
+
+// Formula of the fragmentation column (--fragmentation-formula)
+#[derive(Clone, Copy)]
+pub enum Fragmentation {
+    LargestBlock,
+    Blocks,
+}
+
+impl FeatureCounts {
+    // Average feature bases per counted indel event, over both query and target
+    pub fn mean_indel_size(&self) -> Option<f64> {
+        let events = self.indel_events_in_query + self.indel_events_in_target;
+        if events > 0 {
+            Some((self.indels_in_query + self.indels_in_target) as f64 / events as f64)
+        } else {
+            None
+        }
+    }
+
+    // Edits between the query and target copies of the feature: mismatched bases plus the feature bases in indels
+    // (or the indel events, with `events`), leaving out the indels larger than max_indel_size with `exclude_large`
+    pub fn edit_distance(&self, events: bool, exclude_large: bool) -> i64 {
+        let indels = match (events, exclude_large) {
+            (false, false) => self.indels_in_query + self.indels_in_target + self.not_aligned_bases_in_query + self.not_aligned_bases_in_target,
+            (false, true) => self.indels_in_query + self.indels_in_target,
+            (true, false) => self.indel_events_in_query + self.indel_events_in_target + self.large_indel_events_in_query + self.large_indel_events_in_target,
+            (true, true) => self.indel_events_in_query + self.indel_events_in_target,
+        };
+        self.mismatches + indels
+    }
+
+    // Jaccard index of the query feature projected on the target through the alignment and the target feature,
+    // as intervals; None when both are empty
+    pub fn span_jaccard(&self, feature_in_target_start: i64, feature_in_target_end: i64) -> Option<f64> {
+        let feature_length = std::cmp::max(0, feature_in_target_end - feature_in_target_start);
+        let (intersection, union) = match self.query_feature_span_in_target {
+            Some((start, end)) => {
+                let intersection = std::cmp::max(0, std::cmp::min(end, feature_in_target_end) - std::cmp::max(start, feature_in_target_start));
+                (intersection, (end - start) + feature_length - intersection)
+            }
+            None => (0, feature_length),
+        };
+        if union > 0 {
+            Some(intersection as f64 / union as f64)
+        } else {
+            None
+        }
+    }
+
+    // How fragmented the aligned bases are, in [0, 1]: with the largest-block formula 1 - largest block / aligned
+    // bases (0 when contiguous), with the blocks formula aligned blocks / feature length; None without aligned bases
+    pub fn fragmentation(&self, formula: Fragmentation, feature_length: i64) -> Option<f64> {
+        if self.aligned_bases == 0 {
+            return None;
+        }
+        Some(match formula {
+            Fragmentation::LargestBlock => 1.0 - self.largest_aligned_block as f64 / self.aligned_bases as f64,
+            Fragmentation::Blocks => self.aligned_blocks as f64 / std::cmp::max(feature_length, self.aligned_blocks) as f64,
+        })
+    }
+
+    // Caps the counters of the query and target features at their lengths (the aligned bases and mismatches at the
+    // shorter one), returning the names of the counters that were above them
+    pub fn cap_at_feature_lengths(&mut self, query_length: i64, target_length: i64) -> Vec<&'static str> {
+        let (query_length, target_length) = (std::cmp::max(0, query_length), std::cmp::max(0, target_length));
+        let shorter = std::cmp::min(query_length, target_length);
+        let mut capped = Vec::new();
+        for (name, value, length) in [
+            ("aligned.bp", &mut self.aligned_bases, shorter),
+            ("mismatches.bp", &mut self.mismatches, shorter),
+            ("not.aligned.in.query.bp", &mut self.not_aligned_bases_in_query, query_length),
+            ("not.aligned.in.target.bp", &mut self.not_aligned_bases_in_target, target_length),
+            ("indels.in.query.bp", &mut self.indels_in_query, query_length),
+            ("indels.in.target", &mut self.indels_in_target, target_length),
+            ("ignored.in.query.bp", &mut self.ignored_bases_in_query, query_length),
+            ("ignored.in.target.bp", &mut self.ignored_bases_in_target, target_length),
+            ("edge.adjustment.in.query.bp", &mut self.edge_adjustment_in_query, query_length),
+            ("edge.adjustment.in.target.bp", &mut self.edge_adjustment_in_target, target_length),
+        ] {
+            if *value > length {
+                *value = length;
+                capped.push(name);
+            }
+        }
+        capped
+    }
+
+    // Comma-separated operation types seen within the features, . when there are none
+    pub fn op_types(&self) -> String {
+        let types: Vec<String> = CIGAR_OP_TYPES.chars().enumerate().filter(|(bit, _)| self.op_types & (1 << bit) != 0).map(|(_, op)| op.to_string()).collect();
+        if types.is_empty() {
+            ".".to_string()
+        } else {
+            types.join(",")
+        }
+    }
+
+    // Distance from the 5' end of the target feature (its end for features on the - strand) to its first aligned base
+    pub fn first_aligned_offset(&self, feature_in_target_start: i64, feature_in_target_end: i64, feature_in_target_strand: &str) -> Option<i64> {
+        self.aligned_span_in_target.map(|(start, end)| {
+            if feature_in_target_strand == "-" {
+                feature_in_target_end - end
+            } else {
+                start - feature_in_target_start
+            }
+        })
+    }
+}
+
+// All intervals are 0-based half-open ([start, end)), so an operation covering [pos, pos + length) overlaps
+// a feature by max(0, min(pos + length, end) - max(pos, start)) bases. A feature starting exactly at the
+// alignment end (query_end/target_end) therefore gets zero aligned, not-aligned and indel bases, and all of
+// its bases are reported as ignored (they lie outside the alignment), never as negative residuals.
+#[allow(clippy::too_many_arguments)]
+pub fn count_aligned_bases(query_start: i64, query_end: i64, query_strand: char, target_start: i64, target_end: i64, cigar: &str, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64, max_indel_size: i64) -> Result<FeatureCounts, String> {
+    Ok(count_aligned_bases_by_threshold(query_start, query_end, query_strand, target_start, target_end, cigar, feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end, &[max_indel_size], 0)?.swap_remove(0))
+}
+
+// Counters depending on the indel size threshold, one set per threshold
+#[derive(Default, Clone, Copy)]
+struct IndelClassification {
+    not_aligned_bases_in_query: i64,
+    not_aligned_bases_in_target: i64,
+    indels_in_query: i64,
+    indels_in_target: i64,
+    indel_events_in_query: i64,
+    indel_events_in_target: i64,
+    large_indel_events_in_query: i64,
+    large_indel_events_in_target: i64,
+}
+
+// --max-indel-size when not given: no indel is larger, so all of them are counted. The thresholds are only ever
+// compared with indel lengths (see is_counted_indel), never used in arithmetic, so this sentinel cannot overflow
+pub const UNLIMITED_INDEL_SIZE: i64 = i64::MAX;
+
+// Whether an indel of the given length is counted as an indel, rather than as not aligned bases
+pub fn is_counted_indel(length: i64, max_indel_size: i64) -> bool {
+    length <= max_indel_size
+}
+
+// Counters of a feature pair for each of the given indel size thresholds, from a single walk of the CIGAR: only the
+// classification of the indels as counted or not aligned is repeated per threshold. Aligned blocks separated by gaps
+// of at most max_gap_merge bases in both the query and the target count as one block. The CIGAR is invalid when an
+// operation walked has a length of 0 or overflowing a 64-bit integer
+#[allow(clippy::too_many_arguments)]
+pub fn count_aligned_bases_by_threshold(query_start: i64, query_end: i64, query_strand: char, target_start: i64, target_end: i64, cigar: &str, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64, max_indel_sizes: &[i64], max_gap_merge: i64) -> Result<Vec<FeatureCounts>, String> {
+    let mut ops = cigar::ops(cigar);
+    if let Some(op) = aligned_op_holding_features(query_start, query_end, query_strand, target_start, ops.by_ref(), feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end) {
+        // No other operation reaches the features, so all their bases are aligned to each other; the operations past
+        // them are still read, to reject an invalid CIGAR as the walk does
+        ops.by_ref().for_each(drop);
+        if let Some(reason) = ops.error() {
+            return Err(reason);
+        }
+        let length = feature_in_target_end - feature_in_target_start;
+        let overlap = |start: i64, end: i64, feature_start: i64, feature_end: i64| std::cmp::max(0, std::cmp::min(end, feature_end) - std::cmp::max(start, feature_start));
+        let counts = FeatureCounts {
+            aligned_bases: length,
+            mismatches: if op == 'X' { length } else { 0 },
+            outside_alignment_in_query: length - overlap(query_start, query_end, feature_in_query_start, feature_in_query_end),
+            outside_alignment_in_target: length - overlap(target_start, target_end, feature_in_target_start, feature_in_target_end),
+            aligned_span_in_target: Some((feature_in_target_start, feature_in_target_end)),
+            query_feature_span_in_target: Some((feature_in_target_start, feature_in_target_end)),
+            aligned_blocks: 1,
+            largest_aligned_block: length,
+            op_types: op_type_bit(op),
+            ..FeatureCounts::default()
+        };
+        return Ok(vec![counts; max_indel_sizes.len()]);
+    }
+    // The operations read by the fast path are read again from the start
+    let mut ops = cigar::ops(cigar);
+    let counts = count_ops_by_threshold(query_start, query_end, query_strand, target_start, target_end, ops.by_ref(), feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end, max_indel_sizes, max_gap_merge);
+    ops.error().map_or(Ok(counts), Err)
+}
+
+// Walk of count_aligned_bases_by_threshold over the (length, type) operations of a CIGAR, parsed or not
+#[allow(clippy::too_many_arguments)]
+pub fn count_ops_by_threshold(query_start: i64, query_end: i64, query_strand: char, target_start: i64, target_end: i64, ops: impl Iterator<Item = (i64, char)>, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64, max_indel_sizes: &[i64], max_gap_merge: i64) -> Vec<FeatureCounts> {
+    let mut aligned_bases = 0;
+    let mut classifications = vec![IndelClassification::default(); max_indel_sizes.len()];
+    let mut mismatches = 0;
+    let mut edge_adjustment_in_query = 0;
+    let mut edge_adjustment_in_target = 0;
+    let mut aligned_span_in_target: Option<Interval> = None;
+    let mut query_feature_span_in_target: Option<Interval> = None;
+    let mut aligned_blocks = 0;
+    let mut largest_aligned_block = 0;
+    let mut current_block = 0;
+    // Gap since the last aligned operation, on each side
+    let mut gap_in_query = 0;
+    let mut gap_in_target = 0;
+    let mut op_types = 0u8;
+    let query_rev = query_strand == '-';
+
+    // Initialize counters for the current position within the query and target sequences
+    let mut query_pos = if query_rev { query_end } else { query_start };
+    let mut target_pos = target_start;
+
+    // Iterate over CIGAR operations
+    for (length, op) in ops {
+        // Zero-length operations (aligner quirks like the 0M of 0M5I) are no-ops: an empty aligned operation would
+        // otherwise end the current aligned block
+        if length == 0 {
+            continue;
+        }
+        match op {
+            'M' | '=' | 'X' => {
+                // Handle match/mismatch, which affects both query and target
+                let overlap_query = if query_rev {
+                    std::cmp::max(0, std::cmp::min(query_pos, feature_in_query_end) - std::cmp::max(query_pos - length, feature_in_query_start))
+                } else {
+                    std::cmp::max(0, std::cmp::min(query_pos + length, feature_in_query_end) - std::cmp::max(query_pos, feature_in_query_start))
+                };
+                let overlap_target = std::cmp::max(0, std::cmp::min(target_pos + length, feature_in_target_end) - std::cmp::max(target_pos, feature_in_target_start));
+                // At the feature edges the two overlaps can differ: only the shorter one is aligned on both
+                // sides, the excess being tracked as edge adjustment
+                let overlap = std::cmp::min(overlap_query, overlap_target);
+                aligned_bases += overlap;
+                if overlap_query > 0 || overlap_target > 0 {
+                    op_types |= op_type_bit(op);
+                }
+                edge_adjustment_in_query += overlap_query - overlap;
+                edge_adjustment_in_target += overlap_target - overlap;
+                if op == 'X' {
+                    mismatches += overlap;
+                }
+                // Adjacent aligned operations extend the same block, as do the ones past a merged gap
+                gap_in_query = 0;
+                gap_in_target = 0;
+                if overlap > 0 {
+                    if current_block == 0 {
+                        aligned_blocks += 1;
+                    }
+                    current_block += overlap;
+                    largest_aligned_block = std::cmp::max(largest_aligned_block, current_block);
+                } else {
+                    current_block = 0;
+                }
+                // Offsets within the operation of the bases inside both features
+                let (first_in_query, end_in_query) = if query_rev {
+                    (query_pos - feature_in_query_end, query_pos - feature_in_query_start)
+                } else {
+                    (feature_in_query_start - query_pos, feature_in_query_end - query_pos)
+                };
+                let (first, end) = (std::cmp::max(0, first_in_query), std::cmp::min(length, end_in_query));
+                if first < end {
+                    let (start, end) = (target_pos + first, target_pos + end);
+                    query_feature_span_in_target = Some(match query_feature_span_in_target {
+                        Some((span_start, span_end)) => (std::cmp::min(span_start, start), std::cmp::max(span_end, end)),
+                        None => (start, end),
+                    });
+                }
+                let first = std::cmp::max(0, std::cmp::max(first_in_query, feature_in_target_start - target_pos));
+                let end = std::cmp::min(length, std::cmp::min(end_in_query, feature_in_target_end - target_pos));
+                if first < end {
+                    let (start, end) = (target_pos + first, target_pos + end);
+                    aligned_span_in_target = Some(match aligned_span_in_target {
+                        Some((span_start, span_end)) => (std::cmp::min(span_start, start), std::cmp::max(span_end, end)),
+                        None => (start, end),
+                    });
+                }
+
+                if query_rev {
+                    query_pos -= length;
+                } else {
+                    query_pos += length;
+                }
+                target_pos += length;
+            },
+            'D' => {
+                // Handle deletion in the query (insertion in the target)
+                gap_in_target += length;
+                if gap_in_target > max_gap_merge {
+                    current_block = 0;
+                }
+                let overlap_target = std::cmp::max(0, std::cmp::min(target_pos + length, feature_in_target_end) - std::cmp::max(target_pos, feature_in_target_start));
+                if overlap_target > 0 {
+                    op_types |= op_type_bit(op);
+                }
+                for (classification, &max_indel_size) in classifications.iter_mut().zip(max_indel_sizes) {
+                    if is_counted_indel(length, max_indel_size) {
+                        classification.indels_in_target += overlap_target;
+                        if overlap_target > 0 {
+                            classification.indel_events_in_target += 1;
+                        }
+                    } else {
+                        classification.not_aligned_bases_in_target += overlap_target;
+                        if overlap_target > 0 {
+                            classification.large_indel_events_in_target += 1;
+                        }
+                    }
+                }
+
+                target_pos += length;
+            },
+            'I' => {
+                // Handle insertion in the query (gap in the target)
+                gap_in_query += length;
+                if gap_in_query > max_gap_merge {
+                    current_block = 0;
+                }
+                let overlap_query = if query_rev {
+                    std::cmp::max(0, std::cmp::min(query_pos, feature_in_query_end) - std::cmp::max(query_pos - length, feature_in_query_start))
+                } else {
+                    std::cmp::max(0, std::cmp::min(query_pos + length, feature_in_query_end) - std::cmp::max(query_pos, feature_in_query_start))
+                };
+                if overlap_query > 0 {
+                    op_types |= op_type_bit(op);
+                }
+                for (classification, &max_indel_size) in classifications.iter_mut().zip(max_indel_sizes) {
+                    if is_counted_indel(length, max_indel_size) {
+                        classification.indels_in_query += overlap_query;
+                        if overlap_query > 0 {
+                            classification.indel_events_in_query += 1;
+                        }
+                    } else {
+                        classification.not_aligned_bases_in_query += overlap_query;
+                        if overlap_query > 0 {
+                            classification.large_indel_events_in_query += 1;
+                        }
+                    }
+                }
+
+                if query_rev {
+                    query_pos -= length;
+                } else {
+                    query_pos += length;
+                }
+            },
+            _ => {
+                // The operations consuming no position in the walk are within the features when they fall between
+                // two of their bases
+                let inside_query = feature_in_query_start < query_pos && query_pos < feature_in_query_end;
+                let inside_target = feature_in_target_start < target_pos && target_pos < feature_in_target_end;
+                if inside_query || inside_target {
+                    op_types |= op_type_bit(op);
+                }
+            }
+        }
+
+        // Check if we have already passed the features in both query and target: on the reverse strand too, the
+        // remaining operations can still overlap the target feature (indels, in particular)
+        let passed_query_feature = if query_rev { query_pos <= feature_in_query_start } else { query_pos >= feature_in_query_end };
+        if passed_query_feature && target_pos >= feature_in_target_end {
+            break;
+        }
+    }
+    let overlap = |start: i64, end: i64, feature_start: i64, feature_end: i64| std::cmp::max(0, std::cmp::min(end, feature_end) - std::cmp::max(start, feature_start));
+    let unconsumed_in_query = if query_rev { overlap(query_start, query_pos, feature_in_query_start, feature_in_query_end) } else { overlap(query_pos, query_end, feature_in_query_start, feature_in_query_end) };
+    let unconsumed_in_target = overlap(target_pos, target_end, feature_in_target_start, feature_in_target_end);
+    let outside_alignment_in_query = std::cmp::max(0, feature_in_query_end - feature_in_query_start) - overlap(query_start, query_end, feature_in_query_start, feature_in_query_end);
+    let outside_alignment_in_target = std::cmp::max(0, feature_in_target_end - feature_in_target_start) - overlap(target_start, target_end, feature_in_target_start, feature_in_target_end);
+    classifications.into_iter().map(|classification| FeatureCounts {
+        aligned_bases,
+        not_aligned_bases_in_query: classification.not_aligned_bases_in_query,
+        not_aligned_bases_in_target: classification.not_aligned_bases_in_target,
+        indels_in_query: classification.indels_in_query,
+        indels_in_target: classification.indels_in_target,
+        ignored_bases_in_query: (feature_in_query_end - feature_in_query_start) - aligned_bases - classification.indels_in_query - classification.not_aligned_bases_in_query,
+        ignored_bases_in_target: (feature_in_target_end - feature_in_target_start) - aligned_bases - classification.indels_in_target - classification.not_aligned_bases_in_target,
+        mismatches,
+        indel_events_in_query: classification.indel_events_in_query,
+        indel_events_in_target: classification.indel_events_in_target,
+        large_indel_events_in_query: classification.large_indel_events_in_query,
+        large_indel_events_in_target: classification.large_indel_events_in_target,
+        edge_adjustment_in_query,
+        edge_adjustment_in_target,
+        outside_alignment_in_query,
+        outside_alignment_in_target,
+        unconsumed_in_query,
+        unconsumed_in_target,
+        aligned_span_in_target,
+        query_feature_span_in_target,
+        aligned_blocks,
+        largest_aligned_block,
+        op_types,
+    }).collect()
+}
+
+// Type of the aligned operation (M, =, or X) holding both features whole, with the query feature aligned exactly onto
+// the target feature, when there is one: the operations are read up to that operation
+#[allow(clippy::too_many_arguments)]
+fn aligned_op_holding_features(query_start: i64, query_end: i64, query_strand: char, target_start: i64, ops: impl Iterator<Item = (i64, char)>, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64) -> Option<char> {
+    let query_rev = query_strand == '-';
+    if feature_in_target_end <= feature_in_target_start || feature_in_query_end - feature_in_query_start != feature_in_target_end - feature_in_target_start {
+        return None;
+    }
+    let mut query_pos = if query_rev { query_end } else { query_start };
+    let mut target_pos = target_start;
+    for (length, op) in ops {
+        // Invalid operations are left to the walk, which reports them
+        if length == 0 {
+            return None;
+        }
+        match op {
+            'M' | '=' | 'X' => {
+                if feature_in_target_start < target_pos + length {
+                    // The first operation reaching the target feature has to hold it whole, with the query feature
+                    // at the same offset
+                    let offset = feature_in_target_start - target_pos;
+                    let query_offset = if query_rev { query_pos - feature_in_query_end } else { feature_in_query_start - query_pos };
+                    return (offset >= 0 && feature_in_target_end <= target_pos + length && query_offset == offset).then_some(op);
+                }
+                target_pos += length;
+                query_pos += if query_rev { -length } else { length };
+            }
+            'D' => {
+                if feature_in_target_start < target_pos + length {
+                    return None;
+                }
+                target_pos += length;
+            }
+            'I' => query_pos += if query_rev { -length } else { length },
+            'N' | 'S' | 'H' | 'P' => {}
+            _ => return None,
+        }
+    }
+    None
+}
+
+// Bit of an operation type in FeatureCounts::op_types (none for the types not in CIGAR_OP_TYPES)
+fn op_type_bit(op: char) -> u8 {
+    CIGAR_OP_TYPES.find(op).map(|index| 1 << index).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts of a feature pair over an alignment from the start of both sequences, checking that the walk agrees
+    // with the fast path when it applies
+    fn count(query_strand: char, cigar: &str, feature_in_query: Interval, feature_in_target: Interval) -> FeatureCounts {
+        let (query_end, target_end) = cigar::ops(cigar).fold((0, 0), |(query, target), (length, op)| match op {
+            'M' | '=' | 'X' => (query + length, target + length),
+            'I' => (query + length, target),
+            'D' => (query, target + length),
+            _ => (query, target),
+        });
+        let counts = count_aligned_bases(0, query_end, query_strand, 0, target_end, cigar, feature_in_query.0, feature_in_query.1, feature_in_target.0, feature_in_target.1, UNLIMITED_INDEL_SIZE).unwrap();
+        let walked = count_ops_by_threshold(0, query_end, query_strand, 0, target_end, cigar::ops(cigar), feature_in_query.0, feature_in_query.1, feature_in_target.0, feature_in_target.1, &[UNLIMITED_INDEL_SIZE], 0);
+        assert_eq!(counts, walked[0]);
+        counts
+    }
+
+    #[test]
+    fn reverse_strand_walk_continues_past_the_query_feature_start() {
+        // Walking the reverse strand, the query feature [25, 35) is passed after the first block, where its start
+        // abuts the insertion [20, 25) of the query; the deletion [20, 25) of the target comes later but is still in
+        // the target feature
+        let counts = count('-', "10M5I10M5D10M", (25, 35), (0, 25));
+        assert_eq!((counts.aligned_bases, counts.indels_in_query, counts.indel_events_in_query), (10, 0, 0));
+        assert_eq!((counts.indels_in_target, counts.indel_events_in_target), (5, 1));
+        // The target bases of the second block are aligned to query bases outside the query feature
+        assert_eq!((counts.ignored_bases_in_target, counts.edge_adjustment_in_target), (10, 10));
+    }
+
+    #[test]
+    fn indels_of_any_size_are_counted_without_a_threshold() {
+        // Indels longer than any genome, past the 32-bit lengths
+        let (deletion, insertion) = (5_000_000_000, 3_000_000_000);
+        let counts = count('+', &format!("10M{}D10M{}I10M", deletion, insertion), (0, insertion + 30), (0, deletion + 30));
+        assert_eq!((counts.indels_in_target, counts.indel_events_in_target, counts.large_indel_events_in_target), (deletion, 1, 0));
+        assert_eq!((counts.indels_in_query, counts.indel_events_in_query, counts.large_indel_events_in_query), (insertion, 1, 0));
+        assert_eq!((counts.aligned_bases, counts.not_aligned_bases_in_query, counts.not_aligned_bases_in_target), (30, 0, 0));
+        assert!(is_counted_indel(i64::MAX, UNLIMITED_INDEL_SIZE));
+    }
+
+    #[test]
+    fn zero_length_operations_are_rejected_on_both_paths() {
+        // The features at [10, 20) are held by the first operation of each CIGAR but one, so most of them would be
+        // counted by the fast path without the rest of the walk
+        for (cigar, expected) in [
+            ("0M100M", "operation 1 (at byte 0) has a length of 0"), ("50M0I50M", "operation 2 (at byte 3) has a length of 0"),
+            ("100M0I", "operation 2 (at byte 4) has a length of 0"), ("0=100=", "operation 1 (at byte 0) has a length of 0"),
+            ("50=0X50=", "operation 2 (at byte 3) has a length of 0"), ("100=0I", "operation 2 (at byte 4) has a length of 0"),
+            ("40=0D60=", "operation 2 (at byte 3) has a length of 0"),
+        ] {
+            let result = count_aligned_bases(0, 100, '+', 0, 100, cigar, 10, 20, 10, 20, UNLIMITED_INDEL_SIZE);
+            assert_eq!(result.unwrap_err(), expected, "{}", cigar);
+        }
+    }
+
+    #[test]
+    fn feature_starting_on_a_block_boundary() {
+        // The second block starts at query 30 and target 40, right after the deletion [30, 40) of the target
+        let counts = count('+', "30M10D30M", (30, 40), (40, 50));
+        assert_eq!((counts.aligned_bases, counts.indels_in_target, counts.indel_events_in_target), (10, 0, 0));
+        assert_eq!((counts.ignored_bases_in_query, counts.ignored_bases_in_target), (0, 0));
+        assert_eq!(counts.aligned_span_in_target, Some((40, 50)));
+    }
+
+    #[test]
+    fn feature_ending_on_a_block_boundary() {
+        // The first block ends at query and target 30, where the deletion starts
+        let counts = count('+', "30M10D30M", (20, 30), (20, 30));
+        assert_eq!((counts.aligned_bases, counts.indels_in_target, counts.indel_events_in_target), (10, 0, 0));
+        assert_eq!((counts.ignored_bases_in_query, counts.ignored_bases_in_target), (0, 0));
+        assert_eq!((counts.aligned_blocks, counts.largest_aligned_block), (1, 10));
+    }
+
+    #[test]
+    fn feature_spanning_a_block_boundary() {
+        let counts = count('+', "30M10D30M", (25, 35), (25, 45));
+        assert_eq!((counts.aligned_bases, counts.indels_in_target, counts.indel_events_in_target), (10, 10, 1));
+        assert_eq!((counts.ignored_bases_in_query, counts.ignored_bases_in_target), (0, 0));
+        assert_eq!((counts.aligned_blocks, counts.largest_aligned_block), (2, 5));
+    }
+
+    #[test]
+    fn feature_on_a_block_boundary_of_the_reverse_strand() {
+        // On the reverse strand the second block holds the query [0, 30), from its end, at target [40, 70)
+        let counts = count('-', "30M10D30M", (20, 30), (40, 50));
+        assert_eq!((counts.aligned_bases, counts.indels_in_target), (10, 0));
+        assert_eq!((counts.ignored_bases_in_query, counts.ignored_bases_in_target), (0, 0));
+        assert_eq!(counts.query_feature_span_in_target, Some((40, 50)));
+    }
+
+    #[test]
+    fn feature_held_by_a_deletion() {
+        // The query feature is empty, between the two blocks
+        let counts = count('+', "30M10D30M", (30, 30), (30, 40));
+        assert_eq!((counts.aligned_bases, counts.indels_in_target, counts.indel_events_in_target), (0, 10, 1));
+        assert_eq!((counts.ignored_bases_in_query, counts.ignored_bases_in_target), (0, 0));
+    }
+
+    #[test]
+    fn feature_starting_at_the_alignment_end() {
+        let counts = count('+', "30M10D30M", (60, 70), (70, 80));
+        assert_eq!((counts.aligned_bases, counts.indels_in_query, counts.indels_in_target), (0, 0, 0));
+        assert_eq!((counts.ignored_bases_in_query, counts.ignored_bases_in_target), (10, 10));
+        assert_eq!((counts.outside_alignment_in_query, counts.outside_alignment_in_target), (10, 10));
+        assert_eq!((counts.unconsumed_in_query, counts.unconsumed_in_target), (0, 0));
+    }
+
+    #[test]
+    fn feature_ending_at_the_alignment_start() {
+        let counts = count_aligned_bases(10, 70, '+', 10, 80, "30M10D30M", 0, 10, 0, 10, UNLIMITED_INDEL_SIZE).unwrap();
+        assert_eq!(counts.aligned_bases, 0);
+        assert_eq!((counts.ignored_bases_in_query, counts.ignored_bases_in_target), (10, 10));
+        assert_eq!((counts.outside_alignment_in_query, counts.outside_alignment_in_target), (10, 10));
+    }
+
+    #[test]
+    fn empty_features_on_a_boundary_count_nothing() {
+        for (feature_in_query, feature_in_target) in [((30, 30), (30, 30)), ((30, 30), (40, 40)), ((0, 0), (0, 0)), ((60, 60), (70, 70))] {
+            let counts = count('+', "30M10D30M", feature_in_query, feature_in_target);
+            assert_eq!(counts, FeatureCounts::default(), "{:?} {:?}", feature_in_query, feature_in_target);
+        }
+    }
+
+    #[test]
+    fn operations_touching_a_feature_do_not_overlap_it() {
+        // The insertion at query [30, 40) ends where the query feature starts, and the next block starts there
+        let counts = count('+', "30M10I30M", (40, 50), (30, 40));
+        assert_eq!((counts.aligned_bases, counts.indels_in_query, counts.indel_events_in_query), (10, 0, 0));
+        assert_eq!(counts.op_types(), "M");
+        // The insertion ends a block touching the query feature, which has no aligned base
+        let counts = count('+', "30M10I30M", (40, 40), (30, 30));
+        assert_eq!((counts.aligned_bases, counts.indels_in_query, counts.indel_events_in_query), (0, 0, 0));
+        assert_eq!(counts.op_types(), ".");
+    }
+
+    // Counts of the fast path (when it applies) and of the walk over all the operations, for several thresholds
+    fn fast_and_walked(query_start: i64, query_strand: char, target_start: i64, cigar: &str, feature_in_query: Interval, feature_in_target: Interval, max_gap_merge: i64) -> Option<(Vec<FeatureCounts>, Vec<FeatureCounts>)> {
+        let (query_length, target_length) = cigar::ops(cigar).fold((0, 0), |(query, target), (length, op)| match op {
+            'M' | '=' | 'X' => (query + length, target + length),
+            'I' => (query + length, target),
+            'D' => (query, target + length),
+            _ => (query, target),
+        });
+        let (query_end, target_end) = (query_start + query_length, target_start + target_length);
+        aligned_op_holding_features(query_start, query_end, query_strand, target_start, cigar::ops(cigar), feature_in_query.0, feature_in_query.1, feature_in_target.0, feature_in_target.1)?;
+        let max_indel_sizes = [UNLIMITED_INDEL_SIZE, 0, 3];
+        let fast = count_aligned_bases_by_threshold(query_start, query_end, query_strand, target_start, target_end, cigar, feature_in_query.0, feature_in_query.1, feature_in_target.0, feature_in_target.1, &max_indel_sizes, max_gap_merge).unwrap();
+        let walked = count_ops_by_threshold(query_start, query_end, query_strand, target_start, target_end, cigar::ops(cigar), feature_in_query.0, feature_in_query.1, feature_in_target.0, feature_in_target.1, &max_indel_sizes, max_gap_merge);
+        Some((fast, walked))
+    }
+
+    #[test]
+    fn fast_path_matches_the_walk_on_a_table_of_features() {
+        // (query strand, CIGAR, query feature, target feature) held by a single aligned operation
+        let cases: [(char, &str, Interval, Interval); 8] = [
+            ('+', "100M", (0, 100), (0, 100)),
+            ('+', "10M5I50=5D10M", (15, 65), (10, 60)),
+            ('+', "10M5I50=5D10M", (20, 30), (15, 25)),
+            ('+', "10M5D20X", (10, 30), (15, 35)),
+            ('-', "100M", (0, 100), (0, 100)),
+            ('-', "10M5I50M5D10M", (10, 60), (10, 60)),
+            ('-', "10M5I50M5D10M", (30, 40), (30, 40)),
+            ('+', "5S3H40M", (2, 38), (2, 38)),
+        ];
+        for (query_strand, cigar, feature_in_query, feature_in_target) in cases {
+            for (query_start, target_start) in [(0, 0), (1000, 7)] {
+                let shift = |(start, end): Interval, by: i64| (start + by, end + by);
+                let (fast, walked) = fast_and_walked(query_start, query_strand, target_start, cigar, shift(feature_in_query, query_start), shift(feature_in_target, target_start), 0)
+                    .unwrap_or_else(|| panic!("no fast path for {} {}", query_strand, cigar));
+                assert_eq!(fast, walked, "{} {}", query_strand, cigar);
+            }
+        }
+        // Features reaching another operation, or at another offset in the query, are walked
+        for (query_strand, cigar, feature_in_query, feature_in_target) in [
+            ('+', "10M5I50M", (5, 20), (5, 15)),
+            ('+', "10M5D50M", (5, 15), (5, 20)),
+            ('+', "100M", (11, 21), (10, 20)),
+            ('-', "100M", (10, 20), (10, 20)),
+            ('+', "10M5D50M", (10, 15), (10, 15)),
+        ] {
+            assert!(fast_and_walked(0, query_strand, 0, cigar, feature_in_query, feature_in_target, 0).is_none(), "{} {}", query_strand, cigar);
+        }
+    }
+
+    #[test]
+    fn fast_path_matches_the_walk_on_random_alignments() {
+        // Deterministic xorshift generator, so that a failure can be reproduced
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = |bound: i64| -> i64 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as i64
+        };
+        let mut fast_paths = 0;
+        for _ in 0..20_000 {
+            let ops: Vec<(i64, char)> = (0..1 + next(5)).map(|_| (1 + next(29), ['M', '=', 'X', 'I', 'D', 'M', '='][next(7) as usize])).collect();
+            let cigar: String = ops.iter().map(|(length, op)| format!("{}{}", length, op)).collect();
+            let query_strand = if next(2) == 0 { '+' } else { '-' };
+            let (query_start, target_start) = (next(50), next(50));
+            let (query_length, target_length) = ops.iter().fold((0, 0), |(query, target), &(length, op)| match op {
+                'I' => (query + length, target),
+                'D' => (query, target + length),
+                _ => (query + length, target + length),
+            });
+            // Features of the same length on both sides, mostly inside the alignment
+            let length = next(20);
+            let feature_in_target_start = target_start - 2 + next(target_length + 4);
+            let feature_in_query_start = query_start - 2 + next(query_length + 4);
+            let max_gap_merge = next(3) * 5;
+            if let Some((fast, walked)) = fast_and_walked(query_start, query_strand, target_start, &cigar, (feature_in_query_start, feature_in_query_start + length), (feature_in_target_start, feature_in_target_start + length), max_gap_merge) {
+                assert_eq!(fast, walked, "{} {} query {} target {}", query_strand, cigar, feature_in_query_start, feature_in_target_start);
+                fast_paths += 1;
+            }
+        }
+        assert!(fast_paths > 100, "only {} alignments took the fast path", fast_paths);
+    }
+}
@@ -0,0 +1,126 @@
+use clap::ArgMatches;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufWriter, Write};
+
+use crate::{open_file, read_report_header};
+
+// Columns identifying a report row; rows sharing them (several alignments of the same feature pair) are matched in
+// order of appearance
+const KEY_COLUMNS: [&str; 8] = ["feature.name", "query", "query.feature.start", "query.feature.end", "query.strand", "target", "target.feature.start", "target.feature.end"];
+
+// Report loaded in memory: header, rows, and row index by key and occurrence
+struct Report {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+    index: HashMap<(Vec<String>, usize), usize>,
+}
+
+impl Report {
+    fn load(file_path: &str) -> io::Result<Report> {
+        let mut lines = open_file(file_path).lines();
+        let (header_line_number, header) = match read_report_header(&mut lines)? {
+            Some(header) => header,
+            None => {
+                eprintln!("ERROR: {} is empty", file_path);
+                std::process::exit(1);
+            }
+        };
+        let header: Vec<String> = header.split('\t').map(|column| column.to_string()).collect();
+        let key_indices: Vec<usize> = KEY_COLUMNS.iter().map(|name| {
+            header.iter().position(|column| column == name).unwrap_or_else(|| {
+                eprintln!("ERROR: {} has no {} column, needed to match the rows ({})", file_path, name, KEY_COLUMNS.join(", "));
+                std::process::exit(1);
+            })
+        }).collect();
+
+        let mut rows = Vec::new();
+        let mut index = HashMap::new();
+        let mut occurrences: HashMap<Vec<String>, usize> = HashMap::new();
+        for (line_number, line) in lines.enumerate() {
+            let line = line?;
+            let fields: Vec<String> = line.split('\t').map(|field| field.to_string()).collect();
+            if fields.len() != header.len() {
+                eprintln!("ERROR: {}: line {}: expected {} columns, found {}", file_path, header_line_number + line_number + 1, header.len(), fields.len());
+                std::process::exit(1);
+            }
+            let key: Vec<String> = key_indices.iter().map(|&i| fields[i].clone()).collect();
+            let occurrence = occurrences.entry(key.clone()).or_insert(0);
+            index.insert((key, *occurrence), rows.len());
+            *occurrence += 1;
+            rows.push(fields);
+        }
+        Ok(Report { header, rows, index })
+    }
+
+    fn key(&self, row: &[String]) -> Vec<String> {
+        KEY_COLUMNS.iter().map(|name| row[self.column(name).unwrap()].clone()).collect()
+    }
+
+    fn column(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|column| column == name)
+    }
+}
+
+pub fn run(matches: &ArgMatches) -> io::Result<()> {
+    let first_file = matches.value_of("first").unwrap();
+    let second_file = matches.value_of("second").unwrap();
+    let first = Report::load(first_file)?;
+    let second = Report::load(second_file)?;
+
+    // Columns compared: those of the first report also found in the second, key columns excluded
+    let mut compared: Vec<(usize, usize)> = Vec::new();
+    for (first_index, name) in first.header.iter().enumerate() {
+        match second.column(name) {
+            Some(second_index) if !KEY_COLUMNS.contains(&name.as_str()) => compared.push((first_index, second_index)),
+            Some(_) => {}
+            None => eprintln!("WARNING: column {} is only in {}, not compared", name, first_file),
+        }
+    }
+    for name in second.header.iter().filter(|name| first.column(name).is_none()) {
+        eprintln!("WARNING: column {} is only in {}, not compared", name, second_file);
+    }
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    writeln!(out, "difference\t{}\tcolumn\tvalue.a\tvalue.b", KEY_COLUMNS.join("\t"))?;
+    let (mut only_in_first, mut only_in_second, mut changed) = (0u64, 0u64, 0u64);
+    let mut occurrences: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut matched = vec![false; second.rows.len()];
+    for row in &first.rows {
+        let key = first.key(row);
+        let occurrence = occurrences.entry(key.clone()).or_insert(0);
+        let second_row = second.index.get(&(key.clone(), *occurrence)).copied();
+        *occurrence += 1;
+        match second_row {
+            Some(second_row) => {
+                matched[second_row] = true;
+                let mut row_changed = false;
+                for &(first_index, second_index) in &compared {
+                    let (value_a, value_b) = (&row[first_index], &second.rows[second_row][second_index]);
+                    if value_a != value_b {
+                        writeln!(out, "changed\t{}\t{}\t{}\t{}", key.join("\t"), first.header[first_index], value_a, value_b)?;
+                        row_changed = true;
+                    }
+                }
+                if row_changed {
+                    changed += 1;
+                }
+            }
+            None => {
+                writeln!(out, "only.in.a\t{}\t.\t.\t.", key.join("\t"))?;
+                only_in_first += 1;
+            }
+        }
+    }
+    for (row, _) in second.rows.iter().zip(&matched).filter(|(_, matched)| !**matched) {
+        writeln!(out, "only.in.b\t{}\t.\t.\t.", second.key(row).join("\t"))?;
+        only_in_second += 1;
+    }
+    out.flush()?;
+
+    eprintln!("INFO: {} and {} rows compared, {} only in {}, {} only in {}, {} with different values", first.rows.len(), second.rows.len(), only_in_first, first_file, only_in_second, second_file, changed);
+    if only_in_first + only_in_second + changed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::{cigar, open_file, Interval};
+
+// Per-base error rates of the target sequences from a bedGraph (chrom, start, end, value), the intervals of each
+// sequence sorted by start; the bases outside the intervals have no error
+pub struct ErrorTrack {
+    intervals: HashMap<String, Vec<(Interval, f64)>>,
+}
+
+impl ErrorTrack {
+    pub fn load(file_path: &str) -> ErrorTrack {
+        let mut intervals: HashMap<String, Vec<(Interval, f64)>> = HashMap::new();
+        for (line_number, line) in open_file(file_path).lines().enumerate() {
+            let line = line.expect("Failed to read error track");
+            if line.is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let parts: Vec<&str> = line.split('\t').collect();
+            let number = |index: usize| parts.get(index).and_then(|value| value.parse::<i64>().ok());
+            match (number(1), number(2), parts.get(3).and_then(|value| value.parse::<f64>().ok())) {
+                (Some(start), Some(end), Some(error)) if start <= end && (0.0..=1.0).contains(&error) => {
+                    intervals.entry(parts[0].to_string()).or_default().push(((start, end), error));
+                }
+                _ => {
+                    eprintln!("ERROR: {}: line {}: expected chrom, start, end, and an error rate in [0, 1]", file_path, line_number + 1);
+                    std::process::exit(1);
+                }
+            }
+        }
+        for (chrom, chrom_intervals) in intervals.iter_mut() {
+            chrom_intervals.sort_by_key(|(interval, _)| *interval);
+            if chrom_intervals.windows(2).any(|pair| pair[1].0 .0 < pair[0].0 .1) {
+                eprintln!("ERROR: {}: overlapping intervals on {}", file_path, chrom);
+                std::process::exit(1);
+            }
+        }
+        ErrorTrack { intervals }
+    }
+
+    // Sum of the error rates of the bases [start, end) of a sequence
+    fn error_sum(&self, chrom: &str, start: i64, end: i64) -> f64 {
+        let chrom_intervals = match self.intervals.get(chrom) {
+            Some(chrom_intervals) => chrom_intervals,
+            None => return 0.0,
+        };
+        let first = chrom_intervals.partition_point(|((_, interval_end), _)| *interval_end <= start);
+        chrom_intervals[first..].iter()
+            .take_while(|((interval_start, _), _)| *interval_start < end)
+            .map(|((interval_start, interval_end), error)| (std::cmp::min(end, *interval_end) - std::cmp::max(start, *interval_start)) as f64 * error)
+            .sum()
+    }
+
+    // Identity of the aligned bases inside both features, each matched base weighted by 1 - its error rate in the
+    // target: the weighted matches over the aligned bases. None for CIGARs with `M` operations, which do not tell
+    // the matches, and when no base is aligned
+    #[allow(clippy::too_many_arguments)]
+    pub fn weighted_identity(&self, cigar: &str, query_start: i64, query_end: i64, query_strand: char, target_name: &str, target_start: i64, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64) -> Option<f64> {
+        let query_rev = query_strand == '-';
+        let mut query_pos = if query_rev { query_end } else { query_start };
+        let mut target_pos = target_start;
+        let mut aligned_bases = 0;
+        let mut weighted_matches = 0.0;
+
+        for (length, op) in cigar::ops(cigar) {
+            match op {
+                'M' => return None,
+                '=' | 'X' => {
+                    // Offsets within the operation of the bases inside both features
+                    let (first_in_query, end_in_query) = if query_rev {
+                        (query_pos - feature_in_query_end, query_pos - feature_in_query_start)
+                    } else {
+                        (feature_in_query_start - query_pos, feature_in_query_end - query_pos)
+                    };
+                    let first = first_in_query.max(feature_in_target_start - target_pos).max(0);
+                    let end = end_in_query.min(feature_in_target_end - target_pos).min(length);
+                    if first < end {
+                        aligned_bases += end - first;
+                        if op == '=' {
+                            weighted_matches += (end - first) as f64 - self.error_sum(target_name, target_pos + first, target_pos + end);
+                        }
+                    }
+                    target_pos += length;
+                    query_pos += if query_rev { -length } else { length };
+                }
+                'D' => target_pos += length,
+                'I' => query_pos += if query_rev { -length } else { length },
+                _ => {}
+            }
+        }
+        (aligned_bases > 0).then(|| weighted_matches / aligned_bases as f64)
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::{self, Write};
+
+// Uniform sample of the lines of --estimate, each line passing the line filters being drawn independently with the
+// sampling fraction, and the totals of the rows sampled extrapolated to all the lines by the inverse of the fraction
+pub struct Estimate {
+    fraction: f64,
+    seed: u64,
+    confidence: f64,
+    // State of the splitmix64 generator drawing the lines
+    state: u64,
+    // Lines passing the line filters, and those drawn
+    lines: u64,
+    sampled: u64,
+    // Rows of the lines drawn that pass --where, and the sums over them of the aligned bases, of the target feature
+    // lengths, and of their squares and products, for the variances
+    rows: u64,
+    aligned_bases: f64,
+    feature_bases: f64,
+    aligned_bases_squares: f64,
+    feature_bases_squares: f64,
+    products: f64,
+}
+
+// Extrapolated total and the bounds of its confidence interval
+type Interval = (f64, f64, f64);
+
+// Quantile of the standard normal distribution (Acklam's rational approximation, relative error below 1.2e-9)
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969683028665376e1, 2.209460984245205e2, -2.759285104469687e2, 1.38357751867269e2, -3.066479806614716e1, 2.506628277459239];
+    const B: [f64; 5] = [-5.447609879822406e1, 1.615858368580409e2, -1.556989798598866e2, 6.680131188771972e1, -1.328068155288572e1];
+    const C: [f64; 6] = [-7.784894002430293e-3, -3.223964580411365e-1, -2.400758277161838, -2.549732539343734, 4.374664141464968, 2.938163982698783];
+    const D: [f64; 4] = [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996, 3.754408661907416];
+    let tail = |q: f64| (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0);
+    if p < 0.02425 {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - 0.02425 {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+impl Estimate {
+    pub fn new(fraction: f64, seed: u64, confidence: f64) -> Estimate {
+        Estimate { fraction, seed, confidence, state: seed, lines: 0, sampled: 0, rows: 0, aligned_bases: 0.0, feature_bases: 0.0, aligned_bases_squares: 0.0, feature_bases_squares: 0.0, products: 0.0 }
+    }
+
+    // Whether the next line passing the line filters is drawn
+    pub fn sample(&mut self) -> bool {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        self.lines += 1;
+        let drawn = ((z >> 11) as f64 / (1u64 << 53) as f64) < self.fraction;
+        if drawn {
+            self.sampled += 1;
+        }
+        drawn
+    }
+
+    // Adds a row of a line drawn
+    pub fn add(&mut self, aligned_bases: i64, feature_length: i64) {
+        let (aligned_bases, feature_bases) = (aligned_bases as f64, feature_length as f64);
+        self.rows += 1;
+        self.aligned_bases += aligned_bases;
+        self.feature_bases += feature_bases;
+        self.aligned_bases_squares += aligned_bases * aligned_bases;
+        self.feature_bases_squares += feature_bases * feature_bases;
+        self.products += aligned_bases * feature_bases;
+    }
+
+    // Half-width of the interval of an estimate with the given variance
+    fn margin(&self, variance: f64) -> f64 {
+        normal_quantile(0.5 + self.confidence / 2.0) * variance.max(0.0).sqrt()
+    }
+
+    // Extrapolated total of a sum over the rows drawn, with the variance of the Horvitz-Thompson estimator under
+    // Bernoulli sampling, (1 - f) / f^2 times the sum of the squares
+    fn total(&self, sum: f64, squares: f64) -> Interval {
+        let total = sum / self.fraction;
+        let margin = self.margin((1.0 - self.fraction) / (self.fraction * self.fraction) * squares);
+        (total, (total - margin).max(0.0), total + margin)
+    }
+
+    fn row_total(&self) -> Interval {
+        self.total(self.rows as f64, self.rows as f64)
+    }
+
+    fn aligned_bases_total(&self) -> Interval {
+        self.total(self.aligned_bases, self.aligned_bases_squares)
+    }
+
+    fn feature_bases_total(&self) -> Interval {
+        self.total(self.feature_bases, self.feature_bases_squares)
+    }
+
+    // Aligned bases over target feature bases, with the linearized variance of the ratio of the two totals; None
+    // without feature bases sampled
+    fn aligned_fraction(&self) -> Option<Interval> {
+        if self.feature_bases <= 0.0 {
+            return None;
+        }
+        let ratio = self.aligned_bases / self.feature_bases;
+        let residual_squares = self.aligned_bases_squares - 2.0 * ratio * self.products + ratio * ratio * self.feature_bases_squares;
+        let margin = self.margin((1.0 - self.fraction) * residual_squares / (self.feature_bases * self.feature_bases));
+        Some((ratio, (ratio - margin).max(0.0), ratio + margin))
+    }
+
+    // Writes the estimates on stderr, prefixed with `source` (the input file, when there are several)
+    pub fn report(&self, source: &str) {
+        let format = |(total, low, high): Interval| format!("{:.0} [{:.0}, {:.0}]", total, low, high);
+        eprintln!("INFO: {}ESTIMATE from {} of {} lines passing the line filters (--sample-fraction {}, --seed {}), {}% confidence intervals: {} rows, {} aligned bases, {} target feature bases, aligned fraction {}",
+            source, self.sampled, self.lines, self.fraction, self.seed, self.confidence * 100.0,
+            format(self.row_total()), format(self.aligned_bases_total()), format(self.feature_bases_total()),
+            self.aligned_fraction().map(|(ratio, low, high)| format!("{:.4} [{:.4}, {:.4}]", ratio, low, high)).unwrap_or_else(|| "NA".to_string()));
+    }
+
+    // Writes the estimates as the members of a JSON object
+    pub fn write_json(&self, writer: &mut impl Write) -> io::Result<()> {
+        let interval = |(total, low, high): Interval| format!("{{\"estimate\": {:.3}, \"low\": {:.3}, \"high\": {:.3}}}", total, low, high);
+        writeln!(writer, "  \"estimate\": {{\"sample_fraction\": {}, \"seed\": {}, \"confidence\": {}, \"lines\": {}, \"sampled_lines\": {}, \"sampled_rows\": {}, \"rows\": {}, \"aligned_bases\": {}, \"feature_bases\": {}, \"aligned_fraction\": {}}},",
+            self.fraction, self.seed, self.confidence, self.lines, self.sampled, self.rows,
+            interval(self.row_total()), interval(self.aligned_bases_total()), interval(self.feature_bases_total()),
+            self.aligned_fraction().map(|(ratio, low, high)| format!("{{\"estimate\": {:.6}, \"low\": {:.6}, \"high\": {:.6}}}", ratio, low, high)).unwrap_or_else(|| "null".to_string()))
+    }
+}
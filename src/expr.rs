@@ -0,0 +1,373 @@
+// Small expression language for filters: comparisons (== != < <= > >=) between named fields and
+// number/string literals, combined with !, && and || (in decreasing order of precedence) and parentheses.
+// Field names are resolved to indices once, when the expression is compiled.
+
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value<'a> {
+    Number(f64),
+    Text(&'a str),
+    Missing,
+}
+
+impl<'a> Value<'a> {
+    // Interprets a field as a number when it looks like one, as text otherwise
+    pub fn parse(s: &'a str) -> Value<'a> {
+        if s.is_empty() || s == "NA" {
+            Value::Missing
+        } else {
+            match s.parse::<f64>() {
+                Ok(number) => Value::Number(number),
+                Err(_) => Value::Text(s),
+            }
+        }
+    }
+
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Number(number) => *number != 0.0,
+            Value::Text(text) => *text == "true",
+            Value::Missing => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Field(usize),
+    Literal(Literal),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Compare(Operand, Comparison, Operand),
+    // A lone operand is true for non-zero numbers and the text `true`
+    Truthy(Operand),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(f64),
+    Text(String),
+    Compare(Comparison),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+// Error with the 0-based character position it refers to
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    // Formats the error with the expression and a caret under the offending character
+    pub fn display(&self, expression: &str) -> String {
+        format!("{} at character {}\n  {}\n  {}^", self.message, self.position + 1, expression, " ".repeat(self.position))
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let two = |next: char| chars.get(i + 1) == Some(&next);
+        let token = match c {
+            ' ' | '\t' => {
+                i += 1;
+                continue;
+            }
+            '(' => { i += 1; Token::Open }
+            ')' => { i += 1; Token::Close }
+            '&' if two('&') => { i += 2; Token::And }
+            '|' if two('|') => { i += 2; Token::Or }
+            '=' if two('=') => { i += 2; Token::Compare(Comparison::Eq) }
+            '!' if two('=') => { i += 2; Token::Compare(Comparison::Ne) }
+            '<' if two('=') => { i += 2; Token::Compare(Comparison::Le) }
+            '>' if two('=') => { i += 2; Token::Compare(Comparison::Ge) }
+            '!' => { i += 1; Token::Not }
+            '<' => { i += 1; Token::Compare(Comparison::Lt) }
+            '>' => { i += 1; Token::Compare(Comparison::Gt) }
+            '"' | '\'' => {
+                let end = chars[i + 1..].iter().position(|&d| d == c).ok_or(ParseError { position: start, message: "unterminated string".to_string() })?;
+                let text: String = chars[i + 1..i + 1 + end].iter().collect();
+                i += end + 2;
+                Token::Text(text)
+            }
+            c if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit() || *d == '.')) || c == '.' => {
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.' || chars[i] == 'e' || chars[i] == 'E' || ((chars[i] == '-' || chars[i] == '+') && (chars[i - 1] == 'e' || chars[i - 1] == 'E'))) {
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().collect();
+                let number = literal.parse::<f64>().map_err(|_| ParseError { position: start, message: format!("invalid number '{}'", literal) })?;
+                Token::Number(number)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.' || chars[i] == ':') {
+                    i += 1;
+                }
+                Token::Identifier(chars[start..i].iter().collect())
+            }
+            _ => return Err(ParseError { position: start, message: format!("unexpected character '{}'", c) }),
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end_position: usize,
+    resolve: &'a mut dyn FnMut(&str) -> Option<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map(|(position, _)| *position).unwrap_or(self.end_position)
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError { position: self.position(), message: message.to_string() }
+    }
+
+    fn parse_or(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            node = Node::Or(Box::new(node), Box::new(self.parse_and()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next += 1;
+            node = Node::And(Box::new(node), Box::new(self.parse_not()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_not(&mut self) -> Result<Node, ParseError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.next += 1;
+                Ok(Node::Not(Box::new(self.parse_not()?)))
+            }
+            Some(Token::Open) => {
+                self.next += 1;
+                let node = self.parse_or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(self.error("expected ')'"));
+                }
+                self.next += 1;
+                Ok(node)
+            }
+            _ => self.parse_comparison(),
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Node, ParseError> {
+        let left = self.parse_operand()?;
+        if let Some(Token::Compare(comparison)) = self.peek() {
+            let comparison = *comparison;
+            self.next += 1;
+            let right = self.parse_operand()?;
+            Ok(Node::Compare(left, comparison, right))
+        } else {
+            Ok(Node::Truthy(left))
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, ParseError> {
+        let position = self.position();
+        let operand = match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                match (self.resolve)(&name) {
+                    Some(index) => Operand::Field(index),
+                    None => return Err(ParseError { position, message: format!("unknown field '{}'", name) }),
+                }
+            }
+            Some(Token::Number(number)) => Operand::Literal(Literal::Number(*number)),
+            Some(Token::Text(text)) => Operand::Literal(Literal::Text(text.clone())),
+            Some(_) => return Err(self.error("expected a field name or a value")),
+            None => return Err(self.error("unexpected end of expression")),
+        };
+        self.next += 1;
+        Ok(operand)
+    }
+}
+
+// Compiled filter expression
+#[derive(Debug, Clone)]
+pub struct Expr {
+    root: Node,
+}
+
+impl Expr {
+    // Compiles the expression, resolving each field name to the index later passed to the value lookup
+    pub fn compile(expression: &str, resolve: &mut dyn FnMut(&str) -> Option<usize>) -> Result<Expr, ParseError> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser { tokens, next: 0, end_position: expression.chars().count(), resolve };
+        let root = parser.parse_or()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected token"));
+        }
+        Ok(Expr { root })
+    }
+
+    pub fn eval<'v>(&'v self, field: &dyn Fn(usize) -> Value<'v>) -> bool {
+        eval_node(&self.root, field)
+    }
+}
+
+fn operand_value<'v>(operand: &'v Operand, field: &dyn Fn(usize) -> Value<'v>) -> Value<'v> {
+    match operand {
+        Operand::Field(index) => field(*index),
+        Operand::Literal(Literal::Number(number)) => Value::Number(*number),
+        Operand::Literal(Literal::Text(text)) => Value::Text(text),
+    }
+}
+
+fn eval_node<'v>(node: &'v Node, field: &dyn Fn(usize) -> Value<'v>) -> bool {
+    match node {
+        Node::Compare(left, comparison, right) => {
+            // Comparisons involving missing values are false
+            let ordering = match (operand_value(left, field), operand_value(right, field)) {
+                (Value::Number(a), Value::Number(b)) => a.partial_cmp(&b),
+                (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+                (Value::Number(_), Value::Text(_)) | (Value::Text(_), Value::Number(_)) => {
+                    return *comparison == Comparison::Ne;
+                }
+                _ => None,
+            };
+            match ordering {
+                Some(ordering) => match comparison {
+                    Comparison::Eq => ordering == Ordering::Equal,
+                    Comparison::Ne => ordering != Ordering::Equal,
+                    Comparison::Lt => ordering == Ordering::Less,
+                    Comparison::Le => ordering != Ordering::Greater,
+                    Comparison::Gt => ordering == Ordering::Greater,
+                    Comparison::Ge => ordering != Ordering::Less,
+                },
+                None => false,
+            }
+        }
+        Node::Truthy(operand) => operand_value(operand, field).is_true(),
+        Node::Not(node) => !eval_node(node, field),
+        Node::And(left, right) => eval_node(left, field) && eval_node(right, field),
+        Node::Or(left, right) => eval_node(left, field) || eval_node(right, field),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLUMNS: [&str; 4] = ["a", "b", "name", "empty"];
+    const ROW: [&str; 4] = ["1", "2.5", "gene1", "NA"];
+
+    // Evaluates the expression on one row of the COLUMNS
+    fn eval(expression: &str, row: [&str; 4]) -> Result<bool, ParseError> {
+        let mut resolve = |name: &str| COLUMNS.iter().position(|column| *column == name);
+        let expr = Expr::compile(expression, &mut resolve)?;
+        Ok(expr.eval(&|index| Value::parse(row[index])))
+    }
+
+    #[test]
+    fn comparison_operators() {
+        for (expression, expected) in [
+            ("a == 1", true), ("a != 1", false), ("a < 2.5", true), ("b <= 2.5", true), ("b > 2.5", false), ("b >= 2.5", true),
+            ("b > a", true), ("a >= b", false), ("1e0 == a", true), ("a > -1", true),
+            ("name == 'gene1'", true), ("name != \"gene1\"", false), ("name < 'gene2'", true), ("name >= 'gene2'", false),
+            // A number and a text are only ever different
+            ("name == 1", false), ("name != 1", true), ("name < 1", false),
+        ] {
+            assert_eq!(eval(expression, ROW), Ok(expected), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        for (expression, expected) in [
+            // ! binds tighter than &&, which binds tighter than ||
+            ("a == 1 || a == 2 && b == 0", true),
+            ("(a == 1 || a == 2) && b == 0", false),
+            ("a == 2 && b == 0 || a == 1", true),
+            ("a == 2 && (b == 0 || a == 1)", false),
+            ("!a == 2", true),
+            ("!(a == 1 && b == 0)", true),
+            ("!!(a == 1)", true),
+            ("!(a == 1) || !(b == 2.5)", false),
+            ("((a == 1))", true),
+            // A lone field is true for non-zero numbers
+            ("a && b", true),
+            ("a && !b", false),
+        ] {
+            assert_eq!(eval(expression, ROW), Ok(expected), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn missing_values_fail_every_comparison() {
+        for expression in ["empty == 0", "empty != 0", "empty < 1", "empty >= 1", "empty == 'NA'", "empty", "a == empty"] {
+            assert_eq!(eval(expression, ROW), Ok(false), "{}", expression);
+        }
+        assert_eq!(eval("!(empty == 0)", ROW), Ok(true));
+        assert_eq!(eval("empty == 0 || a == 1", ROW), Ok(true));
+        assert_eq!(eval("a > 0", ["", "2.5", "gene1", "NA"]), Ok(false));
+    }
+
+    #[test]
+    fn invalid_expressions_are_reported_at_their_position() {
+        for (expression, position, message) in [
+            ("a == 1 && length > 10", 10, "unknown field 'length'"),
+            ("(a == 1", 7, "expected ')'"),
+            ("a == 1)", 6, "unexpected token"),
+            ("a ==", 4, "unexpected end of expression"),
+            ("a == && b", 5, "expected a field name or a value"),
+            ("name == 'gene1", 8, "unterminated string"),
+            ("a = 1", 2, "unexpected character '='"),
+            ("a == 1.2.3", 5, "invalid number '1.2.3'"),
+        ] {
+            assert_eq!(eval(expression, ROW), Err(ParseError { position, message: message.to_string() }), "{}", expression);
+        }
+        let error = eval("a == 1 && length > 10", ROW).unwrap_err();
+        assert_eq!(error.display("a == 1 && length > 10"), "unknown field 'length' at character 11\n  a == 1 && length > 10\n            ^");
+    }
+}
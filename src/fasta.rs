@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+use crate::cigar;
+
+// Line of a FASTA index (.fai): length of the sequence, offset of its first base, and bases and bytes of its lines
+struct FaiEntry {
+    length: i64,
+    offset: u64,
+    line_bases: u64,
+    line_bytes: u64,
+}
+
+// FASTA file read by random access through its samtools faidx index, only the index being kept in memory
+pub struct IndexedFasta {
+    file: File,
+    file_path: String,
+    index: HashMap<String, FaiEntry>,
+}
+
+impl IndexedFasta {
+    pub fn open(file_path: &str) -> io::Result<IndexedFasta> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let index_path = format!("{}.fai", file_path);
+        let index_file = File::open(&index_path).map_err(|e| invalid(format!("{}: {}; index the FASTA with samtools faidx (it has to be uncompressed)", index_path, e)))?;
+        let mut index = HashMap::new();
+        for (line_number, line) in BufReader::new(index_file).lines().enumerate() {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            let number = |index: usize| fields.get(index).and_then(|value| value.parse::<u64>().ok());
+            match (number(1), number(2), number(3), number(4)) {
+                (Some(length), Some(offset), Some(line_bases), Some(line_bytes)) if line_bases > 0 && line_bytes >= line_bases => {
+                    index.insert(fields[0].to_string(), FaiEntry { length: length as i64, offset, line_bases, line_bytes });
+                }
+                _ => return Err(invalid(format!("{}: line {}: expected name, length, offset, line bases, and line bytes", index_path, line_number + 1))),
+            }
+        }
+        Ok(IndexedFasta { file: File::open(file_path)?, file_path: file_path.to_string(), index })
+    }
+
+    // Bases [start, end) of a sequence, in upper case
+    pub fn fetch(&mut self, name: &str, start: i64, end: i64) -> io::Result<Result<Vec<u8>, String>> {
+        let mut bases = self.fetch_soft_masked(name, start, end)?;
+        if let Ok(bases) = &mut bases {
+            bases.make_ascii_uppercase();
+        }
+        Ok(bases)
+    }
+
+    // Bases [start, end) of a sequence, keeping the lower case of the soft-masked ones
+    pub fn fetch_soft_masked(&mut self, name: &str, start: i64, end: i64) -> io::Result<Result<Vec<u8>, String>> {
+        let entry = match self.index.get(name) {
+            Some(entry) => entry,
+            None => return Ok(Err(format!("sequence '{}' not found in {}", name, self.file_path))),
+        };
+        if start < 0 || end > entry.length || start > end {
+            return Ok(Err(format!("range {}:{}-{} outside the {} bases of the sequence in {}", name, start, end, entry.length, self.file_path)));
+        }
+        if start == end {
+            return Ok(Ok(Vec::new()));
+        }
+        let position = |base: u64| entry.offset + base / entry.line_bases * entry.line_bytes + base % entry.line_bases;
+        let (first, last) = (position(start as u64), position(end as u64 - 1));
+        let mut bytes = vec![0u8; (last - first + 1) as usize];
+        self.file.seek(SeekFrom::Start(first))?;
+        self.file.read_exact(&mut bytes)?;
+        bytes.retain(|base| !base.is_ascii_whitespace());
+        Ok(Ok(bytes))
+    }
+}
+
+// How the N bases are compared by resolve_matches
+#[derive(Clone, Copy)]
+pub enum NPolicy {
+    // An N is a mismatch, even against another N
+    Mismatch,
+    // An N matches any base
+    Match,
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        other => other,
+    }
+}
+
+// Target and query bases of an aligned stretch
+type AlignedBases = (Vec<u8>, Vec<u8>);
+
+// Bases [first, end) of an aligned operation at the given positions, in the target and in the query (reverse-complemented
+// on the - strand, so that they pair with the target bases)
+#[allow(clippy::too_many_arguments)]
+fn fetch_aligned(query_fasta: &mut IndexedFasta, target_fasta: &mut IndexedFasta, query_name: &str, query_pos: i64, query_rev: bool, target_name: &str, target_pos: i64, first: i64, end: i64) -> io::Result<Result<AlignedBases, String>> {
+    let target_bases = match target_fasta.fetch(target_name, target_pos + first, target_pos + end)? {
+        Ok(bases) => bases,
+        Err(reason) => return Ok(Err(reason)),
+    };
+    let (fetch_start, fetch_end) = if query_rev { (query_pos - end, query_pos - first) } else { (query_pos + first, query_pos + end) };
+    let mut query_bases = match query_fasta.fetch(query_name, fetch_start, fetch_end)? {
+        Ok(bases) => bases,
+        Err(reason) => return Ok(Err(reason)),
+    };
+    if query_rev {
+        query_bases.reverse();
+        query_bases.iter_mut().for_each(|base| *base = complement(*base));
+    }
+    Ok(Ok((target_bases, query_bases)))
+}
+
+fn push_op(ops: &mut Vec<(i64, char)>, length: i64, op: char) {
+    match ops.last_mut() {
+        Some((last_length, last_op)) if *last_op == op => *last_length += length,
+        _ if length > 0 => ops.push((length, op)),
+        _ => {}
+    }
+}
+
+// CIGAR with the `M` bases inside the target feature resolved into `=` and `X` by comparing the query and target
+// sequences (reverse-complementing the query on the - strand), as an alignment written with --eqx would have them.
+// The other `M` bases cannot add mismatches to the feature, whose counts only take the bases inside it, so they are
+// written as `=` without fetching their sequence
+#[allow(clippy::too_many_arguments)]
+pub fn resolve_matches(cigar: &str, query_name: &str, query_start: i64, query_end: i64, query_strand: char, target_name: &str, target_start: i64, feature_in_target_start: i64, feature_in_target_end: i64, query_fasta: &mut IndexedFasta, target_fasta: &mut IndexedFasta, n_policy: NPolicy) -> io::Result<Result<String, String>> {
+    let query_rev = query_strand == '-';
+    let mut query_pos = if query_rev { query_end } else { query_start };
+    let mut target_pos = target_start;
+    let mut ops: Vec<(i64, char)> = Vec::new();
+
+    for (length, op) in cigar::ops(cigar) {
+        match op {
+            'M' => {
+                // Offsets within the operation of the bases inside the target feature
+                let first = (feature_in_target_start - target_pos).clamp(0, length);
+                let end = (feature_in_target_end - target_pos).clamp(first, length);
+                push_op(&mut ops, first, '=');
+                if first < end {
+                    let (target_bases, query_bases) = match fetch_aligned(query_fasta, target_fasta, query_name, query_pos, query_rev, target_name, target_pos, first, end)? {
+                        Ok(bases) => bases,
+                        Err(reason) => return Ok(Err(reason)),
+                    };
+                    for (target_base, query_base) in target_bases.iter().zip(&query_bases) {
+                        let matched = match n_policy {
+                            NPolicy::Match if *target_base == b'N' || *query_base == b'N' => true,
+                            _ => target_base == query_base && *target_base != b'N',
+                        };
+                        push_op(&mut ops, 1, if matched { '=' } else { 'X' });
+                    }
+                }
+                push_op(&mut ops, length - end, '=');
+            }
+            _ => push_op(&mut ops, length, op),
+        }
+        match op {
+            'M' | '=' | 'X' => {
+                target_pos += length;
+                query_pos += if query_rev { -length } else { length };
+            }
+            'D' => target_pos += length,
+            'I' => query_pos += if query_rev { -length } else { length },
+            _ => {}
+        }
+    }
+    Ok(Ok(ops.iter().map(|(length, op)| format!("{}{}", length, op)).collect()))
+}
+
+// Mismatched bases inside both features, by kind of substitution
+#[derive(Default)]
+pub struct Substitutions {
+    pub transitions: i64,
+    pub transversions: i64,
+    // Mismatches involving an N or another ambiguity code
+    pub ambiguous: i64,
+}
+
+impl Substitutions {
+    fn add(&mut self, target_base: u8, query_base: u8) {
+        let is_purine = |base: u8| base == b'A' || base == b'G';
+        if !b"ACGT".contains(&target_base) || !b"ACGT".contains(&query_base) {
+            self.ambiguous += 1;
+        } else if target_base == query_base {
+            // An X operation over identical bases is not a substitution
+        } else if is_purine(target_base) == is_purine(query_base) {
+            self.transitions += 1;
+        } else {
+            self.transversions += 1;
+        }
+    }
+}
+
+// Substitutions of the `X` operations of an extended CIGAR inside both the query and the target features, from the
+// bases of the sequences (the query reverse-complemented on the - strand)
+#[allow(clippy::too_many_arguments)]
+pub fn classify_mismatches(cigar: &str, query_name: &str, query_start: i64, query_end: i64, query_strand: char, target_name: &str, target_start: i64, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64, query_fasta: &mut IndexedFasta, target_fasta: &mut IndexedFasta) -> io::Result<Result<Substitutions, String>> {
+    let query_rev = query_strand == '-';
+    let mut query_pos = if query_rev { query_end } else { query_start };
+    let mut target_pos = target_start;
+    let mut substitutions = Substitutions::default();
+
+    for (length, op) in cigar::ops(cigar) {
+        if op == 'X' {
+            // Offsets within the operation of the bases inside both features
+            let (first_in_query, end_in_query) = if query_rev {
+                (query_pos - feature_in_query_end, query_pos - feature_in_query_start)
+            } else {
+                (feature_in_query_start - query_pos, feature_in_query_end - query_pos)
+            };
+            let first = first_in_query.max(feature_in_target_start - target_pos).max(0);
+            let end = end_in_query.min(feature_in_target_end - target_pos).min(length);
+            if first < end {
+                let (target_bases, query_bases) = match fetch_aligned(query_fasta, target_fasta, query_name, query_pos, query_rev, target_name, target_pos, first, end)? {
+                    Ok(bases) => bases,
+                    Err(reason) => return Ok(Err(reason)),
+                };
+                for (target_base, query_base) in target_bases.into_iter().zip(query_bases) {
+                    substitutions.add(target_base, query_base);
+                }
+            }
+        }
+        match op {
+            'M' | '=' | 'X' => {
+                target_pos += length;
+                query_pos += if query_rev { -length } else { length };
+            }
+            'D' => target_pos += length,
+            'I' => query_pos += if query_rev { -length } else { length },
+            _ => {}
+        }
+    }
+    Ok(Ok(substitutions))
+}
+
+// Base composition of a feature
+#[derive(Clone, Copy)]
+pub struct Composition {
+    length: i64,
+    gc: i64,
+    n: i64,
+    // Longest run of the same base (A, C, G, or T)
+    pub max_homopolymer: i64,
+}
+
+impl Composition {
+    // Composition of bases in either case; with masked_as_n, the soft-masked (lower case) bases count as N
+    pub fn of(bases: &[u8], masked_as_n: bool) -> Composition {
+        let mut composition = Composition { length: bases.len() as i64, gc: 0, n: 0, max_homopolymer: 0 };
+        let mut run: Option<(u8, i64)> = None;
+        for &base in bases {
+            let base = if masked_as_n && base.is_ascii_lowercase() { b'N' } else { base.to_ascii_uppercase() };
+            match base {
+                b'G' | b'C' | b'S' => composition.gc += 1,
+                b'N' => composition.n += 1,
+                _ => {}
+            }
+            run = match run {
+                Some((run_base, length)) if run_base == base => Some((base, length + 1)),
+                _ if b"ACGT".contains(&base) => Some((base, 1)),
+                _ => None,
+            };
+            if let Some((_, length)) = run {
+                composition.max_homopolymer = std::cmp::max(composition.max_homopolymer, length);
+            }
+        }
+        composition
+    }
+
+    // Fractions of G, C (or S) bases and of N bases over all the bases; None for empty features
+    pub fn gc_fraction(&self) -> Option<f64> {
+        (self.length > 0).then(|| self.gc as f64 / self.length as f64)
+    }
+
+    pub fn n_fraction(&self) -> Option<f64> {
+        (self.length > 0).then(|| self.n as f64 / self.length as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spill::TempFile;
+
+    // FASTA file of the records with their sequences wrapped at line_bases (lines ending with `newline`), and its
+    // index as written by samtools faidx
+    fn write_fasta(fasta: &TempFile, records: &[(&str, &str)], line_bases: usize, newline: &str) {
+        let (mut content, mut index) = (String::new(), String::new());
+        for (name, sequence) in records {
+            content.push_str(&format!(">{} description\n", name));
+            index.push_str(&format!("{}\t{}\t{}\t{}\t{}\n", name, sequence.len(), content.len(), line_bases, line_bases + newline.len()));
+            for line in sequence.as_bytes().chunks(line_bases) {
+                content.push_str(std::str::from_utf8(line).unwrap());
+                content.push_str(newline);
+            }
+        }
+        std::fs::write(fasta.path(), content).unwrap();
+        std::fs::write(format!("{}.fai", fasta.path().to_str().unwrap()), index).unwrap();
+    }
+
+    fn remove_index(fasta: &TempFile) {
+        std::fs::remove_file(format!("{}.fai", fasta.path().to_str().unwrap())).unwrap();
+    }
+
+    #[test]
+    fn bases_are_fetched_across_wrapped_lines_of_each_record() {
+        let records = [("chr1", "ACGTACGTACgtacgtNNNNacgtACGTAC"), ("chr2", "TTTTGGGGCCCCAAAA"), ("chr3", "G")];
+        for newline in ["\n", "\r\n"] {
+            let fasta = TempFile::new(&std::env::temp_dir());
+            write_fasta(&fasta, &records, 7, newline);
+            let mut indexed = IndexedFasta::open(fasta.path().to_str().unwrap()).unwrap();
+            for (name, sequence) in records {
+                let ranges = [(0, sequence.len()), (0, 1), (5, 9), (6, 7), (7, 8), (3, 16), (sequence.len() - 1, sequence.len())];
+                for (start, end) in ranges.into_iter().filter(|(_, end)| *end <= sequence.len()) {
+                    let expected = &sequence[start..end];
+                    assert_eq!(indexed.fetch_soft_masked(name, start as i64, end as i64).unwrap().unwrap(), expected.as_bytes(), "{}:{}-{}", name, start, end);
+                    assert_eq!(indexed.fetch(name, start as i64, end as i64).unwrap().unwrap(), expected.to_ascii_uppercase().as_bytes(), "{}:{}-{}", name, start, end);
+                }
+            }
+            assert_eq!(indexed.fetch("chr2", 4, 4).unwrap().unwrap(), b"");
+            remove_index(&fasta);
+        }
+    }
+
+    #[test]
+    fn missing_sequences_and_ranges_are_errors() {
+        let fasta = TempFile::new(&std::env::temp_dir());
+        let path = fasta.path().to_str().unwrap().to_string();
+        write_fasta(&fasta, &[("chr1", "ACGTACGTAC"), ("chr2", "TTTT")], 4, "\n");
+        let mut indexed = IndexedFasta::open(&path).unwrap();
+        assert_eq!(indexed.fetch("chr3", 0, 2).unwrap().unwrap_err(), format!("sequence 'chr3' not found in {}", path));
+        assert_eq!(indexed.fetch("chr2", 2, 5).unwrap().unwrap_err(), format!("range chr2:2-5 outside the 4 bases of the sequence in {}", path));
+        assert_eq!(indexed.fetch("chr1", 6, 5).unwrap().unwrap_err(), format!("range chr1:6-5 outside the 10 bases of the sequence in {}", path));
+        assert!(indexed.fetch("chr1", -1, 5).unwrap().is_err());
+        remove_index(&fasta);
+        // Without its index, the FASTA cannot be opened
+        let error = IndexedFasta::open(&path).err().unwrap();
+        assert!(error.to_string().starts_with(&format!("{}.fai: ", path)) && error.to_string().ends_with("; index the FASTA with samtools faidx (it has to be uncompressed)"), "{}", error);
+    }
+}
@@ -0,0 +1,439 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead};
+
+use crate::open_file;
+
+// Feature of a --features BED file, with the columns of the PAF + feature layout
+struct Feature {
+    start: i64,
+    end: i64,
+    name: String,
+    score: String,
+    strand: String,
+    class: String,
+    // All the columns of the BED line, for --passthrough-cols
+    columns: Vec<String>,
+    // 1-based index, from the 5' end, of the feature among the parts sharing its GeneID on its sequence in a SAF
+    // file (0 for the other files); only parts with the same index are paired
+    part: usize,
+}
+
+// Features of each sequence, sorted by start, with the length of the longest one to bound the overlap searches
+struct SequenceFeatures {
+    features: Vec<Feature>,
+    max_length: i64,
+}
+
+impl SequenceFeatures {
+    // Features overlapping the half-open interval [start, end)
+    fn overlapping(&self, start: i64, end: i64) -> impl Iterator<Item = &Feature> {
+        let first = self.features.partition_point(|feature| feature.start < start.saturating_sub(self.max_length));
+        let last = self.features.partition_point(|feature| feature.start < end);
+        self.features[first..last].iter().filter(move |feature| feature.end > start)
+    }
+}
+
+// Paths of a path-length table laid out one after the other, as in a concatenated reference, to convert the
+// path:offset feature coordinates of --feature-coordinates path-offset to linear coordinates
+pub struct PathOffsets {
+    file_path: String,
+    // Linear sequence (if given), start, and length of each path
+    paths: HashMap<String, (Option<String>, i64, i64)>,
+}
+
+impl PathOffsets {
+    // Loads a table with the name, length, and optionally linear sequence of each path, in their linear order: the
+    // paths of each sequence (or all of them, without sequence column) start where the previous one ends
+    pub fn load(file_path: &str) -> PathOffsets {
+        let mut paths = HashMap::new();
+        let mut linear_starts: HashMap<Option<String>, i64> = HashMap::new();
+        for (line_number, line) in open_file(file_path).lines().enumerate() {
+            let line = line.expect("Failed to read path lengths");
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split('\t').collect();
+            match parts.get(1).and_then(|length| length.parse::<i64>().ok()).filter(|length| *length >= 0) {
+                Some(length) => {
+                    let sequence = parts.get(2).map(|sequence| sequence.to_string());
+                    let linear_start = linear_starts.entry(sequence.clone()).or_insert(0);
+                    if paths.insert(parts[0].to_string(), (sequence, *linear_start, length)).is_some() {
+                        eprintln!("ERROR: {}: line {}: path {} is listed more than once", file_path, line_number + 1, parts[0]);
+                        std::process::exit(1);
+                    }
+                    *linear_start += length;
+                }
+                None => {
+                    eprintln!("ERROR: {}: line {}: expected a path name and a length", file_path, line_number + 1);
+                    std::process::exit(1);
+                }
+            }
+        }
+        PathOffsets { file_path: file_path.to_string(), paths }
+    }
+
+    // Linear coordinate on a sequence of an offset on a path; None if the path is not in the table, an error if the
+    // offset is past its end or the path lies on another sequence
+    fn linear(&self, sequence: &str, path: &str, offset: i64) -> Result<Option<i64>, String> {
+        match self.paths.get(path) {
+            Some((Some(path_sequence), _, _)) if path_sequence != sequence => Err(format!("path {} is on {} in {}, not on {}", path, path_sequence, self.file_path, sequence)),
+            Some(&(_, start, length)) if (0..=length).contains(&offset) => Ok(Some(start + offset)),
+            Some(&(_, _, length)) => Err(format!("offset {} is outside path {} of length {} in {}", offset, path, length, self.file_path)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn is_interval_list(file_path: &str) -> bool {
+    file_path.ends_with(".interval_list") || file_path.ends_with(".interval_list.gz")
+}
+
+// Name and length of the sequence of a @SQ header line (without the @), None for the other header lines
+fn parse_sq_line(header: &str) -> Option<(String, i64)> {
+    let mut tags = header.split('\t');
+    if tags.next() != Some("SQ") {
+        return None;
+    }
+    let (mut name, mut length) = (None, None);
+    for tag in tags {
+        if let Some(value) = tag.strip_prefix("SN:") {
+            name = Some(value.to_string());
+        } else if let Some(value) = tag.strip_prefix("LN:") {
+            length = value.parse::<i64>().ok();
+        }
+    }
+    name.zip(length)
+}
+
+// Feature of a line of a Picard interval list: sequence, 1-based inclusive start and end, strand, and name, converted
+// to 0-based half-open coordinates and checked against the @SQ lengths when the header lists the sequence
+fn parse_interval(fields: &[&str], sequence_lengths: &HashMap<String, i64>) -> Result<Feature, String> {
+    if fields.len() < 5 {
+        return Err(format!("expected sequence, start, end, strand, and name columns, found {}", fields.len()));
+    }
+    let coordinate = |index: usize| fields[index].parse::<i64>().map_err(|_| format!("invalid coordinate '{}'", fields[index]));
+    let (start, end) = (coordinate(1)?, coordinate(2)?);
+    if start < 1 || end < start - 1 {
+        return Err(format!("invalid 1-based interval {}-{}", start, end));
+    }
+    if !sequence_lengths.is_empty() {
+        match sequence_lengths.get(fields[0]) {
+            Some(&length) if end > length => return Err(format!("interval {}-{} is past the end of {} (length {} in the header)", start, end, fields[0], length)),
+            Some(_) => {}
+            None => return Err(format!("sequence {} is not in the @SQ lines of the header", fields[0])),
+        }
+    }
+    if !["+", "-", "."].contains(&fields[3]) {
+        return Err(format!("invalid strand '{}'", fields[3]));
+    }
+    Ok(Feature { start: start - 1, end, name: fields[4].to_string(), score: "0".to_string(), strand: fields[3].to_string(), class: ".".to_string(), columns: fields.iter().map(|field| field.to_string()).collect(), part: 0 })
+}
+
+// Feature of a line of a SAF file (featureCounts annotation): GeneID, Chr, 1-based inclusive Start and End, and
+// Strand, converted to 0-based half-open coordinates; its part index is given once the whole file is loaded
+fn parse_saf(fields: &[&str]) -> Result<Feature, String> {
+    if fields.len() < 5 {
+        return Err(format!("expected GeneID, Chr, Start, End, and Strand columns, found {}", fields.len()));
+    }
+    let coordinate = |index: usize| fields[index].parse::<i64>().map_err(|_| format!("invalid coordinate '{}'", fields[index]));
+    let (start, end) = (coordinate(2)?, coordinate(3)?);
+    if start < 1 || end < start - 1 {
+        return Err(format!("invalid 1-based interval {}-{}", start, end));
+    }
+    if !["+", "-", "."].contains(&fields[4]) {
+        return Err(format!("invalid strand '{}'", fields[4]));
+    }
+    Ok(Feature { start: start - 1, end, name: fields[0].to_string(), score: "0".to_string(), strand: fields[4].to_string(), class: ".".to_string(), columns: fields.iter().map(|field| field.to_string()).collect(), part: 0 })
+}
+
+// Numbers the parts of each GeneID of the features of a sequence sorted by start, from the 5' end (the last part
+// for GeneIDs on the - strand), so that the parts of a query and a target gene are paired in transcription order
+fn number_parts(features: &mut [Feature], separate_parts: bool) {
+    let mut parts: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, feature) in features.iter().enumerate() {
+        parts.entry(feature.name.clone()).or_default().push(index);
+    }
+    for indices in parts.values_mut() {
+        if features[indices[0]].strand == "-" {
+            indices.reverse();
+        }
+        for (part, &index) in indices.iter().enumerate() {
+            features[index].part = part + 1;
+            if separate_parts {
+                features[index].name = format!("{}.part{}", features[index].name, part + 1);
+            }
+        }
+    }
+}
+
+// Features by sequence, used to join plain PAF lines with the features they overlap (--input-layout paf)
+pub struct FeatureIndex {
+    sequences: HashMap<String, SequenceFeatures>,
+    // Features left out at load time for their length
+    pub filtered_by_length: u64,
+}
+
+impl FeatureIndex {
+    // Loads a BED file with chrom, start, end, and optionally name, score, strand, and class columns, keeping only
+    // the features whose length is in the given inclusive range. With path offsets, the start and end columns are
+    // path:offset coordinates, and the features on paths missing from the table are left out with a warning.
+    // Files named *.interval_list(.gz) are read as Picard interval lists instead (see parse_interval), and files whose
+    // first line starts with a GeneID column as SAF (see parse_saf); the parts of a SAF GeneID keep it as their name,
+    // or are named GeneID.partN with `separate_parts`.
+    pub fn load(file_path: &str, length_range: (i64, i64), path_offsets: Option<&PathOffsets>, separate_parts: bool) -> io::Result<FeatureIndex> {
+        let interval_list = is_interval_list(file_path);
+        if interval_list && path_offsets.is_some() {
+            eprintln!("ERROR: {}: --feature-coordinates path-offset does not apply to interval lists, whose coordinates are linear", file_path);
+            std::process::exit(1);
+        }
+        let mut sequences: HashMap<String, SequenceFeatures> = HashMap::new();
+        let mut filtered_by_length = 0;
+        let (mut unknown_path_features, mut unknown_paths) = (0u64, BTreeSet::new());
+        // Sequence lengths of the @SQ lines of an interval list
+        let mut sequence_lengths: HashMap<String, i64> = HashMap::new();
+        let (mut first_line, mut saf) = (true, false);
+        for (line_number, line) in open_file(file_path).lines().enumerate() {
+            let line = line?;
+            if interval_list {
+                if let Some(header) = line.strip_prefix('@') {
+                    if let Some((name, length)) = parse_sq_line(header) {
+                        sequence_lengths.insert(name, length);
+                    }
+                    continue;
+                }
+            }
+            if line.is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if std::mem::take(&mut first_line) && !interval_list && fields[0] == "GeneID" {
+                if path_offsets.is_some() {
+                    eprintln!("ERROR: {}: --feature-coordinates path-offset does not apply to SAF files, whose coordinates are linear", file_path);
+                    std::process::exit(1);
+                }
+                saf = true;
+                continue;
+            }
+            if interval_list || saf {
+                let feature = if saf { parse_saf(&fields) } else { parse_interval(&fields, &sequence_lengths) }.unwrap_or_else(|message| {
+                    eprintln!("ERROR: {}: line {}: {}", file_path, line_number + 1, message);
+                    std::process::exit(1);
+                });
+                if feature.end - feature.start < length_range.0 || feature.end - feature.start > length_range.1 {
+                    filtered_by_length += 1;
+                    continue;
+                }
+                let sequence = sequences.entry(fields[if saf { 1 } else { 0 }].to_string()).or_insert_with(|| SequenceFeatures { features: Vec::new(), max_length: 0 });
+                sequence.max_length = std::cmp::max(sequence.max_length, feature.end - feature.start);
+                sequence.features.push(feature);
+                continue;
+            }
+            let (start, end) = match path_offsets {
+                None => {
+                    let coordinate = |index: usize| fields.get(index).and_then(|value| value.parse::<i64>().ok());
+                    match (coordinate(1), coordinate(2)) {
+                        (Some(start), Some(end)) => (start, end),
+                        _ => {
+                            eprintln!("ERROR: {}: line {}: expected chrom, start, and end columns", file_path, line_number + 1);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Some(path_offsets) => {
+                    let coordinate = |index: usize| fields.get(index)
+                        .and_then(|value| value.rsplit_once(':'))
+                        .and_then(|(path, offset)| offset.parse::<i64>().ok().map(|offset| (path, offset)));
+                    let ((start_path, start_offset), (end_path, end_offset)) = match (coordinate(1), coordinate(2)) {
+                        (Some(start), Some(end)) => (start, end),
+                        _ => {
+                            eprintln!("ERROR: {}: line {}: expected chrom, path:start, and path:end columns", file_path, line_number + 1);
+                            std::process::exit(1);
+                        }
+                    };
+                    match (path_offsets.linear(fields[0], start_path, start_offset), path_offsets.linear(fields[0], end_path, end_offset)) {
+                        (Ok(Some(start)), Ok(Some(end))) => (start, end),
+                        (Err(message), _) | (_, Err(message)) => {
+                            eprintln!("ERROR: {}: line {}: {}", file_path, line_number + 1, message);
+                            std::process::exit(1);
+                        }
+                        (start, _) => {
+                            unknown_path_features += 1;
+                            unknown_paths.insert(if start == Ok(None) { start_path.to_string() } else { end_path.to_string() });
+                            continue;
+                        }
+                    }
+                }
+            };
+            if end - start < length_range.0 || end - start > length_range.1 {
+                filtered_by_length += 1;
+                continue;
+            }
+            let column = |index: usize, default: &str| fields.get(index).unwrap_or(&default).to_string();
+            let sequence = sequences.entry(fields[0].to_string()).or_insert_with(|| SequenceFeatures { features: Vec::new(), max_length: 0 });
+            sequence.max_length = std::cmp::max(sequence.max_length, end - start);
+            sequence.features.push(Feature { start, end, name: column(3, "."), score: column(4, "0"), strand: column(5, "."), class: column(6, "."), columns: fields.iter().map(|field| field.to_string()).collect(), part: 0 });
+        }
+        for sequence in sequences.values_mut() {
+            sequence.features.sort_by_key(|feature| feature.start);
+            if saf {
+                number_parts(&mut sequence.features, separate_parts);
+            }
+        }
+        if let (Some(path_offsets), Some(example)) = (path_offsets, unknown_paths.iter().next()) {
+            eprintln!("WARNING: {}: {} features on {} paths not in {} (e.g. '{}') were not loaded", file_path, unknown_path_features, unknown_paths.len(), path_offsets.file_path, example);
+        }
+        Ok(FeatureIndex { sequences, filtered_by_length })
+    }
+
+    // Number of features with each name, the parts of a SAF GeneID counting as one
+    pub fn name_counts(&self) -> HashMap<&str, usize> {
+        let mut counts = HashMap::new();
+        for feature in self.sequences.values().flat_map(|sequence| &sequence.features).filter(|feature| feature.part <= 1) {
+            *counts.entry(feature.name.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    // Lines in the PAF + feature layout for each feature with the same name overlapping both the query and the
+    // target range of a PAF line, with the BED columns of the target feature; the query features are taken from this
+    // index and the target features from target_index (the same index with --features, another one with
+    // --query-features and --target-features)
+    pub fn join<'a>(&'a self, target_index: &'a FeatureIndex, paf_line: &str) -> Vec<(String, Option<&'a [String]>)> {
+        let fields: Vec<&str> = paf_line.split('\t').collect();
+        if fields.len() < 12 {
+            // Left as it is, to be reported as invalid
+            return vec![(paf_line.to_string(), None)];
+        }
+        let range = |start: usize, end: usize| (fields[start].parse::<i64>().ok(), fields[end].parse::<i64>().ok());
+        let (query_start, query_end, target_start, target_end) = match (range(2, 3), range(7, 8)) {
+            ((Some(query_start), Some(query_end)), (Some(target_start), Some(target_end))) => (query_start, query_end, target_start, target_end),
+            _ => return vec![(paf_line.to_string(), None)],
+        };
+        let (query_features, target_features) = match (self.sequences.get(fields[0]), target_index.sequences.get(fields[5])) {
+            (Some(query_features), Some(target_features)) => (query_features, target_features),
+            _ => return Vec::new(),
+        };
+        let target_overlaps: Vec<&Feature> = target_features.overlapping(target_start, target_end).collect();
+        let mut joined = Vec::new();
+        for query_feature in query_features.overlapping(query_start, query_end) {
+            for target_feature in target_overlaps.iter().filter(|feature| feature.name == query_feature.name && feature.part == query_feature.part) {
+                let line = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    paf_line,
+                    fields[0], query_feature.start, query_feature.end, query_feature.name, query_feature.score, query_feature.strand, query_feature.class,
+                    fields[5], target_feature.start, target_feature.end, target_feature.name, target_feature.score, target_feature.strand, target_feature.class);
+                joined.push((line, Some(&target_feature.columns[..])));
+            }
+        }
+        joined
+    }
+}
+
+// Line in the PAF + feature layout whose query and target features are the alignment ranges (--whole-alignment),
+// without name (.) and strand (.)
+pub fn whole_alignment(paf_line: &str) -> String {
+    let fields: Vec<&str> = paf_line.split('\t').collect();
+    if fields.len() < 12 {
+        // Left as it is, to be reported as invalid
+        return paf_line.to_string();
+    }
+    format!("{}\t{}\t{}\t{}\t.\t0\t.\t.\t{}\t{}\t{}\t.\t0\t.\t.", paf_line, fields[0], fields[2], fields[3], fields[5], fields[7], fields[8])
+}
+
+pub fn is_paf_tag(field: &str) -> bool {
+    let bytes = field.as_bytes();
+    bytes.len() >= 5 && bytes[0].is_ascii_alphabetic() && bytes[1].is_ascii_alphanumeric() && bytes[2] == b':' && b"AifZHB".contains(&bytes[3]) && bytes[4] == b':'
+}
+
+pub fn is_cigar(field: &str) -> bool {
+    !field.is_empty() && field.bytes().all(|b| b.is_ascii_digit() || b"MIDNSHP=X".contains(&b))
+}
+
+// Number of columns of the query and target features in the paf-feature layout
+pub const FEATURE_COLUMNS: usize = 14;
+
+// Order of the PAF and feature columns of the paf-feature layout (--order)
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColumnOrder {
+    // PAF columns and tags, then the feature columns
+    PafFirst,
+    // Feature columns, then the PAF columns and tags, as written by `bedtools intersect -a features -b alignments`
+    BedFirst,
+}
+
+impl ColumnOrder {
+    pub fn name(self) -> &'static str {
+        match self {
+            ColumnOrder::PafFirst => "paf-first",
+            ColumnOrder::BedFirst => "bed-first",
+        }
+    }
+
+    // Whether the fields have the PAF columns (with a query strand, and a CIGAR as a column or a tag, or only a tag
+    // without with_cigar) and the feature columns (with coordinates and strands) where this order puts them
+    fn matches(self, fields: &[&str], with_cigar: bool) -> bool {
+        if fields.len() < 12 + FEATURE_COLUMNS + 1 {
+            return false;
+        }
+        let (paf, feature) = match self {
+            ColumnOrder::PafFirst => (&fields[..fields.len() - FEATURE_COLUMNS], &fields[fields.len() - FEATURE_COLUMNS..]),
+            ColumnOrder::BedFirst => (&fields[FEATURE_COLUMNS..], &fields[..FEATURE_COLUMNS]),
+        };
+        let is_number = |fields: &[&str], index: usize| fields[index].parse::<i64>().is_ok();
+        let paf_valid = [1, 2, 3, 6, 7, 8].iter().all(|&index| is_number(paf, index))
+            && (paf[4] == "+" || paf[4] == "-")
+            && paf[12..].iter().any(|field| if with_cigar { is_cigar(field) || (is_paf_tag(field) && is_cigar(&field[5..])) } else { is_paf_tag(field) });
+        let feature_valid = [1, 2, 8, 9].iter().all(|&index| is_number(feature, index))
+            && [5, 12].iter().all(|&index| ["+", "-", "."].contains(&feature[index]));
+        paf_valid && feature_valid
+    }
+
+    // Fields in the PAF-first order
+    pub fn paf_first<'a, 'b>(self, fields: &'b [&'a str]) -> Cow<'b, [&'a str]> {
+        if self == ColumnOrder::PafFirst || fields.len() < FEATURE_COLUMNS {
+            return Cow::Borrowed(fields);
+        }
+        let mut fields = fields.to_vec();
+        fields.rotate_left(FEATURE_COLUMNS);
+        Cow::Owned(fields)
+    }
+}
+
+// Order of the columns of the first line of paf-feature input: the declared one, checked, or the only one its
+// columns are valid in; a line valid in both orders or in none is an error rather than a guess. Without with_cigar
+// (the alignments being given by --blocks), the lines have PAF tags but no CIGAR
+pub fn detect_order(line: &str, declared: Option<ColumnOrder>, with_cigar: bool) -> Result<ColumnOrder, String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let (paf_first, bed_first) = (ColumnOrder::PafFirst.matches(&fields, with_cigar), ColumnOrder::BedFirst.matches(&fields, with_cigar));
+    match (declared, paf_first, bed_first) {
+        (Some(order), _, _) if order.matches(&fields, with_cigar) => Ok(order),
+        (Some(order), _, _) => {
+            let hint = if paf_first || bed_first { format!(", but it is valid as --order {}", if paf_first { "paf-first" } else { "bed-first" }) } else { String::new() };
+            Err(format!("the first line does not have the PAF columns and the 14 columns of the query and target features in the --order {} positions ({} fields){}", order.name(), fields.len(), hint))
+        }
+        (None, true, false) => Ok(ColumnOrder::PafFirst),
+        (None, false, true) => Ok(ColumnOrder::BedFirst),
+        (None, true, true) => Err("the columns of the first line are valid both with the PAF columns first and with the feature columns first; give the order with --order".to_string()),
+        (None, false, false) => Err(format!("the first line has neither the PAF columns followed by the 14 columns of the query and target features, nor the features followed by the PAF columns ({} fields), is it plain PAF?", fields.len())),
+    }
+}
+
+// Checks that the first line of the input matches the layout given with --input-layout
+pub fn validate_layout(line: &str, plain_paf: bool) -> Result<(), String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let is_number = |index: usize| fields.get(index).is_some_and(|value| value.parse::<i64>().is_ok());
+    if fields.len() < 12 || ![1, 2, 3, 6, 7, 8].iter().all(|&index| is_number(index)) {
+        return Err(format!("the first line does not start with the 12 PAF columns ({} fields)", fields.len()));
+    }
+    if plain_paf {
+        if let Some(field) = fields[12..].iter().find(|field| !is_paf_tag(field) && !is_cigar(field)) {
+            return Err(format!("the first line has a column that is not a PAF tag ('{}'), is it in the paf-feature layout?", field));
+        }
+    } else {
+        let feature_columns = fields.len().saturating_sub(14);
+        if fields.len() < 27 || ![1, 2, 8, 9].iter().all(|&offset| is_number(feature_columns + offset)) {
+            return Err(format!("the first line does not end with the 14 columns of the query and target features ({} fields), is it plain PAF?", fields.len()));
+        }
+    }
+    Ok(())
+}
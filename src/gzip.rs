@@ -0,0 +1,46 @@
+use flate2::read::GzDecoder;
+use std::io::{self, Read};
+
+// Counts the bytes read from the underlying reader
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+// Decompresses a gzip file, turning the errors of a corrupt or truncated stream into errors naming the file and how
+// far it was read, instead of the bare message of the decoder
+pub struct GzipReader<R: Read> {
+    decoder: GzDecoder<CountingReader<R>>,
+    file_path: String,
+    decompressed: u64,
+}
+
+impl<R: Read> GzipReader<R> {
+    pub fn new(inner: R, file_path: &str) -> GzipReader<R> {
+        GzipReader { decoder: GzDecoder::new(CountingReader { inner, count: 0 }), file_path: file_path.to_string(), decompressed: 0 }
+    }
+}
+
+impl<R: Read> Read for GzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.decoder.read(buf) {
+            Ok(read) => {
+                self.decompressed += read as u64;
+                Ok(read)
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Err(e),
+            // The decoder reads ahead, so the error is somewhere in the last block of compressed bytes read
+            Err(e) => Err(io::Error::new(e.kind(), format!(
+                "{}: corrupt or truncated gzip data within the first {} compressed bytes (after {} decompressed bytes): {}; check that the file is complete and not damaged (e.g. with gzip -t)",
+                self.file_path, self.decoder.get_ref().count, self.decompressed, e))),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Index of an interned name
+pub type Symbol = u32;
+
+// Stores each distinct name once, shared by the index and the symbol table, so that aggregation keys hold 32-bit
+// symbols instead of owned strings
+#[derive(Default)]
+pub struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+    names: Vec<Rc<str>>,
+}
+
+impl Interner {
+    // Symbol of a name, and the bytes newly allocated for it (0 if it was already interned)
+    pub fn intern(&mut self, name: &str) -> (Symbol, usize) {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return (symbol, 0);
+        }
+        let symbol = Symbol::try_from(self.names.len()).expect("More than 2^32 distinct names");
+        let name: Rc<str> = Rc::from(name);
+        self.names.push(Rc::clone(&name));
+        self.symbols.insert(name, symbol);
+        (symbol, self.names[symbol as usize].len() + 2 * std::mem::size_of::<usize>() + 2 * std::mem::size_of::<Rc<str>>() + std::mem::size_of::<Symbol>())
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol as usize]
+    }
+
+    // Forgets all the names; the symbols given so far become invalid
+    pub fn clear(&mut self) {
+        self.symbols.clear();
+        self.names.clear();
+    }
+}
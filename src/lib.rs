@@ -0,0 +1,10 @@
+// Library target: the CIGAR tokenizer, shared with the benchmarks, and the C API of the capi feature, i.e. the
+// counting kernel of the report callable from C and C++ through include/feature_level_report.h (the report itself is
+// the binary)
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod cigar;
+// Shared with the binary, which uses all of it
+#[cfg(feature = "capi")]
+#[allow(dead_code)]
+mod counting;
@@ -0,0 +1,348 @@
+use clap::ArgMatches;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufWriter, Write};
+
+use crate::{features, json_string, open_file};
+
+// Columns expected for each of the query and target features at the end of a line
+const FEATURE_COLUMNS: usize = 7;
+
+// Offending sequence names listed for the name mismatches
+const TOP_OFFENDERS: usize = 10;
+
+// Counts of what is found in a combined input, by section and item
+#[derive(Default)]
+struct Profile {
+    lines: u64,
+    field_counts: BTreeMap<usize, u64>,
+    tags: BTreeMap<String, u64>,
+    cigar: BTreeMap<&'static str, u64>,
+    // Number of trailing columns that look like the query feature (and as many for the target feature)
+    feature_widths: BTreeMap<String, u64>,
+    strands: BTreeMap<String, u64>,
+    coordinates: BTreeMap<&'static str, u64>,
+    mismatches: BTreeMap<&'static str, u64>,
+    offenders: HashMap<String, u64>,
+    // Lines whose features are on the opposite sequences (query feature on the target and vice versa)
+    swapped_features: u64,
+}
+
+fn is_number(fields: &[&str], index: usize) -> bool {
+    fields.get(index).is_some_and(|value| value.parse::<i64>().is_ok())
+}
+
+// Number of columns of each of the two features ending the line: the first trailing layout (7 columns first) whose
+// start and end columns are numbers and whose sequence columns are not
+fn feature_width(fields: &[&str]) -> Option<usize> {
+    let n = fields.len();
+    std::iter::once(FEATURE_COLUMNS).chain((3..=12).filter(|&width| width != FEATURE_COLUMNS)).find(|&width| {
+        n >= 12 + 2 * width
+            && is_number(fields, n - 2 * width + 1) && is_number(fields, n - 2 * width + 2)
+            && is_number(fields, n - width + 1) && is_number(fields, n - width + 2)
+            && !is_number(fields, n - 2 * width) && !is_number(fields, n - width)
+    })
+}
+
+impl Profile {
+    fn add(&mut self, line: &str) {
+        self.lines += 1;
+        let fields: Vec<&str> = line.split('\t').collect();
+        let n = fields.len();
+        *self.field_counts.entry(n).or_insert(0) += 1;
+
+        let width = feature_width(&fields);
+        // Tags sit between the 12 PAF columns and the feature columns
+        let tags_end = n - width.map_or(0, |width| 2 * width);
+        let tags: &[&str] = if n > 12 && tags_end > 12 { &fields[12..tags_end] } else { &[] };
+        for tag in tags.iter().filter(|field| features::is_paf_tag(field)) {
+            *self.tags.entry(tag[..4].to_string()).or_insert(0) += 1;
+        }
+        let cigar = if tags.iter().any(|tag| tag.starts_with("cg:Z:")) {
+            "cg.tag"
+        } else if tags.first().is_some_and(|field| features::is_cigar(field)) {
+            "column.13"
+        } else {
+            "missing"
+        };
+        *self.cigar.entry(cigar).or_insert(0) += 1;
+        *self.feature_widths.entry(width.map_or("none".to_string(), |width| width.to_string())).or_insert(0) += 1;
+
+        if n < 12 {
+            return;
+        }
+        let coordinate = |index: usize| fields[index].parse::<i64>().ok();
+        let (query_length, query_start, query_end) = (coordinate(1), coordinate(2), coordinate(3));
+        let (target_length, target_start, target_end) = (coordinate(6), coordinate(7), coordinate(8));
+        let mut violations = Vec::new();
+        for (name, value) in [("query.length", query_length), ("query.start", query_start), ("query.end", query_end), ("target.length", target_length), ("target.start", target_start), ("target.end", target_end)] {
+            match value {
+                None => violations.push(match name {
+                    "query.length" | "query.start" | "query.end" => "query.not.a.number",
+                    _ => "target.not.a.number",
+                }),
+                Some(value) if value < 0 => violations.push("negative.coordinate"),
+                _ => {}
+            }
+        }
+        if let (Some(start), Some(end)) = (query_start, query_end) {
+            if start > end {
+                violations.push("query.start.after.end");
+            }
+        }
+        if let (Some(end), Some(length)) = (query_end, query_length) {
+            if end > length {
+                violations.push("query.end.past.length");
+            }
+        }
+        if let (Some(start), Some(end)) = (target_start, target_end) {
+            if start > end {
+                violations.push("target.start.after.end");
+            }
+        }
+        if let (Some(end), Some(length)) = (target_end, target_length) {
+            if end > length {
+                violations.push("target.end.past.length");
+            }
+        }
+
+        let alignment_strand = fields[4];
+        match width {
+            Some(width) => {
+                let (query_feature, target_feature) = (n - 2 * width, n - width);
+                let strand = |feature: usize| if width > 5 { fields[feature + 5] } else { "." };
+                *self.strands.entry(format!("{}/{}/{}", alignment_strand, strand(query_feature), strand(target_feature))).or_insert(0) += 1;
+                for (name, feature) in [("query.feature.start.after.end", query_feature), ("target.feature.start.after.end", target_feature)] {
+                    if coordinate(feature + 1) > coordinate(feature + 2) {
+                        violations.push(name);
+                    }
+                }
+                let mut mismatched = |kind: &'static str, name: String| {
+                    *self.mismatches.entry(kind).or_insert(0) += 1;
+                    *self.offenders.entry(name).or_insert(0) += 1;
+                };
+                if fields[query_feature] != fields[0] {
+                    mismatched("query.sequence", format!("{} (query, feature on {})", fields[0], fields[query_feature]));
+                }
+                if fields[target_feature] != fields[5] {
+                    mismatched("target.sequence", format!("{} (target, feature on {})", fields[5], fields[target_feature]));
+                }
+                if width > 3 && fields[query_feature + 3] != fields[target_feature + 3] {
+                    mismatched("feature.name", format!("{} (feature, {} in the target)", fields[query_feature + 3], fields[target_feature + 3]));
+                }
+                if fields[query_feature] != fields[0] && fields[query_feature] == fields[5] && fields[target_feature] == fields[0] {
+                    self.swapped_features += 1;
+                }
+            }
+            None => *self.strands.entry(format!("{}/./.", alignment_strand)).or_insert(0) += 1,
+        }
+        for violation in violations {
+            *self.coordinates.entry(violation).or_insert(0) += 1;
+        }
+    }
+
+    fn top_offenders(&self) -> Vec<(&str, u64)> {
+        let mut offenders: Vec<(&str, u64)> = self.offenders.iter().map(|(name, count)| (name.as_str(), *count)).collect();
+        offenders.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        offenders.truncate(TOP_OFFENDERS);
+        offenders
+    }
+
+    // Most likely layout misconfiguration, when all the lines have the same number of fields
+    fn diagnosis(&self) -> Option<String> {
+        if self.field_counts.len() != 1 {
+            return None;
+        }
+        let fields = *self.field_counts.keys().next().unwrap();
+        let widths: Vec<&String> = self.feature_widths.keys().collect();
+        if fields < 12 {
+            return Some(format!("all lines have {} fields, fewer than the 12 PAF columns: is the file tab-separated?", fields));
+        }
+        if widths == ["none"] {
+            return Some(format!("all lines have {} fields and no feature columns at the end: they look like plain PAF, to be read with --input-layout paf and --features, or --whole-alignment", fields));
+        }
+        if widths.len() == 1 && *widths[0] != FEATURE_COLUMNS.to_string() {
+            return Some(format!("all lines have {} fields ending with 2 x {} feature columns, while {} are expected for each feature (chrom, start, end, name, score, strand, class): were the features intersected as BED{}?", fields, widths[0], FEATURE_COLUMNS, widths[0]));
+        }
+        if self.swapped_features * 2 > self.lines {
+            return Some(format!("{} of {} lines have the query feature on the target sequence and the target feature on the query sequence: were the query and target features swapped?", self.swapped_features, self.lines));
+        }
+        None
+    }
+
+    fn write_table(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "section\titem\tcount")?;
+        writeln!(writer, "lines\tall\t{}", self.lines)?;
+        for (fields, count) in &self.field_counts {
+            writeln!(writer, "fields\t{}\t{}", fields, count)?;
+        }
+        for (tag, count) in &self.tags {
+            writeln!(writer, "tags\t{}\t{}", tag, count)?;
+        }
+        for (cigar, count) in &self.cigar {
+            writeln!(writer, "cigar\t{}\t{}", cigar, count)?;
+        }
+        for (width, count) in &self.feature_widths {
+            writeln!(writer, "feature.columns\t{}\t{}", width, count)?;
+        }
+        for (strands, count) in &self.strands {
+            writeln!(writer, "strands\t{}\t{}", strands, count)?;
+        }
+        for (violation, count) in &self.coordinates {
+            writeln!(writer, "coordinates\t{}\t{}", violation, count)?;
+        }
+        for (kind, count) in &self.mismatches {
+            writeln!(writer, "mismatches\t{}\t{}", kind, count)?;
+        }
+        for (name, count) in self.top_offenders() {
+            writeln!(writer, "offenders\t{}\t{}", name, count)?;
+        }
+        if let Some(diagnosis) = self.diagnosis() {
+            writeln!(writer, "diagnosis\t{}\t{}", diagnosis, self.lines)?;
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut impl Write) -> io::Result<()> {
+        fn object<K: ToString>(entries: impl Iterator<Item = (K, u64)>) -> String {
+            let entries: Vec<String> = entries.map(|(key, count)| format!("{}: {}", json_string(&key.to_string()), count)).collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"lines\": {},", self.lines)?;
+        writeln!(writer, "  \"fields\": {},", object(self.field_counts.iter().map(|(k, v)| (k, *v))))?;
+        writeln!(writer, "  \"tags\": {},", object(self.tags.iter().map(|(k, v)| (k, *v))))?;
+        writeln!(writer, "  \"cigar\": {},", object(self.cigar.iter().map(|(k, v)| (k, *v))))?;
+        writeln!(writer, "  \"feature_columns\": {},", object(self.feature_widths.iter().map(|(k, v)| (k, *v))))?;
+        writeln!(writer, "  \"strands\": {},", object(self.strands.iter().map(|(k, v)| (k, *v))))?;
+        writeln!(writer, "  \"coordinates\": {},", object(self.coordinates.iter().map(|(k, v)| (k, *v))))?;
+        writeln!(writer, "  \"mismatches\": {},", object(self.mismatches.iter().map(|(k, v)| (k, *v))))?;
+        let offenders: Vec<String> = self.top_offenders().iter().map(|(name, count)| format!("{{\"name\": {}, \"count\": {}}}", json_string(name), count)).collect();
+        writeln!(writer, "  \"offenders\": [{}],", offenders.join(", "))?;
+        writeln!(writer, "  \"diagnosis\": {}", self.diagnosis().map_or("null".to_string(), |diagnosis| json_string(&diagnosis)))?;
+        writeln!(writer, "}}")
+    }
+}
+
+pub fn run(matches: &ArgMatches) -> io::Result<()> {
+    let input_file = matches.value_of("input").unwrap();
+
+    let mut profile = Profile::default();
+    for line in open_file(input_file).lines() {
+        let line = line?;
+        if !line.is_empty() {
+            profile.add(&line);
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match matches.value_of("format") {
+        Some("json") => profile.write_json(&mut out)?,
+        _ => profile.write_table(&mut out)?,
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAF: &str = "q1\t100\t0\t100\t+\tt1\t200\t50\t150\t100\t100\t60\tcg:Z:100M";
+    const QUERY_FEATURE: &str = "q1\t10\t20\tf1\t0\t+\tgene";
+    const TARGET_FEATURE: &str = "t1\t60\t70\tf1\t0\t+\tgene";
+
+    fn profile(lines: &[String]) -> Profile {
+        let mut profile = Profile::default();
+        for line in lines {
+            profile.add(line);
+        }
+        profile
+    }
+
+    fn table(profile: &Profile) -> Vec<String> {
+        let mut table = Vec::new();
+        profile.write_table(&mut table).unwrap();
+        String::from_utf8(table).unwrap().lines().skip(1).map(str::to_string).collect()
+    }
+
+    fn line(paf: &str, query_feature: &str, target_feature: &str) -> String {
+        format!("{}\t{}\t{}", paf, query_feature, target_feature)
+    }
+
+    #[test]
+    fn clean_input_has_no_violation_and_no_diagnosis() {
+        let profile = profile(&[line(PAF, QUERY_FEATURE, TARGET_FEATURE), line(PAF, QUERY_FEATURE, TARGET_FEATURE)]);
+        assert_eq!(table(&profile), [
+            "lines\tall\t2", "fields\t27\t2", "tags\tcg:Z\t2", "cigar\tcg.tag\t2", "feature.columns\t7\t2", "strands\t+/+/+\t2",
+        ]);
+        let mut json = Vec::new();
+        profile.write_json(&mut json).unwrap();
+        assert!(String::from_utf8(json).unwrap().ends_with("  \"offenders\": [],\n  \"diagnosis\": null\n}\n"));
+    }
+
+    #[test]
+    fn cigar_column_and_missing_cigar_are_told_apart() {
+        let profile = profile(&[
+            line(&PAF.replace("cg:Z:", ""), QUERY_FEATURE, TARGET_FEATURE),
+            line(&PAF.replace("\tcg:Z:100M", ""), QUERY_FEATURE, TARGET_FEATURE),
+        ]);
+        let table = table(&profile);
+        assert!(table.contains(&"cigar\tcolumn.13\t1".to_string()) && table.contains(&"cigar\tmissing\t1".to_string()), "{:?}", table);
+    }
+
+    #[test]
+    fn coordinate_violations_are_counted() {
+        let profile = profile(&[
+            line(&PAF.replace("\t0\t100\t+", "\t60\t50\t+"), QUERY_FEATURE, TARGET_FEATURE),
+            line(&PAF.replace("\t200\t50\t150\t", "\t100\t50\t150\t"), QUERY_FEATURE, TARGET_FEATURE),
+            line(&PAF.replace("\t200\t50\t", "\t200\t-5\t"), QUERY_FEATURE, "t1\t70\t60\tf1\t0\t+\tgene"),
+            line(&PAF.replace("q1\t100\t", "q1\tNA\t"), QUERY_FEATURE, TARGET_FEATURE),
+        ]);
+        let coordinates: Vec<String> = table(&profile).into_iter().filter(|row| row.starts_with("coordinates\t")).collect();
+        assert_eq!(coordinates, [
+            "coordinates\tnegative.coordinate\t1", "coordinates\tquery.not.a.number\t1", "coordinates\tquery.start.after.end\t1",
+            "coordinates\ttarget.end.past.length\t1", "coordinates\ttarget.feature.start.after.end\t1",
+        ]);
+    }
+
+    #[test]
+    fn name_mismatches_are_counted_with_their_offenders() {
+        let profile = profile(&[
+            line(PAF, &QUERY_FEATURE.replace("q1", "q2"), TARGET_FEATURE),
+            line(PAF, QUERY_FEATURE, &TARGET_FEATURE.replace("f1", "f2")),
+            line(PAF, QUERY_FEATURE, TARGET_FEATURE),
+        ]);
+        let mismatches: Vec<String> = table(&profile).into_iter().filter(|row| row.starts_with("mismatches\t") || row.starts_with("offenders\t")).collect();
+        assert_eq!(mismatches, [
+            "mismatches\tfeature.name\t1", "mismatches\tquery.sequence\t1",
+            "offenders\tf1 (feature, f2 in the target)\t1", "offenders\tq1 (query, feature on q2)\t1",
+        ]);
+        assert_eq!(profile.diagnosis(), None);
+    }
+
+    #[test]
+    fn lines_that_are_not_tab_separated_are_diagnosed() {
+        let profile = profile(&[line(PAF, QUERY_FEATURE, TARGET_FEATURE).replace('\t', " ")]);
+        assert_eq!(profile.diagnosis().unwrap(), "all lines have 1 fields, fewer than the 12 PAF columns: is the file tab-separated?");
+    }
+
+    #[test]
+    fn plain_paf_is_diagnosed() {
+        let profile = profile(&[PAF.to_string(), PAF.to_string()]);
+        assert!(profile.diagnosis().unwrap().starts_with("all lines have 13 fields and no feature columns at the end: they look like plain PAF"));
+    }
+
+    #[test]
+    fn features_of_another_width_are_diagnosed() {
+        let profile = profile(&[line(PAF, "q1\t10\t20\tf1\t0\t+", "t1\t60\t70\tf1\t0\t+")]);
+        assert!(table(&profile).contains(&"feature.columns\t6\t1".to_string()));
+        assert!(profile.diagnosis().unwrap().starts_with("all lines have 25 fields ending with 2 x 6 feature columns, while 7 are expected"));
+    }
+
+    #[test]
+    fn swapped_features_are_diagnosed() {
+        let swapped = line(PAF, TARGET_FEATURE, QUERY_FEATURE);
+        let profile = profile(&[swapped.clone(), swapped, line(PAF, QUERY_FEATURE, TARGET_FEATURE)]);
+        assert_eq!(profile.diagnosis().unwrap(), "2 of 3 lines have the query feature on the target sequence and the target feature on the query sequence: were the query and target features swapped?");
+    }
+}
@@ -1,177 +1,568 @@
 use clap::{App, Arg};
-use regex::Regex;
-use std::{fs::File, io::{self, BufRead, BufReader}};
-use flate2::read::GzDecoder;
-
-fn count_aligned_bases(query_start: i64, query_end: i64, query_strand: char, target_start: i64, _target_end: i64, cigar: &str, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64, max_indel_size: i64) -> (i64, i64, i64, i64, i64, i64, i64) {
-    let mut aligned_bases = 0;
-    let mut not_aligned_bases_in_query = 0;
-    let mut not_aligned_bases_in_target = 0;
-    let mut indels_in_query = 0;
-    let mut indels_in_target =  0;
-    let query_rev = query_strand == '-';
+use std::{fs::File, io::{self, BufRead, BufReader, BufWriter, Read, Write}};
+use std::collections::HashMap;
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use counting::{count_aligned_bases, count_aligned_bases_by_threshold, count_ops_by_threshold, is_counted_indel, FeatureCounts, Fragmentation, Interval, UNLIMITED_INDEL_SIZE};
+
+#[cfg(feature = "cloud")]
+mod cloud;
+mod annotate;
+mod binning;
+mod blocks;
+mod cigar;
+mod counting;
+mod diff;
+mod error_track;
+mod estimate;
+mod expr;
+mod fasta;
+mod features;
+mod gzip;
+mod intern;
+mod lint;
+mod match_blocks;
+mod merge;
+mod names;
+mod output;
+mod pairs;
+mod report;
+mod spill;
+mod summarize;
+mod validate;
+mod zstd;
+
+// Fields of an input line: 12 PAF columns, the optional PAF tags (with at least the CIGAR), and the 7 columns
+// of the feature in the query followed by the 7 columns of the feature in the target
+struct Record<'a> {
+    query_name: &'a str,
+    query_length: &'a str,
+    query_start: i64,
+    query_end: i64,
+    query_strand: &'a str,
+    target_name: &'a str,
+    target_start: i64,
+    target_end: i64,
+    residue_matches: &'a str,
+    alignment_block_length: &'a str,
+    mapping_quality: &'a str,
+    paf_tags: &'a [&'a str],
+    cigar: &'a str,
+    query_name_2: &'a str,
+    feature_in_query_start: i64,
+    feature_in_query_end: i64,
+    feature_in_query_name: &'a str,
+    feature_in_query_strand: &'a str,
+    target_name_2: &'a str,
+    feature_in_target_start: i64,
+    feature_in_target_end: i64,
+    feature_in_target_name: &'a str,
+    feature_in_target_strand: &'a str,
+    feature_in_target_class: &'a str,
+}
+
+fn parse_field(value: &str, name: &str) -> Result<i64, String> {
+    value.parse::<i64>().map_err(|_| format!("invalid {}: '{}'", name, value))
+}
+
+fn parse_record<'a>(parts: &'a [&'a str]) -> Result<Record<'a>, String> {
+    // Ensure there are enough parts to unpack
+    if parts.len() < 27 {
+        return Err(format!("line contains {} fields, at least 27 are expected", parts.len()));
+    }
+    let paf_tags = &parts[12..parts.len() - 14];
+    let feature_parts = &parts[parts.len() - 14..];
+
+    let query_strand = parts[4];
+    if query_strand != "+" && query_strand != "-" {
+        return Err(format!("invalid query strand: '{}'", query_strand));
+    }
+
+    Ok(Record {
+        query_name: parts[0],
+        query_length: parts[1],
+        query_start: parse_field(parts[2], "query start")?,
+        query_end: parse_field(parts[3], "query end")?,
+        query_strand,
+        target_name: parts[5],
+        //target_len: parts[6]
+        target_start: parse_field(parts[7], "target start")?,
+        target_end: parse_field(parts[8], "target end")?,
+        residue_matches: parts[9],
+        alignment_block_length: parts[10],
+        mapping_quality: parts[11],
+        paf_tags,
+        cigar: find_tag(paf_tags, "cg:Z:").unwrap_or(parts[12]),
+        query_name_2: feature_parts[0],
+        feature_in_query_start: parse_field(feature_parts[1], "feature in query start")?,
+        feature_in_query_end: parse_field(feature_parts[2], "feature in query end")?,
+        feature_in_query_name: feature_parts[3],
+        //_
+        feature_in_query_strand: feature_parts[5],
+        //feature_in_query_class: feature_parts[6]
+        target_name_2: feature_parts[7],
+        feature_in_target_start: parse_field(feature_parts[8], "feature in target start")?,
+        feature_in_target_end: parse_field(feature_parts[9], "feature in target end")?,
+        feature_in_target_name: feature_parts[10],
+        //_
+        feature_in_target_strand: feature_parts[12],
+        feature_in_target_class: feature_parts[13],
+    })
+}
 
-    // Initialize counters for the current position within the query and target sequences
+// Returns the value of the first PAF tag with the given prefix (for example `cg:Z:`)
+fn find_tag<'a>(tags: &[&'a str], prefix: &str) -> Option<&'a str> {
+    tags.iter().find_map(|tag| tag.strip_prefix(prefix))
+}
+
+// Returns the parts of the query and target features covered by aligned (`M`/`=`/`X`) operations
+#[allow(clippy::too_many_arguments)]
+fn aligned_blocks_in_features(query_start: i64, query_end: i64, query_strand: char, target_start: i64, cigar: &str, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64) -> (Vec<Interval>, Vec<Interval>) {
+    let mut query_blocks = Vec::new();
+    let mut target_blocks = Vec::new();
+    let query_rev = query_strand == '-';
     let mut query_pos = if query_rev { query_end } else { query_start };
     let mut target_pos = target_start;
 
-    // Iterate over CIGAR operations
-    let cigar_re = Regex::new(r"(\d+)([MIDNSHP=X])").unwrap();
-    for cap in cigar_re.captures_iter(cigar) {
-        let length = cap[1].parse::<i64>().unwrap();
-        let op = &cap[2];
-        match op {
-            "M" | "=" | "X" => {
-                // Handle match/mismatch, which affects both query and target
-                let overlap_query = if query_rev {
-                    std::cmp::max(0, std::cmp::min(query_pos, feature_in_query_end) - std::cmp::max(query_pos - length, feature_in_query_start))
-                } else {
-                    std::cmp::max(0, std::cmp::min(query_pos + length, feature_in_query_end) - std::cmp::max(query_pos, feature_in_query_start))
-                };
-                let overlap_target = std::cmp::max(0, std::cmp::min(target_pos + length, feature_in_target_end) - std::cmp::max(target_pos, feature_in_target_start));
-                aligned_bases += std::cmp::min(overlap_query, overlap_target);
-
-                if query_rev {
-                    query_pos -= length;
-                } else {
-                    query_pos += length;
+    for (length, op) in cigar::ops(cigar) {
+        let (query_length, target_length) = match op {
+            'M' | '=' | 'X' => {
+                let (block_start, block_end) = if query_rev { (query_pos - length, query_pos) } else { (query_pos, query_pos + length) };
+                let (start, end) = (std::cmp::max(block_start, feature_in_query_start), std::cmp::min(block_end, feature_in_query_end));
+                if start < end {
+                    query_blocks.push((start, end));
                 }
-                target_pos += length;
-            },
-            "D" => {
-                // Handle deletion in the query (insertion in the target)
-                let overlap_target = std::cmp::max(0, std::cmp::min(target_pos + length, feature_in_target_end) - std::cmp::max(target_pos, feature_in_target_start));
-                if length <= max_indel_size {
-                    indels_in_target += overlap_target;
-                } else {
-                    not_aligned_bases_in_target += overlap_target;
+                let (start, end) = (std::cmp::max(target_pos, feature_in_target_start), std::cmp::min(target_pos + length, feature_in_target_end));
+                if start < end {
+                    target_blocks.push((start, end));
                 }
-
-                target_pos += length;
+                (length, length)
             },
-            "I" => {
-                // Handle insertion in the query (gap in the target)
-                let overlap_query = if query_rev {
-                    std::cmp::max(0, std::cmp::min(query_pos, feature_in_query_end) - std::cmp::max(query_pos - length, feature_in_query_start))
-                } else {
-                    std::cmp::max(0, std::cmp::min(query_pos + length, feature_in_query_end) - std::cmp::max(query_pos, feature_in_query_start))
-                };
-                if length <= max_indel_size {
-                    indels_in_query += overlap_query;
-                } else {
-                    not_aligned_bases_in_query += overlap_query;
-                }
+            'D' => (0, length),
+            'I' => (length, 0),
+            _ => (0, 0),
+        };
+        query_pos += if query_rev { -query_length } else { query_length };
+        target_pos += target_length;
+    }
+    (query_blocks, target_blocks)
+}
 
-                if query_rev {
-                    query_pos -= length;
-                } else {
-                    query_pos += length;
+// Returns the parts of the query and target features covered by counted indels (not larger than max_indel_size),
+// with the operation (`I` in the query, `D` in the target), in alignment order
+#[allow(clippy::too_many_arguments)]
+fn indels_in_features(query_start: i64, query_end: i64, query_strand: char, target_start: i64, cigar: &str, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64, max_indel_size: i64) -> Vec<(Interval, char)> {
+    let mut indels = Vec::new();
+    let query_rev = query_strand == '-';
+    let mut query_pos = if query_rev { query_end } else { query_start };
+    let mut target_pos = target_start;
+
+    for (length, op) in cigar::ops(cigar) {
+        let (query_length, target_length) = match op {
+            'M' | '=' | 'X' => (length, length),
+            'D' => {
+                let (start, end) = (std::cmp::max(target_pos, feature_in_target_start), std::cmp::min(target_pos + length, feature_in_target_end));
+                if start < end && is_counted_indel(length, max_indel_size) {
+                    indels.push(((start, end), 'D'));
                 }
+                (0, length)
             },
-            _ => {}
-        }
+            'I' => {
+                let (gap_start, gap_end) = if query_rev { (query_pos - length, query_pos) } else { (query_pos, query_pos + length) };
+                let (start, end) = (std::cmp::max(gap_start, feature_in_query_start), std::cmp::min(gap_end, feature_in_query_end));
+                if start < end && is_counted_indel(length, max_indel_size) {
+                    indels.push(((start, end), 'I'));
+                }
+                (length, 0)
+            },
+            _ => (0, 0),
+        };
+        query_pos += if query_rev { -query_length } else { query_length };
+        target_pos += target_length;
+    }
+    indels
+}
 
-        // Check if we have already passed the features in both query and target
-        if (query_rev && query_pos <= feature_in_query_start) || (!query_rev && query_pos >= feature_in_query_end) && target_pos >= feature_in_target_end {
-            break;
+// Per-base depth of a set of intervals over a feature: (max depth, mean depth), the mean being None for empty features
+fn depth_over_feature(intervals: &[Interval], feature_start: i64, feature_end: i64) -> (i64, Option<f64>) {
+    let mut events: Vec<(i64, i64)> = Vec::with_capacity(intervals.len() * 2);
+    let mut covered_bases = 0;
+    for &(start, end) in intervals {
+        events.push((start, 1));
+        events.push((end, -1));
+        covered_bases += end - start;
+    }
+    // Interval ends sort before starts at the same position (half-open intervals)
+    events.sort();
+    let mut depth = 0;
+    let mut max_depth = 0;
+    for (_, delta) in events {
+        depth += delta;
+        max_depth = std::cmp::max(max_depth, depth);
+    }
+    let feature_len = feature_end - feature_start;
+    let mean_depth = if feature_len > 0 { Some(covered_bases as f64 / feature_len as f64) } else { None };
+    (max_depth, mean_depth)
+}
+
+// Number of bases covered by at least one of the intervals
+fn covered_bases(intervals: &[Interval]) -> i64 {
+    let mut sorted = intervals.to_vec();
+    sorted.sort_unstable();
+    let mut covered = 0;
+    let mut covered_end = i64::MIN;
+    for (start, end) in sorted {
+        if end > covered_end {
+            covered += end - std::cmp::max(start, covered_end);
+            covered_end = end;
         }
     }
-    (aligned_bases, not_aligned_bases_in_query, not_aligned_bases_in_target, indels_in_query, indels_in_target, (feature_in_query_end - feature_in_query_start) - aligned_bases - indels_in_query - not_aligned_bases_in_query, (feature_in_target_end - feature_in_target_start) - aligned_bases - indels_in_target - not_aligned_bases_in_target)
+    covered
+}
+
+// Returns the value of the first PAF tag with the given name, whatever its type (for example `NM` for `NM:i:3`)
+fn find_tag_value<'a>(tags: &[&'a str], name: &str) -> Option<&'a str> {
+    tags.iter().find_map(|tag| {
+        let mut fields = tag.splitn(3, ':');
+        if fields.next() == Some(name) {
+            fields.nth(1)
+        } else {
+            None
+        }
+    })
 }
 
 fn open_file(file_path: &str) -> Box<dyn BufRead> {
+    #[cfg(feature = "cloud")]
+    if cloud::is_object_url(file_path) {
+        let reader = cloud::ObjectReader::open(file_path).expect("Failed to open object");
+        return decompressed(reader, file_path);
+    }
+    if let Some(format) = binary_alignment_format(file_path) {
+        eprintln!("ERROR: {}: {} input is not supported, convert it to PAF first, e.g. with `samtools view -h{} {} | paftools.js sam2paf -`", file_path, format, if format == "CRAM" { " --reference REF.fa" } else { "" }, file_path);
+        std::process::exit(1);
+    }
+    decompressed(File::open(file_path).expect("Failed to open file"), file_path)
+}
+
+// Binary alignment format (CRAM or BAM) of a local file, recognized by its magic number, that of BAM within its first
+// BGZF block: the alignments are read as PAF lines, so these files have to be converted first
+fn binary_alignment_format(file_path: &str) -> Option<&'static str> {
+    let mut magic = [0u8; 4];
+    File::open(file_path).and_then(|mut file| file.read_exact(&mut magic)).ok()?;
+    if &magic == b"CRAM" {
+        return Some("CRAM");
+    }
+    let mut decompressed = [0u8; 4];
+    let is_bam = magic[..2] == [0x1f, 0x8b] && File::open(file_path).and_then(|file| GzDecoder::new(file).read_exact(&mut decompressed)).is_ok() && &decompressed == b"BAM\x01";
+    is_bam.then_some("BAM")
+}
+
+// Whether a file is compressed, by its extension: gzip (.gz) or zstd (.zst)
+fn is_compressed(file_path: &str) -> bool {
+    file_path.ends_with(".gz") || file_path.ends_with(".zst")
+}
+
+fn decompressed(reader: impl Read + Send + 'static, file_path: &str) -> Box<dyn BufRead> {
     if file_path.ends_with(".gz") {
-        Box::new(BufReader::new(GzDecoder::new(File::open(file_path).expect("Failed to open file"))))
+        Box::new(BufReader::new(gzip::GzipReader::new(reader, file_path)))
+    } else if file_path.ends_with(".zst") {
+        match zstd::ZstdReader::new(reader, file_path) {
+            Ok(reader) => Box::new(BufReader::new(reader)),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        }
     } else {
-        Box::new(BufReader::new(File::open(file_path).expect("Failed to open file")))
+        Box::new(BufReader::new(reader))
     }
 }
 
-fn main() -> io::Result<()> {
-    let matches = App::new("Alignment Feature Counter")
-        .version("1.0")
-        .author("Andrea Guarracino Name <aguarra1@uthsc.edu>")
-        .about("Counts aligned bases for features in alignment data")
-        .arg(Arg::with_name("input")
-            .short('i')
-            .long("input")
-            .value_name("FILE")
-            .help("Input file, can be gzipped")
-            .takes_value(true))
-        .arg(Arg::with_name("max_indel_size")
-            .short('m')
-            .long("max-indel-size")
-            .value_name("INT")
-            .help("Maximum size of indels to consider in feature intervals")
-            .takes_value(true))
-        .get_matches();
-
-    let input_file = matches.value_of("input").unwrap_or("");
-    let max_indel_size = matches.value_of("max_indel_size")
-        .map(|s| s.parse::<i64>().expect("Invalid value for max indel size"))
-        .unwrap_or(i64::MAX);
-
-    println!("feature.name\tquery\tquery.feature.start\tquery.feature.end\tquery.strand\ttarget\ttarget.feature.start\ttarget.feature.end\taligned.bp\tnot.aligned.in.query.bp\tnot.aligned.in.target.bp\tindels.in.query.bp\tindels.in.target\tignored.in.query.bp\tignored.in.target.bp");
-
-    if !input_file.is_empty() {
-        let file = open_file(input_file);
-        for line in file.lines() {
-            let line = line?;
-            // Assuming `line` is a String obtained from iterating over lines of the file
-            let parts: Vec<&str> = line.split('\t').collect();
-
-            // Ensure there are enough parts to unpack
-            // if parts.len() < 27 {
-            //     eprintln!("ERROR: Line does not contain enough fields.");
-            //     std::process::exit(1);
-            // }
-
-            let query_name = parts[0];
-            //let query_len = parts[1].parse::<i64>().expect("Invalid query len");
-            let query_start = parts[2].parse::<i64>().expect("Invalid query start");
-            let query_end = parts[3].parse::<i64>().expect("Invalid query end");
-            let query_strand = parts[4];
-            let target_name = parts[5];
-            //let target_len = parts[6].parse::<i64>().expect("Invalid target len");
-            let target_start = parts[7].parse::<i64>().expect("Invalid target start");
-            let target_end = parts[8].parse::<i64>().expect("Invalid target end");
-            //_
-            //_
-            //_
-            let cigar = parts[12].split("cg:Z:").last().unwrap_or_default();
-            let query_name_2 = parts[13];
-            let feature_in_query_start = parts[14].parse::<i64>().expect("Invalid feature in query start");
-            let feature_in_query_end = parts[15].parse::<i64>().expect("Invalid feature in query end");
-            let feature_in_query_name = parts[16];
-            //_
-            let feature_in_query_strand = parts[18];
-            //let feature_in_query_class = parts[19];
-            let target_name_2 = parts[20];
-            let feature_in_target_start = parts[21].parse::<i64>().expect("Invalid feature in target start");
-            let feature_in_target_end = parts[22].parse::<i64>().expect("Invalid feature in target start");
-            let feature_in_target_name = parts[23];
-            //_
-            let feature_in_target_strand = parts[25];
-            //let feature_in_target_class = parts[26];
-
-            // Checking for matching names and strands
-            if query_name != query_name_2 || target_name != target_name_2 || feature_in_query_name != feature_in_target_name {
-                eprintln!("WARNING: query, target, and/or feature name do not match! Skip this line: {}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", feature_in_query_name, query_name, feature_in_query_start, feature_in_query_end, query_strand, target_name, feature_in_target_start, feature_in_target_end);
-                continue;
+fn create_file(file_path: &str) -> Box<dyn Write> {
+    if file_path.ends_with(".gz") {
+        Box::new(BufWriter::new(GzEncoder::new(File::create(file_path).expect("Failed to create file"), Compression::default())))
+    } else {
+        Box::new(BufWriter::new(File::create(file_path).expect("Failed to create file")))
+    }
+}
+
+// Returns the header of a report and its 1-based line number, skipping the `#` metadata lines preceding it
+fn read_report_header(lines: &mut impl Iterator<Item = io::Result<String>>) -> io::Result<Option<(usize, String)>> {
+    let mut line_number = 0;
+    for line in lines {
+        let line = line?;
+        line_number += 1;
+        if !line.starts_with('#') {
+            return Ok(Some((line_number, line)));
+        }
+    }
+    Ok(None)
+}
+
+// Reads a two-column (feature name, length) file with the canonical lengths of the features (--reference-lengths)
+fn load_reference_lengths(file_path: &str) -> HashMap<String, i64> {
+    let mut reference_lengths = HashMap::new();
+    for (line_number, line) in open_file(file_path).lines().enumerate() {
+        let line = line.expect("Failed to read reference lengths");
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').collect();
+        match parts.get(1).and_then(|length| length.parse::<i64>().ok()).filter(|length| *length >= 0) {
+            Some(length) => {
+                reference_lengths.insert(parts[0].to_string(), length);
             }
-            if feature_in_query_strand != feature_in_target_strand && query_strand == "+" {
-                // If the features are on different strands, the query should be reversed in order to align them
-                eprintln!("WARNING: the feature is on different strands in query and target, but query and target are in the same orientation! Skip this line:  {}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", feature_in_query_name, query_name, feature_in_query_start, feature_in_query_end, query_strand, target_name, feature_in_target_start, feature_in_target_end);
-                continue;
+            None => {
+                eprintln!("ERROR: {}: line {}: expected a feature name and a length", file_path, line_number + 1);
+                std::process::exit(1);
             }
+        }
+    }
+    reference_lengths
+}
+
+// Length of a feature: its reference length if listed, else the length of its interval
+fn reference_length(reference_lengths: &HashMap<String, i64>, name: &str, start: i64, end: i64) -> i64 {
+    reference_lengths.get(name).copied().unwrap_or(end - start)
+}
 
-            let (aligned_bases, not_aligned_bases_in_query, not_aligned_bases_in_target, indels_in_query, indels_in_target, ignored_bases_in_query, ignored_bases_in_target) = count_aligned_bases(
-                query_start, query_end, query_strand.chars().next().unwrap(), target_start, target_end, cigar, feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end, max_indel_size
-            );
+// Quotes and escapes a string as a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
 
-            println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", feature_in_query_name, query_name, feature_in_query_start, feature_in_query_end, query_strand, target_name, feature_in_target_start, feature_in_target_end, aligned_bases, not_aligned_bases_in_query, not_aligned_bases_in_target, indels_in_query, indels_in_target, ignored_bases_in_query, ignored_bases_in_target);
+fn main() {
+    if let Err(e) = run() {
+        // The reader of the output went away (e.g. `| head`): stop quietly, like other command line tools
+        if e.kind() == io::ErrorKind::BrokenPipe {
+            std::process::exit(0);
         }
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
     }
+}
+
+// Without the zstd feature, .zst input needs the zstd command line tool in PATH
+const INPUT_HELP: &str = match (cfg!(feature = "cloud"), cfg!(feature = "zstd")) {
+    (true, true) => "Input file, can be gzipped (.gz) or zstd-compressed (.zst), or an s3:// or gs:// object URL",
+    (true, false) => "Input file, can be gzipped (.gz) or zstd-compressed (.zst, needs the zstd command line tool), or an s3:// or gs:// object URL",
+    (false, true) => "Input file, can be gzipped (.gz) or zstd-compressed (.zst)",
+    (false, false) => "Input file, can be gzipped (.gz) or zstd-compressed (.zst, needs the zstd command line tool)",
+};
 
-    Ok(())
+// Input option shared by the report and the subcommands reading alignments
+fn input_arg() -> Arg<'static> {
+    Arg::with_name("input")
+        .short('i')
+        .long("input")
+        .value_name("FILE")
+        .help(INPUT_HELP)
+        .takes_value(true)
+}
+
+fn run() -> io::Result<()> {
+    // Without a subcommand, the report options are taken at the top level, as `report` does
+    let app = App::new("Alignment Feature Counter")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author("Andrea Guarracino Name <aguarra1@uthsc.edu>")
+        .about("Counts aligned bases for features in alignment data; without a subcommand, writes the report as `report` does")
+        .args(report::args())
+        .args_conflicts_with_subcommands(true)
+        .subcommand(App::new("report")
+            .about("Reports the aligned, not aligned, indel, and ignored bases of each feature in each alignment (the default)")
+            .args(report::args()))
+        .subcommand(App::new("summary")
+            .about("Reports the counts of each feature summed over its alignments, as --summarize does")
+            .args(report::args())
+            .mut_arg("summarize", |arg| arg.hide(true)))
+        .subcommand(App::new("diff")
+            .about("Compares two reports row by row, matching rows by feature, query, and target, and writes the rows found in only one of them and the columns that differ; exits with status 1 if they differ")
+            .arg(Arg::with_name("first")
+                .value_name("REPORT_A")
+                .help("First report, plain or gzipped")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("second")
+                .value_name("REPORT_B")
+                .help("Second report, plain or gzipped")
+                .required(true)
+                .index(2)))
+        .subcommand(App::new("lint")
+            .about("Profiles a combined input in a single pass, without counting: field counts, tags, CIGARs, strand combinations, coordinate violations, and name mismatches, with the most likely layout misconfiguration")
+            .arg(input_arg()
+                .required(true))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Output format of the profile: table (section, item, count) or json")
+                .possible_values(["table", "json"])
+                .default_value("table")
+                .takes_value(true)))
+        .subcommand(App::new("validate")
+            .about("Checks that every input line parses and has consistent query, target, and feature names and strands, without counting; exits with status 1 if any line is invalid")
+            .arg(input_arg()
+                .required(true))
+            .arg(Arg::with_name("input_layout")
+                .long("input-layout")
+                .value_name("LAYOUT")
+                .help("Layout of the input lines: paf-feature (PAF columns and tags followed by the 14 columns of the query and target features) or paf (plain PAF)")
+                .possible_values(["paf-feature", "paf"])
+                .default_value("paf-feature")
+                .takes_value(true))
+            .arg(Arg::with_name("independent_strands")
+                .long("independent-strands")
+                .help("Do not check the feature strands against the alignment strand, as report --independent-strands")))
+        .subcommand(App::new("merge")
+            .about("Combines report files (plain or gzipped) sharing the same columns into a single report")
+            .args(merge::args()))
+        .subcommand(App::new("annotate")
+            .about("Appends the aligned bases, aligned fraction, and classification (complete, partial, unaligned, or absent from the report) of each feature to the lines of a BED file, keeping all its lines and columns")
+            .arg(Arg::with_name("bed")
+                .long("bed")
+                .value_name("FILE")
+                .help("BED file with the features (chrom, start, end, name, and any other columns), plain or gzipped")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("report")
+                .long("report")
+                .value_name("FILE")
+                .help("Report whose rows are matched to the features by sequence, coordinates, and name, on the query and the target side")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("Annotated BED file, gzipped if it ends with .gz")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("aggregate")
+                .long("aggregate")
+                .value_name("MODE")
+                .help("How the rows of a feature are combined: best (the alignment with the most aligned bases) or unique-coverage (aligned bases of all the alignments, capped at the feature length, exact when they do not overlap)")
+                .possible_values(["best", "unique-coverage"])
+                .default_value("best")
+                .takes_value(true))
+            .arg(Arg::with_name("reference_lengths")
+                .long("reference-lengths")
+                .value_name("FILE")
+                .help("Two-column file (feature name, length) with the canonical length of the features, used instead of the interval length for the aligned fraction and the classification")
+                .takes_value(true))
+            .arg(Arg::with_name("overlap_policy")
+                .long("overlap-policy")
+                .value_name("POLICY")
+                .help("With --aggregate unique-coverage, which overlapping features of the BED file the bases they share are credited to: all of them (count-both), the first one in the file (first), the longest one (longest), or the one whose class (7th column) comes first in --priority-file (priority-file); the aligned bases of the other features are capped at the bases they do not lose")
+                .possible_values(["count-both", "first", "longest", "priority-file"])
+                .default_value("count-both")
+                .takes_value(true))
+            .arg(Arg::with_name("priority_file")
+                .long("priority-file")
+                .value_name("FILE")
+                .help("Feature classes by decreasing priority, one per line, for --overlap-policy priority-file (unlisted classes come last)")
+                .takes_value(true))
+            .arg(Arg::with_name("limit_memory")
+                .long("limit-memory")
+                .value_name("BYTES")
+                .help("Approximate memory budget for the aggregates of the features; beyond it, they are spilled to temporary files sorted by feature, merged, and joined with the BED file on disk")
+                .takes_value(true))
+            .arg(Arg::with_name("temp_dir")
+                .long("temp-dir")
+                .value_name("DIR")
+                .help("Directory for the temporary files written when --limit-memory is exceeded (default: the system temporary directory); they are removed when the annotation ends")
+                .takes_value(true)))
+        .subcommand(App::new("blocks")
+            .about("Collapses runs of adjacent target features (in target order, on the same sequence) whose aligned fraction reaches a threshold into conserved blocks, with their coordinates, number of features, and aligned bases; the features below the threshold are written as break rows between the blocks")
+            .arg(Arg::with_name("report")
+                .long("report")
+                .value_name("FILE")
+                .help("Report whose rows are combined by target feature (target, coordinates, and name)")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("aggregate")
+                .long("aggregate")
+                .value_name("MODE")
+                .help("How the rows of a feature are combined: best (the alignment with the most aligned bases) or unique-coverage (aligned bases of all the alignments, capped at the feature length)")
+                .possible_values(["best", "unique-coverage"])
+                .default_value("best")
+                .takes_value(true))
+            .arg(Arg::with_name("block_threshold")
+                .long("block-threshold")
+                .value_name("FRACTION")
+                .help("Minimum aligned fraction (aligned bases / feature length) of the features of a block [default: 0.9]")
+                .takes_value(true))
+            .arg(Arg::with_name("max_feature_gap")
+                .long("max-feature-gap")
+                .value_name("BP")
+                .help("Maximum distance between the end of a block and the start of the next feature for the feature to extend the block [default: 100000]")
+                .takes_value(true)))
+        .subcommand(App::new("bin")
+            .about("Reports the counts in windows tiling each feature along the target, numbered in feature orientation, and the corresponding query windows obtained by projecting their boundaries through the alignment; the counts of the windows of a feature add up to the counts of the whole feature")
+            .args(binning::args()));
+    let matches = app.get_matches();
+
+    match matches.subcommand() {
+        Some(("report", report_matches)) => report::run(report_matches, report_matches.is_present("summarize")),
+        Some(("summary", summary_matches)) => {
+            for conflicting in ["depth", "strand_coverage", "z_normalize", "mosdepth_summary", "trace_line", "cumulative"] {
+                if summary_matches.is_present(conflicting) {
+                    eprintln!("ERROR: --{} cannot be used with the summary subcommand", conflicting.replace('_', "-"));
+                    std::process::exit(1);
+                }
+            }
+            report::run(summary_matches, true)
+        }
+        Some(("diff", diff_matches)) => diff::run(diff_matches),
+        Some(("lint", lint_matches)) => lint::run(lint_matches),
+        Some(("validate", validate_matches)) => validate::run(validate_matches),
+        Some(("merge", merge_matches)) => merge::run(merge_matches),
+        Some(("annotate", annotate_matches)) => annotate::run(annotate_matches),
+        Some(("blocks", blocks_matches)) => blocks::run(blocks_matches),
+        Some(("bin", bin_matches)) => binning::run(bin_matches),
+        _ => report::run(&matches, matches.is_present("summarize")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spill::TempFile;
+
+    fn gzipped(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn binary_alignment_files_are_recognized() {
+        let temp_dir = std::env::temp_dir();
+        let paf = "q1\t100\t0\t100\t+\tt1\t100\t0\t100\t100\t100\t60\tcg:Z:100M\n";
+        for (content, expected) in [
+            (b"CRAM\x03\x00".to_vec(), Some("CRAM")),
+            (gzipped(b"BAM\x01\x00\x00\x00\x00"), Some("BAM")),
+            (paf.as_bytes().to_vec(), None),
+            (gzipped(paf.as_bytes()), None),
+            (b"CR".to_vec(), None),
+        ] {
+            let file = TempFile::new(&temp_dir);
+            std::fs::write(file.path(), content).unwrap();
+            assert_eq!(binary_alignment_format(file.path().to_str().unwrap()), expected);
+        }
+    }
 }
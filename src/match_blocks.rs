@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::open_file;
+
+// Aligned block of an alignment: query start and end, target start and end
+type MatchBlock = (i64, i64, i64, i64);
+
+// Aligned blocks of the alignments given as a BEDPE file (query, query start, query end, target, target start, target
+// end, alignment id) instead of CIGARs, by alignment id; the blocks of each alignment are kept in target order, which
+// is the alignment order on both strands
+pub struct MatchBlocks {
+    file_path: String,
+    alignments: HashMap<String, Vec<MatchBlock>>,
+}
+
+impl MatchBlocks {
+    pub fn load(file_path: &str) -> MatchBlocks {
+        let mut alignments: HashMap<String, Vec<MatchBlock>> = HashMap::new();
+        for (line_number, line) in open_file(file_path).lines().enumerate() {
+            let line = line.expect("Failed to read match blocks");
+            if line.is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let parts: Vec<&str> = line.split('\t').collect();
+            let number = |index: usize| parts.get(index).and_then(|value| value.parse::<i64>().ok());
+            match (number(1), number(2), number(4), number(5), parts.get(6)) {
+                (Some(query_start), Some(query_end), Some(target_start), Some(target_end), Some(id)) if query_start < query_end && query_end - query_start == target_end - target_start => {
+                    alignments.entry(id.to_string()).or_default().push((query_start, query_end, target_start, target_end));
+                }
+                _ => {
+                    eprintln!("ERROR: {}: line {}: expected query, query start, query end, target, target start, target end, and alignment id, with blocks of the same non-zero length in query and target", file_path, line_number + 1);
+                    std::process::exit(1);
+                }
+            }
+        }
+        for blocks in alignments.values_mut() {
+            blocks.sort_by_key(|&(_, _, target_start, _)| target_start);
+        }
+        MatchBlocks { file_path: file_path.to_string(), alignments }
+    }
+
+    // CIGAR of the alignment with the given id: its blocks as M operations, and the gaps between them (and the ends of
+    // the alignment) as I operations on the query side and D operations on the target side
+    pub fn cigar(&self, id: &str, query_start: i64, query_end: i64, query_strand: &str, target_start: i64, target_end: i64) -> Result<String, String> {
+        let blocks = self.alignments.get(id).ok_or_else(|| format!("no blocks of alignment '{}' in {}", id, self.file_path))?;
+        let query_rev = query_strand == "-";
+        let mut query_pos = if query_rev { query_end } else { query_start };
+        let mut target_pos = target_start;
+        let mut cigar = String::new();
+        let push_gaps = |query_gap: i64, target_gap: i64, cigar: &mut String| {
+            if query_gap > 0 {
+                cigar.push_str(&format!("{}I", query_gap));
+            }
+            if target_gap > 0 {
+                cigar.push_str(&format!("{}D", target_gap));
+            }
+        };
+        for &(block_query_start, block_query_end, block_target_start, block_target_end) in blocks {
+            // On the - strand, the query blocks go from the end of the query range to its start
+            let query_gap = if query_rev { query_pos - block_query_end } else { block_query_start - query_pos };
+            let target_gap = block_target_start - target_pos;
+            if query_gap < 0 || target_gap < 0 || block_query_start < query_start || block_query_end > query_end {
+                return Err(format!("the blocks of alignment '{}' overlap, are not in alignment order for the {} strand, or are outside the alignment", id, query_strand));
+            }
+            push_gaps(query_gap, target_gap, &mut cigar);
+            cigar.push_str(&format!("{}M", block_target_end - block_target_start));
+            query_pos = if query_rev { block_query_start } else { block_query_end };
+            target_pos = block_target_end;
+        }
+        let query_gap = if query_rev { query_pos - query_start } else { query_end - query_pos };
+        let target_gap = target_end - target_pos;
+        if target_gap < 0 {
+            return Err(format!("the blocks of alignment '{}' are outside the alignment", id));
+        }
+        push_gaps(query_gap, target_gap, &mut cigar);
+        Ok(cigar)
+    }
+}
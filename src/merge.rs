@@ -0,0 +1,337 @@
+use clap::{Arg, ArgMatches};
+use std::cmp::Ordering;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use crate::spill::ExternalSorter;
+use crate::summarize::Summarizer;
+use crate::{create_file, open_file};
+
+// Columns used to order rows with --sort, in priority order (those missing from the header are ignored)
+const SORT_COLUMNS: [&str; 7] = ["target", "target.feature.start", "target.feature.end", "query", "query.feature.start", "query.feature.end", "feature.name"];
+
+// Metadata lines (of --with-metadata) that differ between the reports of the chunks of an input, and are not compared
+const CHUNK_METADATA: [&str; 5] = ["command", "date", "input", "skip", "head"];
+
+pub fn args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::with_name("output")
+        .value_name("OUTPUT")
+        .help("Output report file, gzipped if it ends with .gz")
+        .required(true)
+        .index(1),
+        Arg::with_name("inputs")
+        .value_name("INPUT")
+        .help("Report files to merge; their `##` metadata lines, when they have some, have to agree but for the command, date, input, skip, and head")
+        .required(true)
+        .multiple_values(true)
+        .index(2),
+        Arg::with_name("source_column")
+        .long("source-column")
+        .help("Add a source column with the input file each row comes from")
+        .conflicts_with("summarize"),
+        Arg::with_name("sort")
+        .long("sort")
+        .help("Sort rows by target, target feature coordinates, query, query feature coordinates, and feature name"),
+        Arg::with_name("summarize")
+        .long("summarize")
+        .help("Aggregate rows by feature, query, and target, summing the count columns"),
+        Arg::with_name("limit_memory")
+        .long("limit-memory")
+        .value_name("BYTES")
+        .help("Approximate memory budget for --sort and --summarize; beyond it, rows and partial aggregates are spilled to temporary files and merged on disk")
+        .takes_value(true),
+        Arg::with_name("temp_dir")
+        .long("temp-dir")
+        .value_name("DIR")
+        .help("Directory for the temporary files written when --limit-memory is exceeded (default: the system temporary directory); they are removed when the merge ends")
+        .takes_value(true),
+    ]
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Returns the header of a report and its 1-based line number, with the `##name=value` metadata lines preceding it
+// but those of CHUNK_METADATA
+fn read_header(lines: &mut impl Iterator<Item = io::Result<String>>) -> io::Result<Option<(usize, String, Vec<String>)>> {
+    let mut metadata = Vec::new();
+    let mut line_number = 0;
+    for line in lines {
+        let line = line?;
+        line_number += 1;
+        if !line.starts_with('#') {
+            return Ok(Some((line_number, line, metadata)));
+        }
+        let name = line.trim_start_matches('#').split('=').next().unwrap_or("");
+        if line.starts_with("##") && !CHUNK_METADATA.contains(&name) {
+            metadata.push(line);
+        }
+    }
+    Ok(None)
+}
+
+// Reports the metadata lines of the reference report missing from another one, and the other way around
+fn metadata_diff(reference: &[String], metadata: &[String]) -> Option<String> {
+    let missing: Vec<&str> = reference.iter().filter(|line| !metadata.contains(line)).map(|s| s.as_str()).collect();
+    let extra: Vec<&str> = metadata.iter().filter(|line| !reference.contains(line)).map(|s| s.as_str()).collect();
+    (!missing.is_empty() || !extra.is_empty()).then(|| format!("{} instead of {}", if extra.is_empty() { "nothing".to_string() } else { extra.join(", ") }, if missing.is_empty() { "nothing".to_string() } else { missing.join(", ") }))
+}
+
+// Reports the columns that differ between the reference header and the header of another input
+fn header_diff(reference: &[String], header: &[String]) -> String {
+    let missing: Vec<&str> = reference.iter().filter(|column| !header.contains(column)).map(|s| s.as_str()).collect();
+    let extra: Vec<&str> = header.iter().filter(|column| !reference.contains(column)).map(|s| s.as_str()).collect();
+    let mut diff = Vec::new();
+    if !missing.is_empty() {
+        diff.push(format!("missing columns: {}", missing.join(", ")));
+    }
+    if !extra.is_empty() {
+        diff.push(format!("extra columns: {}", extra.join(", ")));
+    }
+    if diff.is_empty() {
+        diff.push("duplicated columns".to_string());
+    }
+    diff.join("; ")
+}
+
+fn compare_fields(a: &str, b: &str) -> Ordering {
+    match (a.parse::<i64>(), b.parse::<i64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+fn row_comparator(header: &[String]) -> impl Fn(&[String], &[String]) -> Ordering + 'static {
+    let indices: Vec<usize> = SORT_COLUMNS.iter()
+        .filter_map(|name| header.iter().position(|column| column == name))
+        .collect();
+    move |a: &[String], b: &[String]| {
+        indices.iter()
+            .map(|&index| compare_fields(&a[index], &b[index]))
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+fn write_row(writer: &mut impl Write, row: &[String]) -> io::Result<()> {
+    writeln!(writer, "{}", row.join("\t"))
+}
+
+pub fn run(matches: &ArgMatches) -> io::Result<()> {
+    let output_file = matches.value_of("output").unwrap();
+    let input_files: Vec<&str> = matches.values_of("inputs").unwrap().collect();
+    let source_column = matches.is_present("source_column");
+    let sort = matches.is_present("sort");
+    let summarize = matches.is_present("summarize");
+    let memory_limit = matches.value_of("limit_memory")
+        .map(|s| s.parse::<usize>().expect("Invalid value for memory limit"))
+        .unwrap_or(usize::MAX);
+    let temp_dir = matches.value_of("temp_dir").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+
+    let mut writer = create_file(output_file);
+
+    // Header of the first input; rows of the other inputs are reordered to match it
+    let mut reference_header: Option<Vec<String>> = None;
+    let mut reference_metadata: Option<Vec<String>> = None;
+    let mut reference_file = "";
+    let mut sorter = None;
+    let mut summarizer: Option<Summarizer> = None;
+
+    for input_file in &input_files {
+        let mut lines = open_file(input_file).lines();
+        let (header_line_number, header, metadata): (usize, Vec<String>, Vec<String>) = match read_header(&mut lines)? {
+            Some((line_number, line, metadata)) => (line_number, line.split('\t').map(|s| s.to_string()).collect(), metadata),
+            None => {
+                eprintln!("WARNING: {} is empty, skipping it", input_file);
+                continue;
+            }
+        };
+
+        let reference = match &reference_header {
+            Some(reference) => reference,
+            None => {
+                let mut output_header = header.clone();
+                if source_column {
+                    output_header.push("source".to_string());
+                }
+                if summarize {
+                    let new_summarizer = Summarizer::new(&header)
+                        .map_err(|e| invalid(format!("{}: {}", input_file, e)))?
+                        .with_memory_limit(memory_limit, &temp_dir);
+                    output_header = new_summarizer.header();
+                    summarizer = Some(new_summarizer);
+                }
+                if sort {
+                    sorter = Some(ExternalSorter::new(row_comparator(&output_header), memory_limit, &temp_dir));
+                }
+                write_row(&mut writer, &output_header)?;
+                reference_file = input_file;
+                reference_header.insert(header.clone())
+            }
+        };
+        // Reports without metadata (not written with --with-metadata) are not compared
+        if !metadata.is_empty() {
+            match &reference_metadata {
+                Some(reference_metadata) => if let Some(diff) = metadata_diff(reference_metadata, &metadata) {
+                    return Err(invalid(format!("the metadata of {} differ from those of {}: {}", input_file, reference_file, diff)));
+                },
+                None => reference_metadata = Some(metadata),
+            }
+        }
+
+        // Position in this input of each column of the reference header
+        let mut column_order = Vec::with_capacity(reference.len());
+        for column in reference {
+            match header.iter().position(|c| c == column) {
+                Some(index) if header.len() == reference.len() => column_order.push(index),
+                _ => return Err(invalid(format!("the header of {} differs from the header of {}: {}", input_file, reference_file, header_diff(reference, &header)))),
+            }
+        }
+
+        for (line_number, line) in lines.enumerate() {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != header.len() {
+                return Err(invalid(format!("{} line {} has {} fields, but the header has {} columns", input_file, header_line_number + line_number + 1, fields.len(), header.len())));
+            }
+
+            if let Some(summarizer) = summarizer.as_mut() {
+                let ordered_fields: Vec<&str> = column_order.iter().map(|&index| fields[index]).collect();
+                match summarizer.add(&ordered_fields) {
+                    Ok(Some(row)) => match sorter.as_mut() {
+                        Some(sorter) => sorter.push(row)?,
+                        None => write_row(&mut writer, &row)?,
+                    },
+                    Ok(None) => {}
+                    Err(e) => return Err(invalid(format!("{} line {}: {}", input_file, header_line_number + line_number + 1, e))),
+                }
+                continue;
+            }
+
+            let mut row: Vec<String> = column_order.iter().map(|&index| fields[index].to_string()).collect();
+            if source_column {
+                row.push(input_file.to_string());
+            }
+            match sorter.as_mut() {
+                Some(sorter) => sorter.push(row)?,
+                None => write_row(&mut writer, &row)?,
+            }
+        }
+    }
+
+    let mut spilled_chunks = 0;
+    if let Some(summarizer) = summarizer {
+        spilled_chunks += summarizer.spilled_chunks();
+        let rows = summarizer.finish()?;
+        match sorter.as_mut() {
+            Some(sorter) => {
+                for row in rows {
+                    sorter.push(row?)?;
+                }
+            }
+            None => {
+                for row in rows {
+                    write_row(&mut writer, &row?)?;
+                }
+            }
+        }
+    }
+    if let Some(sorter) = sorter {
+        spilled_chunks += sorter.spilled_chunks();
+        for row in sorter.finish()? {
+            write_row(&mut writer, &row?)?;
+        }
+    }
+    if spilled_chunks > 0 {
+        eprintln!("INFO: the memory limit was exceeded, {} chunks were spilled to {}", spilled_chunks, temp_dir.display());
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report;
+    use crate::spill::TempFile;
+    use clap::App;
+
+    // Lines of the paf-feature layout: features of 100M alignments and of alignments with indels and mismatches, some
+    // on the - strand, several features sharing each query and target
+    fn paf_feature_lines() -> String {
+        (0..20).map(|index| {
+            let (strand, cigar) = if index % 3 == 0 { ("-", "40=2X8I30=5D20=") } else { ("+", "100M") };
+            let (start, end) = (5 * (index % 4), 50 + 5 * (index % 4));
+            format!("q{0}\t100\t0\t100\t{1}\tt{0}\t100\t0\t100\t100\t100\t60\tcg:Z:{2}\tq{0}\t{3}\t{4}\tf{5}\t0\t{1}\tgene\tt{0}\t{3}\t{4}\tf{5}\t0\t+\tgene\n", index % 7, strand, cigar, start, end, index % 5)
+        }).collect()
+    }
+
+    fn report_of(input: &TempFile, arguments: &[&str]) -> TempFile {
+        let output = TempFile::new(&std::env::temp_dir());
+        let mut all_arguments = vec!["report", "-i", input.path().to_str().unwrap(), "-o", output.path().to_str().unwrap()];
+        all_arguments.extend(arguments);
+        let matches = App::new("report").args(report::args()).get_matches_from(all_arguments);
+        report::run(&matches, matches.is_present("summarize")).unwrap();
+        output
+    }
+
+    fn merge(inputs: &[&TempFile], arguments: &[&str]) -> io::Result<String> {
+        let output = TempFile::new(&std::env::temp_dir());
+        let mut all_arguments = vec!["merge", output.path().to_str().unwrap()];
+        all_arguments.extend(inputs.iter().map(|input| input.path().to_str().unwrap()));
+        all_arguments.extend(arguments);
+        run(&App::new("merge").args(args()).get_matches_from(all_arguments))?;
+        Ok(std::fs::read_to_string(output.path()).unwrap())
+    }
+
+    #[test]
+    fn merged_chunk_reports_equal_the_direct_report() {
+        let input = TempFile::new(&std::env::temp_dir());
+        std::fs::write(input.path(), paf_feature_lines()).unwrap();
+        for (report_arguments, merge_arguments) in [(vec![], vec![]), (vec!["--summarize"], vec!["--summarize"]), (vec![], vec!["--sort"])] {
+            let direct = std::fs::read_to_string(report_of(&input, &report_arguments).path()).unwrap();
+            // The chunks of a report without --summarize are summarized by the merge with it
+            let chunk_arguments: Vec<&str> = report_arguments.iter().filter(|&&argument| argument != "--summarize").copied().chain(["--with-metadata"]).collect();
+            let chunks: Vec<TempFile> = [["--head", "7"], ["--skip", "7"]].iter()
+                .map(|range| report_of(&input, &[chunk_arguments.as_slice(), range].concat()))
+                .collect();
+            let merged = merge(&chunks.iter().collect::<Vec<_>>(), &merge_arguments).unwrap();
+            let expected = if merge_arguments.contains(&"--sort") {
+                let mut lines: Vec<&str> = direct.lines().collect();
+                let compare = row_comparator(&lines[0].split('\t').map(|s| s.to_string()).collect::<Vec<_>>());
+                let split = |line: &str| line.split('\t').map(|s| s.to_string()).collect::<Vec<_>>();
+                lines[1..].sort_by(|a, b| compare(&split(a), &split(b)));
+                lines.iter().map(|line| format!("{}\n", line)).collect()
+            } else {
+                direct
+            };
+            assert!(expected.lines().count() > 5, "{}", expected);
+            assert_eq!(merged, expected, "{:?}", merge_arguments);
+        }
+    }
+
+    #[test]
+    fn mismatched_headers_are_rejected() {
+        let input = TempFile::new(&std::env::temp_dir());
+        std::fs::write(input.path(), paf_feature_lines()).unwrap();
+        let plain = report_of(&input, &[]);
+        let with_mismatches = report_of(&input, &["--mismatches"]);
+        let error = merge(&[&plain, &with_mismatches], &[]).unwrap_err().to_string();
+        assert!(error.starts_with(&format!("the header of {} differs from the header of {}: extra columns: ", with_mismatches.path().display(), plain.path().display())), "{}", error);
+    }
+
+    #[test]
+    fn mismatched_metadata_are_rejected() {
+        let input = TempFile::new(&std::env::temp_dir());
+        std::fs::write(input.path(), paf_feature_lines()).unwrap();
+        let first = report_of(&input, &["--with-metadata", "--head", "7", "--max-indel-size", "5"]);
+        let second = report_of(&input, &["--with-metadata", "--skip", "7", "--max-indel-size", "10"]);
+        let error = merge(&[&first, &second], &[]).unwrap_err().to_string();
+        assert_eq!(error, format!("the metadata of {} differ from those of {}: ##max-indel-size=10 instead of ##max-indel-size=5", second.path().display(), first.path().display()));
+        // Chunks of the same run differ only by their input range
+        let second = report_of(&input, &["--with-metadata", "--skip", "7", "--max-indel-size", "5"]);
+        assert!(merge(&[&first, &second], &[]).is_ok());
+    }
+}
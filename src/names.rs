@@ -0,0 +1,87 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+// Target sequence, start, and end of a feature
+type Locus = (String, i64, i64);
+
+// Unique names and loci of the features sharing a name, in order of appearance
+#[derive(Default)]
+struct NameLoci {
+    indices: HashMap<Locus, usize>,
+    unique_names: Vec<String>,
+}
+
+// Gives the features sharing a name at different target loci distinct names (--uniquify-names): the first locus
+// keeps the name, the following ones get _2, _3, ... in order of appearance
+#[derive(Default)]
+pub struct NameUniquifier {
+    names: HashMap<String, NameLoci>,
+    order: Vec<String>,
+}
+
+impl NameUniquifier {
+    pub fn unique_name(&mut self, name: &str, target_name: &str, start: i64, end: i64) -> &str {
+        if !self.names.contains_key(name) {
+            self.order.push(name.to_string());
+        }
+        let loci = self.names.entry(name.to_string()).or_default();
+        let next_index = loci.unique_names.len();
+        let index = *loci.indices.entry((target_name.to_string(), start, end)).or_insert(next_index);
+        if index == loci.unique_names.len() {
+            loci.unique_names.push(if index == 0 { name.to_string() } else { format!("{}_{}", name, index + 1) });
+        }
+        &loci.unique_names[index]
+    }
+
+    // Names found at more than one locus, and loci renamed
+    pub fn touched(&self) -> (usize, usize) {
+        self.names.values()
+            .filter(|loci| loci.unique_names.len() > 1)
+            .fold((0, 0), |(names, renamed), loci| (names + 1, renamed + loci.unique_names.len() - 1))
+    }
+
+    // Writes the original and unique names of the features whose name is found at more than one locus
+    pub fn write_map(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "original\tuniquified")?;
+        for name in &self.order {
+            let loci = &self.names[name];
+            if loci.unique_names.len() > 1 {
+                for unique_name in &loci.unique_names {
+                    writeln!(writer, "{}\t{}", name, unique_name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Without --uniquify-names, counts the feature names found at more than one target locus, remembering only the first
+// locus of each name
+#[derive(Default)]
+pub struct DuplicateNameDetector {
+    first_loci: HashMap<String, Locus>,
+    duplicated: HashSet<String>,
+    // First name found at a second locus
+    example: Option<String>,
+}
+
+impl DuplicateNameDetector {
+    pub fn add(&mut self, name: &str, target_name: &str, start: i64, end: i64) {
+        match self.first_loci.get(name) {
+            Some((first_target_name, first_start, first_end)) => {
+                if (first_target_name.as_str(), *first_start, *first_end) != (target_name, start, end) && !self.duplicated.contains(name) {
+                    self.duplicated.insert(name.to_string());
+                    self.example.get_or_insert_with(|| name.to_string());
+                }
+            }
+            None => {
+                self.first_loci.insert(name.to_string(), (target_name.to_string(), start, end));
+            }
+        }
+    }
+
+    // Number of names found at more than one locus, and one of them
+    pub fn duplicated(&self) -> (usize, Option<&str>) {
+        (self.duplicated.len(), self.example.as_deref())
+    }
+}
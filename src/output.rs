@@ -0,0 +1,99 @@
+use regex::Regex;
+use std::io::{self, BufWriter, Write};
+
+use crate::{create_file, json_string};
+
+// Columns kept as JSON strings even when they look like numbers (sequence and feature names)
+const NAME_COLUMNS: [&str; 3] = ["feature.name", "query", "target"];
+
+// Converts the TSV lines of a report to JSON Lines, one object per row keyed by the header columns; the metadata lines
+// (starting with #) are left out, NA values become null and numbers are written as JSON numbers
+struct JsonLines {
+    writer: Box<dyn Write>,
+    header: Option<Vec<String>>,
+    // Bytes of the line not terminated yet
+    pending: Vec<u8>,
+    number: Regex,
+}
+
+impl JsonLines {
+    fn new(writer: Box<dyn Write>) -> JsonLines {
+        JsonLines { writer, header: None, pending: Vec::new(), number: Regex::new(r"^-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?$").unwrap() }
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if line.starts_with('#') {
+            return Ok(());
+        }
+        let header = match &self.header {
+            Some(header) => header,
+            None => {
+                self.header = Some(line.split('\t').map(|column| column.to_string()).collect());
+                return Ok(());
+            }
+        };
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != header.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("JSON output: row with {} columns under a header of {}", fields.len(), header.len())));
+        }
+        let members: Vec<String> = header.iter().zip(&fields).map(|(column, value)| {
+            let value = if *value == "NA" {
+                "null".to_string()
+            } else if !NAME_COLUMNS.contains(&column.as_str()) && self.number.is_match(value) {
+                value.to_string()
+            } else {
+                json_string(value)
+            };
+            format!("{}: {}", json_string(column), value)
+        }).collect();
+        writeln!(self.writer, "{{{}}}", members.join(", "))
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.pending.extend_from_slice(buf);
+        let Some(last_newline) = self.pending.iter().rposition(|&b| b == b'\n') else {
+            return Ok(());
+        };
+        let complete: Vec<u8> = self.pending.drain(..=last_newline).collect();
+        let text = String::from_utf8_lossy(&complete);
+        for line in text.lines() {
+            self.write_line(line)?;
+        }
+        Ok(())
+    }
+}
+
+// Destination of the report: the standard output or the --output file, and with --json-output the same rows also as
+// JSON Lines, both written in a single pass over the input
+pub struct ReportOutput {
+    tsv: Box<dyn Write>,
+    json: Option<JsonLines>,
+}
+
+impl ReportOutput {
+    pub fn new(output_file: Option<&str>, json_file: Option<&str>) -> ReportOutput {
+        let tsv = match output_file {
+            Some(file_path) => create_file(file_path),
+            None => Box::new(BufWriter::new(io::stdout())),
+        };
+        ReportOutput { tsv, json: json_file.map(|file_path| JsonLines::new(create_file(file_path))) }
+    }
+}
+
+impl Write for ReportOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tsv.write_all(buf)?;
+        if let Some(json) = self.json.as_mut() {
+            json.write(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tsv.flush()?;
+        if let Some(json) = self.json.as_mut() {
+            json.writer.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+use crate::{is_compressed, open_file};
+
+// Whether a file is BGZF (blocked gzip, as written by bgzip): its first gzip member has the BC extra subfield
+fn is_bgzf(file_path: &str) -> bool {
+    let mut header = [0u8; 16];
+    File::open(file_path).and_then(|mut file| file.read_exact(&mut header)).is_ok()
+        && header[..4] == [0x1f, 0x8b, 0x08, 0x04] && &header[12..14] == b"BC"
+}
+
+// Reader of a BGZF file that can seek to the virtual offset of a byte (offset of its block in the file << 16, plus its
+// offset in the decompressed block)
+struct BgzfReader {
+    file: BufReader<File>,
+    file_path: String,
+    block_offset: u64,
+    block_size: u64,
+    block: Vec<u8>,
+    position: usize,
+}
+
+impl BgzfReader {
+    fn new(file_path: &str) -> io::Result<BgzfReader> {
+        Ok(BgzfReader { file: BufReader::new(File::open(file_path)?), file_path: file_path.to_string(), block_offset: 0, block_size: 0, block: Vec::new(), position: 0 })
+    }
+
+    fn invalid(&self, message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}: invalid BGZF block at offset {}: {}", self.file_path, self.block_offset, message))
+    }
+
+    // Reads and decompresses the block following the current one, returning false at the end of the file
+    fn load_block(&mut self) -> io::Result<bool> {
+        self.block_offset += self.block_size;
+        self.block_size = 0;
+        self.block.clear();
+        self.position = 0;
+        let mut member = vec![0u8; 12];
+        match self.file.read_exact(&mut member) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        if member[..4] != [0x1f, 0x8b, 0x08, 0x04] {
+            return Err(self.invalid("not a gzip member with extra fields"));
+        }
+        let extra_length = u16::from_le_bytes([member[10], member[11]]) as usize;
+        member.resize(12 + extra_length, 0);
+        self.file.read_exact(&mut member[12..])?;
+        // The BC subfield holds the size of the whole block minus 1
+        let mut subfields = &member[12..];
+        let mut block_size = None;
+        while subfields.len() >= 4 {
+            let length = u16::from_le_bytes([subfields[2], subfields[3]]) as usize;
+            if &subfields[..2] == b"BC" && length == 2 && subfields.len() >= 6 {
+                block_size = Some(u16::from_le_bytes([subfields[4], subfields[5]]) as usize + 1);
+            }
+            subfields = &subfields[std::cmp::min(subfields.len(), 4 + length)..];
+        }
+        let block_size = match block_size {
+            Some(block_size) if block_size >= member.len() => block_size,
+            _ => return Err(self.invalid("no BC subfield with the block size")),
+        };
+        let header_length = member.len();
+        member.resize(block_size, 0);
+        self.file.read_exact(&mut member[header_length..])?;
+        GzDecoder::new(&member[..]).read_to_end(&mut self.block).map_err(|e| self.invalid(&e.to_string()))?;
+        self.block_size = block_size as u64;
+        Ok(true)
+    }
+
+    fn virtual_offset(&self) -> u64 {
+        if self.position < self.block.len() {
+            (self.block_offset << 16) | self.position as u64
+        } else {
+            (self.block_offset + self.block_size) << 16
+        }
+    }
+
+    fn seek(&mut self, virtual_offset: u64) -> io::Result<()> {
+        let (block_offset, position) = (virtual_offset >> 16, (virtual_offset & 0xffff) as usize);
+        self.file.seek(SeekFrom::Start(block_offset))?;
+        self.block_offset = block_offset;
+        self.block_size = 0;
+        self.load_block()?;
+        self.position = std::cmp::min(position, self.block.len());
+        Ok(())
+    }
+}
+
+impl Read for BgzfReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = std::cmp::min(available.len(), buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for BgzfReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Blocks without data (like the end-of-file marker) are skipped
+        while self.position == self.block.len() {
+            if !self.load_block()? {
+                break;
+            }
+        }
+        Ok(&self.block[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position += amount;
+    }
+}
+
+// How the lines of the PAF file are reached again: by seeking in plain and BGZF files, by reading the other compressed files
+// again from the start
+enum PafSource {
+    Plain(BufReader<File>),
+    Bgzf(BgzfReader),
+    Compressed(Box<dyn BufRead>),
+}
+
+// Line reference of a --pairs line that cannot be resolved
+#[derive(Clone, Copy)]
+enum ReferenceError {
+    // Not a positive line number
+    Invalid,
+    // Past the last line of the PAF file
+    PastEnd,
+}
+
+// Lines of the --paf file fetched by line number for the --pairs lines: the positions of the lines are indexed while
+// the file is read, so pairs sorted by line number read it once, while the other pairs seek back to the lines indexed
+pub struct PafLines {
+    file_path: String,
+    source: PafSource,
+    // Position of the next byte of the plain and compressed sources (the BGZF reader tracks its own)
+    position: u64,
+    // Positions of the starts of the lines read so far (line N at index N - 1)
+    line_starts: Vec<u64>,
+    // Line number of the line the source is positioned at
+    next_line: u64,
+    // Number of lines of the file, once its end is reached
+    total_lines: Option<u64>,
+    invalid_references: u64,
+    past_end_references: u64,
+    // Lines fetched before the last line read (each a seek, or a new read of a compressed file)
+    backward_fetches: u64,
+    // Last line read, fetched again without reading when several pairs refer to it
+    last_line: Option<(u64, String)>,
+}
+
+impl PafLines {
+    pub fn open(file_path: &str) -> io::Result<PafLines> {
+        let source = if !is_compressed(file_path) {
+            PafSource::Plain(BufReader::new(File::open(file_path)?))
+        } else if is_bgzf(file_path) {
+            PafSource::Bgzf(BgzfReader::new(file_path)?)
+        } else {
+            PafSource::Compressed(open_file(file_path))
+        };
+        Ok(PafLines { file_path: file_path.to_string(), source, position: 0, line_starts: Vec::new(), next_line: 1, total_lines: None, invalid_references: 0, past_end_references: 0, backward_fetches: 0, last_line: None })
+    }
+
+    fn current_position(&self) -> u64 {
+        match &self.source {
+            PafSource::Bgzf(reader) => reader.virtual_offset(),
+            _ => self.position,
+        }
+    }
+
+    fn seek(&mut self, line_number: u64) -> io::Result<()> {
+        let position = self.line_starts[line_number as usize - 1];
+        match &mut self.source {
+            PafSource::Plain(reader) => {
+                reader.seek(SeekFrom::Start(position))?;
+            }
+            PafSource::Bgzf(reader) => reader.seek(position)?,
+            PafSource::Compressed(reader) => {
+                if line_number < self.next_line {
+                    if self.backward_fetches == 1 {
+                        eprintln!("WARNING: --pairs: the pairs are not sorted by line number, and {} is compressed but not BGZF: it is read again from the start for each earlier line; sort the pairs or compress the PAF with bgzip", self.file_path);
+                    }
+                    *reader = open_file(&self.file_path);
+                    self.position = 0;
+                }
+                let skipped = io::copy(&mut reader.by_ref().take(position - self.position), &mut io::sink())?;
+                self.position += skipped;
+            }
+        }
+        if let PafSource::Plain(_) = self.source {
+            self.position = position;
+        }
+        self.next_line = line_number;
+        Ok(())
+    }
+
+    // PAF line with the given line number, as given in the first column of a --pairs line
+    fn line(&mut self, reference: &str) -> io::Result<Result<String, (ReferenceError, String)>> {
+        let line_number = match reference.parse::<u64>() {
+            Ok(line_number) if line_number > 0 => line_number,
+            _ => return Ok(Err((ReferenceError::Invalid, format!("invalid PAF line number '{}'", reference)))),
+        };
+        let past_end = |file_path: &str, total_lines: u64| Err((ReferenceError::PastEnd, format!("PAF line {} is past the end of {} ({} lines)", line_number, file_path, total_lines)));
+        if let Some(total_lines) = self.total_lines.filter(|total_lines| line_number > *total_lines) {
+            return Ok(past_end(&self.file_path, total_lines));
+        }
+        if let Some((_, line)) = self.last_line.as_ref().filter(|(number, _)| *number == line_number) {
+            return Ok(Ok(line.clone()));
+        }
+        // Going back, or forward to a line already indexed (after going back): seek to the closest indexed line
+        let indexed = self.line_starts.len() as u64;
+        let seek_line = std::cmp::min(line_number, indexed);
+        if line_number < self.next_line || self.next_line < seek_line {
+            if line_number < self.next_line {
+                self.backward_fetches += 1;
+            }
+            self.seek(seek_line)?;
+        }
+        let mut line = String::new();
+        while self.next_line <= line_number {
+            let start = self.current_position();
+            line.clear();
+            let read = match &mut self.source {
+                PafSource::Plain(reader) => reader.read_line(&mut line)?,
+                PafSource::Bgzf(reader) => reader.read_line(&mut line)?,
+                PafSource::Compressed(reader) => reader.read_line(&mut line)?,
+            };
+            if read == 0 {
+                self.total_lines = Some(self.next_line - 1);
+                return Ok(past_end(&self.file_path, self.next_line - 1));
+            }
+            self.position += read as u64;
+            if self.next_line > self.line_starts.len() as u64 {
+                self.line_starts.push(start);
+            }
+            self.next_line += 1;
+        }
+        let length = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(length);
+        self.last_line = Some((line_number, line.clone()));
+        Ok(Ok(line))
+    }
+
+    // Line in the PAF + feature layout of a --pairs line (PAF line number, then the 7 columns of the query feature and
+    // the 7 of the target feature): the PAF line it refers to followed by the feature columns; the unresolved
+    // references are counted by category
+    pub fn join(&mut self, pairs_line: &str) -> io::Result<Result<String, String>> {
+        let (reference, feature_columns) = pairs_line.split_once('\t').unwrap_or((pairs_line, ""));
+        Ok(match self.line(reference)? {
+            Ok(paf_line) => Ok(format!("{}\t{}", paf_line, feature_columns)),
+            Err((category, reason)) => {
+                match category {
+                    ReferenceError::Invalid => self.invalid_references += 1,
+                    ReferenceError::PastEnd => self.past_end_references += 1,
+                }
+                Err(reason)
+            }
+        })
+    }
+
+    // Writes the unresolved references by category, and how many lines were fetched out of order
+    pub fn report(&self) {
+        eprintln!("INFO: --pairs: {} invalid PAF line numbers, {} PAF line numbers past the end of {}, {} PAF lines fetched before the previous one", self.invalid_references, self.past_end_references, self.file_path, self.backward_fetches);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression, GzBuilder};
+    use std::io::Write;
+
+    // BGZF file of the given blocks (and the empty end-of-file block): each is a gzip member whose BC extra subfield
+    // holds its size minus 1
+    fn bgzf(blocks: &[&str]) -> Vec<u8> {
+        let mut file = Vec::new();
+        for data in blocks.iter().chain(&[""]) {
+            let mut encoder = GzBuilder::new().extra(vec![b'B', b'C', 2, 0, 0, 0]).write(Vec::new(), Compression::default());
+            encoder.write_all(data.as_bytes()).unwrap();
+            let mut block = encoder.finish().unwrap();
+            let size = (block.len() - 1) as u16;
+            block[16..18].copy_from_slice(&size.to_le_bytes());
+            file.extend(block);
+        }
+        file
+    }
+
+    fn write_file(name: &str, content: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("feature_level_report.{}.{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn bgzf_lines_are_fetched_again_by_seeking() {
+        // Line 3 starts in the first block and ends in the second one
+        let file_path = write_file("pairs.paf.gz", &bgzf(&["line1\nline2\nli", "ne3\nline4\n", "line5\n"]));
+        assert!(is_bgzf(&file_path));
+        let mut paf_lines = PafLines::open(&file_path).unwrap();
+        assert!(matches!(paf_lines.source, PafSource::Bgzf(_)));
+        for line_number in [4, 2, 3, 5, 1, 3] {
+            assert_eq!(paf_lines.line(&line_number.to_string()).unwrap().ok(), Some(format!("line{}", line_number)));
+        }
+        // Lines 2 and 1 are before the line read last, line 3 after them is reached by seeking forward
+        assert_eq!(paf_lines.backward_fetches, 2);
+        assert!(paf_lines.line("6").unwrap().is_err());
+        let _ = std::fs::remove_file(&file_path);
+    }
+
+    #[test]
+    fn gzip_lines_are_fetched_again_by_reading_from_the_start() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"line1\nline2\nline3\n").unwrap();
+        let file_path = write_file("pairs.gzip.paf.gz", &encoder.finish().unwrap());
+        assert!(!is_bgzf(&file_path));
+        let mut paf_lines = PafLines::open(&file_path).unwrap();
+        for line_number in [3, 1, 2] {
+            assert_eq!(paf_lines.line(&line_number.to_string()).unwrap().ok(), Some(format!("line{}", line_number)));
+        }
+        let _ = std::fs::remove_file(&file_path);
+    }
+}
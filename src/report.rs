@@ -0,0 +1,1376 @@
+use clap::ArgMatches;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+
+use crate::estimate::Estimate;
+use crate::intern::{Interner, Symbol};
+use crate::output::ReportOutput;
+use crate::spill::TempFile;
+use crate::summarize::{checked_sum, Summarizer};
+use crate::{cigar, count_ops_by_threshold, covered_bases, create_file, depth_over_feature, expr, fasta, features, is_compressed, names, open_file, pairs, FeatureCounts, Interval};
+
+mod options;
+mod row;
+mod run_summary;
+
+pub use options::args;
+use options::{select_output_columns, write_column_order, LineRange, ReportOptions, SINGLE_INPUT_OPTIONS};
+use row::{check_nm, count_record, parse_line_record, CountedRow, RecordError};
+use run_summary::{report_errors, skip_invalid_line, RunSummary};
+
+// Phases of a run timed by --profile
+#[derive(Clone, Copy)]
+enum Phase {
+    // Reading (and decompressing) the input lines
+    Reading,
+    // Joining the lines with their features and parsing their fields
+    Parsing,
+    CigarWalk,
+    // Writing (or summarizing) the rows
+    Writing,
+}
+
+const PHASE_NAMES: [&str; 4] = ["reading", "parsing", "CIGAR walk", "writing"];
+
+// Wall time spent in each phase of the run, measured with a timer around each phase of each line (--profile)
+#[derive(Default)]
+struct Profile {
+    enabled: bool,
+    phases: [std::time::Duration; 4],
+}
+
+impl Profile {
+    // Start of a phase, None without --profile
+    fn start(&self) -> Option<std::time::Instant> {
+        self.enabled.then(std::time::Instant::now)
+    }
+
+    fn add(&mut self, phase: Phase, start: Option<std::time::Instant>) {
+        if let Some(start) = start {
+            self.phases[phase as usize] += start.elapsed();
+        }
+    }
+
+    // Writes the time of each phase and its share of the total, the rest of the run (setup, filters, other
+    // columns) being reported as other
+    fn report(&self, source: &str, total: std::time::Duration) {
+        let share = |duration: std::time::Duration| if total.is_zero() { 0.0 } else { 100.0 * duration.as_secs_f64() / total.as_secs_f64() };
+        let mut phases: Vec<String> = PHASE_NAMES.iter().zip(&self.phases)
+            .map(|(name, duration)| format!("{} {:.3} s ({:.1}%)", name, duration.as_secs_f64(), share(*duration)))
+            .collect();
+        let other = total.saturating_sub(self.phases.iter().sum());
+        phases.push(format!("other {:.3} s ({:.1}%)", other.as_secs_f64(), share(other)));
+        eprintln!("INFO: {}profile: {}, total {:.3} s", source, phases.join(", "), total.as_secs_f64());
+    }
+}
+
+fn row_matches(filter: &expr::Expr, row: &[String]) -> bool {
+    filter.eval(&|index| expr::Value::parse(&row[index]))
+}
+
+// Outcome of the NM:i check of the alignments (--check-nm)
+#[derive(Default)]
+struct NmCheck {
+    checked: u64,
+    without_nm: u64,
+    below_cigar: u64,
+    above_cigar: u64,
+}
+
+// Running sum of the aligned bases down an input sorted by target and target feature start (--cumulative), reset at
+// each target with per_target
+struct RunningSum {
+    per_target: bool,
+    sum: i64,
+    // Target and feature start of the last row, and the targets before it
+    last: Option<(String, i64)>,
+    finished_targets: HashSet<String>,
+}
+
+impl RunningSum {
+    fn new(per_target: bool) -> RunningSum {
+        RunningSum { per_target, sum: 0, last: None, finished_targets: HashSet::new() }
+    }
+
+    // Adds the aligned bases of a row, returning the running sum, or why the input is not sorted
+    fn add(&mut self, target: &str, start: i64, aligned_bases: i64) -> Result<i64, String> {
+        match &mut self.last {
+            Some((last_target, last_start)) if last_target == target => {
+                if start < *last_start {
+                    return Err(format!("target feature start {} after {} on {}, so the input is not sorted by target feature start", start, last_start, target));
+                }
+                *last_start = start;
+            }
+            last => {
+                if self.finished_targets.contains(target) {
+                    return Err(format!("the rows of target {} are not adjacent, so the input is not sorted by target", target));
+                }
+                if let Some((last_target, _)) = last.replace((target.to_string(), start)) {
+                    self.finished_targets.insert(last_target);
+                    if self.per_target {
+                        self.sum = 0;
+                    }
+                }
+            }
+        }
+        self.sum = self.sum.checked_add(aligned_bases).ok_or_else(|| "the cumulative aligned bases overflow".to_string())?;
+        Ok(self.sum)
+    }
+}
+
+// Columns of the --trace-line output
+const TRACE_HEADER: [&str; 18] = ["feature.name", "op.index", "op", "length", "query.pos.before", "query.pos.after", "target.pos.before", "target.pos.after", "aligned.bp", "not.aligned.in.query.bp", "not.aligned.in.target.bp", "indels.in.query.bp", "indels.in.target", "edge.adjustment.in.query.bp", "edge.adjustment.in.target.bp", "mismatches.bp", "aligned.blocks", "longest.aligned.block"];
+
+// Step of the CIGAR walk shown by --trace-line and --explain: an operation, the positions before and after it, and
+// its contributions to the counts
+struct WalkStep {
+    index: usize,
+    length: i64,
+    op: char,
+    query_before: i64,
+    query_after: i64,
+    target_before: i64,
+    target_after: i64,
+    counts: FeatureCounts,
+}
+
+// Steps of the CIGAR walk, the contributions of each operation being obtained by counting it alone from the position
+// where the walk reaches it
+#[allow(clippy::too_many_arguments)]
+fn walk_steps(query_start: i64, query_end: i64, query_strand: char, target_start: i64, target_end: i64, cigar: &str, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64, max_indel_size: i64) -> impl Iterator<Item = WalkStep> + '_ {
+    let query_rev = query_strand == '-';
+    let mut query_pos = if query_rev { query_end } else { query_start };
+    let mut target_pos = target_start;
+    cigar::ops(cigar).enumerate().map(move |(index, (length, op))| {
+        let (query_length, target_length) = match op {
+            'M' | '=' | 'X' => (length, length),
+            'D' => (0, length),
+            'I' => (length, 0),
+            _ => (0, 0),
+        };
+        let (query_after, target_after) = (if query_rev { query_pos - query_length } else { query_pos + query_length }, target_pos + target_length);
+        let (op_query_start, op_query_end) = if query_rev { (query_after, query_pos) } else { (query_pos, query_after) };
+        let counts = count_ops_by_threshold(op_query_start, op_query_end, query_strand, target_pos, target_end, std::iter::once((length, op)), feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end, &[max_indel_size], 0).swap_remove(0);
+        let step = WalkStep { index, length, op, query_before: query_pos, query_after, target_before: target_pos, target_after, counts };
+        query_pos = query_after;
+        target_pos = target_after;
+        step
+    })
+}
+
+// Writes one row per CIGAR operation with the positions before and after it and its contributions to the counts
+#[allow(clippy::too_many_arguments)]
+fn write_trace(writer: &mut impl Write, feature_name: &str, query_start: i64, query_end: i64, query_strand: char, target_start: i64, target_end: i64, cigar: &str, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64, max_indel_size: i64) -> io::Result<()> {
+    for step in walk_steps(query_start, query_end, query_strand, target_start, target_end, cigar, feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end, max_indel_size) {
+        let counts = &step.counts;
+        write_fields(writer, &[
+            feature_name.to_string(), step.index.to_string(), step.op.to_string(), step.length.to_string(),
+            step.query_before.to_string(), step.query_after.to_string(), step.target_before.to_string(), step.target_after.to_string(),
+            counts.aligned_bases.to_string(), counts.not_aligned_bases_in_query.to_string(), counts.not_aligned_bases_in_target.to_string(),
+            counts.indels_in_query.to_string(), counts.indels_in_target.to_string(),
+            counts.edge_adjustment_in_query.to_string(), counts.edge_adjustment_in_target.to_string(),
+            counts.mismatches.to_string(), counts.aligned_blocks.to_string(), counts.largest_aligned_block.to_string(),
+        ], &None)?;
+    }
+    Ok(())
+}
+
+// Writes the steps of the CIGAR walk of a line for --explain: for each operation, the positions before and after it,
+// its overlaps with the query and target features, and the counters it increments (as in the rows of --trace-line)
+#[allow(clippy::too_many_arguments)]
+fn write_explanation(writer: &mut impl Write, line_number: u64, feature_name: &str, query_name: &str, query_start: i64, query_end: i64, query_strand: char, target_name: &str, target_start: i64, target_end: i64, cigar: &str, feature_in_query_start: i64, feature_in_query_end: i64, feature_in_target_start: i64, feature_in_target_end: i64, max_indel_size: i64) -> io::Result<()> {
+    writeln!(writer, "line {}: feature {}, query feature {}:{}-{}, target feature {}:{}-{}", line_number, feature_name, query_name, feature_in_query_start, feature_in_query_end, target_name, feature_in_target_start, feature_in_target_end)?;
+    writeln!(writer, "  alignment {}:{}-{} {} {}:{}-{}, max indel size {}", query_name, query_start, query_end, query_strand, target_name, target_start, target_end, max_indel_size)?;
+    for step in walk_steps(query_start, query_end, query_strand, target_start, target_end, cigar, feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end, max_indel_size) {
+        let counts = &step.counts;
+        // Every feature base overlapped by the operation goes to exactly one of these counters
+        let overlap_query = counts.aligned_bases + counts.edge_adjustment_in_query + counts.not_aligned_bases_in_query + counts.indels_in_query;
+        let overlap_target = counts.aligned_bases + counts.edge_adjustment_in_target + counts.not_aligned_bases_in_target + counts.indels_in_target;
+        let increments: Vec<String> = [
+            ("aligned.bp", counts.aligned_bases),
+            ("mismatches.bp", counts.mismatches),
+            ("not.aligned.in.query.bp", counts.not_aligned_bases_in_query),
+            ("not.aligned.in.target.bp", counts.not_aligned_bases_in_target),
+            ("indels.in.query.bp", counts.indels_in_query),
+            ("indels.in.target", counts.indels_in_target),
+            ("edge.adjustment.in.query.bp", counts.edge_adjustment_in_query),
+            ("edge.adjustment.in.target.bp", counts.edge_adjustment_in_target),
+        ].iter().filter(|(_, value)| *value > 0).map(|(name, value)| format!("{} +{}", name, value)).collect();
+        writeln!(writer, "  op {} {}{}: query {} -> {}, target {} -> {}, overlap query feature {}, target feature {}: {}",
+            step.index, step.length, step.op, step.query_before, step.query_after, step.target_before, step.target_after, overlap_query, overlap_target,
+            if increments.is_empty() { "no counter".to_string() } else { increments.join(", ") })?;
+    }
+    Ok(())
+}
+
+// Ends an --explain trace with the row (as columns and values) and the check that, on each side, the feature bases
+// are all aligned, not aligned, in indels, or ignored (and edge adjustment, with --edge-adjustment); the columns
+// depending on the indel size threshold are those with the given suffix (that of the first threshold)
+fn write_explanation_row(writer: &mut impl Write, header: &[String], row: &[String], threshold_suffix: &str) -> io::Result<()> {
+    let fields: Vec<String> = header.iter().zip(row).map(|(name, value)| format!("{}={}", name, value)).collect();
+    writeln!(writer, "  row: {}", fields.join(" "))?;
+    let value = |name: String| header.iter().position(|column| *column == name).and_then(|index| row[index].parse::<i64>().ok());
+    for (side, indels) in [("query", "indels.in.query.bp"), ("target", "indels.in.target")] {
+        let length = value(format!("{}.feature.end", side)).unwrap_or(0) - value(format!("{}.feature.start", side)).unwrap_or(0);
+        let aligned = value("aligned.bp".to_string()).unwrap_or(0);
+        let not_aligned = value(format!("not.aligned.in.{}.bp{}", side, threshold_suffix)).unwrap_or(0);
+        let indels = value(format!("{}{}", indels, threshold_suffix)).unwrap_or(0);
+        let ignored = value(format!("ignored.in.{}.bp{}", side, threshold_suffix)).unwrap_or(0);
+        let edge_adjustment = value(format!("edge.adjustment.in.{}.bp", side));
+        let total = aligned + not_aligned + indels + ignored + edge_adjustment.unwrap_or(0);
+        writeln!(writer, "  invariant {}: feature length {} = aligned {} + not aligned {} + indels {} + ignored {}{}: {}",
+            side, length, aligned, not_aligned, indels, ignored,
+            edge_adjustment.map(|edge_adjustment| format!(" + edge adjustment {}", edge_adjustment)).unwrap_or_default(),
+            if total == length && ignored >= 0 { "ok" } else { "VIOLATED" })?;
+    }
+    Ok(())
+}
+
+// Bytes of an oversized line kept for the warning
+const OVERSIZED_LINE_START: usize = 60;
+
+// Line longer than the --max-line-length limit: its length and first bytes
+struct OversizedLine {
+    length: usize,
+    start: String,
+}
+
+// Set by SIGTERM with --follow, to stop reading the input and finish the run
+static STOP_FOLLOWING: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn stop_following(_signal: libc::c_int) {
+    STOP_FOLLOWING.store(true, AtomicOrdering::SeqCst);
+}
+
+#[cfg(unix)]
+fn handle_sigterm() {
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGTERM, stop_following as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+fn handle_sigterm() {}
+
+// How --follow waits at the end of the input for more lines
+struct Follow {
+    // No new bytes for this long ends the input
+    idle_limit: Option<std::time::Duration>,
+}
+
+// Interval between the reads of a followed input at its end
+const FOLLOW_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+// Lines of a reader, like BufRead::lines(), but without buffering more than max_length bytes of a line. With follow,
+// the end of the input is waited past until it stays idle or SIGTERM is received, a partial last line being kept
+// until its newline arrives
+struct BoundedLines {
+    reader: Box<dyn BufRead>,
+    max_length: usize,
+    follow: Option<Follow>,
+}
+
+impl Iterator for BoundedLines {
+    type Item = io::Result<Result<String, OversizedLine>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line: Vec<u8> = Vec::new();
+        let mut length = 0;
+        let mut last_byte = None;
+        let mut idle_since: Option<std::time::Instant> = None;
+        loop {
+            if self.follow.is_some() && STOP_FOLLOWING.load(AtomicOrdering::SeqCst) && length == 0 {
+                return None;
+            }
+            let buffer = match self.reader.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e)),
+            };
+            if buffer.is_empty() {
+                if let Some(follow) = &self.follow {
+                    let idle_start = *idle_since.get_or_insert_with(std::time::Instant::now);
+                    let idle = follow.idle_limit.is_some_and(|limit| idle_start.elapsed() >= limit);
+                    if !idle && !STOP_FOLLOWING.load(AtomicOrdering::SeqCst) {
+                        std::thread::sleep(FOLLOW_POLL_INTERVAL);
+                        continue;
+                    }
+                    if length > 0 {
+                        eprintln!("WARNING: --follow: the last line of the input has no newline, {} bytes dropped", length);
+                    }
+                    return None;
+                }
+                if length == 0 {
+                    return None;
+                }
+                break;
+            }
+            idle_since = None;
+            let (chunk, consumed, complete) = match buffer.iter().position(|&b| b == b'\n') {
+                Some(newline) => (&buffer[..newline], newline + 1, true),
+                None => (buffer, buffer.len(), false),
+            };
+            // Bytes beyond the limit are counted and dropped
+            let kept = std::cmp::min(chunk.len(), self.max_length.saturating_sub(line.len()));
+            last_byte = chunk.last().copied().or(last_byte);
+            line.extend_from_slice(&chunk[..kept]);
+            length += consumed;
+            self.reader.consume(consumed);
+            if complete {
+                length -= 1;
+                break;
+            }
+        }
+        // Like BufRead::lines(), CRLF line endings are removed
+        if last_byte == Some(b'\r') {
+            length -= 1;
+            if line.len() > length {
+                line.pop();
+            }
+        }
+        if length > self.max_length {
+            line.truncate(OVERSIZED_LINE_START);
+            return Some(Ok(Err(OversizedLine { length, start: String::from_utf8_lossy(&line).into_owned() })));
+        }
+        Some(String::from_utf8(line).map(Ok).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")))
+    }
+}
+
+// Formats seconds since the Unix epoch as an ISO 8601 UTC timestamp
+fn format_iso8601(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+    // Civil date from days since 1970-01-01 (proleptic Gregorian calendar)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs_of_day / 3600, secs_of_day % 3600 / 60, secs_of_day % 60)
+}
+
+// Returns all fields, or only the selected ones in the selection order
+fn project_fields<'a, T: AsRef<str>>(fields: &'a [T], column_selection: &Option<Vec<usize>>) -> Vec<&'a str> {
+    match column_selection {
+        Some(indices) => indices.iter().map(|&index| fields[index].as_ref()).collect(),
+        None => fields.iter().map(|field| field.as_ref()).collect(),
+    }
+}
+
+// Writes a tab-separated line with all fields, or only the selected ones in the selection order
+fn write_fields<T: AsRef<str>>(writer: &mut impl Write, fields: &[T], column_selection: &Option<Vec<usize>>) -> io::Result<()> {
+    match column_selection {
+        Some(indices) => {
+            for (i, &index) in indices.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b"\t")?;
+                }
+                writer.write_all(fields[index].as_ref().as_bytes())?;
+            }
+        }
+        None => {
+            for (i, field) in fields.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b"\t")?;
+                }
+                writer.write_all(field.as_ref().as_bytes())?;
+            }
+        }
+    }
+    writer.write_all(b"\n")
+}
+
+// Reads a two-column (sequence name, path name) mapping; sequences not listed keep their own name as path name
+#[cfg(feature = "gfa")]
+fn load_path_map(file_path: &str) -> HashMap<String, String> {
+    let mut path_map = HashMap::new();
+    for line in open_file(file_path).lines() {
+        let line = line.expect("Failed to read path map");
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() < 2 {
+            eprintln!("ERROR: path map line does not contain two fields: {}", line);
+            std::process::exit(1);
+        }
+        path_map.insert(parts[0].to_string(), parts[1].to_string());
+    }
+    path_map
+}
+
+// Writes one GFA-like annotation record for a feature on a path, with aligned bases (al:i) and coverage fraction (cv:f) tags
+#[cfg(feature = "gfa")]
+#[allow(clippy::too_many_arguments)]
+fn write_gfa_annotation(writer: &mut impl Write, path_map: &HashMap<String, String>, seq_name: &str, feature_start: i64, feature_end: i64, feature_name: &str, aligned_bases: i64, reference_lengths: &Option<HashMap<String, i64>>) -> io::Result<()> {
+    let path_name = path_map.get(seq_name).map(|s| s.as_str()).unwrap_or(seq_name);
+    let feature_len = reference_lengths.as_ref().map_or(feature_end - feature_start, |reference_lengths| crate::reference_length(reference_lengths, feature_name, feature_start, feature_end));
+    let coverage = if feature_len > 0 { aligned_bases as f64 / feature_len as f64 } else { 0.0 };
+    writeln!(writer, "{}\t{}\t{}\t{}\tal:i:{}\tcv:f:{:.6}", path_name, feature_start, feature_end, feature_name, aligned_bases, coverage)
+}
+
+// Writes the per-feature report, or its per-feature summary with `summarize` (--summarize or the summary subcommand)
+pub fn run(matches: &ArgMatches, summarize: bool) -> io::Result<()> {
+    if matches.is_present("list_columns") {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        write_column_order(&mut out)?;
+        return out.flush();
+    }
+    if matches.is_present("paf") && !matches.is_present("query_features") && !matches.is_present("pairs") {
+        eprintln!("ERROR: --paf is the input of --query-features and --target-features or of --pairs");
+        std::process::exit(1);
+    }
+    // With --pairs, the input lines are those of the pairs file, the PAF being read with them
+    let input_files: Vec<&str> = match (matches.value_of("pairs"), matches.value_of("paf")) {
+        (Some(pairs), _) => vec![pairs],
+        (None, Some(paf)) => vec![paf],
+        (None, None) => matches.values_of("input").map(|files| files.collect()).unwrap_or_default(),
+    };
+    let output = ReportOutput::new(matches.value_of("output"), matches.value_of("json_output"));
+    if input_files.len() <= 1 {
+        let (reported, _) = run_input(matches, summarize, input_files.first().copied().unwrap_or(""), &input_files, true, LineRange::new(matches), output)?;
+        check_nonempty(matches, reported);
+        return Ok(());
+    }
+    if let Some(option) = SINGLE_INPUT_OPTIONS.iter().find(|name| matches.try_contains_id(name).unwrap_or(false) && matches.is_present(name)) {
+        eprintln!("ERROR: --{} cannot be used with several input files", option.replace('_', "-"));
+        std::process::exit(1);
+    }
+    if summarize {
+        eprintln!("ERROR: the summary cannot be computed over several input files; merge their reports with merge --summarize");
+        std::process::exit(1);
+    }
+    // --skip and --head apply to the lines of all the files, taken in order: each file is processed once the
+    // previous ones tell where the range continues
+    if matches.is_present("skip") || matches.is_present("head") {
+        let mut out = output;
+        let mut line_range = LineRange::new(matches);
+        let mut reported = 0;
+        for (index, input_file) in input_files.iter().enumerate() {
+            if index > 0 && line_range.head == Some(0) {
+                break;
+            }
+            let (file_reported, rest) = run_input(matches, summarize, input_file, &input_files, index == 0, line_range, &mut out)?;
+            reported += file_reported;
+            line_range = rest;
+        }
+        out.flush()?;
+        check_nonempty(matches, reported);
+        return Ok(());
+    }
+    let threads = matches.value_of("threads")
+        .map(|s| s.parse::<usize>().ok().filter(|threads| *threads > 0).expect("Invalid value for threads"))
+        .unwrap_or(1);
+
+    // Each file is reported to a temporary file by the first free worker, the header only in the first one; the
+    // temporary files are then copied in the order of the input files
+    let temp_dir = std::env::temp_dir();
+    let outputs: Vec<TempFile> = input_files.iter().map(|_| TempFile::new(&temp_dir)).collect();
+    let next_file = AtomicUsize::new(0);
+    let results: Vec<io::Result<u64>> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..std::cmp::min(threads, input_files.len())).map(|_| scope.spawn(|| -> io::Result<u64> {
+            let mut reported = 0;
+            loop {
+                let index = next_file.fetch_add(1, AtomicOrdering::Relaxed);
+                let Some(input_file) = input_files.get(index) else {
+                    return Ok(reported);
+                };
+                let writer = BufWriter::new(File::create(outputs[index].path())?);
+                reported += run_input(matches, summarize, input_file, &input_files, index == 0, LineRange::new(matches), writer)?.0;
+            }
+        })).collect();
+        workers.into_iter().map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))).collect()
+    });
+    let reported = results.into_iter().sum::<io::Result<u64>>()?;
+
+    let mut out = output;
+    for temp_file in &outputs {
+        io::copy(&mut File::open(temp_file.path())?, &mut out)?;
+    }
+    out.flush()?;
+    check_nonempty(matches, reported);
+    Ok(())
+}
+
+// With --require-nonempty, exits with an error when no row was reported
+fn check_nonempty(matches: &ArgMatches, reported: u64) {
+    if reported == 0 && matches.is_present("require_nonempty") {
+        eprintln!("ERROR: --require-nonempty: no rows were reported (empty input, or no line passing the filters)");
+        std::process::exit(1);
+    }
+}
+
+// Feature of the keyed outputs: sequence, start, end, and feature name (with interned sequence and feature names)
+type FeatureKey = (Symbol, i64, i64, Symbol);
+
+// State of a report run along its input: the counters, and what is read or accumulated with the lines
+struct RunState {
+    summary: RunSummary,
+    errors: Vec<(u64, String)>,
+    profile: Profile,
+    interner: Interner,
+    fastas: Option<(fasta::IndexedFasta, fasta::IndexedFasta)>,
+    // Composition of the target features, computed once per feature
+    compositions: HashMap<(String, i64, i64), fasta::Composition>,
+    paf_lines: Option<pairs::PafLines>,
+    // Whether the layout still has to be checked against the first processed line, and if it is plain PAF
+    layout_to_validate: Option<bool>,
+    // Order of the paf-feature columns, known once the first line is checked
+    column_order: features::ColumnOrder,
+    name_uniquifier: Option<names::NameUniquifier>,
+    duplicate_names: names::DuplicateNameDetector,
+    explain_out: Option<Box<dyn Write>>,
+    nm_check: NmCheck,
+    running_sum: Option<RunningSum>,
+    symmetry_checked: u64,
+    symmetry_failures: u64,
+    capped_rows: u64,
+}
+
+impl RunState {
+    fn new(matches: &ArgMatches, options: &ReportOptions) -> io::Result<RunState> {
+        let estimate = matches.is_present("estimate").then(|| {
+            let fraction = matches.value_of("sample_fraction").and_then(|s| s.parse::<f64>().ok()).filter(|fraction| *fraction > 0.0 && *fraction <= 1.0).expect("Invalid value for sample fraction");
+            let seed = matches.value_of("seed").map(|s| s.parse::<u64>().expect("Invalid value for seed")).unwrap_or(0);
+            let confidence = matches.value_of("confidence").map(|s| s.parse::<f64>().ok().filter(|confidence| *confidence > 0.0 && *confidence < 1.0).expect("Invalid value for confidence")).unwrap_or(0.95);
+            Estimate::new(fraction, seed, confidence)
+        });
+        let fastas = match (matches.value_of("query_fasta"), matches.value_of("target_fasta")) {
+            (Some(query_fasta), Some(target_fasta)) => Some((fasta::IndexedFasta::open(query_fasta)?, fasta::IndexedFasta::open(target_fasta)?)),
+            _ => None,
+        };
+        let paf_lines = match matches.value_of("pairs").and(matches.value_of("paf")) {
+            Some(paf) => Some(pairs::PafLines::open(paf)?),
+            None => None,
+        };
+        let explain_out = (options.explain_line.is_some() || options.explain_feature.is_some())
+            .then(|| matches.value_of("explain_output").map_or_else(|| Box::new(io::stderr()) as Box<dyn Write>, create_file));
+        Ok(RunState {
+            summary: RunSummary { estimate, ..RunSummary::default() },
+            errors: Vec::new(),
+            profile: Profile { enabled: matches.is_present("profile"), ..Profile::default() },
+            interner: Interner::default(),
+            fastas,
+            compositions: HashMap::new(),
+            paf_lines,
+            layout_to_validate: Some(options.input_layout == "paf"),
+            column_order: features::ColumnOrder::PafFirst,
+            name_uniquifier: matches.value_of("uniquify_names").map(|_| names::NameUniquifier::default()),
+            duplicate_names: names::DuplicateNameDetector::default(),
+            explain_out,
+            nm_check: NmCheck::default(),
+            running_sum: matches.is_present("cumulative").then(|| RunningSum::new(matches.value_of("cumulative_reset") != Some("none"))),
+            symmetry_checked: 0,
+            symmetry_failures: 0,
+            capped_rows: 0,
+        })
+    }
+
+    // Warns about the current line being invalid and counts it (see skip_invalid_line)
+    fn skip_invalid_line(&mut self, options: &ReportOptions, reason: String, out: &mut impl Write) -> io::Result<()> {
+        skip_invalid_line(&mut self.summary, &mut self.errors, options.max_errors, reason, out)
+    }
+
+    // Writes the counters and checks of the run on stderr, and the --stats-json and --uniquify-names files
+    fn report(&self, matches: &ArgMatches, options: &ReportOptions, source: &str, input_files: &[&str], elapsed: std::time::Duration) -> io::Result<()> {
+        if !self.errors.is_empty() {
+            report_errors("WARNING", &self.errors);
+        }
+        if matches.is_present("verbose") {
+            self.summary.report(&mut io::stderr(), source)?;
+        }
+        if let Some(estimate) = &self.summary.estimate {
+            estimate.report(source);
+        }
+        if let Some(paf_lines) = &self.paf_lines {
+            paf_lines.report();
+        }
+        match (&self.name_uniquifier, matches.value_of("uniquify_names")) {
+            (Some(name_uniquifier), Some(map_file)) => {
+                let (names, renamed) = name_uniquifier.touched();
+                let mut writer = create_file(map_file);
+                name_uniquifier.write_map(&mut writer)?;
+                writer.flush()?;
+                eprintln!("INFO: {} feature names found at more than one target locus, {} loci renamed (see {})", names, renamed, map_file);
+            }
+            _ => {
+                if let (names @ 1.., Some(example)) = self.duplicate_names.duplicated() {
+                    if options.feature_id_fields.is_some() {
+                        eprintln!("WARNING: {} feature ids (e.g. '{}') are found at more than one target locus, and their rows are merged by the aggregations by id; adding target-coords to --feature-id-from tells them apart", names, example);
+                    } else {
+                        eprintln!("WARNING: {} feature names (e.g. '{}') are found at more than one target locus, and their rows are merged by the aggregations by name; --uniquify-names gives them distinct names", names, example);
+                    }
+                }
+            }
+        }
+        if options.cap_at_feature_length {
+            eprintln!("INFO: {}{} rows with counts capped at the feature length", source, self.capped_rows);
+        }
+        if options.check_nm {
+            eprintln!("INFO: NM check: {} alignments checked, {} with NM below the CIGAR edit distance, {} above it, {} without a valid NM:i tag", self.nm_check.checked, self.nm_check.below_cigar, self.nm_check.above_cigar, self.nm_check.without_nm);
+        }
+        if options.self_test_symmetry {
+            if self.symmetry_failures > 0 {
+                eprintln!("ERROR: symmetry self-test: {} of {} checked alignments have different counts with query and target swapped", self.symmetry_failures, self.symmetry_checked);
+            } else {
+                eprintln!("INFO: symmetry self-test: {} alignments checked, no discrepancies", self.symmetry_checked);
+            }
+        }
+        if self.profile.enabled {
+            self.profile.report(source, elapsed);
+        }
+        if let Some(stats_file) = matches.value_of("stats_json") {
+            let mut writer = create_file(stats_file);
+            self.summary.write_json(&mut writer, &options.effective_options, input_files, elapsed)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Reports the lines in `line_range` of one of the input files to `out`, with the header (and the metadata lines) only
+// with `with_header`, returning the number of rows reported and the range left for the next file; with several input
+// files, the counters on stderr are prefixed with the file
+#[allow(clippy::too_many_arguments)]
+fn run_input(matches: &ArgMatches, summarize: bool, input_file: &str, input_files: &[&str], with_header: bool, line_range: LineRange, out: impl Write) -> io::Result<(u64, LineRange)> {
+    let source = if input_files.len() > 1 { format!("{}: ", input_file) } else { String::new() };
+    let options = ReportOptions::parse(matches, summarize, input_files, line_range)?;
+    let start_time = std::time::Instant::now();
+    let mut state = RunState::new(matches, &options)?;
+    let mut sink = RowSink::new(matches, &options, with_header, state.summary.estimate.is_some(), out)?;
+
+    if !input_file.is_empty() {
+        let follow = matches.is_present("follow").then(|| Follow {
+            idle_limit: matches.value_of("follow_until_idle").map(|s| std::time::Duration::from_secs_f64(s.parse::<f64>().ok().filter(|&seconds| seconds >= 0.0).expect("Invalid value for follow idle time"))),
+        });
+        if follow.is_some() {
+            if is_compressed(input_file) {
+                eprintln!("ERROR: --follow needs an uncompressed input file, {} is compressed", input_file);
+                std::process::exit(1);
+            }
+            handle_sigterm();
+        }
+        let lines = BoundedLines { reader: open_file(input_file), max_length: options.max_line_length, follow };
+        read_input(&options, &mut state, &mut sink, lines)?;
+    }
+
+    let write_start = state.profile.start();
+    sink.finish(&options, &mut state)?;
+    state.profile.add(Phase::Writing, write_start);
+    state.report(matches, &options, &source, input_files, start_time.elapsed())?;
+    if state.symmetry_failures > 0 {
+        std::process::exit(1);
+    }
+    let processed = state.summary.lines - state.summary.skipped_lines;
+    Ok((state.summary.reported, options.line_range.after(state.summary.skipped_lines, processed)))
+}
+
+// Reads the input lines in the --skip/--head range, joining them with their features into records that are counted
+// and written to the sink
+fn read_input<W: Write>(options: &ReportOptions, state: &mut RunState, sink: &mut RowSink<W>, lines: BoundedLines) -> io::Result<()> {
+    let following = lines.follow.is_some();
+    // Lines after the --head range are never read
+    let mut lines = lines.take(options.line_range.head.map(|head| options.line_range.skip.saturating_add(head)).unwrap_or(usize::MAX));
+    loop {
+        // The rows of the lines processed so far are written before waiting for more
+        if following {
+            sink.out.flush()?;
+        }
+        let read_start = state.profile.start();
+        let Some(line) = lines.next() else { break };
+        state.profile.add(Phase::Reading, read_start);
+        let line = match line {
+            Ok(line) => line,
+            Err(e) if options.keep_going && e.kind() != io::ErrorKind::BrokenPipe => {
+                eprintln!("WARNING: {}", e);
+                eprintln!("WARNING: --keep-going: the input could not be read past line {}, the results cover the lines before", state.summary.lines);
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        state.summary.lines += 1;
+        if state.summary.lines <= options.line_range.skip as u64 {
+            state.summary.skipped_lines += 1;
+            continue;
+        }
+        let line = match line {
+            Ok(line) => line,
+            Err(oversized) => {
+                let reason = format!("line of {} bytes, longer than --max-line-length {} (starting with '{}...')", oversized.length, options.max_line_length, oversized.start);
+                state.skip_invalid_line(options, reason, &mut sink.out)?;
+                continue;
+            }
+        };
+        let line_start_time = options.slow_line_threshold.map(|_| std::time::Instant::now());
+        let parse_start = state.profile.start();
+        let records = match line_records(options, state, line)? {
+            Ok(records) => records,
+            Err(reason) => {
+                state.skip_invalid_line(options, reason, &mut sink.out)?;
+                continue;
+            }
+        };
+        state.profile.add(Phase::Parsing, parse_start);
+        if records.is_empty() {
+            state.summary.skipped_no_feature += 1;
+        }
+        // An input line is one alignment, checked once whatever the number of features it is joined with
+        let mut nm_checked = !options.check_nm;
+        // Longest CIGAR walked for the line (from --cigar-tag, --cigar-column, or --blocks), for --slow-line-threshold
+        let mut walked_cigar_length = 0;
+        for (line, feature_columns, feature_set) in &records {
+            let parse_start = state.profile.start();
+            let fields: Vec<&str> = line.split('\t').collect();
+            let parts = state.column_order.paf_first(&fields);
+            let record = match parse_line_record(options, &parts, &fields) {
+                Ok(record) => record,
+                Err(reason) => {
+                    state.skip_invalid_line(options, reason, &mut sink.out)?;
+                    continue;
+                }
+            };
+            walked_cigar_length = std::cmp::max(walked_cigar_length, record.cigar.len());
+            state.profile.add(Phase::Parsing, parse_start);
+            if options.trace_line.is_some() {
+                let alignment = &record.alignment;
+                let walk_strand = options.forced_strand.unwrap_or_else(|| alignment.query_strand.chars().next().unwrap());
+                write_trace(&mut sink.out, alignment.feature_in_query_name, alignment.query_start, alignment.query_end, walk_strand, alignment.target_start, alignment.target_end, &record.cigar, alignment.feature_in_query_start, alignment.feature_in_query_end, alignment.feature_in_target_start, alignment.feature_in_target_end, options.max_indel_size)?;
+                state.summary.reported += 1;
+                continue;
+            }
+            if !nm_checked {
+                nm_checked = true;
+                check_nm(options, state, &record);
+            }
+            match count_record(options, state, &record, *feature_columns, &fields, feature_set)? {
+                Ok(Some(counted)) => sink.add(options, state, counted)?,
+                Ok(None) => {}
+                Err(RecordError::Invalid(reason)) => state.skip_invalid_line(options, reason, &mut sink.out)?,
+                Err(RecordError::Fatal(message)) => {
+                    sink.out.flush()?;
+                    eprintln!("ERROR: line {}: {}", state.summary.lines, message);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let (Some(threshold), Some(line_start_time)) = (options.slow_line_threshold, line_start_time) {
+            let elapsed = line_start_time.elapsed();
+            if elapsed > threshold {
+                eprintln!("WARNING: line {}: processed in {} ms (threshold {} ms), CIGAR of {} characters", state.summary.lines, elapsed.as_millis(), threshold.as_millis(), walked_cigar_length);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Records of an input line, as (line in the paf-feature layout, feature BED columns, feature set)
+type LineRecords<'o> = Vec<(String, Option<&'o [String]>, &'o str)>;
+
+// Records of an input line: with --pairs, the PAF line the line refers to followed by its feature columns; with plain
+// PAF input, the line once for each feature it overlaps, in each feature set
+fn line_records<'o>(options: &'o ReportOptions, state: &mut RunState, line: String) -> io::Result<Result<LineRecords<'o>, String>> {
+    let line = match state.paf_lines.as_mut() {
+        Some(paf_lines) => match paf_lines.join(&line)? {
+            Ok(joined) => joined,
+            Err(reason) => return Ok(Err(reason)),
+        },
+        None => line,
+    };
+    if let Some(plain_paf) = state.layout_to_validate.take() {
+        let validated = if plain_paf {
+            features::validate_layout(&line, plain_paf)
+        } else {
+            features::detect_order(&line, options.declared_order, options.match_blocks.is_none()).map(|order| state.column_order = order)
+        };
+        if let Err(reason) = validated {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {} (--input-layout {})", state.summary.lines, reason, options.input_layout)));
+        }
+    }
+    let mut records: LineRecords = Vec::new();
+    for (label, feature_index, target_feature_index) in &options.feature_sets {
+        let joined = feature_index.join(target_feature_index.as_ref().unwrap_or(feature_index), &line);
+        // A line that cannot be joined is left as it is by every set, to be reported once as invalid
+        if joined.first().is_some_and(|(_, feature_columns)| feature_columns.is_none()) {
+            records = joined.into_iter().map(|(line, feature_columns)| (line, feature_columns, *label)).collect();
+            break;
+        }
+        records.extend(joined.into_iter().map(|(line, feature_columns)| (line, feature_columns, *label)));
+    }
+    if options.feature_sets.is_empty() {
+        records.push((if options.whole_alignment { features::whole_alignment(&line) } else { line }, None, ""));
+    }
+    Ok(Ok(records))
+}
+
+// Row waiting for the columns computed over all the rows, with its aligned bases, the keys of its features, and its
+// aligned fraction
+type BufferedRow = (Vec<String>, i64, Option<(FeatureKey, FeatureKey)>, Option<f64>);
+
+// Where the rows go: the report (with the selected columns) or its summary, the --mosdepth-summary coverage, or the
+// rows waiting for the columns computed over all of them (--depth, --strand-coverage, and --z-normalize)
+struct RowSink<W: Write> {
+    out: W,
+    summarizer: Option<Summarizer>,
+    // Columns of the summary selected by --columns and --no-ignored
+    summary_selection: Option<Vec<usize>>,
+    // With --mosdepth-summary, aligned bases of each target feature, in order of first appearance
+    mosdepth_coverage: Option<(Vec<FeatureKey>, HashMap<FeatureKey, i64>)>,
+    buffered_rows: Vec<BufferedRow>,
+    query_feature_blocks: HashMap<FeatureKey, Vec<Interval>>,
+    target_feature_blocks: HashMap<FeatureKey, Vec<Interval>>,
+    // Aligned blocks over the target features of the + and - strand alignments, for --strand-coverage
+    target_strand_blocks: HashMap<FeatureKey, [Vec<Interval>; 2]>,
+    #[cfg(feature = "gfa")]
+    path_map: HashMap<String, String>,
+    #[cfg(feature = "gfa")]
+    gfa_writer: Option<BufWriter<File>>,
+}
+
+impl<W: Write> RowSink<W> {
+    // Writes the metadata lines and the header of the output
+    fn new(matches: &ArgMatches, options: &ReportOptions, with_header: bool, estimate: bool, mut out: W) -> io::Result<RowSink<W>> {
+        if with_header && matches.is_present("with_metadata") {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            writeln!(out, "##feature_level_report={}", env!("CARGO_PKG_VERSION"))?;
+            writeln!(out, "##command={}", std::env::args().collect::<Vec<String>>().join(" "))?;
+            writeln!(out, "##date={}", format_iso8601(now))?;
+            for (name, value) in &options.effective_options {
+                writeln!(out, "##{}={}", name, value)?;
+            }
+        }
+        let mut mosdepth_coverage = None;
+        let mut summarizer = None;
+        let mut summary_selection = None;
+        if matches.is_present("mosdepth_summary") {
+            mosdepth_coverage = Some((Vec::new(), HashMap::new()));
+        } else if options.trace_line.is_some() {
+            write_fields(&mut out, &TRACE_HEADER, &None)?;
+        } else if options.summarize {
+            let mut new_summarizer = Summarizer::new(&options.header).unwrap_or_else(|e| {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            });
+            if matches.is_present("assume_sorted_by_feature") {
+                new_summarizer = new_summarizer.assume_sorted();
+            }
+            if let Some(threshold) = options.sample_threshold {
+                new_summarizer = new_summarizer.with_sample_threshold(&options.header, threshold).unwrap_or_else(|e| {
+                    eprintln!("ERROR: {}", e);
+                    std::process::exit(1);
+                });
+            }
+            let summary_header = new_summarizer.header();
+            summary_selection = select_output_columns(&summary_header, &options.columns, options.no_ignored);
+            write_fields(&mut out, &summary_header, &summary_selection)?;
+            summarizer = Some(new_summarizer);
+        } else if with_header && !estimate {
+            write_fields(&mut out, &options.header, &options.column_selection)?;
+        }
+        Ok(RowSink {
+            out,
+            summarizer,
+            summary_selection,
+            mosdepth_coverage,
+            buffered_rows: Vec::new(),
+            query_feature_blocks: HashMap::new(),
+            target_feature_blocks: HashMap::new(),
+            target_strand_blocks: HashMap::new(),
+            #[cfg(feature = "gfa")]
+            path_map: matches.value_of("path_map").map(load_path_map).unwrap_or_default(),
+            #[cfg(feature = "gfa")]
+            gfa_writer: matches.value_of("gfa_annotate")
+                .map(|file_path| BufWriter::new(File::create(file_path).expect("Failed to create GFA annotation file"))),
+        })
+    }
+
+    fn add(&mut self, options: &ReportOptions, state: &mut RunState, counted: CountedRow) -> io::Result<()> {
+        let CountedRow { row, aligned_bases, target_feature_length, aligned_fraction, depth_blocks, reverse, feature_keys } = counted;
+        if options.emit_depth || options.strand_coverage || options.z_normalize {
+            let depth_keys = depth_blocks.map(|(query_key, query_blocks, target_key, target_blocks)| {
+                if options.strand_coverage {
+                    self.target_strand_blocks.entry(target_key).or_default()[usize::from(reverse)].extend(target_blocks.iter().copied());
+                }
+                if options.emit_depth {
+                    self.query_feature_blocks.entry(query_key).or_default().extend(query_blocks);
+                    self.target_feature_blocks.entry(target_key).or_default().extend(target_blocks);
+                }
+                (query_key, target_key)
+            });
+            self.buffered_rows.push((row, aligned_bases, depth_keys, aligned_fraction));
+            return Ok(());
+        }
+        if options.row_filter.as_ref().is_some_and(|filter| !row_matches(filter, &row)) {
+            state.summary.skipped_where += 1;
+            return Ok(());
+        }
+        if let Some(estimate) = state.summary.estimate.as_mut() {
+            estimate.add(aligned_bases, target_feature_length);
+            return Ok(());
+        }
+        if let Some(((order, coverage), (_, target_key))) = self.mosdepth_coverage.as_mut().zip(feature_keys) {
+            if !coverage.contains_key(&target_key) {
+                order.push(target_key);
+            }
+            let covered = coverage.entry(target_key).or_insert(0);
+            *covered = checked_sum(*covered, aligned_bases, "aligned.bp").unwrap_or_else(|e| {
+                eprintln!("ERROR: line {}: {}", state.summary.lines, e);
+                std::process::exit(1);
+            });
+        } else {
+            let write_start = state.profile.start();
+            match self.summarizer.as_mut() {
+                Some(summarizer) => match summarizer.add(&project_fields(&row, &None)) {
+                    Ok(Some(summary_row)) => write_fields(&mut self.out, &summary_row, &self.summary_selection)?,
+                    Ok(None) => {}
+                    Err(e) => {
+                        self.out.flush()?;
+                        eprintln!("ERROR: line {}: {}", state.summary.lines, e);
+                        std::process::exit(1);
+                    }
+                },
+                None => write_fields(&mut self.out, &row, &options.column_selection)?,
+            }
+            state.profile.add(Phase::Writing, write_start);
+        }
+        if let Err(e) = state.summary.add_reported(aligned_bases) {
+            self.out.flush()?;
+            eprintln!("ERROR: line {}: {}", state.summary.lines, e);
+            std::process::exit(1);
+        }
+
+        #[cfg(feature = "gfa")]
+        if let Some((writer, (query_key, target_key))) = self.gfa_writer.as_mut().zip(feature_keys) {
+            for (sequence, start, end, name) in [query_key, target_key] {
+                write_gfa_annotation(writer, &self.path_map, state.interner.resolve(sequence), start, end, state.interner.resolve(name), aligned_bases, &options.reference_lengths)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes the buffered rows, the --mosdepth-summary coverage, and the summary, once all the rows are added
+    fn finish(mut self, options: &ReportOptions, state: &mut RunState) -> io::Result<()> {
+        let format_depth = |(max_depth, mean_depth): (i64, Option<f64>)| [max_depth.to_string(), mean_depth.map(|mean| format!("{:.2}", mean)).unwrap_or_else(|| "NA".to_string())];
+        // Mean and (population) standard deviation of the aligned fractions, for --z-normalize
+        let fractions: Vec<f64> = self.buffered_rows.iter().filter_map(|(_, _, _, fraction)| *fraction).collect();
+        let mean_fraction = fractions.iter().sum::<f64>() / fractions.len().max(1) as f64;
+        let sd_fraction = (fractions.iter().map(|fraction| (fraction - mean_fraction).powi(2)).sum::<f64>() / fractions.len().max(1) as f64).sqrt();
+        for (mut row, aligned_bases, depth_keys, aligned_fraction) in std::mem::take(&mut self.buffered_rows) {
+            if let Some((query_key, target_key)) = depth_keys.filter(|_| options.emit_depth) {
+                row.extend(format_depth(depth_over_feature(&self.query_feature_blocks[&query_key], query_key.1, query_key.2)));
+                row.extend(format_depth(depth_over_feature(&self.target_feature_blocks[&target_key], target_key.1, target_key.2)));
+            }
+            if let Some((_, target_key)) = depth_keys.filter(|_| options.strand_coverage) {
+                let [forward_blocks, reverse_blocks] = &self.target_strand_blocks[&target_key];
+                let all_blocks: Vec<Interval> = forward_blocks.iter().chain(reverse_blocks).copied().collect();
+                row.extend([covered_bases(&all_blocks), covered_bases(forward_blocks), covered_bases(reverse_blocks)].map(|covered| covered.to_string()));
+            }
+            if options.z_normalize {
+                row.push(aligned_fraction.map(|fraction| format!("{:.4}", fraction)).unwrap_or_else(|| "NA".to_string()));
+                row.push(aligned_fraction.filter(|_| sd_fraction > 0.0).map(|fraction| format!("{:.4}", (fraction - mean_fraction) / sd_fraction)).unwrap_or_else(|| "NA".to_string()));
+            }
+            debug_assert_eq!(row.len(), options.header.len());
+            if options.row_filter.as_ref().is_some_and(|filter| !row_matches(filter, &row)) {
+                state.summary.skipped_where += 1;
+                continue;
+            }
+            write_fields(&mut self.out, &row, &options.column_selection)?;
+            if let Err(e) = state.summary.add_reported(aligned_bases) {
+                self.out.flush()?;
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        }
+
+        if let Some((order, coverage)) = &self.mosdepth_coverage {
+            for key in order {
+                let (chrom, start, end, name) = (state.interner.resolve(key.0), key.1, key.2, state.interner.resolve(key.3));
+                let length = options.feature_length(name, start, end);
+                let mean = if length > 0 { coverage[key] as f64 / length as f64 } else { 0.0 };
+                writeln!(self.out, "{}\t{}\t{}\t{}\t{:.2}", chrom, start, end, name, mean)?;
+            }
+        }
+        if let Some(summarizer) = self.summarizer.take() {
+            for summary_row in summarizer.finish()? {
+                write_fields(&mut self.out, &summary_row?, &self.summary_selection)?;
+            }
+        }
+        self.out.flush()?;
+        #[cfg(feature = "gfa")]
+        if let Some(mut writer) = self.gfa_writer.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summarize::MAX_INDEL_SUFFIX;
+    use crate::UNLIMITED_INDEL_SIZE;
+    use clap::App;
+
+    // Line of the paf-feature layout for feature `name` of a 100M alignment of query `query`
+    fn paf_feature_line(query: &str, name: &str) -> String {
+        format!("{0}\t100\t0\t100\t+\tt1\t100\t0\t100\t100\t100\t60\tcg:Z:100M\t{0}\t10\t20\t{1}\t0\t+\tgene\tt1\t10\t20\t{1}\t0\t+\tgene\n", query, name)
+    }
+
+    #[test]
+    fn line_range_spans_several_input_files() {
+        let temp_dir = std::env::temp_dir();
+        let inputs: Vec<TempFile> = (0..2).map(|_| TempFile::new(&temp_dir)).collect();
+        for (index, input) in inputs.iter().enumerate() {
+            let lines: String = (1..=5).map(|line| paf_feature_line(&format!("q{}", index + 1), &format!("f{}", line))).collect();
+            std::fs::write(input.path(), lines).unwrap();
+        }
+        let output = TempFile::new(&temp_dir);
+        for threads in ["1", "2"] {
+            let matches = App::new("test").args(args()).get_matches_from([
+                "test", "-i", inputs[0].path().to_str().unwrap(), "-i", inputs[1].path().to_str().unwrap(),
+                "--skip", "3", "--head", "4", "--threads", threads, "-o", output.path().to_str().unwrap(),
+            ]);
+            run(&matches, false).unwrap();
+            let report = std::fs::read_to_string(output.path()).unwrap();
+            let rows: Vec<(&str, &str)> = report.lines().skip(1).map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (fields[1], fields[0])
+            }).collect();
+            assert_eq!(rows, vec![("q1", "f4"), ("q1", "f5"), ("q2", "f1"), ("q2", "f2")]);
+        }
+    }
+
+    #[test]
+    fn max_errors_allows_exactly_that_many_invalid_lines() {
+        let temp_dir = std::env::temp_dir();
+        let input = TempFile::new(&temp_dir);
+        // Two valid lines, then three invalid ones (a query start that is not a number) between valid lines
+        let invalid = paf_feature_line("q2", "bad").replacen("\t0\t100\t", "\tx\t100\t", 1);
+        let lines = [paf_feature_line("q1", "f1"), paf_feature_line("q1", "f2"), invalid.clone(), invalid.clone(), paf_feature_line("q3", "f3"), invalid];
+        std::fs::write(input.path(), lines.concat()).unwrap();
+        let output = TempFile::new(&temp_dir);
+        let report_with = |max_errors: &str| {
+            let matches = App::new("test").args(args()).get_matches_from([
+                "test", "-i", input.path().to_str().unwrap(), "-o", output.path().to_str().unwrap(), "--max-errors", max_errors,
+            ]);
+            let result = run(&matches, false);
+            let report = std::fs::read_to_string(output.path()).unwrap();
+            (result, report.lines().skip(1).map(|line| line.split('\t').next().unwrap().to_string()).collect::<Vec<_>>())
+        };
+        let (result, rows) = report_with("3");
+        assert!(result.is_ok());
+        assert_eq!(rows, ["f1", "f2", "f3"]);
+        // The rows before the invalid line that aborts the run are written out
+        for (max_errors, aborting_after, expected) in [("2", 3, vec!["f1", "f2", "f3"]), ("1", 2, vec!["f1", "f2"]), ("0", 1, vec!["f1", "f2"])] {
+            let (result, rows) = report_with(max_errors);
+            let error = result.unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(error.to_string(), format!("aborting after {} invalid lines (--max-errors {})", aborting_after, max_errors));
+            assert_eq!(rows, expected, "--max-errors {}", max_errors);
+        }
+    }
+
+    #[test]
+    fn zero_length_cigar_operations_are_invalid_lines() {
+        let temp_dir = std::env::temp_dir();
+        let input = TempFile::new(&temp_dir);
+        // Each CIGAR aligns 100 bases with one operation of length 0, in M or =/X CIGARs
+        let cigars = ["0M100M", "50M0I50M", "100M0I", "0=100=", "50=0X50=", "100=0I"];
+        let invalid = cigars.map(|cigar| paf_feature_line("q2", cigar).replacen("cg:Z:100M", &format!("cg:Z:{}", cigar), 1));
+        let lines: Vec<String> = std::iter::once(paf_feature_line("q1", "f1")).chain(invalid).chain(std::iter::once(paf_feature_line("q3", "f2"))).collect();
+        std::fs::write(input.path(), lines.concat()).unwrap();
+        let output = TempFile::new(&temp_dir);
+        let report_with = |max_errors: &str| {
+            let matches = App::new("test").args(args()).get_matches_from([
+                "test", "-i", input.path().to_str().unwrap(), "-o", output.path().to_str().unwrap(), "--max-errors", max_errors,
+            ]);
+            let result = run(&matches, false);
+            let report = std::fs::read_to_string(output.path()).unwrap();
+            (result, report.lines().skip(1).map(|line| line.split('\t').next().unwrap().to_string()).collect::<Vec<_>>())
+        };
+        // Every invalid line is skipped
+        let (result, rows) = report_with(&cigars.len().to_string());
+        assert!(result.is_ok());
+        assert_eq!(rows, ["f1", "f2"]);
+        // Without --max-errors the first one aborts the run
+        let (result, rows) = report_with("0");
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "aborting after 1 invalid lines (--max-errors 0)");
+        assert_eq!(rows, ["f1"]);
+    }
+
+    #[test]
+    fn columns_select_the_columns_of_the_summary() {
+        let temp_dir = std::env::temp_dir();
+        let input = TempFile::new(&temp_dir);
+        std::fs::write(input.path(), [paf_feature_line("q1", "f1"), paf_feature_line("q1", "f1"), paf_feature_line("q3", "f2")].concat()).unwrap();
+        let output = TempFile::new(&temp_dir);
+        let summarize_with = |arguments: &[&str]| {
+            let matches = App::new("test").args(args()).get_matches_from(
+                ["test", "-i", input.path().to_str().unwrap(), "-o", output.path().to_str().unwrap(), "--summarize"].into_iter().chain(arguments.iter().copied()));
+            run(&matches, true).unwrap();
+            std::fs::read_to_string(output.path()).unwrap()
+        };
+        // The query column grouping the rows can be left out, and the alignments column of the summary selected
+        assert_eq!(summarize_with(&["--columns", "feature.name,alignments,aligned.bp"]), "feature.name\talignments\taligned.bp\nf1\t2\t20\nf2\t1\t10\n");
+        let header = summarize_with(&["--no-ignored"]).lines().next().unwrap().to_string();
+        assert!(header.starts_with("feature.name\tquery\ttarget\talignments\t"), "{}", header);
+        assert!(!header.contains("ignored.in."), "{}", header);
+        assert_eq!(summarize_with(&["--columns", "alignments,ignored.in.query.bp", "--no-ignored"]), "alignments\n2\n1\n");
+    }
+
+    #[test]
+    fn mismatch_columns_are_na_without_extended_cigar() {
+        let temp_dir = std::env::temp_dir();
+        let input = TempFile::new(&temp_dir);
+        let with_cigar = |cigar: &str| paf_feature_line("q1", "f1").replacen("cg:Z:100M", cigar, 1);
+        let lines = [with_cigar("cg:Z:90=10X"), with_cigar("cg:Z:100M"), with_cigar("cg:Z:*"), with_cigar("cg:Z:"), with_cigar("cg:Z:50=50M"), with_cigar("cg:Z:5I")];
+        std::fs::write(input.path(), lines.concat()).unwrap();
+        let output = TempFile::new(&temp_dir);
+        let matches = App::new("test").args(args()).get_matches_from([
+            "test", "-i", input.path().to_str().unwrap(), "-o", output.path().to_str().unwrap(), "--mismatches", "--edit-distance", "--columns", "mismatches.bp,edit.distance",
+        ]);
+        run(&matches, false).unwrap();
+        // Only the =/X CIGAR tells the mismatches apart; the missing ones (* or empty) do not
+        assert_eq!(std::fs::read_to_string(output.path()).unwrap(), "mismatches.bp\tedit.distance\n0\t0\nNA\tNA\nNA\tNA\nNA\tNA\nNA\tNA\nNA\tNA\n");
+    }
+
+    #[test]
+    fn mean_indel_size_has_four_decimals() {
+        let temp_dir = std::env::temp_dir();
+        let input = TempFile::new(&temp_dir);
+        // One insertion of 2 bases and two deletions of 1 base inside the features: 4 bases in 3 events
+        let line = "q1\t100\t0\t98\t+\tt1\t100\t0\t98\t96\t100\t60\tcg:Z:30=2I30=1D30=1D6=\tq1\t0\t98\tf1\t0\t+\tgene\tt1\t0\t98\tf1\t0\t+\tgene\n";
+        std::fs::write(input.path(), line).unwrap();
+        let output = TempFile::new(&temp_dir);
+        let matches = App::new("test").args(args()).get_matches_from([
+            "test", "-i", input.path().to_str().unwrap(), "-o", output.path().to_str().unwrap(), "--indel-stats", "--columns", "indel.events.in.query,indel.events.in.target,mean.indel.size",
+        ]);
+        run(&matches, false).unwrap();
+        assert_eq!(std::fs::read_to_string(output.path()).unwrap(), "indel.events.in.query\tindel.events.in.target\tmean.indel.size\n1\t2\t1.3333\n");
+    }
+
+    #[test]
+    fn plain_paf_with_features_reports_the_rows_of_the_paf_feature_layout() {
+        let temp_dir = std::env::temp_dir();
+        let paf = "q1\t100\t0\t100\t+\tt1\t100\t0\t100\t95\t100\t60\tcg:Z:40=1X14=5I5D40=\n";
+        // f1 and f2 overlap both sequences of the alignment, f3 only its query, f4 neither
+        let bed_lines = [
+            "q1\t10\t20\tf1\t0\t+\tgene", "t1\t10\t20\tf1\t0\t+\tgene", "q1\t30\t60\tf2\t0\t+\texon", "t1\t35\t65\tf2\t0\t+\texon",
+            "q1\t70\t80\tf3\t0\t+\tgene", "q2\t10\t20\tf4\t0\t+\tgene",
+        ];
+        let features = TempFile::new(&temp_dir);
+        std::fs::write(features.path(), bed_lines.map(|line| format!("{}\n", line)).concat()).unwrap();
+        let plain_paf = TempFile::new(&temp_dir);
+        std::fs::write(plain_paf.path(), paf).unwrap();
+        let paf_feature = TempFile::new(&temp_dir);
+        let joined: String = [(0, 1), (2, 3)].iter().map(|(query, target)| format!("{}\t{}\t{}\n", paf.trim_end(), bed_lines[*query], bed_lines[*target])).collect();
+        std::fs::write(paf_feature.path(), joined).unwrap();
+
+        let output = TempFile::new(&temp_dir);
+        let report_of = |arguments: &[&str]| {
+            let arguments = ["test", "-o", output.path().to_str().unwrap()].into_iter().chain(arguments.iter().copied());
+            run(&App::new("test").args(args()).get_matches_from(arguments), false).map(|()| std::fs::read_to_string(output.path()).unwrap())
+        };
+        let expected = report_of(&["-i", paf_feature.path().to_str().unwrap()]).unwrap();
+        assert_eq!(expected.lines().count(), 3);
+        let with_features = report_of(&["-i", plain_paf.path().to_str().unwrap(), "--input-layout", "paf", "--features", features.path().to_str().unwrap()]);
+        assert_eq!(with_features.unwrap(), expected);
+
+        // Each layout is checked against the first line, before any row is reported
+        let error = report_of(&["-i", paf_feature.path().to_str().unwrap(), "--input-layout", "paf", "--features", features.path().to_str().unwrap()]).unwrap_err();
+        assert_eq!(error.to_string(), "line 1: the first line has a column that is not a PAF tag ('q1'), is it in the paf-feature layout? (--input-layout paf)");
+        let error = report_of(&["-i", plain_paf.path().to_str().unwrap()]).unwrap_err();
+        assert!(error.to_string().starts_with("line 1: ") && error.to_string().ends_with(" (--input-layout paf-feature)"), "{}", error);
+    }
+
+    #[test]
+    fn pairs_report_the_rows_of_plain_paf_with_features() {
+        let temp_dir = std::env::temp_dir();
+        let paf_lines = [
+            "q1\t100\t0\t100\t+\tt1\t100\t0\t100\t95\t100\t60\tcg:Z:40=1X14=5I5D40=",
+            "q1\t100\t10\t90\t-\tt2\t200\t100\t182\t78\t82\t60\tcg:Z:30=2D20=2X28=",
+            "q2\t50\t0\t50\t+\tt1\t100\t50\t100\t50\t50\t60\tcg:Z:50=",
+        ];
+        let bed_lines = [
+            "q1\t15\t25\tf1\t0\t+\tgene", "t1\t10\t20\tf1\t0\t+\tgene", "t2\t150\t170\tf1\t0\t-\tgene",
+            "q1\t50\t70\tf2\t0\t+\texon", "t2\t110\t130\tf2\t0\t-\texon", "q2\t10\t30\tf3\t0\t+\tgene", "t1\t60\t80\tf3\t0\t+\tgene",
+        ];
+        let paf = TempFile::new(&temp_dir);
+        std::fs::write(paf.path(), paf_lines.map(|line| format!("{}\n", line)).concat()).unwrap();
+        let features = TempFile::new(&temp_dir);
+        std::fs::write(features.path(), bed_lines.map(|line| format!("{}\n", line)).concat()).unwrap();
+        // The PAF line, query feature, and target feature of each pair that --features finds
+        let pairs = [(1, 0, 1), (2, 0, 2), (2, 3, 4), (3, 5, 6)];
+        let pairs_file = |order: &[usize]| {
+            let file = TempFile::new(&temp_dir);
+            let lines: String = order.iter().map(|&index| {
+                let (line, query, target) = pairs[index];
+                format!("{}\t{}\t{}\n", line, bed_lines[query], bed_lines[target])
+            }).collect();
+            std::fs::write(file.path(), lines).unwrap();
+            file
+        };
+
+        let output = TempFile::new(&temp_dir);
+        let report_of = |arguments: &[&str]| {
+            let arguments = ["test", "-o", output.path().to_str().unwrap(), "--mismatches", "--indel-stats"].into_iter().chain(arguments.iter().copied());
+            run(&App::new("test").args(args()).get_matches_from(arguments), false).unwrap();
+            std::fs::read_to_string(output.path()).unwrap()
+        };
+        let expected = report_of(&["-i", paf.path().to_str().unwrap(), "--input-layout", "paf", "--features", features.path().to_str().unwrap()]);
+        assert_eq!(expected.lines().count(), 1 + pairs.len());
+        // Sorted by PAF line, the PAF is read along the pairs; otherwise its lines are fetched again
+        let sorted = pairs_file(&[0, 1, 2, 3]);
+        assert_eq!(report_of(&["--pairs", sorted.path().to_str().unwrap(), "--paf", paf.path().to_str().unwrap()]), expected);
+        let shuffled = pairs_file(&[3, 1, 0, 2]);
+        let report = report_of(&["--pairs", shuffled.path().to_str().unwrap(), "--paf", paf.path().to_str().unwrap()]);
+        let sorted_rows = |report: &str| {
+            let mut rows: Vec<String> = report.lines().map(str::to_string).collect();
+            rows[1..].sort();
+            rows
+        };
+        assert_eq!(sorted_rows(&report), sorted_rows(&expected));
+    }
+
+    #[test]
+    fn each_indel_size_threshold_reports_the_columns_of_its_own_run() {
+        let temp_dir = std::env::temp_dir();
+        let input = TempFile::new(&temp_dir);
+        // Indels of 2, 7, 25, and 30 bases inside the features, on both strands
+        let lines = [
+            paf_feature_line("q1", "f1").replace("cg:Z:100M", "cg:Z:10M2I30M7D20M30I25D8M"),
+            paf_feature_line("q2", "f2").replacen("\t+\t", "\t-\t", 1).replace("cg:Z:100M", "cg:Z:10=2X2D28=7I20=30D8=25I").replace("\t0\t+\tgene", "\t0\t-\tgene"),
+        ].map(|line| line.replace("\t10\t20\t", "\t0\t100\t"));
+        std::fs::write(input.path(), lines.concat()).unwrap();
+        let output = TempFile::new(&temp_dir);
+        let report_with = |max_indel_size: &str| {
+            let matches = App::new("test").args(args()).get_matches_from([
+                "test", "-i", input.path().to_str().unwrap(), "-o", output.path().to_str().unwrap(), "-m", max_indel_size,
+                "--mismatches", "--indel-stats", "--edit-distance", "--fragmentation", "--edge-adjustment",
+            ]);
+            run(&matches, false).unwrap();
+            let report = std::fs::read_to_string(output.path()).unwrap();
+            let mut rows = report.lines().map(|line| line.split('\t').map(str::to_string).collect::<Vec<String>>());
+            let header = rows.next().unwrap();
+            (header, rows.collect::<Vec<_>>())
+        };
+        let thresholds = ["1", "5", "10", "50"];
+        let (header, rows) = report_with(&thresholds.join(","));
+        assert_eq!(rows.len(), 2);
+        // The thresholds split the indels differently
+        let indels = |threshold: &str| header.iter().position(|name| *name == format!("indels.in.query.bp{}{}", MAX_INDEL_SUFFIX, threshold)).unwrap();
+        assert_ne!(rows[0][indels("5")], rows[0][indels("50")]);
+        assert_ne!(rows[1][indels("10")], rows[1][indels("50")]);
+        let mut compared = 0;
+        for (index, threshold) in thresholds.iter().enumerate() {
+            let (single_header, single_rows) = report_with(threshold);
+            assert_eq!(single_rows.len(), rows.len());
+            for (column, name) in single_header.iter().enumerate() {
+                let suffixed = format!("{}{}{}", name, MAX_INDEL_SUFFIX, threshold);
+                // The columns that do not depend on the threshold are those of the first one
+                let Some(multi_column) = header.iter().position(|multi_name| *multi_name == suffixed || (index == 0 && multi_name == name)) else {
+                    assert!(index > 0 && header.contains(name), "{} missing", name);
+                    continue;
+                };
+                for (row, single_row) in rows.iter().zip(&single_rows) {
+                    assert_eq!(row[multi_column], single_row[column], "{} of -m {}", name, threshold);
+                }
+                compared += 1;
+            }
+        }
+        // Every column of the multi-threshold run is compared once
+        assert_eq!(compared, header.len());
+    }
+
+    #[test]
+    fn large_indels_are_counted_by_default() {
+        let temp_dir = std::env::temp_dir();
+        let input = TempFile::new(&temp_dir);
+        let deletion: i64 = 5_000_000_000;
+        let line = format!("q1\t100\t0\t100\t+\tt1\t{0}\t0\t{0}\t100\t{0}\t60\tcg:Z:50M{1}D50M\tq1\t0\t100\tf1\t0\t+\tgene\tt1\t0\t{0}\tf1\t0\t+\tgene\n", deletion + 100, deletion);
+        std::fs::write(input.path(), line).unwrap();
+        let output = TempFile::new(&temp_dir);
+        let matches = App::new("test").args(args()).get_matches_from(["test", "-i", input.path().to_str().unwrap(), "-o", output.path().to_str().unwrap()]);
+        run(&matches, false).unwrap();
+        let report = std::fs::read_to_string(output.path()).unwrap();
+        let mut lines = report.lines().map(|line| line.split('\t').collect::<Vec<_>>());
+        let header = lines.next().unwrap();
+        let row = lines.next().unwrap();
+        let column = |name: &str| row[header.iter().position(|column| *column == name).unwrap()];
+        assert_eq!((column("aligned.bp"), column("indels.in.target"), column("not.aligned.in.target.bp")), ("100", deletion.to_string().as_str(), "0"));
+    }
+
+    #[test]
+    fn query_coords_frames() {
+        let temp_dir = std::env::temp_dir();
+        let input = TempFile::new(&temp_dir);
+        // Alignments on both strands, and one on the - strand without the query length
+        let lines = [
+            paf_feature_line("q1", "f1"),
+            paf_feature_line("q2", "f2").replace("\t+\tt1\t", "\t-\tt1\t").replace("\t0\t+\tgene\tt1", "\t0\t-\tgene\tt1"),
+            paf_feature_line("q3", "f3").replacen("\t100\t", "\t*\t", 1).replace("\t+\tt1\t", "\t-\tt1\t").replace("\t0\t+\tgene\tt1", "\t0\t-\tgene\tt1"),
+        ];
+        std::fs::write(input.path(), lines.concat()).unwrap();
+        let output = TempFile::new(&temp_dir);
+        // PAF query coordinates are already forward, so the default frame reports them as given
+        let as_given = vec![("f1", "10", "20"), ("f2", "10", "20"), ("f3", "10", "20")];
+        for (frame, expected) in [
+            (None, as_given.clone()),
+            (Some("forward"), as_given),
+            (Some("alignment"), vec![("f1", "10", "20"), ("f2", "80", "90")]),
+        ] {
+            let mut arguments = vec!["test", "-i", input.path().to_str().unwrap(), "-o", output.path().to_str().unwrap()];
+            arguments.extend(frame.map(|frame| ["--query-coords-frame", frame]).into_iter().flatten());
+            let matches = App::new("test").args(args()).get_matches_from(arguments);
+            run(&matches, false).unwrap();
+            let report = std::fs::read_to_string(output.path()).unwrap();
+            let rows: Vec<(&str, &str, &str)> = report.lines().skip(1).map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (fields[0], fields[2], fields[3])
+            }).collect();
+            assert_eq!(rows, expected, "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn walk_steps_chain_and_add_up_to_the_counts() {
+        for strand in ['+', '-'] {
+            let cigar = "10M5D10M5I10=2X";
+            let steps: Vec<WalkStep> = walk_steps(100, 137, strand, 200, 237, cigar, 105, 130, 203, 230, UNLIMITED_INDEL_SIZE).collect();
+            assert_eq!(steps.iter().map(|step| (step.length, step.op)).collect::<Vec<_>>(), cigar::ops(cigar).collect::<Vec<_>>());
+            assert_eq!((steps[0].query_before, steps[0].target_before), (if strand == '-' { 137 } else { 100 }, 200));
+            for pair in steps.windows(2) {
+                assert_eq!((pair[0].query_after, pair[0].target_after), (pair[1].query_before, pair[1].target_before));
+            }
+            assert_eq!(steps.last().map(|step| (step.query_after, step.target_after)), Some((if strand == '-' { 100 } else { 137 }, 237)));
+            let counts = crate::count_aligned_bases(100, 137, strand, 200, 237, cigar, 105, 130, 203, 230, UNLIMITED_INDEL_SIZE).unwrap();
+            assert_eq!(steps.iter().map(|step| step.counts.aligned_bases).sum::<i64>(), counts.aligned_bases);
+            assert_eq!(steps.iter().map(|step| step.counts.indels_in_target).sum::<i64>(), counts.indels_in_target);
+        }
+    }
+
+    #[test]
+    fn trace_and_explanation_show_the_same_steps() {
+        let mut trace = Vec::new();
+        write_trace(&mut trace, "f1", 100, 130, '-', 200, 230, "10M5D10M5I5M", 105, 125, 203, 228, UNLIMITED_INDEL_SIZE).unwrap();
+        let mut explanation = Vec::new();
+        write_explanation(&mut explanation, 1, "f1", "q1", 100, 130, '-', "t1", 200, 230, "10M5D10M5I5M", 105, 125, 203, 228, UNLIMITED_INDEL_SIZE).unwrap();
+        let trace_steps: Vec<String> = String::from_utf8(trace).unwrap().lines().map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            format!("op {} {}{}: query {} -> {}, target {} -> {}", fields[1], fields[3], fields[2], fields[4], fields[5], fields[6], fields[7])
+        }).collect();
+        let explanation = String::from_utf8(explanation).unwrap();
+        let explained_steps: Vec<&str> = explanation.lines().skip(2).map(|line| line.trim_start().split(", overlap").next().unwrap()).collect();
+        assert_eq!(trace_steps, explained_steps);
+    }
+}
@@ -0,0 +1,1185 @@
+// Options of the report: the command line arguments, and the options of a run read from them
+
+use clap::{Arg, ArgMatches};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::error_track::ErrorTrack;
+use crate::match_blocks::MatchBlocks;
+use crate::summarize::{MAX_INDEL_SUFFIX, SAMPLE_COLUMN};
+use crate::{expr, fasta, features, input_arg, load_reference_lengths, open_file, reference_length, Fragmentation, UNLIMITED_INDEL_SIZE};
+
+// Parses the --passthrough-cols specification: comma-separated INDEX=NAME pairs, with 1-based column indices
+fn parse_passthrough_columns(spec: &str) -> Vec<(usize, String)> {
+    spec.split(',').map(|pair| {
+        match pair.split_once('=').map(|(index, name)| (index.trim().parse::<usize>(), name.trim())) {
+            Some((Ok(index), name)) if index > 0 && !name.is_empty() => (index, name.to_string()),
+            _ => {
+                eprintln!("ERROR: invalid --passthrough-cols entry '{}', expected INDEX=NAME with a 1-based column index", pair);
+                std::process::exit(1);
+            }
+        }
+    }).collect()
+}
+
+// Fields a feature.id can be composed from with --feature-id-from
+const FEATURE_ID_FIELDS: [&str; 5] = ["name", "class", "strand", "query-coords", "target-coords"];
+
+fn parse_feature_id_fields(spec: &str) -> Vec<&str> {
+    spec.split(',').map(|field| {
+        let field = field.trim();
+        if !FEATURE_ID_FIELDS.contains(&field) {
+            eprintln!("ERROR: invalid --feature-id-from field '{}' (available fields: {})", field, FEATURE_ID_FIELDS.join(", "));
+            std::process::exit(1);
+        }
+        field
+    }).collect()
+}
+
+// Label and path of a --features value, LABEL=FILE or FILE (labeled with the path itself)
+fn parse_feature_set(value: &str) -> (&str, &str) {
+    match value.split_once('=') {
+        Some((label, path)) if !label.is_empty() && !label.contains('/') => (label, path),
+        _ => (value, value),
+    }
+}
+
+// Alignment fields available to --filter, besides the PAF tags as `tag.<NAME>`
+pub(super) const ALIGNMENT_FILTER_FIELDS: [&str; 6] = ["query", "target", "strand", "length", "mapq", "identity"];
+
+// Compiles the --filter expression, returning it with the names of the PAF tags it refers to
+fn compile_alignment_filter(expression: &str) -> (expr::Expr, Vec<String>) {
+    let mut tag_names: Vec<String> = Vec::new();
+    let mut resolve = |name: &str| {
+        if let Some(index) = ALIGNMENT_FILTER_FIELDS.iter().position(|field| *field == name) {
+            return Some(index);
+        }
+        let tag_name = name.strip_prefix("tag.")?;
+        let index = tag_names.iter().position(|t| t == tag_name).unwrap_or_else(|| {
+            tag_names.push(tag_name.to_string());
+            tag_names.len() - 1
+        });
+        Some(ALIGNMENT_FILTER_FIELDS.len() + index)
+    };
+    match expr::Expr::compile(expression, &mut resolve) {
+        Ok(filter) => (filter, tag_names),
+        Err(e) => {
+            eprintln!("ERROR: invalid --filter expression (available fields: {}, tag.<NAME>): {}", ALIGNMENT_FILTER_FIELDS.join(", "), e.display(expression));
+            std::process::exit(1);
+        }
+    }
+}
+
+// Compiles the --where expression, whose fields are the output columns
+fn compile_row_filter(expression: &str, header: &[String]) -> expr::Expr {
+    let mut resolve = |name: &str| header.iter().position(|column| column == name);
+    match expr::Expr::compile(expression, &mut resolve) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("ERROR: invalid --where expression (available columns: {}): {}", header.join(", "), e.display(expression));
+            std::process::exit(1);
+        }
+    }
+}
+
+// Resolves a comma-separated list of column names to their positions in the header, exiting on unknown names
+fn select_columns(header: &[String], columns: &str, source: &str) -> Vec<usize> {
+    columns.split(',').map(|name| {
+        let name = name.trim();
+        header.iter().position(|column| column == name).unwrap_or_else(|| {
+            eprintln!("ERROR: unknown column '{}' in {}. Available columns: {}", name, source, header.join(","));
+            std::process::exit(1);
+        })
+    }).collect()
+}
+
+// Positions of the output columns selected by --columns (or --columns-file) and --no-ignored, None for all of them
+pub(super) fn select_output_columns(header: &[String], columns: &Option<(String, String)>, no_ignored: bool) -> Option<Vec<usize>> {
+    let column_selection = columns.as_ref().map(|(columns, source)| select_columns(header, columns, source));
+    if !no_ignored {
+        return column_selection;
+    }
+    let selection = column_selection.unwrap_or_else(|| (0..header.len()).collect());
+    Some(selection.into_iter()
+        .filter(|&index| !header[index].starts_with("ignored.in.query.bp") && !header[index].starts_with("ignored.in.target.bp"))
+        .collect())
+}
+
+// Reads the column names of --columns-file as a --columns list
+fn read_columns_file(file_path: &str) -> String {
+    let mut names: Vec<String> = Vec::new();
+    for line in open_file(file_path).lines() {
+        let line = line.expect("Failed to read columns file");
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        names.extend(line.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string));
+    }
+    if names.is_empty() {
+        eprintln!("ERROR: no column names in {}", file_path);
+        std::process::exit(1);
+    }
+    names.join(",")
+}
+
+// Options of the report, shared by the top-level invocation and the report and summary subcommands
+pub fn args() -> Vec<Arg<'static>> {
+    #[allow(unused_mut)]
+    let mut args = vec![
+        input_arg()
+        .multiple_occurrences(true)
+        .help(if cfg!(feature = "cloud") {
+            "Input file, can be gzipped, or an s3:// or gs:// object URL; repeated, the files are processed one by one (or by --threads workers) and their rows written in the order given, with a single header"
+        } else {
+            "Input file, can be gzipped; repeated, the files are processed one by one (or by --threads workers) and their rows written in the order given, with a single header"
+        }),
+        Arg::with_name("threads")
+        .long("threads")
+        .value_name("N")
+        .help("Number of input files (-i) processed at the same time [default: 1]")
+        .takes_value(true),
+        Arg::with_name("input_layout")
+        .long("input-layout")
+        .value_name("LAYOUT")
+        .help("Layout of the input lines: paf-feature (PAF columns and tags followed by the 7 BED columns of the query feature and the 7 of the target feature) or paf (plain PAF, joined with the --features files); checked against the first line")
+        .possible_values(["paf-feature", "paf"])
+        .default_value("paf-feature")
+        .takes_value(true),
+        Arg::with_name("order")
+        .long("order")
+        .value_name("ORDER")
+        .help("Order of the columns of the paf-feature layout: paf-first (PAF columns and tags, then the 14 feature columns), bed-first (the 14 feature columns, then the PAF columns and tags, as written by bedtools intersect -a FEATURES -b ALIGNMENTS), or auto (the only order in which the strand, CIGAR, and coordinate columns of the first line are valid, failing when both or none are) [default: auto]")
+        .possible_values(["auto", "paf-first", "bed-first"])
+        .takes_value(true)
+        .conflicts_with_all(&["features", "query_features", "whole_alignment", "pairs"]),
+        Arg::with_name("features")
+        .long("features")
+        .value_name("[LABEL=]FILE")
+        .help("With --input-layout paf, BED file (chrom, start, end, name, score, strand, class) with the features of both query and target sequences, or a Picard interval list (*.interval_list, 1-based inclusive intervals with sequence, start, end, strand, and name columns after a SAM header, whose @SQ lengths are checked), or a SAF file (GeneID, Chr, Start, End, Strand, 1-based inclusive, recognized by its GeneID header; see --saf-separate-parts); each alignment is counted for every feature name overlapping both its query and target ranges. Repeated, or with a LABEL, each alignment is counted against all the feature sets in the same pass, with a feature.set column giving the LABEL (or the FILE) of the set of each row, also grouping the rows of --summarize")
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .required_if_eq("input_layout", "paf"),
+        Arg::with_name("query_features")
+        .long("query-features")
+        .value_name("FILE")
+        .help("BED file (chrom, start, end, name, score, strand, class) with the features of the query sequences, paired by name with those of --target-features; the input is plain PAF (-i or --paf), each alignment being counted for every pair of features with the same name overlapping its query and target ranges")
+        .takes_value(true)
+        .requires("target_features")
+        .conflicts_with_all(&["input_layout", "features", "whole_alignment"]),
+        Arg::with_name("target_features")
+        .long("target-features")
+        .value_name("FILE")
+        .help("BED file with the features of the target sequences, paired by name with those of --query-features; both can also be Picard interval lists or SAF files, as for --features")
+        .takes_value(true)
+        .requires("query_features"),
+        Arg::with_name("paf")
+        .long("paf")
+        .value_name("FILE")
+        .help("Plain PAF input of --query-features and --target-features, or PAF file whose lines are referred to by --pairs, instead of -i")
+        .takes_value(true)
+        .conflicts_with("input"),
+        Arg::with_name("pairs")
+        .long("pairs")
+        .value_name("FILE")
+        .help("Input file with a 1-based line number of the --paf file followed by the 7 BED columns of the query feature and the 7 of the target feature on each line, counted as the line of the paf-feature layout joining them; the PAF is read once when the pairs are sorted by line number, otherwise the lines are indexed as they are read and fetched again by seeking (plain and BGZF files) or by reading the file again from the start (other gzip files); line numbers that are not positive integers or past the end of the PAF are invalid")
+        .takes_value(true)
+        .requires("paf")
+        .conflicts_with_all(&["input", "input_layout", "features", "query_features", "whole_alignment"]),
+        Arg::with_name("allow_unpaired")
+        .long("allow-unpaired")
+        .help("With --query-features and --target-features, only warn about the feature names found in one of the two files, instead of exiting with an error")
+        .requires("query_features"),
+        Arg::with_name("saf_separate_parts")
+        .long("saf-separate-parts")
+        .help("Name the parts of each GeneID of SAF feature files (rows sharing a GeneID on a sequence, like the exons of a gene) GeneID.part1, GeneID.part2, ... from the 5' end, reporting and summarizing them separately, instead of naming them all GeneID so that --summarize adds them up by gene; the parts are paired by index in both cases"),
+        Arg::with_name("feature_coordinates")
+        .long("feature-coordinates")
+        .value_name("NOTATION")
+        .help("Notation of the start and end columns of the --features, --query-features, and --target-features files: linear (BED coordinates) or path-offset (path:offset, converted to linear coordinates with --path-lengths)")
+        .possible_values(["linear", "path-offset"])
+        .default_value("linear")
+        .takes_value(true),
+        Arg::with_name("path_lengths")
+        .long("path-lengths")
+        .value_name("FILE")
+        .help("With --feature-coordinates path-offset, TSV file with the name, length, and optionally linear sequence (the BED chrom) of each path, in the order in which they are laid out in the linear coordinates of their sequence (the first path starting at 0)")
+        .takes_value(true)
+        .required_if_eq("feature_coordinates", "path-offset"),
+        Arg::with_name("whole_alignment")
+        .long("whole-alignment")
+        .help("Read plain PAF and report each alignment over its whole query and target ranges, as if they were the features (with . as feature name)")
+        .conflicts_with_all(&["input_layout", "features"]),
+        Arg::with_name("uniquify_names")
+        .long("uniquify-names")
+        .value_name("MAP")
+        .help("Rename the features sharing a name with another target locus (seen before in the input) by appending _2, _3, ..., and write the original and new names of all the loci of those names to the MAP file")
+        .takes_value(true),
+        Arg::with_name("feature_id_from")
+        .long("feature-id-from")
+        .value_name("FIELDS")
+        .help("Identify the features by a key composed of the comma-separated FIELDS (name, class, strand, and the query-coords and target-coords of the feature as sequence:start-end, those of the target feature for class and strand), written in a feature.id column with the components separated by | (escaped with a backslash inside them); the key is used instead of the name to group the rows of --summarize, to merge the aligned blocks of --depth, and to detect features sharing a name at several loci")
+        .takes_value(true)
+        .conflicts_with("mosdepth_summary"),
+        Arg::with_name("sample_column")
+        .long("sample-column")
+        .value_name("INDEX")
+        .help("1-based column of the input lines with the sample of each line, for inputs concatenating several samples: written in a sample column, which groups the rows of --summarize (and of merge --summarize) by sample too, and gives the sample of the rows to --sample-threshold instead of the PanSN sample of the query; lines without the column are skipped as invalid")
+        .takes_value(true)
+        .conflicts_with("mosdepth_summary"),
+        Arg::with_name("max_indel_size")
+        .short('m')
+        .long("max-indel-size")
+        .value_name("INT[,INT...]")
+        .help("Maximum size of indels to consider in feature intervals; with a comma-separated list, the columns depending on it are reported for each threshold, suffixed with .max.indel.INT (other options use the first threshold); by default there is no limit and all the indels are counted")
+        .takes_value(true),
+        Arg::with_name("rescue_strand_mismatch")
+        .long("rescue-strand-mismatch")
+        .help("Process lines whose feature strands disagree while query and target are in the same orientation, treating the feature as unstranded, instead of skipping them (adds a strand.rescued column)"),
+        Arg::with_name("cigar_tag")
+        .long("cigar-tag")
+        .value_name("TAG")
+        .help("Name of the PAF tag holding the CIGAR (TAG:Z:); the 13th column is used as the CIGAR when the line has no such tag. Empty to take a bare CIGAR from --cigar-column instead [default: cg]")
+        .takes_value(true),
+        Arg::with_name("cigar_column")
+        .long("cigar-column")
+        .value_name("INDEX")
+        .help("With --cigar-tag \"\", 1-based column of the input lines holding the CIGAR as a bare string (like the CIGAR column of SAM); lines whose column is not a valid CIGAR are skipped as invalid")
+        .takes_value(true)
+        .requires("cigar_tag"),
+        Arg::with_name("match_blocks")
+        .long("blocks")
+        .value_name("FILE")
+        .help("BEDPE file with the aligned blocks of the alignments (query, query start, query end, target, target start, target end, alignment id), used instead of their CIGARs: each line is matched to the blocks of the alignment named by its id:Z: tag, the gaps between consecutive blocks counting as indels (or not aligned bases beyond --max-indel-size) on the query and the target side; the blocks carry no mismatches, as CIGARs using `M`")
+        .takes_value(true)
+        .conflicts_with_all(&["cigar_tag", "cigar_column"]),
+        Arg::with_name("query_fasta")
+        .long("query-fasta")
+        .value_name("FILE")
+        .help("FASTA of the query sequences, indexed with samtools faidx; with --target-fasta, the M operations of the CIGARs are resolved into matches and mismatches by comparing the bases inside the target feature (reverse-complementing the query on the - strand), as if the alignment had an extended CIGAR")
+        .takes_value(true)
+        .requires("target_fasta"),
+        Arg::with_name("target_fasta")
+        .long("target-fasta")
+        .value_name("FILE")
+        .help("FASTA of the target sequences, indexed with samtools faidx (see --query-fasta)")
+        .takes_value(true)
+        .requires("query_fasta"),
+        Arg::with_name("n_policy")
+        .long("n-policy")
+        .value_name("POLICY")
+        .help("How the N bases compare when resolving M operations with --query-fasta: as mismatches, even against another N, or as matches of any base [default: mismatch]")
+        .takes_value(true)
+        .possible_values(["mismatch", "match"])
+        .requires("query_fasta"),
+        Arg::with_name("independent_strands")
+        .long("independent-strands")
+        .help("Take the feature strands as independent of the alignment strand: skip the check comparing them, and walk every alignment in the orientation of its strand column alone, the feature strands only giving the 5' end of the features (for --first-aligned-offset and --emit-indel-positions)")
+        .conflicts_with("rescue_strand_mismatch"),
+        Arg::with_name("cap_at_feature_length")
+        .long("cap-at-feature-length")
+        .help("Cap each count of a row at the length of its feature (the aligned and mismatched bases at the shorter of the query and target features), warning about the rows capped; a safeguard for strict downstream validators, the counts being expected to fit already"),
+        Arg::with_name("mismatches")
+        .long("mismatches")
+        .help("Add a mismatches.bp column with the aligned bases in `X` operations (NA for CIGARs using `M`)"),
+        Arg::with_name("ts_tv")
+        .long("ts-tv")
+        .help("Add ts.count, tv.count, ts.tv.ratio (NA without transversions), and ambiguous.mismatch.count columns classifying the mismatched bases inside both features as transitions (A<->G, C<->T) or transversions from the bases of --query-fasta and --target-fasta, the mismatches involving N or other ambiguity codes being counted apart")
+        .requires("query_fasta"),
+        Arg::with_name("composition")
+        .long("composition")
+        .help("Add gc.frac and n.frac columns with the fractions of G and C (or S) bases and of N bases in the target feature, from the bases of --target-fasta (NA for empty features)")
+        .requires("target_fasta"),
+        Arg::with_name("max_homopolymer")
+        .long("max-homopolymer")
+        .help("With --composition, add a max.homopolymer column with the longest run of the same base in the target feature")
+        .requires("composition"),
+        Arg::with_name("masked_as_n")
+        .long("masked-as-n")
+        .help("With --composition, count the soft-masked (lower case) bases of the target feature as N, rather than by their base")
+        .requires("composition"),
+        Arg::with_name("error_track")
+        .long("error-track")
+        .value_name("FILE")
+        .help("bedGraph of per-base error rates in [0, 1] over the target sequences (bases without a value having no error); adds a weighted.identity column with the identity of the aligned bases inside both features, each matched base weighted by 1 - its error rate (NA for CIGARs using `M`, unless resolved with --query-fasta, and without aligned bases)")
+        .takes_value(true),
+        Arg::with_name("indel_stats")
+        .long("indel-stats")
+        .help("Add indel.events.in.query, indel.events.in.target, and mean.indel.size columns (counted indels overlapping the feature and their mean feature bp per event, NA without indels)"),
+        Arg::with_name("edit_distance")
+        .long("edit-distance")
+        .help("Add an edit.distance column with the edits between the query and target copies of the feature: `X` bases plus the feature bases in indels (NA for CIGARs using `M`)"),
+        Arg::with_name("edit_distance_mode")
+        .long("edit-distance-mode")
+        .value_name("MODE")
+        .help("What the indels add to edit.distance: length (their feature bases) or events (one per indel overlapping the feature)")
+        .possible_values(["length", "events"])
+        .default_value("length")
+        .takes_value(true),
+        Arg::with_name("edit_distance_exclude_large")
+        .long("edit-distance-exclude-large")
+        .help("Leave the indels larger than --max-indel-size (structural events) out of edit.distance")
+        .requires("edit_distance"),
+        Arg::with_name("ignored_by_cause")
+        .long("ignored-by-cause")
+        .help("Add columns splitting the ignored bases of each side by cause, adding up to ignored.in.query.bp and ignored.in.target.bp: outside.alignment.in.*.bp (outside the alignment range), outside.other.feature.in.*.bp (aligned to bases outside the feature on the other sequence), and other.ignored.in.*.bp (not reached by the CIGAR operations); the bases of indels larger than --max-indel-size are not ignored but not.aligned")
+        .conflicts_with("edge_adjustment"),
+        Arg::with_name("force_reverse")
+        .long("force-reverse")
+        .help("[QC only] Count every alignment as if it were on the reverse strand, regardless of the strand column")
+        .conflicts_with("force_forward"),
+        Arg::with_name("force_forward")
+        .long("force-forward")
+        .help("[QC only] Count every alignment as if it were on the forward strand, regardless of the strand column"),
+        Arg::with_name("filter")
+        .long("filter")
+        .value_name("EXPR")
+        .help("Only count alignments matching EXPR, made of comparisons (== != < <= > >=) of the fields query, target, strand, length, mapq, identity, and tag.<NAME> (PAF tags) with numbers or quoted strings, combined with !, &&, || and parentheses (e.g. 'mapq >= 30 || (length >= 50000 && identity >= 0.95)')")
+        .takes_value(true),
+        Arg::with_name("where")
+        .long("where")
+        .value_name("EXPR")
+        .help("Only report rows whose output columns match EXPR, with the same syntax as --filter and the column names as fields (e.g. 'aligned.bp > 0 && ignored.in.query.bp > 1000'); applied before --summarize")
+        .takes_value(true),
+        Arg::with_name("edge_adjustment")
+        .long("edge-adjustment")
+        .help("Move the feature bases of aligned operations whose opposite base falls outside the feature on the other sequence from the ignored columns to edge.adjustment.in.query.bp and edge.adjustment.in.target.bp, so that feature length = aligned + not aligned + indels + edge adjustment + ignored, with ignored only counting bases outside the alignment"),
+        Arg::with_name("min_feature_length")
+        .long("min-feature-length")
+        .value_name("BP")
+        .help("Skip the lines whose feature is shorter than BP (on the side chosen with --feature-length-side)")
+        .takes_value(true),
+        Arg::with_name("max_feature_length")
+        .long("max-feature-length")
+        .value_name("BP")
+        .help("Skip the lines whose feature is longer than BP (on the side chosen with --feature-length-side)")
+        .takes_value(true),
+        Arg::with_name("feature_length_side")
+        .long("feature-length-side")
+        .value_name("SIDE")
+        .help("Feature checked by --min-feature-length and --max-feature-length: target, query, or both (with --input-layout paf, both filters the --features file when it is loaded)")
+        .possible_values(["target", "query", "both"])
+        .default_value("target")
+        .takes_value(true),
+        Arg::with_name("min_alignment_length")
+        .long("min-alignment-length")
+        .value_name("BP")
+        .help("Skip the alignments spanning fewer than BP bases (on the side chosen with --alignment-length-side) before counting their features")
+        .takes_value(true),
+        Arg::with_name("alignment_length_side")
+        .long("alignment-length-side")
+        .value_name("SIDE")
+        .help("Span checked by --min-alignment-length: target (target end - target start), query (query end - query start), or both")
+        .possible_values(["target", "query", "both"])
+        .default_value("target")
+        .takes_value(true)
+        .requires("min_alignment_length"),
+        Arg::with_name("exclude_self")
+        .long("exclude-self")
+        .help("Skip the alignments of a sequence to itself (same query and target name)"),
+        Arg::with_name("exclude_self_sample")
+        .long("exclude-self-sample")
+        .help("Skip the alignments between sequences of the same sample, taken from PanSN names (sample#haplotype#contig), including the alignments of a sequence to itself"),
+        Arg::with_name("exclude_self_diagonal")
+        .long("exclude-self-diagonal")
+        .help("Skip the alignments of a sequence to itself whose query and target ranges overlap (the trivial diagonal), keeping those between different parts of the sequence"),
+        Arg::with_name("primary_only")
+        .long("primary-only")
+        .help("Skip alignments that are not primary according to the PAF tp:A: tag (alignments without the tag are kept)"),
+        Arg::with_name("alignment_type")
+        .long("alignment-type")
+        .help("Add an alignment.type column with the value of the PAF tp:A: tag (P primary, S secondary, unknown if absent)"),
+        Arg::with_name("chain_tag")
+        .long("chain-tag")
+        .value_name("KEY")
+        .help("Add a chain.id column with the value of the PAF tag KEY (for example ch for ch:i:12) of each alignment, to group the features by chain or cluster; empty when the alignment has no such tag")
+        .takes_value(true),
+        Arg::with_name("first_aligned_offset")
+        .long("first-aligned-offset")
+        .help("Add a first.aligned.offset column with the distance from the 5' end of the target feature (its end for features on the - strand) to its first aligned base, NA if none is aligned"),
+        Arg::with_name("cumulative")
+        .long("cumulative")
+        .help("Add a cumulative.aligned.bp column with the running sum of aligned.bp down the input, which has to be sorted by target and target feature start (checked while reading it, exiting with an error at the first line out of order); the sum restarts at each target, or runs over the whole input with --cumulative-reset none. Rows dropped by --where still add to the sum")
+        .conflicts_with_all(&["summarize", "mosdepth_summary"]),
+        Arg::with_name("cumulative_reset")
+        .long("cumulative-reset")
+        .value_name("SCOPE")
+        .help("Where the --cumulative sum restarts: at each target, or never [default: target]")
+        .takes_value(true)
+        .possible_values(["target", "none"])
+        .requires("cumulative"),
+        Arg::with_name("span_jaccard")
+        .long("span-jaccard")
+        .help("Add a span.jaccard column with the intersection over union of the target feature and the span of the target bases aligned to the query feature, NA when both are empty"),
+        Arg::with_name("fraction_of_alignment")
+        .long("fraction-of-alignment")
+        .help("Add an alignment.aligned.bp column with the aligned bases of the whole alignment (M, =, and X operations of its CIGAR) and a fraction.of.alignment column with the share of them aligned over the feature (aligned.bp / alignment.aligned.bp), NA for alignments without aligned bases"),
+        Arg::with_name("emit_indel_positions")
+        .long("emit-indel-positions")
+        .help("Add an indel.positions column listing the counted indels overlapping the features as semicolon-separated pos:len:type entries, pos being the 0-based offset of the indel from the 5' end of the feature (its end for features on the - strand), of the query feature for I and of the target feature for D; . when there are none"),
+        Arg::with_name("cigar_op_types")
+        .long("cigar-op-types")
+        .help("Add a cigar.op.types column listing the CIGAR operation types (M, =, X, I, D, N, S, in this order) seen within the features, comma-separated, like M,I,D; . when there are none"),
+        Arg::with_name("fragmentation")
+        .long("fragmentation")
+        .help("Add a fragmentation column in [0, 1] telling how fragmented the aligned bases of the feature are, the blocks being runs of aligned bases not interrupted by indels (see --fragmentation-formula), NA when aligned.bp is 0"),
+        Arg::with_name("fragmentation_formula")
+        .long("fragmentation-formula")
+        .value_name("FORMULA")
+        .help("Formula of the fragmentation column: largest-block (1 - longest block / aligned.bp, 0 when the aligned bases are contiguous) or blocks (number of blocks / target feature length)")
+        .possible_values(["largest-block", "blocks"])
+        .default_value("largest-block")
+        .takes_value(true),
+        Arg::with_name("fragmentation_precision")
+        .long("fragmentation-precision")
+        .value_name("DIGITS")
+        .help("Number of decimal digits of the fragmentation column [default: 4]")
+        .takes_value(true)
+        .requires("fragmentation"),
+        Arg::with_name("max_gap_merge")
+        .long("max-gap-merge")
+        .value_name("INT")
+        .help("Merge the aligned blocks of the fragmentation column separated by gaps of at most INT bases in both the query and the target (the indels between them summed per side), so that short indels do not fragment the feature; the base counts are not affected [default: 0]")
+        .takes_value(true)
+        .requires("fragmentation"),
+        Arg::with_name("query_coords_frame")
+        .long("query-coords-frame")
+        .value_name("FRAME")
+        .help("Frame of the reported query feature coordinates: forward (of the query sequence as given, as in PAF) or alignment (of the query strand aligned to the target: query length - end, query length - start for alignments on the - strand), needing the query length column [default: forward]")
+        .possible_values(["forward", "alignment"])
+        .takes_value(true),
+        Arg::with_name("dual_coords")
+        .long("dual-coords")
+        .help("Add the feature coordinates also as 1-based, inclusive positions (query.feature.start.1based, query.feature.end.1based, target.feature.start.1based, target.feature.end.1based), next to the 0-based, half-open ones"),
+        Arg::with_name("reference_lengths")
+        .long("reference-lengths")
+        .value_name("FILE")
+        .help("Two-column file (feature name, length) with the canonical length of the features; adds reference.length and aligned.fraction (aligned bases / reference length) columns, and is used as the length of the features for --mosdepth-summary and --gfa-annotate. Features not listed keep the length of their target interval")
+        .takes_value(true),
+        Arg::with_name("combine")
+        .long("combine")
+        .value_name("MODE")
+        .help("Add a combined.aligned.fraction column combining the aligned fractions of the query and the target feature (aligned bases / feature length, or / reference length with --reference-lengths): min, mean, or harmonic (harmonic mean); NA for zero-length features")
+        .possible_values(["min", "mean", "harmonic"])
+        .takes_value(true),
+        Arg::with_name("length_asymmetry")
+        .long("length-asymmetry")
+        .help("Add query.length.over.target.length (query feature length / target feature length), aligned.over.shorter.feature.length and aligned.over.longer.feature.length (aligned bases / length of the shorter and longer copy of the feature), and length.asymmetric (yes when the longer copy is more than --asymmetry-threshold times the shorter one) columns, NA for zero-length denominators; requiring aligned.over.longer.feature.length to pass a threshold (e.g. with --where) requires both aligned fractions to pass it"),
+        Arg::with_name("asymmetry_threshold")
+        .long("asymmetry-threshold")
+        .value_name("RATIO")
+        .help("Ratio of the longer to the shorter copy of a feature above which --length-asymmetry flags it as length.asymmetric [default: 2]")
+        .takes_value(true)
+        .requires("length_asymmetry"),
+        Arg::with_name("passthrough_cols")
+        .long("passthrough-cols")
+        .value_name("INDEX=NAME,...")
+        .help("Copy the given 1-based input columns verbatim into output columns with the given names (with --input-layout paf, the columns of the BED line of the target feature); lines without one of the columns are invalid")
+        .takes_value(true),
+        Arg::with_name("columns")
+        .long("columns")
+        .value_name("NAMES")
+        .help("Comma-separated list of the output columns to emit, in the given order (default: all columns); with --summarize, the columns of the summary (e.g. alignments)")
+        .takes_value(true),
+        Arg::with_name("columns_file")
+        .long("columns-file")
+        .value_name("FILE")
+        .help("Like --columns, with the column names read from FILE, one per line or comma-separated (empty lines and lines starting with # are ignored)")
+        .takes_value(true)
+        .conflicts_with("columns"),
+        Arg::with_name("output")
+        .short('o')
+        .long("output")
+        .value_name("FILE")
+        .help("Write the report to FILE, gzipped if it ends with .gz, instead of the standard output")
+        .takes_value(true),
+        Arg::with_name("json_output")
+        .long("json-output")
+        .value_name("FILE")
+        .help("Also write the rows of the report to FILE as JSON Lines (one object per row, keyed by the column names, with null for NA), gzipped if it ends with .gz; written in the same pass as the TSV report")
+        .takes_value(true)
+        .conflicts_with("mosdepth_summary"),
+        Arg::with_name("list_columns")
+        .long("list-columns")
+        .help("Write the canonical order of the output columns, each with the option adding it, and exit; the columns of a report always follow this order, those of the options not given being left out"),
+        Arg::with_name("skip")
+        .long("skip")
+        .value_name("N")
+        .help("Skip the first N input lines without processing them (they still count for the line numbers); with several input files, the lines of the files taken in order")
+        .takes_value(true),
+        Arg::with_name("head")
+        .long("head")
+        .value_name("N")
+        .help("Stop after processing N input lines (following those skipped with --skip), without reading the rest of the input; with several input files, the lines of the files taken in order, which are then processed one after the other")
+        .takes_value(true),
+        Arg::with_name("trace_line")
+        .long("trace-line")
+        .value_name("N")
+        .help("[debugging] Instead of the report, write a trace of the CIGAR walk of input line N (1-based): for each operation, its index, type, and length, the query and target positions before and after it, and its contributions to the counts of the feature")
+        .takes_value(true)
+        .conflicts_with_all(&["skip", "head", "summarize", "depth", "strand_coverage", "z_normalize", "mosdepth_summary"]),
+        Arg::with_name("explain")
+        .long("explain")
+        .value_name("N")
+        .help("[debugging] Also write a step-by-step explanation of the counts of input line N (1-based): each CIGAR operation with the positions before and after it, its overlaps with the features, and the counters it increments, then the row and the check that the counts add up to the feature lengths")
+        .takes_value(true),
+        Arg::with_name("explain_feature")
+        .long("explain-feature")
+        .value_name("NAME")
+        .help("[debugging] Like --explain, for every line of the feature NAME")
+        .takes_value(true),
+        Arg::with_name("explain_output")
+        .long("explain-output")
+        .value_name("FILE")
+        .help("Write the --explain and --explain-feature explanations to FILE instead of stderr")
+        .takes_value(true),
+        Arg::with_name("keep_going")
+        .long("keep-going")
+        .help("When the input cannot be read to its end (e.g. a truncated or corrupt gzip file), warn and write the results of the lines read so far, instead of exiting with an error")
+        .conflicts_with("trace_line"),
+        Arg::with_name("follow")
+        .long("follow")
+        .help("At the end of the uncompressed input file, wait for more lines as it grows (written by a running job) instead of ending, writing each row as soon as it is produced; the input ends after --follow-until-idle, or on SIGTERM, the run then finishing as usual. A partial last line is only read once its newline arrives"),
+        Arg::with_name("follow_until_idle")
+        .long("follow-until-idle")
+        .value_name("SECONDS")
+        .help("With --follow, end the input once it has not grown for SECONDS")
+        .takes_value(true)
+        .requires("follow"),
+        Arg::with_name("max_line_length")
+        .long("max-line-length")
+        .value_name("BYTES")
+        .help("Skip as invalid, without keeping them in memory, the input lines longer than BYTES (default: no limit)")
+        .takes_value(true),
+        Arg::with_name("max_errors")
+        .long("max-errors")
+        .value_name("INT")
+        .help("Abort with a report of all invalid lines once more than INT of them have been found (0 aborts at the first one); by default invalid lines are skipped with a warning")
+        .takes_value(true),
+        Arg::with_name("stats_json")
+        .long("stats-json")
+        .value_name("FILE")
+        .help("Write a JSON document with the run metadata and statistics (options, inputs, line and skip counts, aligned bases, elapsed time) at exit")
+        .takes_value(true),
+        Arg::with_name("no_ignored")
+        .long("no-ignored")
+        .help("Drop the ignored.in.query.bp and ignored.in.target.bp columns (also from a --columns selection)"),
+        Arg::with_name("depth")
+        .long("depth")
+        .help("Add max.depth.in.query, mean.depth.in.query, max.depth.in.target, and mean.depth.in.target columns with the per-base depth of the aligned blocks of all alignments over each feature (rows are buffered until the end of the input)")
+        .conflicts_with("summarize"),
+        Arg::with_name("strand_coverage")
+        .long("strand-coverage")
+        .help("Add unique.covered.bp, unique.covered.fwd.bp, and unique.covered.rev.bp columns with the bases of the target feature covered by the aligned blocks of all its alignments, counted once however many alignments cover them, and of its + and - strand alignments alone: the blocks of both orientations are in the forward frame of the target, so inverted and split alignments add up (rows are buffered until the end of the input)")
+        .conflicts_with_all(&["summarize", "mosdepth_summary"]),
+        Arg::with_name("z_normalize")
+        .long("z-normalize")
+        .help("Add a feature.aligned.fraction column (aligned bases / target feature length, or / reference length with --reference-lengths; NA for zero-length features) and an aligned.fraction.z column with it standardized over all the rows (mean 0, standard deviation 1; NA when all the fractions are equal), to spot outlier features; rows are buffered until the end of the input")
+        .conflicts_with_all(&["summarize", "mosdepth_summary"]),
+        Arg::with_name("summarize")
+        .long("summarize")
+        .help("Aggregate the output rows by feature, query, and target, summing the count columns, instead of reporting each line"),
+        Arg::with_name("assume_sorted_by_feature")
+        .long("assume-sorted-by-feature")
+        .help("With --summarize, assume that the lines of each feature, query, and target are adjacent, keeping only the current group in memory (exits with an error if a group reappears later)")
+        .requires("summarize"),
+        Arg::with_name("sample_threshold")
+        .long("sample-threshold")
+        .value_name("FRACTION")
+        .help("With --summarize, add an n.samples.above.threshold column with the number of samples (PanSN sample of the query, or the whole query name) covering at least FRACTION of the target feature of the row (same feature name and target): the aligned bases of the alignments of each sample are added up over each target feature interval and capped at its length, as annotate --aggregate unique-coverage does, then divided by the feature length (its reference.length column when present)")
+        .takes_value(true)
+        .conflicts_with("assume_sorted_by_feature"),
+        Arg::with_name("estimate")
+        .long("estimate")
+        .help("Instead of the report, estimate the totals of the rows from a uniform sample of the lines (see --sample-fraction): each line passing the line filters is drawn with the sampling fraction, the rows of the lines drawn passing --where are counted, and the run summary on stderr (and --stats-json) gives the number of rows, aligned bases, target feature bases, and aligned fraction extrapolated to all the lines, with their confidence intervals; no row is written")
+        .requires("sample_fraction")
+        .conflicts_with_all(&["summarize", "depth", "strand_coverage", "z_normalize", "mosdepth_summary", "trace_line", "with_metadata", "json_output"]),
+        Arg::with_name("sample_fraction")
+        .long("sample-fraction")
+        .value_name("FRACTION")
+        .help("With --estimate, probability in (0, 1] of drawing each line passing the line filters")
+        .takes_value(true)
+        .requires("estimate"),
+        Arg::with_name("seed")
+        .long("seed")
+        .value_name("INT")
+        .help("With --estimate, seed of the draws, the same seed drawing the same lines of the same input [default: 0]")
+        .takes_value(true)
+        .requires("estimate"),
+        Arg::with_name("confidence")
+        .long("confidence")
+        .value_name("LEVEL")
+        .help("With --estimate, confidence level in (0, 1) of the intervals, from the normal approximation of the sampling variance [default: 0.95]")
+        .takes_value(true)
+        .requires("estimate"),
+        Arg::with_name("mosdepth_summary")
+        .long("mosdepth-summary")
+        .help("Instead of the report, write the mean coverage of each target feature (aligned bases of all its alignments / feature length) like the mosdepth regions output: chrom, start, end, name, mean, without header")
+        .conflicts_with_all(&["summarize", "depth", "with_metadata"]),
+        Arg::with_name("slow_line_threshold")
+        .long("slow-line-threshold")
+        .value_name("MS")
+        .help("Log the input lines taking more than MS milliseconds to process, with their line number and CIGAR length")
+        .takes_value(true),
+        Arg::with_name("verbose")
+        .long("verbose")
+        .help("Write the counters of the run at the end: input lines processed, rows reported, and lines skipped by cause"),
+        Arg::with_name("profile")
+        .long("profile")
+        .help("Time the phases of the run (reading and decompressing the input, parsing the lines, walking the CIGARs, and writing the rows) and write their wall time and share of the total at the end"),
+        Arg::with_name("self_test_symmetry")
+        .long("self-test-symmetry")
+        .help("[QC only] Also count each alignment with query and target swapped (all of the first 10000 lines, then one every 1000) and report, with exit status 1, those whose counts are not the same with query and target sides exchanged"),
+        Arg::with_name("check_nm")
+        .long("check-nm")
+        .help("[QC only] Compare NM:i, when present, with the edit distance implied by the CIGAR (X and indel bases, up to as many more as the M bases, which may be matches or mismatches) and warn about the alignments outside that range"),
+        Arg::with_name("nm_tolerance")
+        .long("nm-tolerance")
+        .value_name("INT")
+        .help("Difference between NM:i and the CIGAR edit distance range allowed by --check-nm [default: 0]")
+        .takes_value(true)
+        .requires("check_nm"),
+        Arg::with_name("require_nonempty")
+        .long("require-nonempty")
+        .help("Exit with an error, after writing the output, when no row was reported (empty input, or all the lines skipped or filtered out); by default an empty report is a success"),
+        Arg::with_name("with_metadata")
+        .long("with-metadata")
+        .help("Write `##` comment lines with the tool version, command line, date, and effective options before the header"),
+    ];
+    #[cfg(feature = "gfa")]
+    args.extend([
+        Arg::with_name("gfa_annotate")
+        .long("gfa-annotate")
+        .value_name("FILE")
+        .help("[experimental] Write per-feature coverage as GFA-like path annotations (path, start, end, feature, al:i, cv:f) for both query and target")
+        .takes_value(true),
+        Arg::with_name("path_map")
+        .long("path-map")
+        .value_name("FILE")
+        .help("[experimental] Two-column file mapping sequence names to GFA path names for --gfa-annotate")
+        .takes_value(true)
+        .requires("gfa_annotate"),
+    ]);
+    args
+}
+
+// Canonical order of the report columns, by group: id of the option adding the group (None for the columns always
+// written), whether the group is repeated for each --max-indel-size (suffixed with it when there are several), and
+// its columns. Disabled groups are omitted without moving the others; the columns of --passthrough-cols take the
+// names given to them
+const COLUMN_ORDER: [(Option<&str>, bool, &[&str]); 32] = [
+    (None, false, &["feature.name", "query", "query.feature.start", "query.feature.end", "query.strand", "target", "target.feature.start", "target.feature.end", "aligned.bp"]),
+    (None, true, &["not.aligned.in.query.bp", "not.aligned.in.target.bp", "indels.in.query.bp", "indels.in.target", "ignored.in.query.bp", "ignored.in.target.bp"]),
+    (Some("features"), false, &["feature.set"]),
+    (Some("feature_id_from"), false, &["feature.id"]),
+    (Some("sample_column"), false, &[SAMPLE_COLUMN]),
+    (Some("rescue_strand_mismatch"), false, &["strand.rescued"]),
+    (Some("mismatches"), false, &["mismatches.bp"]),
+    (Some("ts_tv"), false, &["ts.count", "tv.count", "ts.tv.ratio", "ambiguous.mismatch.count"]),
+    (Some("composition"), false, &["gc.frac", "n.frac"]),
+    (Some("max_homopolymer"), false, &["max.homopolymer"]),
+    (Some("error_track"), false, &["weighted.identity"]),
+    (Some("indel_stats"), true, &["indel.events.in.query", "indel.events.in.target", "mean.indel.size"]),
+    (Some("edit_distance"), false, &["edit.distance"]),
+    (Some("alignment_type"), false, &["alignment.type"]),
+    (Some("chain_tag"), false, &["chain.id"]),
+    (Some("edge_adjustment"), false, &["edge.adjustment.in.query.bp", "edge.adjustment.in.target.bp"]),
+    (Some("ignored_by_cause"), false, &["outside.alignment.in.query.bp", "outside.alignment.in.target.bp", "outside.other.feature.in.query.bp", "outside.other.feature.in.target.bp", "other.ignored.in.query.bp", "other.ignored.in.target.bp"]),
+    (Some("first_aligned_offset"), false, &["first.aligned.offset"]),
+    (Some("emit_indel_positions"), false, &["indel.positions"]),
+    (Some("cigar_op_types"), false, &["cigar.op.types"]),
+    (Some("fragmentation"), false, &["fragmentation"]),
+    (Some("span_jaccard"), false, &["span.jaccard"]),
+    (Some("fraction_of_alignment"), false, &["alignment.aligned.bp", "fraction.of.alignment"]),
+    (Some("cumulative"), false, &["cumulative.aligned.bp"]),
+    (Some("reference_lengths"), false, &["reference.length", "aligned.fraction"]),
+    (Some("combine"), false, &["combined.aligned.fraction"]),
+    (Some("length_asymmetry"), false, &["query.length.over.target.length", "aligned.over.shorter.feature.length", "aligned.over.longer.feature.length", "length.asymmetric"]),
+    (Some("dual_coords"), false, &["query.feature.start.1based", "query.feature.end.1based", "target.feature.start.1based", "target.feature.end.1based"]),
+    (Some("passthrough_cols"), false, &[]),
+    (Some("depth"), false, &["max.depth.in.query", "mean.depth.in.query", "max.depth.in.target", "mean.depth.in.target"]),
+    (Some("strand_coverage"), false, &["unique.covered.bp", "unique.covered.fwd.bp", "unique.covered.rev.bp"]),
+    (Some("z_normalize"), false, &["feature.aligned.fraction", "aligned.fraction.z"]),
+];
+
+// Writes the canonical column order, one column per line with the option adding it, for --list-columns
+pub(super) fn write_column_order(out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "column\toption")?;
+    for (option, per_threshold, columns) in COLUMN_ORDER {
+        let option = option.map(|id| format!("--{}", id.replace('_', "-"))).unwrap_or_else(|| "always".to_string());
+        if columns.is_empty() {
+            writeln!(out, "NAME\t{}", option)?;
+        }
+        for column in columns {
+            let suffix = if per_threshold { format!("[{}INT]", MAX_INDEL_SUFFIX) } else { String::new() };
+            writeln!(out, "{}{}\t{}", column, suffix, option)?;
+        }
+    }
+    Ok(())
+}
+
+// Options that need all the input in a single run (aggregations and the files written next to the report)
+pub(super) const SINGLE_INPUT_OPTIONS: [&str; 13] = ["cumulative", "estimate", "follow", "summarize", "depth", "strand_coverage", "z_normalize", "mosdepth_summary", "trace_line", "uniquify_names", "stats_json", "explain_output", "gfa_annotate"];
+
+// Input lines processed: those after the first `skip`, and at most `head` of them
+#[derive(Clone, Copy)]
+pub(super) struct LineRange {
+    pub(super) skip: usize,
+    pub(super) head: Option<usize>,
+}
+
+impl LineRange {
+    pub(super) fn new(matches: &ArgMatches) -> LineRange {
+        // Tracing line N processes it alone, as --skip N-1 --head 1
+        match matches.value_of("trace_line").map(|s| s.parse::<usize>().ok().filter(|line| *line > 0).expect("Invalid value for trace line")) {
+            Some(trace_line) => LineRange { skip: trace_line - 1, head: Some(1) },
+            None => LineRange {
+                skip: matches.value_of("skip").map(|s| s.parse::<usize>().expect("Invalid value for skip")).unwrap_or(0),
+                head: matches.value_of("head").map(|s| s.parse::<usize>().expect("Invalid value for head")),
+            },
+        }
+    }
+
+    // Range left for the lines after those of an input of which `skipped` lines were skipped and `processed` processed
+    pub(super) fn after(self, skipped: u64, processed: u64) -> LineRange {
+        LineRange {
+            skip: self.skip - skipped as usize,
+            head: self.head.map(|head| head - processed as usize),
+        }
+    }
+}
+
+// Options of a report run, read once for each input file
+pub(super) struct ReportOptions<'a> {
+    pub(super) summarize: bool,
+    pub(super) max_indel_sizes: Vec<i64>,
+    pub(super) max_indel_size: i64,
+    pub(super) several_thresholds: bool,
+    pub(super) rescue_strand_mismatch: bool,
+    pub(super) independent_strands: bool,
+    pub(super) feature_id_fields: Option<Vec<&'a str>>,
+    pub(super) sample_column: Option<usize>,
+    pub(super) cigar_tag: &'a str,
+    pub(super) cigar_column: Option<usize>,
+    pub(super) cigar_tag_prefix: String,
+    pub(super) match_blocks: Option<MatchBlocks>,
+    pub(super) emit_composition: bool,
+    pub(super) emit_max_homopolymer: bool,
+    pub(super) masked_as_n: bool,
+    pub(super) n_policy: fasta::NPolicy,
+    pub(super) cap_at_feature_length: bool,
+    pub(super) emit_mismatches: bool,
+    pub(super) emit_ts_tv: bool,
+    pub(super) error_track: Option<ErrorTrack>,
+    pub(super) emit_indel_stats: bool,
+    pub(super) emit_edit_distance: bool,
+    pub(super) edit_distance_events: bool,
+    pub(super) edit_distance_exclude_large: bool,
+    pub(super) emit_depth: bool,
+    pub(super) strand_coverage: bool,
+    pub(super) z_normalize: bool,
+    pub(super) edge_adjustment: bool,
+    pub(super) ignored_by_cause: bool,
+    pub(super) max_errors: Option<u64>,
+    pub(super) primary_only: bool,
+    pub(super) feature_length_range: (i64, i64),
+    pub(super) feature_length_side: &'a str,
+    pub(super) exclude_self: bool,
+    pub(super) exclude_self_sample: bool,
+    pub(super) exclude_self_diagonal: bool,
+    pub(super) min_alignment_length: Option<i64>,
+    pub(super) alignment_length_side: &'a str,
+    pub(super) sample_threshold: Option<f64>,
+    pub(super) whole_alignment: bool,
+    pub(super) input_layout: &'a str,
+    // With several (or labeled) --features sets, rows tell which set their feature comes from
+    pub(super) feature_set_column: bool,
+    // Feature sets joined with plain PAF lines: label, query features, and target features when they are in another index
+    pub(super) feature_sets: Vec<(&'a str, features::FeatureIndex, Option<features::FeatureIndex>)>,
+    pub(super) declared_order: Option<features::ColumnOrder>,
+    pub(super) keep_going: bool,
+    pub(super) max_line_length: usize,
+    pub(super) trace_line: Option<usize>,
+    pub(super) explain_line: Option<u64>,
+    pub(super) explain_feature: Option<&'a str>,
+    pub(super) line_range: LineRange,
+    pub(super) alignment_filter: Option<(expr::Expr, Vec<String>)>,
+    pub(super) forced_strand: Option<char>,
+    pub(super) emit_alignment_type: bool,
+    pub(super) chain_tag: Option<&'a str>,
+    pub(super) emit_first_aligned_offset: bool,
+    pub(super) dual_coords: bool,
+    pub(super) reference_lengths: Option<HashMap<String, i64>>,
+    pub(super) combine: Option<&'a str>,
+    pub(super) alignment_frame: bool,
+    pub(super) asymmetry_threshold: Option<f64>,
+    pub(super) emit_span_jaccard: bool,
+    pub(super) emit_fraction_of_alignment: bool,
+    pub(super) emit_indel_positions: bool,
+    pub(super) emit_cigar_op_types: bool,
+    pub(super) fragmentation: Option<Fragmentation>,
+    pub(super) fragmentation_precision: usize,
+    pub(super) max_gap_merge: i64,
+    pub(super) passthrough_columns: Vec<(usize, String)>,
+    // Whether the rows need the keys of their features, for --mosdepth-summary and --gfa-annotate
+    pub(super) keyed_features: bool,
+    pub(super) self_test_symmetry: bool,
+    pub(super) slow_line_threshold: Option<std::time::Duration>,
+    pub(super) check_nm: bool,
+    pub(super) nm_tolerance: i64,
+    // Header in the canonical column order, and the first of its columns computed once all the rows are buffered
+    pub(super) header: Vec<String>,
+    pub(super) buffered_column: usize,
+    pub(super) row_filter: Option<expr::Expr>,
+    pub(super) column_selection: Option<Vec<usize>>,
+    // --columns (or --columns-file) list with the option it comes from, and --no-ignored, selecting the columns of
+    // the summary with --summarize
+    pub(super) columns: Option<(String, String)>,
+    pub(super) no_ignored: bool,
+    // Effective values of the options affecting the results, recorded in the metadata and statistics
+    pub(super) effective_options: Vec<(&'static str, String)>,
+}
+
+impl<'a> ReportOptions<'a> {
+    pub(super) fn parse(matches: &'a ArgMatches, summarize: bool, input_files: &[&str], line_range: LineRange) -> io::Result<ReportOptions<'a>> {
+        let max_indel_sizes: Vec<i64> = matches.value_of("max_indel_size")
+            .map(|s| s.split(',').map(|size| size.trim().parse::<i64>().expect("Invalid value for max indel size")).collect())
+            .unwrap_or_else(|| vec![UNLIMITED_INDEL_SIZE]);
+        if let Some(size) = max_indel_sizes.iter().enumerate().find_map(|(i, size)| max_indel_sizes[..i].contains(size).then_some(size)) {
+            eprintln!("ERROR: --max-indel-size {} is given more than once", size);
+            std::process::exit(1);
+        }
+        let max_indel_size = max_indel_sizes[0];
+        let rescue_strand_mismatch = matches.is_present("rescue_strand_mismatch");
+        let independent_strands = matches.is_present("independent_strands");
+        let feature_id_fields = matches.value_of("feature_id_from").map(parse_feature_id_fields);
+        let sample_column = matches.value_of("sample_column").map(|s| s.parse::<usize>().ok().filter(|&index| index > 0).expect("Invalid value for sample column"));
+        let cigar_tag = matches.value_of("cigar_tag").unwrap_or("cg");
+        let cigar_column = matches.value_of("cigar_column").map(|s| s.parse::<usize>().ok().filter(|&index| index > 0).expect("Invalid value for CIGAR column"));
+        match (cigar_tag.is_empty(), cigar_column.is_some()) {
+            (true, false) => {
+                eprintln!("ERROR: --cigar-tag \"\" needs --cigar-column with the column of the CIGAR");
+                std::process::exit(1);
+            }
+            (false, true) => {
+                eprintln!("ERROR: --cigar-column needs --cigar-tag \"\", the CIGAR being read from the {}:Z: tag otherwise", cigar_tag);
+                std::process::exit(1);
+            }
+            _ => {}
+        }
+        let match_blocks = matches.value_of("match_blocks").map(MatchBlocks::load);
+        let n_policy = match matches.value_of("n_policy") {
+            Some("match") => fasta::NPolicy::Match,
+            _ => fasta::NPolicy::Mismatch,
+        };
+        let error_track = matches.value_of("error_track").map(ErrorTrack::load);
+        if matches.is_present("estimate") && summarize {
+            eprintln!("ERROR: --estimate cannot be used with the summary subcommand");
+            std::process::exit(1);
+        }
+        let max_errors = matches.value_of("max_errors")
+            .map(|s| s.parse::<u64>().expect("Invalid value for max errors"));
+        let feature_length_range = (
+            matches.value_of("min_feature_length").map(|s| s.parse::<i64>().expect("Invalid value for min feature length")).unwrap_or(i64::MIN),
+            matches.value_of("max_feature_length").map(|s| s.parse::<i64>().expect("Invalid value for max feature length")).unwrap_or(i64::MAX),
+        );
+        let feature_length_side = matches.value_of("feature_length_side").unwrap_or("target");
+        let min_alignment_length = matches.value_of("min_alignment_length").map(|s| s.parse::<i64>().expect("Invalid value for min alignment length"));
+        let alignment_length_side = matches.value_of("alignment_length_side").unwrap_or("target");
+        let sample_threshold = matches.value_of("sample_threshold").map(|s| s.parse::<f64>().expect("Invalid value for sample threshold"));
+        if sample_threshold.is_some() && !summarize {
+            eprintln!("ERROR: --sample-threshold needs --summarize or the summary subcommand");
+            std::process::exit(1);
+        }
+        let whole_alignment = matches.is_present("whole_alignment");
+        let paired_features = matches.value_of("query_features").zip(matches.value_of("target_features"));
+        let input_layout = if whole_alignment || paired_features.is_some() { "paf" } else { matches.value_of("input_layout").unwrap_or("paf-feature") };
+        let path_offsets = matches.value_of("path_lengths").filter(|_| matches.value_of("feature_coordinates") == Some("path-offset")).map(features::PathOffsets::load);
+        let separate_parts = matches.is_present("saf_separate_parts");
+        let feature_set_values: Vec<&str> = matches.values_of("features").map(|values| values.collect()).unwrap_or_default();
+        let feature_set_column = feature_set_values.len() > 1 || feature_set_values.iter().any(|value| parse_feature_set(value).0 != *value);
+        let mut feature_sets: Vec<(&str, features::FeatureIndex, Option<features::FeatureIndex>)> = Vec::new();
+        if let Some((query_features, target_features)) = paired_features {
+            // Each file is filtered at load time when its side is checked for length
+            let load_length_range = |side: &str| if feature_length_side == side || feature_length_side == "both" { feature_length_range } else { (i64::MIN, i64::MAX) };
+            let query_index = features::FeatureIndex::load(query_features, load_length_range("query"), path_offsets.as_ref(), separate_parts)?;
+            let target_index = features::FeatureIndex::load(target_features, load_length_range("target"), path_offsets.as_ref(), separate_parts)?;
+            if query_index.filtered_by_length + target_index.filtered_by_length > 0 {
+                eprintln!("INFO: {} features outside the feature length range were not loaded", query_index.filtered_by_length + target_index.filtered_by_length);
+            }
+            let (query_names, target_names) = (query_index.name_counts(), target_index.name_counts());
+            let mut unpaired: Vec<&str> = query_names.keys().filter(|name| !target_names.contains_key(*name))
+                .chain(target_names.keys().filter(|name| !query_names.contains_key(*name)))
+                .copied()
+                .collect();
+            unpaired.sort_unstable();
+            if let Some(example) = unpaired.first() {
+                if !matches.is_present("allow_unpaired") {
+                    eprintln!("ERROR: {} feature names (e.g. '{}') are only in one of {} and {}; use --allow-unpaired to skip them", unpaired.len(), example, query_features, target_features);
+                    std::process::exit(1);
+                }
+                eprintln!("WARNING: {} feature names (e.g. '{}') are only in one of {} and {}, their features are never counted", unpaired.len(), example, query_features, target_features);
+            }
+            let mut duplicated: Vec<&str> = query_names.iter().chain(&target_names).filter(|(_, count)| **count > 1).map(|(name, _)| *name).collect();
+            duplicated.sort_unstable();
+            duplicated.dedup();
+            if let Some(example) = duplicated.first() {
+                eprintln!("WARNING: {} feature names (e.g. '{}') are found more than once in {} or {}; an alignment is counted for every pair of their query and target features it overlaps", duplicated.len(), example, query_features, target_features);
+            }
+            feature_sets.push(("", query_index, Some(target_index)));
+        } else if input_layout == "paf" && !whole_alignment {
+            // Features of any length may be needed on the side whose length is not checked
+            let load_length_range = if feature_length_side == "both" { feature_length_range } else { (i64::MIN, i64::MAX) };
+            for value in &feature_set_values {
+                let (label, file_path) = parse_feature_set(value);
+                if feature_sets.iter().any(|(other, _, _)| *other == label) {
+                    eprintln!("ERROR: --features set '{}' is given more than once", label);
+                    std::process::exit(1);
+                }
+                let feature_index = features::FeatureIndex::load(file_path, load_length_range, path_offsets.as_ref(), separate_parts)?;
+                if feature_index.filtered_by_length > 0 {
+                    eprintln!("INFO: {}{} features outside the feature length range were not loaded", if feature_set_column { format!("{}: ", label) } else { String::new() }, feature_index.filtered_by_length);
+                }
+                feature_sets.push((label, feature_index, None));
+            }
+        }
+        let declared_order = match matches.value_of("order") {
+            Some("paf-first") => Some(features::ColumnOrder::PafFirst),
+            Some("bed-first") => Some(features::ColumnOrder::BedFirst),
+            _ => None,
+        };
+        if declared_order.is_some() && input_layout == "paf" {
+            eprintln!("ERROR: --order only applies to the paf-feature layout");
+            std::process::exit(1);
+        }
+        let max_line_length = matches.value_of("max_line_length")
+            .map(|s| s.parse::<usize>().expect("Invalid value for max line length"))
+            .unwrap_or(usize::MAX);
+        let trace_line = matches.value_of("trace_line")
+            .map(|s| s.parse::<usize>().ok().filter(|line| *line > 0).expect("Invalid value for trace line"));
+        let explain_line = matches.value_of("explain")
+            .map(|s| s.parse::<u64>().ok().filter(|line| *line > 0).expect("Invalid value for explain"));
+        let explain_feature = matches.value_of("explain_feature");
+        // Diagnostic overrides for validating the strand handling, not meant for real analyses
+        let forced_strand = if matches.is_present("force_reverse") {
+            Some('-')
+        } else if matches.is_present("force_forward") {
+            Some('+')
+        } else {
+            None
+        };
+        let asymmetry_threshold = matches.is_present("length_asymmetry").then(|| matches.value_of("asymmetry_threshold")
+            .map(|s| s.parse::<f64>().expect("Invalid value for asymmetry threshold"))
+            .unwrap_or(2.0));
+        let fragmentation = matches.is_present("fragmentation").then(|| match matches.value_of("fragmentation_formula") {
+            Some("blocks") => Fragmentation::Blocks,
+            _ => Fragmentation::LargestBlock,
+        });
+        let fragmentation_precision = matches.value_of("fragmentation_precision")
+            .map(|s| s.parse::<usize>().expect("Invalid value for fragmentation precision"))
+            .unwrap_or(4);
+        let max_gap_merge = matches.value_of("max_gap_merge")
+            .map(|s| s.parse::<i64>().ok().filter(|&size| size >= 0).expect("Invalid value for maximum gap to merge"))
+            .unwrap_or(0);
+        let passthrough_columns = matches.value_of("passthrough_cols").map(parse_passthrough_columns).unwrap_or_default();
+        let slow_line_threshold = matches.value_of("slow_line_threshold")
+            .map(|s| std::time::Duration::from_millis(s.parse::<u64>().expect("Invalid value for slow line threshold")));
+        let nm_tolerance = matches.value_of("nm_tolerance")
+            .map(|s| s.parse::<i64>().ok().filter(|tolerance| *tolerance >= 0).expect("Invalid value for NM tolerance"))
+            .unwrap_or(0);
+
+        let effective_options = vec![
+            ("input", input_files.join(",")),
+            ("input-layout", input_layout.to_string()),
+            ("features", feature_set_values.join(",")),
+            ("query-features", matches.value_of("query_features").unwrap_or("").to_string()),
+            ("target-features", matches.value_of("target_features").unwrap_or("").to_string()),
+            ("paf", matches.value_of("paf").unwrap_or("").to_string()),
+            ("pairs", matches.value_of("pairs").unwrap_or("").to_string()),
+            ("feature-coordinates", matches.value_of("feature_coordinates").unwrap_or("linear").to_string()),
+            ("path-lengths", matches.value_of("path_lengths").unwrap_or("").to_string()),
+            ("whole-alignment", whole_alignment.to_string()),
+            ("uniquify-names", matches.is_present("uniquify_names").to_string()),
+            ("max-indel-size", max_indel_sizes.iter().map(|size| size.to_string()).collect::<Vec<String>>().join(",")),
+            ("rescue-strand-mismatch", rescue_strand_mismatch.to_string()),
+            ("cap-at-feature-length", matches.is_present("cap_at_feature_length").to_string()),
+            ("sample-threshold", sample_threshold.map(|threshold| threshold.to_string()).unwrap_or_else(|| "none".to_string())),
+            ("independent-strands", independent_strands.to_string()),
+            ("feature-id-from", feature_id_fields.as_ref().map(|fields| fields.join(",")).unwrap_or_else(|| "name".to_string())),
+            ("sample-column", sample_column.map(|index| index.to_string()).unwrap_or_else(|| "none".to_string())),
+            ("cigar-tag", cigar_tag.to_string()),
+            ("cigar-column", cigar_column.map(|index| index.to_string()).unwrap_or_else(|| "none".to_string())),
+            ("max-gap-merge", max_gap_merge.to_string()),
+            ("cigar-op-types", matches.is_present("cigar_op_types").to_string()),
+            ("blocks", matches.value_of("match_blocks").unwrap_or("none").to_string()),
+            ("query-fasta", matches.value_of("query_fasta").unwrap_or("none").to_string()),
+            ("target-fasta", matches.value_of("target_fasta").unwrap_or("none").to_string()),
+            ("n-policy", matches.value_of("n_policy").unwrap_or("mismatch").to_string()),
+            ("ts-tv", matches.is_present("ts_tv").to_string()),
+            ("composition", matches.is_present("composition").to_string()),
+            ("max-homopolymer", matches.is_present("max_homopolymer").to_string()),
+            ("masked-as-n", matches.is_present("masked_as_n").to_string()),
+            ("error-track", matches.value_of("error_track").unwrap_or("none").to_string()),
+            ("cumulative", matches.is_present("cumulative").to_string()),
+            ("cumulative-reset", matches.value_of("cumulative_reset").unwrap_or("target").to_string()),
+            ("estimate", matches.is_present("estimate").to_string()),
+            ("sample-fraction", matches.value_of("sample_fraction").unwrap_or("none").to_string()),
+            ("seed", matches.value_of("seed").unwrap_or("0").to_string()),
+            ("confidence", matches.value_of("confidence").unwrap_or("0.95").to_string()),
+            ("primary-only", matches.is_present("primary_only").to_string()),
+            ("min-feature-length", matches.value_of("min_feature_length").unwrap_or("none").to_string()),
+            ("max-feature-length", matches.value_of("max_feature_length").unwrap_or("none").to_string()),
+            ("feature-length-side", feature_length_side.to_string()),
+            ("exclude-self", matches.is_present("exclude_self").to_string()),
+            ("exclude-self-sample", matches.is_present("exclude_self_sample").to_string()),
+            ("exclude-self-diagonal", matches.is_present("exclude_self_diagonal").to_string()),
+            ("min-alignment-length", min_alignment_length.map(|length| length.to_string()).unwrap_or_else(|| "none".to_string())),
+            ("alignment-length-side", alignment_length_side.to_string()),
+            ("filter", matches.value_of("filter").unwrap_or("").to_string()),
+            ("where", matches.value_of("where").unwrap_or("").to_string()),
+            ("skip", line_range.skip.to_string()),
+            ("keep-going", matches.is_present("keep_going").to_string()),
+            ("follow", matches.is_present("follow").to_string()),
+            ("follow-until-idle", matches.value_of("follow_until_idle").unwrap_or("none").to_string()),
+            ("head", line_range.head.map(|head| head.to_string()).unwrap_or_else(|| "none".to_string())),
+            ("reference-lengths", matches.value_of("reference_lengths").unwrap_or("").to_string()),
+            ("explain", explain_line.map(|line| line.to_string()).unwrap_or_else(|| "none".to_string())),
+            ("explain-feature", explain_feature.unwrap_or("").to_string()),
+            ("order", matches.value_of("order").unwrap_or("auto").to_string()),
+            ("query-coords-frame", matches.value_of("query_coords_frame").unwrap_or("forward").to_string()),
+            ("forced-strand", forced_strand.map(|strand| strand.to_string()).unwrap_or_else(|| "none".to_string())),
+        ];
+
+        // Names of the columns depending on the indel size threshold, suffixed with it when there are several
+        let several_thresholds = max_indel_sizes.len() > 1;
+        let threshold_columns = |names: &[&str]| -> Vec<String> {
+            max_indel_sizes.iter().flat_map(|size| names.iter().map(move |name| {
+                if several_thresholds { format!("{}{}{}", name, MAX_INDEL_SUFFIX, size) } else { name.to_string() }
+            })).collect()
+        };
+        // Header in the canonical column order, the groups being added by the options given
+        let mut header: Vec<String> = Vec::new();
+        let mut buffered_column = None;
+        for (option, per_threshold, columns) in COLUMN_ORDER {
+            if !option.is_none_or(|id| matches.is_present(id)) {
+                continue;
+            }
+            match option {
+                Some("passthrough_cols") => for (_, name) in &passthrough_columns {
+                    if header.contains(name) {
+                        eprintln!("ERROR: --passthrough-cols column name '{}' is already an output column", name);
+                        std::process::exit(1);
+                    }
+                    header.push(name.clone());
+                },
+                Some("features") => if feature_set_column {
+                    header.extend(columns.iter().map(|name| name.to_string()));
+                },
+                Some("depth") | Some("strand_coverage") | Some("z_normalize") => {
+                    buffered_column.get_or_insert(header.len());
+                    header.extend(columns.iter().map(|name| name.to_string()));
+                }
+                _ if per_threshold => header.extend(threshold_columns(columns)),
+                _ => header.extend(columns.iter().map(|name| name.to_string())),
+            }
+        }
+        let buffered_column = buffered_column.unwrap_or(header.len());
+        let row_filter = matches.value_of("where").map(|expression| compile_row_filter(expression, &header));
+        let columns = match (matches.value_of("columns"), matches.value_of("columns_file")) {
+            (Some(columns), _) => Some((columns.to_string(), "--columns".to_string())),
+            (None, Some(file_path)) => Some((read_columns_file(file_path), format!("--columns-file {}", file_path))),
+            (None, None) => None,
+        };
+        let no_ignored = matches.is_present("no_ignored");
+        // The summary has columns of its own, so its selection is made once its header is known
+        let column_selection = if summarize { None } else { select_output_columns(&header, &columns, no_ignored) };
+
+        Ok(ReportOptions {
+            summarize,
+            max_indel_size,
+            several_thresholds,
+            rescue_strand_mismatch,
+            independent_strands,
+            feature_id_fields,
+            sample_column,
+            cigar_tag,
+            cigar_column,
+            cigar_tag_prefix: format!("{}:Z:", cigar_tag),
+            match_blocks,
+            emit_composition: matches.is_present("composition"),
+            emit_max_homopolymer: matches.is_present("max_homopolymer"),
+            masked_as_n: matches.is_present("masked_as_n"),
+            n_policy,
+            cap_at_feature_length: matches.is_present("cap_at_feature_length"),
+            emit_mismatches: matches.is_present("mismatches"),
+            emit_ts_tv: matches.is_present("ts_tv"),
+            error_track,
+            emit_indel_stats: matches.is_present("indel_stats"),
+            emit_edit_distance: matches.is_present("edit_distance"),
+            edit_distance_events: matches.value_of("edit_distance_mode") == Some("events"),
+            edit_distance_exclude_large: matches.is_present("edit_distance_exclude_large"),
+            emit_depth: matches.is_present("depth"),
+            strand_coverage: matches.is_present("strand_coverage"),
+            z_normalize: matches.is_present("z_normalize"),
+            edge_adjustment: matches.is_present("edge_adjustment"),
+            ignored_by_cause: matches.is_present("ignored_by_cause"),
+            max_errors,
+            primary_only: matches.is_present("primary_only"),
+            feature_length_range,
+            feature_length_side,
+            exclude_self: matches.is_present("exclude_self"),
+            exclude_self_sample: matches.is_present("exclude_self_sample"),
+            exclude_self_diagonal: matches.is_present("exclude_self_diagonal"),
+            min_alignment_length,
+            alignment_length_side,
+            sample_threshold,
+            whole_alignment,
+            input_layout,
+            feature_set_column,
+            feature_sets,
+            declared_order,
+            keep_going: matches.is_present("keep_going"),
+            max_line_length,
+            trace_line,
+            explain_line,
+            explain_feature,
+            line_range,
+            alignment_filter: matches.value_of("filter").map(compile_alignment_filter),
+            forced_strand,
+            emit_alignment_type: matches.is_present("alignment_type"),
+            chain_tag: matches.value_of("chain_tag"),
+            emit_first_aligned_offset: matches.is_present("first_aligned_offset"),
+            dual_coords: matches.is_present("dual_coords"),
+            reference_lengths: matches.value_of("reference_lengths").map(load_reference_lengths),
+            combine: matches.value_of("combine"),
+            alignment_frame: matches.value_of("query_coords_frame") == Some("alignment"),
+            asymmetry_threshold,
+            emit_span_jaccard: matches.is_present("span_jaccard"),
+            emit_fraction_of_alignment: matches.is_present("fraction_of_alignment"),
+            emit_indel_positions: matches.is_present("emit_indel_positions"),
+            emit_cigar_op_types: matches.is_present("cigar_op_types"),
+            fragmentation,
+            fragmentation_precision,
+            max_gap_merge,
+            passthrough_columns,
+            keyed_features: matches.is_present("mosdepth_summary") || (cfg!(feature = "gfa") && matches.is_present("gfa_annotate")),
+            self_test_symmetry: matches.is_present("self_test_symmetry"),
+            slow_line_threshold,
+            check_nm: matches.is_present("check_nm"),
+            nm_tolerance,
+            header,
+            buffered_column,
+            row_filter,
+            column_selection,
+            columns,
+            no_ignored,
+            effective_options,
+            max_indel_sizes,
+        })
+    }
+
+    // Length of a target feature, from --reference-lengths when given
+    pub(super) fn feature_length(&self, name: &str, start: i64, end: i64) -> i64 {
+        self.reference_lengths.as_ref().map_or(end - start, |reference_lengths| reference_length(reference_lengths, name, start, end))
+    }
+}
@@ -0,0 +1,628 @@
+// Rows of the report: the records of an input line, counted and turned into the columns of their rows
+
+use std::io;
+
+use crate::summarize::{pansn_sample, MAX_INDEL_SUFFIX};
+use crate::{aligned_blocks_in_features, cigar, count_aligned_bases_by_threshold, count_ops_by_threshold, expr, fasta, features, find_tag, find_tag_value, indels_in_features, parse_record, FeatureCounts, Interval, Record};
+
+use super::options::{ReportOptions, ALIGNMENT_FILTER_FIELDS};
+use super::{write_explanation, write_explanation_row, FeatureKey, Phase, RunState};
+
+// Separates the components of a feature.id; inside a component, it is escaped with a backslash, as is the backslash
+const FEATURE_ID_SEPARATOR: char = '|';
+
+// Joins the components of a feature.id, in the order of the --feature-id-from fields
+fn compose_feature_id(components: &[String]) -> String {
+    let mut id = String::new();
+    for (index, component) in components.iter().enumerate() {
+        if index > 0 {
+            id.push(FEATURE_ID_SEPARATOR);
+        }
+        for c in component.chars() {
+            if c == FEATURE_ID_SEPARATOR || c == '\\' {
+                id.push('\\');
+            }
+            id.push(c);
+        }
+    }
+    id
+}
+
+// Values of the --passthrough-cols columns of the input line, or of the BED line of the target feature when the
+// features come from --features
+fn passthrough_values<'a>(columns: &[(usize, String)], feature_columns: Option<&'a [String]>, parts: &[&'a str]) -> Result<Vec<&'a str>, String> {
+    columns.iter().map(|(index, name)| {
+        let value = match feature_columns {
+            Some(feature_columns) => feature_columns.get(index - 1).map(|value| value.as_str()),
+            None => parts.get(index - 1).copied(),
+        };
+        value.ok_or_else(|| format!("no column {} for --passthrough-cols {} ({} columns{})", index, name, feature_columns.map(|c| c.len()).unwrap_or(parts.len()), if feature_columns.is_some() { " in the feature BED line" } else { "" }))
+    }).collect()
+}
+
+// CIGAR of the same alignment with query and target swapped: insertions become deletions and vice versa, and
+// for reverse-strand alignments the operations are listed from the other end (the new target is walked forward
+// along the old query)
+fn swap_cigar(cigar: &str, query_strand: char) -> String {
+    let mut operations: Vec<String> = cigar::ops(cigar).map(|(length, op)| {
+        let op = match op {
+            'I' => 'D',
+            'D' => 'I',
+            op => op,
+        };
+        format!("{}{}", length, op)
+    }).collect();
+    if query_strand == '-' {
+        operations.reverse();
+    }
+    operations.concat()
+}
+
+// Lines checked by --self-test-symmetry: all the first ones, then one every SYMMETRY_SAMPLE_EVERY
+const SYMMETRY_SAMPLE_ALL: u64 = 10_000;
+const SYMMETRY_SAMPLE_EVERY: u64 = 1_000;
+
+// Differences between the counts of an alignment and those of the same alignment with query and target swapped,
+// whose query-side and target-side counts are expected to be exchanged
+fn symmetry_discrepancies(counts: &FeatureCounts, swapped: &FeatureCounts) -> Vec<String> {
+    let pairs = [
+        ("aligned.bp", counts.aligned_bases, swapped.aligned_bases),
+        ("mismatches.bp", counts.mismatches, swapped.mismatches),
+        ("not.aligned.in.query.bp", counts.not_aligned_bases_in_query, swapped.not_aligned_bases_in_target),
+        ("not.aligned.in.target.bp", counts.not_aligned_bases_in_target, swapped.not_aligned_bases_in_query),
+        ("indels.in.query.bp", counts.indels_in_query, swapped.indels_in_target),
+        ("indels.in.target", counts.indels_in_target, swapped.indels_in_query),
+        ("ignored.in.query.bp", counts.ignored_bases_in_query, swapped.ignored_bases_in_target),
+        ("ignored.in.target.bp", counts.ignored_bases_in_target, swapped.ignored_bases_in_query),
+        ("indel.events.in.query", counts.indel_events_in_query, swapped.indel_events_in_target),
+        ("indel.events.in.target", counts.indel_events_in_target, swapped.indel_events_in_query),
+        ("edge.adjustment.in.query.bp", counts.edge_adjustment_in_query, swapped.edge_adjustment_in_target),
+        ("edge.adjustment.in.target.bp", counts.edge_adjustment_in_target, swapped.edge_adjustment_in_query),
+    ];
+    pairs.iter()
+        .filter(|(_, value, swapped_value)| value != swapped_value)
+        .map(|(name, value, swapped_value)| format!("{} {} (swapped: {})", name, value, swapped_value))
+        .collect()
+}
+
+// Whether the CIGAR distinguishes matches from mismatches with `=`/`X` operations, and has no `M` one; a missing
+// (`*` or empty) CIGAR tells neither
+fn is_extended_cigar(cigar: &str) -> bool {
+    cigar.contains(['=', 'X']) && !cigar.contains('M')
+}
+
+// Range of edit distances consistent with a CIGAR: `X` and indel bases at least, plus all the `M` bases at most,
+// since `M` does not tell matches from mismatches
+fn cigar_edit_distance_range(cigar: &str) -> (i64, i64) {
+    let (mut known, mut ambiguous) = (0, 0);
+    for (length, op) in cigar::ops(cigar) {
+        match op {
+            'X' | 'I' | 'D' => known += length,
+            'M' => ambiguous += length,
+            _ => {}
+        }
+    }
+    (known, known + ambiguous)
+}
+
+// Aligned bases (`M`, `=`, and `X`) of the whole alignment
+fn cigar_aligned_bases(cigar: &str) -> i64 {
+    cigar::ops(cigar)
+        .filter(|(_, op)| matches!(op, 'M' | '=' | 'X'))
+        .map(|(length, _)| length)
+        .sum()
+}
+
+// Alignment and feature pair of a record, with the CIGAR it is walked with and its sample
+pub(super) struct LineRecord<'a> {
+    pub(super) alignment: Record<'a>,
+    pub(super) cigar: std::borrow::Cow<'a, str>,
+    sample: Option<&'a str>,
+}
+
+// Parses the fields of a record (`parts` being its fields in the PAF-first order), reading the CIGAR from the
+// --cigar-tag, the --cigar-column, or the --blocks of the alignment
+pub(super) fn parse_line_record<'a>(options: &ReportOptions, parts: &'a [&'a str], fields: &[&'a str]) -> Result<LineRecord<'a>, String> {
+    let alignment = parse_record(parts)?;
+    // The CIGAR of a bare column, or of another tag than cg
+    let cigar = match options.cigar_column {
+        Some(index) => match fields.get(index - 1) {
+            Some(value) if features::is_cigar(value) => *value,
+            value => return Err(format!("--cigar-column {} is not a CIGAR: '{}'", index, value.copied().unwrap_or(""))),
+        },
+        None if options.cigar_tag != "cg" => find_tag(alignment.paf_tags, &options.cigar_tag_prefix).unwrap_or(alignment.cigar),
+        None => alignment.cigar,
+    };
+    // The CIGAR of the --blocks of the alignment
+    let cigar = match &options.match_blocks {
+        Some(match_blocks) => {
+            let id = find_tag(alignment.paf_tags, "id:Z:").ok_or_else(|| "no id:Z: tag naming the alignment in --blocks".to_string())?;
+            std::borrow::Cow::Owned(match_blocks.cigar(id, alignment.query_start, alignment.query_end, alignment.query_strand, alignment.target_start, alignment.target_end)?)
+        }
+        None => std::borrow::Cow::Borrowed(cigar),
+    };
+    let sample = match options.sample_column {
+        Some(index) => Some(*fields.get(index - 1).ok_or_else(|| format!("no --sample-column {} ({} fields)", index, fields.len()))?),
+        None => None,
+    };
+    if options.alignment_frame && alignment.query_length.parse::<i64>().map_or(true, |length| length < alignment.query_end) {
+        return Err(format!("invalid query length '{}', needed by --query-coords-frame alignment", alignment.query_length));
+    }
+    Ok(LineRecord { alignment, cigar, sample })
+}
+
+// Compares the NM:i tag of the alignment of a record with the edit distance of its CIGAR (--check-nm)
+pub(super) fn check_nm(options: &ReportOptions, state: &mut RunState, record: &LineRecord) {
+    let Record { query_name, query_start, query_end, query_strand, target_name, target_start, target_end, paf_tags, .. } = record.alignment;
+    match find_tag_value(paf_tags, "NM").map(|nm| nm.parse::<i64>()) {
+        Some(Ok(nm)) => {
+            state.nm_check.checked += 1;
+            let (min_distance, max_distance) = cigar_edit_distance_range(&record.cigar);
+            if nm < min_distance - options.nm_tolerance {
+                state.nm_check.below_cigar += 1;
+                let distance = if max_distance == min_distance { min_distance.to_string() } else { format!("at least {}", min_distance) };
+                eprintln!("WARNING: NM check: line {}: NM below the CIGAR edit distance: NM:i:{}, CIGAR edit distance {} ({}:{}-{} {} {}:{}-{})", state.summary.lines, nm, distance, query_name, query_start, query_end, query_strand, target_name, target_start, target_end);
+            } else if nm > max_distance + options.nm_tolerance {
+                state.nm_check.above_cigar += 1;
+                let distance = if max_distance == min_distance { min_distance.to_string() } else { format!("at most {}", max_distance) };
+                eprintln!("WARNING: NM check: line {}: NM above the CIGAR edit distance: NM:i:{}, CIGAR edit distance {} ({}:{}-{} {} {}:{}-{})", state.summary.lines, nm, distance, query_name, query_start, query_end, query_strand, target_name, target_start, target_end);
+            }
+        }
+        _ => state.nm_check.without_nm += 1,
+    }
+}
+
+// Why a record is not counted: an invalid line, skipped with a warning, or an error aborting the run
+pub(super) enum RecordError {
+    Invalid(String),
+    Fatal(String),
+}
+
+// Row of a counted record, with what the outputs besides the report need
+pub(super) struct CountedRow {
+    pub(super) row: Vec<String>,
+    pub(super) aligned_bases: i64,
+    pub(super) target_feature_length: i64,
+    // Aligned fraction of the target feature, for --z-normalize
+    pub(super) aligned_fraction: Option<f64>,
+    // Query and target features keyed by feature name (or id), with their aligned blocks, for --depth and
+    // --strand-coverage
+    pub(super) depth_blocks: Option<(FeatureKey, Vec<Interval>, FeatureKey, Vec<Interval>)>,
+    pub(super) reverse: bool,
+    // Query and target features keyed by their own names, for --mosdepth-summary and --gfa-annotate
+    pub(super) feature_keys: Option<(FeatureKey, FeatureKey)>,
+}
+
+// Counts the feature pair of a record, applying the alignment and feature filters; None when it is filtered out
+pub(super) fn count_record<'a>(options: &ReportOptions, state: &mut RunState, record: &LineRecord<'a>, feature_columns: Option<&'a [String]>, fields: &[&'a str], feature_set: &str) -> io::Result<Result<Option<CountedRow>, RecordError>> {
+    let Record { query_name, query_start, query_end, query_strand, target_name, target_start, target_end, residue_matches, alignment_block_length, mapping_quality, paf_tags, query_name_2, feature_in_query_start, feature_in_query_end, feature_in_query_name, feature_in_query_strand, target_name_2, feature_in_target_start, feature_in_target_end, feature_in_target_name, feature_in_target_strand, feature_in_target_class, .. } = record.alignment;
+    let passthrough_values = match passthrough_values(&options.passthrough_columns, feature_columns, fields) {
+        Ok(values) => values,
+        Err(reason) => return Ok(Err(RecordError::Invalid(reason))),
+    };
+    let alignment_type = find_tag(paf_tags, "tp:A:").unwrap_or("unknown");
+    let summary = &mut state.summary;
+
+    if let Some((filter, tag_names)) = &options.alignment_filter {
+        let identity = match (residue_matches.parse::<f64>(), alignment_block_length.parse::<f64>()) {
+            (Ok(matches), Ok(length)) if length > 0.0 => expr::Value::Number(matches / length),
+            _ => expr::Value::Missing,
+        };
+        let field = |index: usize| match index {
+            0 => expr::Value::Text(query_name),
+            1 => expr::Value::Text(target_name),
+            2 => expr::Value::Text(query_strand),
+            3 => expr::Value::parse(alignment_block_length),
+            4 => expr::Value::parse(mapping_quality),
+            5 => identity,
+            _ => find_tag_value(paf_tags, &tag_names[index - ALIGNMENT_FILTER_FIELDS.len()]).map(expr::Value::parse).unwrap_or(expr::Value::Missing),
+        };
+        if !filter.eval(&field) {
+            summary.skipped_filter += 1;
+            return Ok(Ok(None));
+        }
+    }
+
+    // Secondary alignments often duplicate the coverage of the primary ones
+    if options.primary_only && alignment_type != "P" && alignment_type != "unknown" {
+        summary.skipped_not_primary += 1;
+        return Ok(Ok(None));
+    }
+
+    let self_alignment = query_name == target_name;
+    if (options.exclude_self && self_alignment)
+        || (options.exclude_self_sample && pansn_sample(query_name) == pansn_sample(target_name))
+        || (options.exclude_self_diagonal && self_alignment && query_start < target_end && target_start < query_end) {
+        summary.skipped_self += 1;
+        return Ok(Ok(None));
+    }
+
+    if let Some(min_alignment_length) = options.min_alignment_length {
+        let (query_long_enough, target_long_enough) = (query_end - query_start >= min_alignment_length, target_end - target_start >= min_alignment_length);
+        let long_enough = match options.alignment_length_side {
+            "query" => query_long_enough,
+            "both" => query_long_enough && target_long_enough,
+            _ => target_long_enough,
+        };
+        if !long_enough {
+            summary.skipped_alignment_length += 1;
+            return Ok(Ok(None));
+        }
+    }
+
+    let length_in_range = |length: i64| length >= options.feature_length_range.0 && length <= options.feature_length_range.1;
+    let query_length_in_range = length_in_range(feature_in_query_end - feature_in_query_start);
+    let target_length_in_range = length_in_range(feature_in_target_end - feature_in_target_start);
+    let feature_length_in_range = match options.feature_length_side {
+        "query" => query_length_in_range,
+        "both" => query_length_in_range && target_length_in_range,
+        _ => target_length_in_range,
+    };
+    if !feature_length_in_range {
+        summary.skipped_feature_length += 1;
+        return Ok(Ok(None));
+    }
+
+    // Checking for matching names and strands
+    if query_name != query_name_2 || target_name != target_name_2 || feature_in_query_name != feature_in_target_name {
+        eprintln!("WARNING: query, target, and/or feature name do not match! Skip this line: {}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", feature_in_query_name, query_name, feature_in_query_start, feature_in_query_end, query_strand, target_name, feature_in_target_start, feature_in_target_end);
+        summary.skipped_name_mismatch += 1;
+        return Ok(Ok(None));
+    }
+    let mut strand_rescued = false;
+    if feature_in_query_strand != feature_in_target_strand && query_strand == "+" && !options.independent_strands {
+        // If the features are on different strands, the query should be reversed in order to align them.
+        // The strand is often just an annotation error, so on request the feature is treated as unstranded
+        if options.rescue_strand_mismatch {
+            strand_rescued = true;
+            summary.rescued_strand_mismatch += 1;
+        } else {
+            eprintln!("WARNING: the feature is on different strands in query and target, but query and target are in the same orientation! Skip this line:  {}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", feature_in_query_name, query_name, feature_in_query_start, feature_in_query_end, query_strand, target_name, feature_in_target_start, feature_in_target_end);
+            summary.skipped_strand_mismatch += 1;
+            return Ok(Ok(None));
+        }
+    }
+
+    // The sample of --estimate is drawn from the lines passing the line filters
+    if summary.estimate.as_mut().is_some_and(|estimate| !estimate.sample()) {
+        return Ok(Ok(None));
+    }
+
+    let detect_duplicate_names = state.name_uniquifier.is_none();
+    let feature_in_query_name = match state.name_uniquifier.as_mut() {
+        Some(name_uniquifier) => name_uniquifier.unique_name(feature_in_query_name, target_name, feature_in_target_start, feature_in_target_end),
+        None => feature_in_query_name,
+    };
+    let feature_in_target_name = feature_in_query_name;
+    let feature_id = options.feature_id_fields.as_ref().map(|fields| {
+        let components: Vec<String> = fields.iter().map(|&field| match field {
+            "name" => feature_in_target_name.to_string(),
+            "class" => feature_in_target_class.to_string(),
+            "strand" => feature_in_target_strand.to_string(),
+            "query-coords" => format!("{}:{}-{}", query_name, feature_in_query_start, feature_in_query_end),
+            _ => format!("{}:{}-{}", target_name, feature_in_target_start, feature_in_target_end),
+        }).collect();
+        compose_feature_id(&components)
+    });
+    // Name identifying the feature in the keyed operations
+    let feature_key = feature_id.as_deref().unwrap_or(feature_in_target_name);
+    if detect_duplicate_names {
+        state.duplicate_names.add(feature_key, target_name, feature_in_target_start, feature_in_target_end);
+    }
+
+    // Forcing the strand only affects the CIGAR walk, the checks and the output keep the strand of the input
+    let walk_strand = options.forced_strand.unwrap_or_else(|| query_strand.chars().next().unwrap());
+    // The M operations resolved from the sequences, the rest of the line reading the CIGAR as extended
+    let resolved_cigar;
+    let cigar = match state.fastas.as_mut().filter(|_| record.cigar.contains('M')) {
+        Some((query_fasta, target_fasta)) => {
+            match fasta::resolve_matches(&record.cigar, query_name, query_start, query_end, walk_strand, target_name, target_start, feature_in_target_start, feature_in_target_end, query_fasta, target_fasta, options.n_policy)? {
+                Ok(extended_cigar) => {
+                    resolved_cigar = extended_cigar;
+                    resolved_cigar.as_str()
+                }
+                Err(reason) => return Ok(Err(RecordError::Invalid(reason))),
+            }
+        }
+        None => &record.cigar,
+    };
+    let substitutions = match state.fastas.as_mut().filter(|_| options.emit_ts_tv) {
+        Some((query_fasta, target_fasta)) => {
+            match fasta::classify_mismatches(cigar, query_name, query_start, query_end, walk_strand, target_name, target_start, feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end, query_fasta, target_fasta)? {
+                Ok(substitutions) => Some(substitutions),
+                Err(reason) => return Ok(Err(RecordError::Invalid(reason))),
+            }
+        }
+        None => None,
+    };
+    let composition = match state.fastas.as_mut().filter(|_| options.emit_composition) {
+        Some((_, target_fasta)) => {
+            let key = (target_name.to_string(), feature_in_target_start, feature_in_target_end);
+            match state.compositions.get(&key) {
+                Some(composition) => Some(*composition),
+                None => match target_fasta.fetch_soft_masked(target_name, feature_in_target_start, feature_in_target_end)? {
+                    Ok(bases) => Some(*state.compositions.entry(key).or_insert(fasta::Composition::of(&bases, options.masked_as_n))),
+                    Err(reason) => return Ok(Err(RecordError::Invalid(reason))),
+                },
+            }
+        }
+        None => None,
+    };
+    let walk_start = state.profile.start();
+    let threshold_counts = count_aligned_bases_by_threshold(
+        query_start, query_end, walk_strand, target_start, target_end, cigar, feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end, &options.max_indel_sizes, options.max_gap_merge
+    );
+    state.profile.add(Phase::CigarWalk, walk_start);
+    let mut threshold_counts = match threshold_counts {
+        Ok(threshold_counts) => threshold_counts,
+        Err(reason) => return Ok(Err(RecordError::Invalid(format!("invalid CIGAR: {}", reason)))),
+    };
+    let line_number = state.summary.lines;
+    let explained = options.explain_line == Some(line_number) || options.explain_feature == Some(feature_in_query_name);
+    if let Some(writer) = state.explain_out.as_mut().filter(|_| explained) {
+        write_explanation(writer, line_number, feature_in_query_name, query_name, query_start, query_end, walk_strand, target_name, target_start, target_end, cigar, feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end, options.max_indel_size)?;
+    }
+    if options.self_test_symmetry && (line_number <= SYMMETRY_SAMPLE_ALL || line_number.is_multiple_of(SYMMETRY_SAMPLE_EVERY)) {
+        let counts = &threshold_counts[0];
+        let swapped_cigar = swap_cigar(cigar, walk_strand);
+        // The swapped walk can reach the operations past the features, which the walk of the line did not check: the
+        // zero-length ones are no-ops there too
+        let swapped = count_ops_by_threshold(
+            target_start, target_end, walk_strand, query_start, query_end, cigar::ops(&swapped_cigar), feature_in_target_start, feature_in_target_end, feature_in_query_start, feature_in_query_end, &[options.max_indel_size], 0
+        ).swap_remove(0);
+        state.symmetry_checked += 1;
+        let discrepancies = symmetry_discrepancies(counts, &swapped);
+        if !discrepancies.is_empty() {
+            state.symmetry_failures += 1;
+            eprintln!("ERROR: symmetry: line {}: {}\t{}\t{}\t{}\t{}\t{}\t{}\t{}: {}", line_number, feature_in_query_name, query_name, feature_in_query_start, feature_in_query_end, query_strand, target_name, feature_in_target_start, feature_in_target_end, discrepancies.join(", "));
+            eprintln!("ERROR: symmetry:   alignment {}:{}-{} {} {}:{}-{}, CIGAR {}, swapped CIGAR {}", query_name, query_start, query_end, walk_strand, target_name, target_start, target_end, cigar, swapped_cigar);
+            eprintln!("ERROR: symmetry:   counts {:?}", counts);
+            eprintln!("ERROR: symmetry:   swapped counts {:?}", swapped);
+        }
+    }
+    if options.cap_at_feature_length {
+        let mut capped: Vec<&str> = Vec::new();
+        for counts in threshold_counts.iter_mut() {
+            for name in counts.cap_at_feature_lengths(feature_in_query_end - feature_in_query_start, feature_in_target_end - feature_in_target_start) {
+                if !capped.contains(&name) {
+                    capped.push(name);
+                }
+            }
+        }
+        if !capped.is_empty() {
+            state.capped_rows += 1;
+            eprintln!("WARNING: line {}: {} capped at the feature length: {}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", line_number, capped.join(", "), feature_in_query_name, query_name, feature_in_query_start, feature_in_query_end, query_strand, target_name, feature_in_target_start, feature_in_target_end);
+        }
+    }
+    let counts = &threshold_counts[0];
+    let cumulative_aligned_bases = match state.running_sum.as_mut().map(|running_sum| running_sum.add(target_name, feature_in_target_start, counts.aligned_bases)) {
+        Some(Ok(sum)) => Some(sum),
+        Some(Err(e)) => return Ok(Err(RecordError::Fatal(format!("--cumulative: {}", e)))),
+        None => None,
+    };
+
+    let columns = RecordColumns {
+        feature_name: feature_in_query_name,
+        walk_strand,
+        cigar,
+        feature_set,
+        feature_id: feature_id.as_deref(),
+        sample: record.sample,
+        strand_rescued,
+        alignment_type,
+        substitutions: substitutions.as_ref(),
+        composition: composition.as_ref(),
+        cumulative_aligned_bases,
+        passthrough_values: &passthrough_values,
+    };
+    let row = build_row(options, &record.alignment, &threshold_counts, &columns);
+    if let Some(writer) = state.explain_out.as_mut().filter(|_| explained) {
+        let threshold_suffix = if options.several_thresholds { format!("{}{}", MAX_INDEL_SUFFIX, options.max_indel_size) } else { String::new() };
+        write_explanation_row(writer, &options.header, &row, &threshold_suffix)?;
+    }
+
+    let interner = &mut state.interner;
+    let depth_blocks = (options.emit_depth || options.strand_coverage).then(|| {
+        let (query_blocks, target_blocks) = aligned_blocks_in_features(query_start, query_end, walk_strand, target_start, cigar, feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end);
+        let query_key = (interner.intern(query_name).0, feature_in_query_start, feature_in_query_end, interner.intern(feature_key).0);
+        let target_key = (interner.intern(target_name).0, feature_in_target_start, feature_in_target_end, interner.intern(feature_key).0);
+        (query_key, query_blocks, target_key, target_blocks)
+    });
+    let target_feature_length = options.feature_length(feature_in_target_name, feature_in_target_start, feature_in_target_end);
+    let feature_keys = options.keyed_features.then(|| (
+        (interner.intern(query_name).0, feature_in_query_start, feature_in_query_end, interner.intern(feature_in_query_name).0),
+        (interner.intern(target_name).0, feature_in_target_start, feature_in_target_end, interner.intern(feature_in_target_name).0),
+    ));
+    Ok(Ok(Some(CountedRow {
+        row,
+        aligned_bases: counts.aligned_bases,
+        target_feature_length: feature_in_target_end - feature_in_target_start,
+        aligned_fraction: (target_feature_length > 0).then(|| counts.aligned_bases as f64 / target_feature_length as f64),
+        depth_blocks,
+        reverse: query_strand == "-",
+        feature_keys,
+    })))
+}
+
+// Values of the columns of a record that do not come from its alignment and counts
+struct RecordColumns<'a> {
+    // Feature name, made unique with --uniquify-names
+    feature_name: &'a str,
+    walk_strand: char,
+    // CIGAR walked, with the M operations resolved with --query-fasta and --target-fasta
+    cigar: &'a str,
+    feature_set: &'a str,
+    feature_id: Option<&'a str>,
+    sample: Option<&'a str>,
+    strand_rescued: bool,
+    alignment_type: &'a str,
+    substitutions: Option<&'a fasta::Substitutions>,
+    composition: Option<&'a fasta::Composition>,
+    cumulative_aligned_bases: Option<i64>,
+    passthrough_values: &'a [&'a str],
+}
+
+// Row of a record up to the buffered columns, in the order of the header
+fn build_row(options: &ReportOptions, alignment: &Record, threshold_counts: &[FeatureCounts], columns: &RecordColumns) -> Vec<String> {
+    let Record { query_name, query_length, query_start, query_end, query_strand, target_name, target_start, paf_tags, feature_in_query_start, feature_in_query_end, feature_in_query_strand, feature_in_target_start, feature_in_target_end, feature_in_target_strand, .. } = *alignment;
+    let RecordColumns { feature_name, walk_strand, cigar, .. } = *columns;
+    let counts = &threshold_counts[0];
+
+    // Query feature coordinates in the --query-coords-frame
+    let (reported_query_start, reported_query_end) = match query_length.parse::<i64>() {
+        Ok(length) if options.alignment_frame && query_strand == "-" => (length - feature_in_query_end, length - feature_in_query_start),
+        _ => (feature_in_query_start, feature_in_query_end),
+    };
+    let mut row = vec![feature_name.to_string(), query_name.to_string(), reported_query_start.to_string(), reported_query_end.to_string(), query_strand.to_string(), target_name.to_string(), feature_in_target_start.to_string(), feature_in_target_end.to_string(), counts.aligned_bases.to_string()];
+    for counts in threshold_counts {
+        let (ignored_bases_in_query, ignored_bases_in_target) = if options.edge_adjustment {
+            (counts.ignored_bases_in_query - counts.edge_adjustment_in_query, counts.ignored_bases_in_target - counts.edge_adjustment_in_target)
+        } else {
+            (counts.ignored_bases_in_query, counts.ignored_bases_in_target)
+        };
+        row.extend([counts.not_aligned_bases_in_query.to_string(), counts.not_aligned_bases_in_target.to_string(), counts.indels_in_query.to_string(), counts.indels_in_target.to_string(), ignored_bases_in_query.to_string(), ignored_bases_in_target.to_string()]);
+    }
+    if options.feature_set_column {
+        row.push(columns.feature_set.to_string());
+    }
+    if let Some(feature_id) = columns.feature_id {
+        row.push(feature_id.to_string());
+    }
+    if let Some(sample) = columns.sample {
+        row.push(sample.to_string());
+    }
+    if options.rescue_strand_mismatch {
+        row.push(columns.strand_rescued.to_string());
+    }
+    if options.emit_mismatches {
+        if is_extended_cigar(cigar) {
+            row.push(counts.mismatches.to_string());
+        } else {
+            row.push("NA".to_string());
+        }
+    }
+    if let Some(substitutions) = columns.substitutions {
+        row.push(substitutions.transitions.to_string());
+        row.push(substitutions.transversions.to_string());
+        row.push(if substitutions.transversions > 0 { format!("{:.4}", substitutions.transitions as f64 / substitutions.transversions as f64) } else { "NA".to_string() });
+        row.push(substitutions.ambiguous.to_string());
+    }
+    if let Some(composition) = columns.composition {
+        let fraction = |fraction: Option<f64>| fraction.map(|fraction| format!("{:.4}", fraction)).unwrap_or_else(|| "NA".to_string());
+        row.push(fraction(composition.gc_fraction()));
+        row.push(fraction(composition.n_fraction()));
+        if options.emit_max_homopolymer {
+            row.push(composition.max_homopolymer.to_string());
+        }
+    }
+    if let Some(error_track) = &options.error_track {
+        let identity = error_track.weighted_identity(cigar, query_start, query_end, walk_strand, target_name, target_start, feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end);
+        row.push(identity.map(|identity| format!("{:.4}", identity)).unwrap_or_else(|| "NA".to_string()));
+    }
+    if options.emit_indel_stats {
+        for counts in threshold_counts {
+            row.push(counts.indel_events_in_query.to_string());
+            row.push(counts.indel_events_in_target.to_string());
+            row.push(counts.mean_indel_size().map(|size| format!("{:.4}", size)).unwrap_or_else(|| "NA".to_string()));
+        }
+    }
+    if options.emit_edit_distance {
+        if is_extended_cigar(cigar) {
+            row.push(counts.edit_distance(options.edit_distance_events, options.edit_distance_exclude_large).to_string());
+        } else {
+            row.push("NA".to_string());
+        }
+    }
+    if options.emit_alignment_type {
+        row.push(columns.alignment_type.to_string());
+    }
+    if let Some(chain_tag) = options.chain_tag {
+        row.push(find_tag_value(paf_tags, chain_tag).unwrap_or("").to_string());
+    }
+    if options.ignored_by_cause {
+        row.extend([counts.outside_alignment_in_query, counts.outside_alignment_in_target, counts.edge_adjustment_in_query, counts.edge_adjustment_in_target, counts.unconsumed_in_query, counts.unconsumed_in_target].iter().map(|bases| bases.to_string()));
+    }
+    if options.edge_adjustment {
+        row.push(counts.edge_adjustment_in_query.to_string());
+        row.push(counts.edge_adjustment_in_target.to_string());
+    }
+    if options.emit_first_aligned_offset {
+        row.push(counts.first_aligned_offset(feature_in_target_start, feature_in_target_end, feature_in_target_strand).map(|offset| offset.to_string()).unwrap_or_else(|| "NA".to_string()));
+    }
+    if options.emit_indel_positions {
+        let mut positions: Vec<(i64, i64, char)> = indels_in_features(query_start, query_end, walk_strand, target_start, cigar, feature_in_query_start, feature_in_query_end, feature_in_target_start, feature_in_target_end, options.max_indel_size)
+            .into_iter()
+            .map(|((start, end), op)| {
+                let (feature_start, feature_end, feature_strand) = if op == 'I' {
+                    (feature_in_query_start, feature_in_query_end, feature_in_query_strand)
+                } else {
+                    (feature_in_target_start, feature_in_target_end, feature_in_target_strand)
+                };
+                let offset = if feature_strand == "-" { feature_end - end } else { start - feature_start };
+                (offset, end - start, op)
+            })
+            .collect();
+        positions.sort();
+        row.push(if positions.is_empty() {
+            ".".to_string()
+        } else {
+            positions.iter().map(|(offset, length, op)| format!("{}:{}:{}", offset, length, op)).collect::<Vec<String>>().join(";")
+        });
+    }
+    if options.emit_cigar_op_types {
+        row.push(counts.op_types());
+    }
+    if let Some(formula) = options.fragmentation {
+        row.push(counts.fragmentation(formula, feature_in_target_end - feature_in_target_start).map(|fragmentation| format!("{:.*}", options.fragmentation_precision, fragmentation)).unwrap_or_else(|| "NA".to_string()));
+    }
+    if options.emit_span_jaccard {
+        row.push(counts.span_jaccard(feature_in_target_start, feature_in_target_end).map(|jaccard| format!("{:.4}", jaccard)).unwrap_or_else(|| "NA".to_string()));
+    }
+    if options.emit_fraction_of_alignment {
+        let alignment_aligned_bases = cigar_aligned_bases(cigar);
+        row.push(alignment_aligned_bases.to_string());
+        row.push(if alignment_aligned_bases > 0 { format!("{:.4}", counts.aligned_bases as f64 / alignment_aligned_bases as f64) } else { "NA".to_string() });
+    }
+    if let Some(sum) = columns.cumulative_aligned_bases {
+        row.push(sum.to_string());
+    }
+    if options.reference_lengths.is_some() {
+        let length = options.feature_length(feature_name, feature_in_target_start, feature_in_target_end);
+        row.push(length.to_string());
+        row.push(if length > 0 { format!("{:.4}", counts.aligned_bases as f64 / length as f64) } else { "NA".to_string() });
+    }
+    if let Some(combine) = options.combine {
+        let (query_length, target_length) = (options.feature_length(feature_name, feature_in_query_start, feature_in_query_end), options.feature_length(feature_name, feature_in_target_start, feature_in_target_end));
+        row.push(if query_length > 0 && target_length > 0 {
+            let (query_fraction, target_fraction) = (counts.aligned_bases as f64 / query_length as f64, counts.aligned_bases as f64 / target_length as f64);
+            let combined = match combine {
+                "min" => query_fraction.min(target_fraction),
+                "mean" => (query_fraction + target_fraction) / 2.0,
+                _ if query_fraction + target_fraction > 0.0 => 2.0 * query_fraction * target_fraction / (query_fraction + target_fraction),
+                _ => 0.0,
+            };
+            format!("{:.4}", combined)
+        } else {
+            "NA".to_string()
+        });
+    }
+    if let Some(asymmetry_threshold) = options.asymmetry_threshold {
+        let (query_length, target_length) = (feature_in_query_end - feature_in_query_start, feature_in_target_end - feature_in_target_start);
+        let (shorter, longer) = (query_length.min(target_length), query_length.max(target_length));
+        let ratio = |numerator: i64, denominator: i64| if denominator > 0 { format!("{:.4}", numerator as f64 / denominator as f64) } else { "NA".to_string() };
+        row.push(ratio(query_length, target_length));
+        row.push(ratio(counts.aligned_bases, shorter));
+        row.push(ratio(counts.aligned_bases, longer));
+        row.push(if longer as f64 > asymmetry_threshold * shorter as f64 { "yes" } else { "no" }.to_string());
+    }
+    if options.dual_coords {
+        // [start, end) 0-based is [start + 1, end] 1-based
+        row.push((reported_query_start + 1).to_string());
+        row.push(reported_query_end.to_string());
+        row.push((feature_in_target_start + 1).to_string());
+        row.push(feature_in_target_end.to_string());
+    }
+    row.extend(columns.passthrough_values.iter().map(|value| value.to_string()));
+    debug_assert_eq!(row.len(), options.buffered_column, "row columns out of the header order");
+    row
+}
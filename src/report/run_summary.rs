@@ -0,0 +1,120 @@
+// Counters of a run, reported on stderr with --verbose and in the --stats-json document, and the invalid lines skipped
+
+use std::io::{self, Write};
+
+use crate::estimate::Estimate;
+use crate::summarize::checked_sum;
+use crate::json_string;
+
+// Counters reported on stderr at the end of the run with --verbose
+#[derive(Default)]
+pub(super) struct RunSummary {
+    pub(super) lines: u64,
+    pub(super) reported: u64,
+    pub(super) skipped_name_mismatch: u64,
+    pub(super) skipped_strand_mismatch: u64,
+    pub(super) rescued_strand_mismatch: u64,
+    pub(super) skipped_not_primary: u64,
+    pub(super) skipped_feature_length: u64,
+    pub(super) skipped_alignment_length: u64,
+    pub(super) skipped_self: u64,
+    pub(super) skipped_invalid: u64,
+    pub(super) skipped_filter: u64,
+    pub(super) skipped_where: u64,
+    // Plain PAF lines overlapping no feature with the same name in query and target
+    pub(super) skipped_no_feature: u64,
+    // Lines before the start of the --skip/--head range, only counted for the line numbers
+    pub(super) skipped_lines: u64,
+    pub(super) aligned_bases: i64,
+    // Sample of the lines with --estimate, and the totals extrapolated from it
+    pub(super) estimate: Option<Estimate>,
+}
+
+// Version of the layout of the --stats-json document, to be increased on incompatible changes
+const STATS_SCHEMA_VERSION: u32 = 1;
+
+impl RunSummary {
+    // Writes the run statistics as a single JSON document
+    pub(super) fn write_json(&self, writer: &mut impl Write, options: &[(&str, String)], input_files: &[&str], elapsed: std::time::Duration) -> io::Result<()> {
+        let command: Vec<String> = std::env::args().map(|arg| json_string(&arg)).collect();
+        let options: Vec<String> = options.iter().map(|(name, value)| format!("{}: {}", json_string(name), json_string(value))).collect();
+        let inputs: Vec<String> = input_files.iter().map(|path| {
+            let size = std::fs::metadata(path).map(|m| m.len().to_string()).unwrap_or_else(|_| "null".to_string());
+            format!("{{\"path\": {}, \"size\": {}}}", json_string(path), size)
+        }).collect();
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"schema_version\": {},", STATS_SCHEMA_VERSION)?;
+        writeln!(writer, "  \"version\": {},", json_string(env!("CARGO_PKG_VERSION")))?;
+        writeln!(writer, "  \"command\": [{}],", command.join(", "))?;
+        writeln!(writer, "  \"options\": {{{}}},", options.join(", "))?;
+        writeln!(writer, "  \"inputs\": [{}],", inputs.join(", "))?;
+        writeln!(writer, "  \"lines\": {},", self.lines)?;
+        writeln!(writer, "  \"reported\": {},", self.reported)?;
+        writeln!(writer, "  \"skipped\": {{\"name_mismatch\": {}, \"strand_mismatch\": {}, \"not_primary\": {}, \"feature_length\": {}, \"alignment_length\": {}, \"self\": {}, \"invalid\": {}, \"filter\": {}, \"where\": {}, \"no_feature\": {}, \"skip\": {}}},", self.skipped_name_mismatch, self.skipped_strand_mismatch, self.skipped_not_primary, self.skipped_feature_length, self.skipped_alignment_length, self.skipped_self, self.skipped_invalid, self.skipped_filter, self.skipped_where, self.skipped_no_feature, self.skipped_lines)?;
+        writeln!(writer, "  \"rescued\": {{\"strand_mismatch\": {}}},", self.rescued_strand_mismatch)?;
+        writeln!(writer, "  \"aligned_bases\": {},", self.aligned_bases)?;
+        if let Some(estimate) = &self.estimate {
+            estimate.write_json(writer)?;
+        }
+        writeln!(writer, "  \"elapsed_seconds\": {:.3}", elapsed.as_secs_f64())?;
+        writeln!(writer, "}}")
+    }
+
+    // Counts a reported row and its aligned bases
+    pub(super) fn add_reported(&mut self, aligned_bases: i64) -> Result<(), String> {
+        self.reported += 1;
+        self.aligned_bases = checked_sum(self.aligned_bases, aligned_bases, "aligned.bp")?;
+        Ok(())
+    }
+
+    // Writes the counters for --verbose, prefixed with `source` (the input file, when there are several)
+    pub(super) fn report(&self, writer: &mut impl Write, source: &str) -> io::Result<()> {
+        writeln!(writer, "INFO: {}processed {} lines: {} reported, {} skipped (name mismatch), {} skipped (strand mismatch), {} rescued (strand mismatch), {} skipped (not primary), {} skipped (feature length), {} skipped (alignment length), {} skipped (self), {} skipped (invalid), {} skipped (filter), {} skipped (where), {} skipped (no feature), {} skipped (--skip)", source, self.lines, self.reported, self.skipped_name_mismatch, self.skipped_strand_mismatch, self.rescued_strand_mismatch, self.skipped_not_primary, self.skipped_feature_length, self.skipped_alignment_length, self.skipped_self, self.skipped_invalid, self.skipped_filter, self.skipped_where, self.skipped_no_feature, self.skipped_lines)
+    }
+}
+
+// Warns about the current line being invalid and counts it, failing once more than --max-errors invalid lines are
+// found
+pub(super) fn skip_invalid_line(summary: &mut RunSummary, errors: &mut Vec<(u64, String)>, max_errors: Option<u64>, reason: String, out: &mut impl Write) -> io::Result<()> {
+    eprintln!("WARNING: line {}: {}. Skip this line", summary.lines, reason);
+    summary.skipped_invalid += 1;
+    if let Some(max_errors) = max_errors {
+        errors.push((summary.lines, reason));
+        if errors.len() as u64 > max_errors {
+            out.flush()?;
+            report_errors("ERROR", errors);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("aborting after {} invalid lines (--max-errors {})", errors.len(), max_errors)));
+        }
+    }
+    Ok(())
+}
+
+// Prints the consolidated list of invalid lines collected with --max-errors
+pub(super) fn report_errors(level: &str, errors: &[(u64, String)]) {
+    eprintln!("{}: {} invalid lines:", level, errors.len());
+    for (line_number, reason) in errors {
+        eprintln!("{}:   line {}: {}", level, line_number, reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reported_aligned_bases_do_not_wrap() {
+        let mut summary = RunSummary { aligned_bases: i64::MAX - 10, ..RunSummary::default() };
+        summary.add_reported(10).unwrap();
+        assert_eq!((summary.reported, summary.aligned_bases), (1, i64::MAX));
+        assert_eq!(summary.add_reported(1).unwrap_err(), format!("the sum of aligned.bp overflows a 64-bit integer ({} + 1)", i64::MAX));
+        assert_eq!(checked_sum(i64::MAX - 1, 1, "aligned.bp"), Ok(i64::MAX));
+    }
+
+    #[test]
+    fn counters_are_written_on_one_line() {
+        let summary = RunSummary { lines: 12, reported: 7, skipped_invalid: 2, skipped_where: 3, ..RunSummary::default() };
+        let mut written = Vec::new();
+        summary.report(&mut written, "input.paf: ").unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), "INFO: input.paf: processed 12 lines: 7 reported, 0 skipped (name mismatch), 0 skipped (strand mismatch), 0 rescued (strand mismatch), 0 skipped (not primary), 0 skipped (feature length), 0 skipped (alignment length), 0 skipped (self), 2 skipped (invalid), 0 skipped (filter), 3 skipped (where), 0 skipped (no feature), 0 skipped (--skip)\n");
+    }
+}
@@ -0,0 +1,215 @@
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Approximate heap usage of a buffered row
+pub fn row_bytes(row: &[String]) -> usize {
+    std::mem::size_of::<Vec<String>>() + row.iter().map(|field| std::mem::size_of::<String>() + field.len()).sum::<usize>()
+}
+
+// Temporary file removed when dropped
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(temp_dir: &Path) -> TempFile {
+        let counter = TEMP_FILE_COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+        TempFile {
+            path: temp_dir.join(format!("feature_level_report.{}.{}.tmp", std::process::id(), counter)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write_rows<'a>(&self, rows: impl Iterator<Item = &'a Vec<String>>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        for row in rows {
+            writeln!(writer, "{}", row.join("\t"))?;
+        }
+        writer.flush()
+    }
+
+    pub fn read_rows(&self) -> io::Result<TempFileRows> {
+        Ok(TempFileRows {
+            lines: BufReader::new(File::open(&self.path)?).lines(),
+        })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub struct TempFileRows {
+    lines: Lines<BufReader<File>>,
+}
+
+impl Iterator for TempFileRows {
+    type Item = io::Result<Vec<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lines.next().map(|line| line.map(|line| line.split('\t').map(|s| s.to_string()).collect()))
+    }
+}
+
+pub type RowStream = Box<dyn Iterator<Item = io::Result<Vec<String>>>>;
+
+// Merges several sorted row streams into one, picking the first stream on ties so that the merge is stable.
+// The temporary files backing the streams are removed when the merge is dropped.
+pub struct MergedRows<F: Fn(&[String], &[String]) -> Ordering> {
+    compare: F,
+    streams: Vec<RowStream>,
+    heads: Vec<Option<Vec<String>>>,
+    _temp_files: Vec<TempFile>,
+}
+
+impl<F: Fn(&[String], &[String]) -> Ordering> MergedRows<F> {
+    pub fn new(compare: F, mut streams: Vec<RowStream>, temp_files: Vec<TempFile>) -> io::Result<MergedRows<F>> {
+        let mut heads = Vec::with_capacity(streams.len());
+        for stream in streams.iter_mut() {
+            heads.push(stream.next().transpose()?);
+        }
+        Ok(MergedRows { compare, streams, heads, _temp_files: temp_files })
+    }
+}
+
+impl<F: Fn(&[String], &[String]) -> Ordering> Iterator for MergedRows<F> {
+    type Item = io::Result<Vec<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut smallest: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            if let Some(row) = head {
+                let is_smaller = match smallest {
+                    Some(j) => (self.compare)(row, self.heads[j].as_ref().unwrap()) == Ordering::Less,
+                    None => true,
+                };
+                if is_smaller {
+                    smallest = Some(i);
+                }
+            }
+        }
+        let i = smallest?;
+        let next_head = match self.streams[i].next().transpose() {
+            Ok(next_head) => next_head,
+            Err(e) => return Some(Err(e)),
+        };
+        std::mem::replace(&mut self.heads[i], next_head).map(Ok)
+    }
+}
+
+// Sorts rows in memory while they fit in the memory limit, otherwise spilling sorted chunks to temporary
+// files that are merged at the end (external merge sort). The sort is stable.
+pub struct ExternalSorter<F: Fn(&[String], &[String]) -> Ordering + 'static> {
+    compare: F,
+    memory_limit: usize,
+    temp_dir: PathBuf,
+    buffer: Vec<Vec<String>>,
+    buffer_bytes: usize,
+    chunks: Vec<TempFile>,
+}
+
+impl<F: Fn(&[String], &[String]) -> Ordering + 'static> ExternalSorter<F> {
+    pub fn new(compare: F, memory_limit: usize, temp_dir: &Path) -> ExternalSorter<F> {
+        ExternalSorter {
+            compare,
+            memory_limit,
+            temp_dir: temp_dir.to_path_buf(),
+            buffer: Vec::new(),
+            buffer_bytes: 0,
+            chunks: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) -> io::Result<()> {
+        self.buffer_bytes += row_bytes(&row);
+        self.buffer.push(row);
+        if self.buffer_bytes > self.memory_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sort_buffer(&mut self) {
+        let compare = &self.compare;
+        self.buffer.sort_by(|a, b| compare(a, b));
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.sort_buffer();
+        let chunk = TempFile::new(&self.temp_dir);
+        chunk.write_rows(self.buffer.iter())?;
+        self.chunks.push(chunk);
+        self.buffer.clear();
+        self.buffer_bytes = 0;
+        Ok(())
+    }
+
+    pub fn spilled_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // Returns the rows in sorted order; temporary files are removed once the returned stream is dropped
+    pub fn finish(mut self) -> io::Result<RowStream> {
+        self.sort_buffer();
+        if self.chunks.is_empty() {
+            return Ok(Box::new(self.buffer.into_iter().map(Ok)));
+        }
+
+        let mut streams: Vec<RowStream> = Vec::with_capacity(self.chunks.len() + 1);
+        for chunk in &self.chunks {
+            streams.push(Box::new(chunk.read_rows()?));
+        }
+        // The buffer holds the most recent rows, so it goes last to keep the merge stable
+        streams.push(Box::new(self.buffer.into_iter().map(Ok)));
+        Ok(Box::new(MergedRows::new(self.compare, streams, self.chunks)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(key: &str, value: &str) -> Vec<String> {
+        vec![key.to_string(), value.to_string()]
+    }
+
+    #[test]
+    fn external_sort_is_stable_across_spills() {
+        let rows = vec![row("b", "1"), row("a", "2"), row("c", "3"), row("a", "4"), row("b", "5"), row("a", "6"), row("c", "7")];
+        let mut expected = rows.clone();
+        expected.sort_by(|a, b| a[0].cmp(&b[0]));
+
+        // Chunks of about two rows are spilled, the last row staying in memory
+        let limit = 2 * row_bytes(&rows[0]) - 1;
+        let mut sorter = ExternalSorter::new(|a: &[String], b: &[String]| a[0].cmp(&b[0]), limit, &std::env::temp_dir());
+        for row in rows {
+            sorter.push(row).unwrap();
+        }
+        assert_eq!(sorter.spilled_chunks(), 3);
+        let sorted: Vec<Vec<String>> = sorter.finish().unwrap().collect::<io::Result<_>>().unwrap();
+        assert_eq!(sorted, expected);
+    }
+
+    #[test]
+    fn temp_files_are_removed_once_merged() {
+        let temp_dir = std::env::temp_dir();
+        let mut sorter = ExternalSorter::new(|a: &[String], b: &[String]| a[0].cmp(&b[0]), 0, &temp_dir);
+        sorter.push(row("b", "1")).unwrap();
+        sorter.push(row("a", "2")).unwrap();
+        let paths: Vec<PathBuf> = sorter.chunks.iter().map(|chunk| chunk.path().to_path_buf()).collect();
+        assert!(paths.iter().all(|path| path.exists()));
+        let sorted = sorter.finish().unwrap();
+        drop(sorted);
+        assert!(paths.iter().all(|path| !path.exists()));
+    }
+}
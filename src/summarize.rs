@@ -0,0 +1,588 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::intern::{Interner, Symbol};
+use crate::spill::{ExternalSorter, MergedRows, RowStream, TempFile};
+
+// Report columns identifying a group
+pub const KEY_COLUMNS: [&str; 3] = ["feature.name", "query", "target"];
+
+// Column of the feature set of the rows (with several --features sets), identifying the group too when present
+pub const SET_COLUMN: &str = "feature.set";
+
+// Column of the composed feature identity of the rows (--feature-id-from), identifying the group too when present
+pub const ID_COLUMN: &str = "feature.id";
+
+// Column of the sample of the rows of a multi-sample input (--sample-column), identifying the group too when present
+pub const SAMPLE_COLUMN: &str = "sample";
+
+// Columns identifying the group after the KEY_COLUMNS, when the rows have them
+const OPTIONAL_KEY_COLUMNS: [&str; 3] = [SET_COLUMN, ID_COLUMN, SAMPLE_COLUMN];
+
+// Report columns that are summed within a group (when present)
+pub const SUMMED_COLUMNS: [&str; 12] = [
+    "aligned.bp",
+    "not.aligned.in.query.bp",
+    "not.aligned.in.target.bp",
+    "indels.in.query.bp",
+    "indels.in.target",
+    "ignored.in.query.bp",
+    "ignored.in.target.bp",
+    "mismatches.bp",
+    "indel.events.in.query",
+    "indel.events.in.target",
+    "edge.adjustment.in.query.bp",
+    "edge.adjustment.in.target.bp",
+];
+
+// Columns added after the sums when the rows have a query.strand column: the aligned.bp of the forward and reverse
+// alignments, the orientation with the most aligned bases (. when tied), and whether both have aligned bases
+pub const ORIENTATION_COLUMNS: [&str; 4] = ["aligned.fwd.bp", "aligned.rev.bp", "dominant.orientation", "mixed.orientation"];
+
+// Separates the name of a column counted for each of several --max-indel-size thresholds from the threshold
+pub const MAX_INDEL_SUFFIX: &str = ".max.indel.";
+
+// Column added by --sample-threshold
+pub const SAMPLES_ABOVE_THRESHOLD_COLUMN: &str = "n.samples.above.threshold";
+
+// Sample of a PanSN sequence name (sample#haplotype#contig), or the whole name when it is not in PanSN format
+pub fn pansn_sample(name: &str) -> &str {
+    name.split('#').next().unwrap_or(name)
+}
+
+// Interned names of the KEY_COLUMNS of a group, followed by those of the OPTIONAL_KEY_COLUMNS of the rows (the empty
+// name for the others)
+type GroupKey = [Symbol; KEY_COLUMNS.len() + OPTIONAL_KEY_COLUMNS.len()];
+
+struct Group {
+    key: GroupKey,
+    // Position of the first row of the group, used to emit groups in input order
+    first_seen: u64,
+    alignments: u64,
+    // None as soon as one of the summed values is NA; followed by the forward and reverse aligned bases when they
+    // are split by orientation
+    sums: Vec<Option<i64>>,
+}
+
+fn format_sum(sum: &Option<i64>) -> String {
+    match sum {
+        Some(total) => total.to_string(),
+        None => "NA".to_string(),
+    }
+}
+
+fn parse_sum(value: &str) -> Option<i64> {
+    value.parse::<i64>().ok()
+}
+
+// Adds a value to a total, failing instead of wrapping around when it would not fit in 64 bits
+pub fn checked_sum(total: i64, value: i64, name: &str) -> Result<i64, String> {
+    total.checked_add(value).ok_or_else(|| format!("the sum of {} overflows a 64-bit integer ({} + {})", name, total, value))
+}
+
+fn add_to_sum(sum: &mut Option<i64>, value: i64, name: &str) -> Result<(), String> {
+    if let Some(total) = sum {
+        *total = checked_sum(*total, value, name)?;
+    }
+    Ok(())
+}
+
+fn group_row(interner: &Interner, group: Group, key_len: usize, split_by_orientation: bool) -> Vec<String> {
+    let mut row: Vec<String> = group.key[..key_len].iter().map(|&symbol| interner.resolve(symbol).to_string()).collect();
+    row.push(group.alignments.to_string());
+    row.extend(group.sums.iter().map(format_sum));
+    if split_by_orientation {
+        add_orientation(&mut row);
+    }
+    row
+}
+
+// Appends the dominant.orientation and mixed.orientation columns of a row ending with the forward and reverse
+// aligned bases
+fn add_orientation(row: &mut Vec<String>) {
+    let (forward, reverse) = (parse_sum(&row[row.len() - 2]), parse_sum(&row[row.len() - 1]));
+    let (dominant, mixed) = match forward.zip(reverse) {
+        Some((forward, reverse)) => (match forward.cmp(&reverse) {
+            Ordering::Greater => "+",
+            Ordering::Less => "-",
+            Ordering::Equal => ".",
+        }, if forward > 0 && reverse > 0 { "yes" } else { "no" }),
+        None => ("NA", "NA"),
+    };
+    row.push(dominant.to_string());
+    row.push(mixed.to_string());
+}
+
+// Target feature of the rows counted by --sample-threshold: OPTIONAL_KEY_COLUMNS of the rows but the SAMPLE_COLUMN
+// (tab-separated, empty without them), feature.name, and target
+type TargetFeature = (String, String, String);
+
+// Aligned bases of each sample over the intervals of a target feature
+#[derive(Default)]
+struct SampleCoverage {
+    lengths: HashMap<(i64, i64), i64>,
+    aligned: HashMap<(String, i64, i64), i64>,
+}
+
+// Samples of the query sequences aligned over each target feature (--sample-threshold)
+struct SampleThreshold {
+    threshold: f64,
+    // Indices of the target.feature.start, target.feature.end, and aligned.bp columns, and of the reference.length
+    // column when present
+    start_index: usize,
+    end_index: usize,
+    aligned_index: usize,
+    length_index: Option<usize>,
+    features: HashMap<TargetFeature, SampleCoverage>,
+}
+
+impl SampleThreshold {
+    fn add(&mut self, feature: TargetFeature, sample: &str, fields: &[&str]) -> Result<(), String> {
+        let parse = |index: usize, name: &str| fields[index].parse::<i64>().map_err(|_| format!("invalid value for {}: {}", name, fields[index]));
+        let start = parse(self.start_index, "target.feature.start")?;
+        let end = parse(self.end_index, "target.feature.end")?;
+        let length = match self.length_index {
+            Some(index) => parse(index, "reference.length")?,
+            None => end - start,
+        };
+        // Unknown aligned bases add none
+        let aligned_bases = if fields[self.aligned_index] == "NA" { 0 } else { parse(self.aligned_index, "aligned.bp")? };
+        let coverage = self.features.entry(feature).or_default();
+        coverage.lengths.insert((start, end), length.max(0));
+        let sample_aligned_bases = coverage.aligned.entry((sample.to_string(), start, end)).or_insert(0);
+        *sample_aligned_bases = sample_aligned_bases.checked_add(aligned_bases).ok_or_else(|| format!("the aligned bases of sample {} overflow a 64-bit integer", sample))?;
+        Ok(())
+    }
+
+    // Number of samples of each target feature whose aligned bases, added up within each interval of the feature
+    // and capped at its length (as annotate --aggregate unique-coverage does), cover at least the threshold fraction
+    // of the feature
+    fn counts(self) -> HashMap<TargetFeature, u64> {
+        let threshold = self.threshold;
+        self.features.into_iter().map(|(feature, coverage)| {
+            let length: i64 = coverage.lengths.values().sum();
+            let mut covered: HashMap<String, i64> = HashMap::new();
+            for ((sample, start, end), aligned_bases) in coverage.aligned {
+                *covered.entry(sample).or_insert(0) += aligned_bases.min(coverage.lengths[&(start, end)]);
+            }
+            let samples = covered.values().filter(|&&bases| length > 0 && bases as f64 / length as f64 >= threshold).count();
+            (feature, samples as u64)
+        }).collect()
+    }
+}
+
+// Target feature and sample of the key of a row (KEY_COLUMNS followed by the optional key columns present) for
+// --sample-threshold: the sample is the SAMPLE_COLUMN when present, or else the PanSN sample of the query
+fn feature_sample<'a, T: AsRef<str>>(optional_keys: &[&str], key: &'a [T]) -> (TargetFeature, &'a str) {
+    let sample_index = optional_keys.iter().position(|&name| name == SAMPLE_COLUMN).map(|index| KEY_COLUMNS.len() + index);
+    let other_keys: Vec<&str> = (KEY_COLUMNS.len()..KEY_COLUMNS.len() + optional_keys.len()).filter(|&index| Some(index) != sample_index).map(|index| key[index].as_ref()).collect();
+    let sample = sample_index.map_or_else(|| pansn_sample(key[1].as_ref()), |index| key[index].as_ref());
+    ((other_keys.join("\t"), key[0].as_ref().to_string(), key[2].as_ref().to_string()), sample)
+}
+
+fn key_hash<T: Hash>(key: &[T]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Aggregates report rows by (feature.name, query, target), and feature.set and feature.id when present, summing
+// the count columns. Groups are emitted in the order in which they are first seen. With a memory limit, partial
+// aggregates are spilled to temporary files sorted by key and merged at the end. With input whose
+// rows of a group are adjacent, only the current group is kept and it is emitted as soon as the key
+// changes; a 64-bit hash of each finished key is retained to detect input violating the assumption.
+// Key names are interned, so that each distinct name is stored once however many groups share it.
+pub struct Summarizer {
+    // Indices of the KEY_COLUMNS, followed by those of the OPTIONAL_KEY_COLUMNS present
+    key_indices: Vec<usize>,
+    optional_keys: Vec<&'static str>,
+    summed_indices: Vec<usize>,
+    summed_names: Vec<String>,
+    // Indices of the query.strand and aligned.bp columns, to split the aligned bases by orientation
+    orientation_indices: Option<(usize, usize)>,
+    interner: Interner,
+    group_index: HashMap<GroupKey, usize>,
+    groups: Vec<Group>,
+    next_sequence: u64,
+    memory_limit: Option<(usize, PathBuf)>,
+    memory_bytes: usize,
+    spills: Vec<TempFile>,
+    // Some with --assume-sorted-by-feature: hashes of the keys of the finished groups
+    finished_keys: Option<HashSet<u64>>,
+    // Some with --sample-threshold
+    sample_threshold: Option<SampleThreshold>,
+}
+
+impl Summarizer {
+    pub fn new(header: &[String]) -> Result<Summarizer, String> {
+        let position = |name: &str| header.iter().position(|column| column == name);
+
+        let mut key_indices = Vec::new();
+        for name in KEY_COLUMNS {
+            match position(name) {
+                Some(index) => key_indices.push(index),
+                None => return Err(format!("cannot summarize without the {} column", name)),
+            }
+        }
+
+        let mut optional_keys = Vec::new();
+        for name in OPTIONAL_KEY_COLUMNS {
+            if let Some(index) = position(name) {
+                key_indices.push(index);
+                optional_keys.push(name);
+            }
+        }
+
+        let mut summed_indices = Vec::new();
+        let mut summed_names = Vec::new();
+        // Also sums the per-threshold copies of a column (e.g. indels.in.query.bp.max.indel.10)
+        for name in SUMMED_COLUMNS {
+            for (index, column) in header.iter().enumerate() {
+                if column == name || column.strip_prefix(name).is_some_and(|suffix| suffix.starts_with(MAX_INDEL_SUFFIX)) {
+                    summed_indices.push(index);
+                    summed_names.push(column.to_string());
+                }
+            }
+        }
+
+        let orientation_indices = position("query.strand").zip(position("aligned.bp"));
+
+        Ok(Summarizer {
+            key_indices,
+            optional_keys,
+            summed_indices,
+            summed_names,
+            orientation_indices,
+            interner: Interner::default(),
+            group_index: HashMap::new(),
+            groups: Vec::new(),
+            next_sequence: 0,
+            memory_limit: None,
+            memory_bytes: 0,
+            spills: Vec::new(),
+            finished_keys: None,
+            sample_threshold: None,
+        })
+    }
+
+    pub fn with_memory_limit(mut self, memory_limit: usize, temp_dir: &Path) -> Summarizer {
+        self.memory_limit = Some((memory_limit, temp_dir.to_path_buf()));
+        self
+    }
+
+    pub fn assume_sorted(mut self) -> Summarizer {
+        self.finished_keys = Some(HashSet::new());
+        self
+    }
+
+    // Adds the SAMPLES_ABOVE_THRESHOLD_COLUMN, which needs the target feature coordinates of the rows
+    pub fn with_sample_threshold(mut self, header: &[String], threshold: f64) -> Result<Summarizer, String> {
+        let position = |name: &str| header.iter().position(|column| column == name).ok_or_else(|| format!("cannot count the samples above the threshold without the {} column", name));
+        self.sample_threshold = Some(SampleThreshold {
+            threshold,
+            start_index: position("target.feature.start")?,
+            end_index: position("target.feature.end")?,
+            aligned_index: position("aligned.bp")?,
+            length_index: position("reference.length").ok(),
+            features: HashMap::new(),
+        });
+        Ok(self)
+    }
+
+    pub fn header(&self) -> Vec<String> {
+        let mut header: Vec<String> = KEY_COLUMNS.iter().map(|name| name.to_string()).collect();
+        header.extend(self.optional_keys.iter().map(|name| name.to_string()));
+        header.push("alignments".to_string());
+        header.extend(self.summed_names.iter().cloned());
+        if self.orientation_indices.is_some() {
+            header.extend(ORIENTATION_COLUMNS.iter().map(|name| name.to_string()));
+        }
+        if self.sample_threshold.is_some() {
+            header.push(SAMPLES_ABOVE_THRESHOLD_COLUMN.to_string());
+        }
+        header
+    }
+
+    // Number of key columns of the summarized rows
+    fn key_len(&self) -> usize {
+        self.key_indices.len()
+    }
+
+    // Summed values of a group, including the forward and reverse aligned bases
+    fn sum_count(&self) -> usize {
+        self.summed_indices.len() + if self.orientation_indices.is_some() { 2 } else { 0 }
+    }
+
+    pub fn spilled_chunks(&self) -> usize {
+        self.spills.len()
+    }
+
+    // Adds a row; with sorted input, returns the previous group once the row starts a new one
+    pub fn add(&mut self, fields: &[&str]) -> Result<Option<Vec<String>>, String> {
+        let names: [&str; KEY_COLUMNS.len() + OPTIONAL_KEY_COLUMNS.len()] = std::array::from_fn(|i| self.key_indices.get(i).map_or("", |&index| fields[index]));
+        let key_len = self.key_len();
+
+        let mut finished_group = None;
+        if let Some(finished_keys) = self.finished_keys.as_mut() {
+            let interner = &self.interner;
+            if self.groups.last().is_some_and(|group| group.key.iter().zip(&names).any(|(&symbol, name)| interner.resolve(symbol) != *name)) {
+                let group = self.groups.pop().unwrap();
+                self.group_index.clear();
+                let row = group_row(interner, group, key_len, self.orientation_indices.is_some());
+                finished_keys.insert(key_hash(&row[..key_len]));
+                finished_group = Some(row);
+                // Only the current group is kept, and with it only its names
+                self.interner.clear();
+            }
+            if self.groups.is_empty() && finished_keys.contains(&key_hash(&names[..key_len])) {
+                return Err(format!("the rows of {} are not adjacent, so the input is not sorted by feature", names[..key_len].join("/")));
+            }
+        }
+
+        let mut key: GroupKey = [0; KEY_COLUMNS.len() + OPTIONAL_KEY_COLUMNS.len()];
+        for (symbol, name) in key.iter_mut().zip(names) {
+            let (interned, bytes) = self.interner.intern(name);
+            *symbol = interned;
+            self.memory_bytes += bytes;
+        }
+        let group_id = match self.group_index.get(&key) {
+            Some(&group_id) => group_id,
+            None => {
+                // The key (symbols) is stored both in the index and in the group
+                self.memory_bytes += 2 * std::mem::size_of::<GroupKey>() + std::mem::size_of::<usize>() + std::mem::size_of::<Group>() + self.sum_count() * std::mem::size_of::<Option<i64>>();
+                self.groups.push(Group {
+                    key,
+                    first_seen: self.next_sequence,
+                    alignments: 0,
+                    sums: vec![Some(0); self.sum_count()],
+                });
+                self.group_index.insert(key, self.groups.len() - 1);
+                self.groups.len() - 1
+            }
+        };
+        self.next_sequence += 1;
+
+        let group = &mut self.groups[group_id];
+        group.alignments += 1;
+        for ((sum, &index), name) in group.sums.iter_mut().zip(&self.summed_indices).zip(&self.summed_names) {
+            let value = fields[index];
+            if value == "NA" {
+                *sum = None;
+            } else {
+                let value = value.parse::<i64>().map_err(|_| format!("invalid value for {}: {}", name, value))?;
+                add_to_sum(sum, value, name)?;
+            }
+        }
+        if let Some((strand_index, aligned_index)) = self.orientation_indices {
+            let value = fields[aligned_index];
+            let sum = &mut group.sums[self.summed_indices.len() + usize::from(fields[strand_index] == "-")];
+            if value == "NA" {
+                *sum = None;
+            } else {
+                let value = value.parse::<i64>().map_err(|_| format!("invalid value for aligned.bp: {}", value))?;
+                add_to_sum(sum, value, ORIENTATION_COLUMNS[usize::from(fields[strand_index] == "-")])?;
+            }
+        }
+
+        if let Some(sample_threshold) = self.sample_threshold.as_mut() {
+            let (feature, sample) = feature_sample(&self.optional_keys, &names);
+            sample_threshold.add(feature, sample, fields)?;
+        }
+
+        if let Some((memory_limit, _)) = &self.memory_limit {
+            if self.finished_keys.is_none() && self.memory_bytes > *memory_limit {
+                self.spill().map_err(|e| format!("failed to spill partial aggregates: {}", e))?;
+            }
+        }
+        Ok(finished_group)
+    }
+
+    // Writes the partial aggregates sorted by key as (key..., first_seen, alignments, sums...) rows
+    fn spill(&mut self) -> io::Result<()> {
+        let temp_dir = &self.memory_limit.as_ref().unwrap().1;
+        let key_len = self.key_len();
+        let interner = &self.interner;
+        self.groups.sort_by(|a, b| a.key.iter().map(|&symbol| interner.resolve(symbol)).cmp(b.key.iter().map(|&symbol| interner.resolve(symbol))));
+        let rows: Vec<Vec<String>> = self.groups.drain(..).map(|group| {
+            let mut row: Vec<String> = group.key[..key_len].iter().map(|&symbol| interner.resolve(symbol).to_string()).collect();
+            row.push(group.first_seen.to_string());
+            row.push(group.alignments.to_string());
+            row.extend(group.sums.iter().map(format_sum));
+            row
+        }).collect();
+        let spill = TempFile::new(temp_dir);
+        spill.write_rows(rows.iter())?;
+        self.spills.push(spill);
+        self.group_index.clear();
+        self.interner.clear();
+        self.memory_bytes = 0;
+        Ok(())
+    }
+
+    // Returns the summarized rows, in the order in which the groups were first seen
+    pub fn finish(mut self) -> io::Result<RowStream> {
+        let sample_counts = self.sample_threshold.take().map(SampleThreshold::counts);
+        let optional_keys = self.optional_keys.clone();
+        let rows = self.finish_groups()?;
+        Ok(match sample_counts {
+            // Rows of the target features without any sample have no entry
+            Some(sample_counts) => Box::new(rows.map(move |row| row.map(|mut row| {
+                let (feature, _) = feature_sample(&optional_keys, &row);
+                row.push(sample_counts.get(&feature).copied().unwrap_or(0).to_string());
+                row
+            }))),
+            None => rows,
+        })
+    }
+
+    fn finish_groups(mut self) -> io::Result<RowStream> {
+        let split_by_orientation = self.orientation_indices.is_some();
+        let key_len = self.key_len();
+        if self.spills.is_empty() {
+            let interner = self.interner;
+            return Ok(Box::new(self.groups.into_iter().map(move |group| Ok(group_row(&interner, group, key_len, split_by_orientation)))));
+        }
+
+        self.spill()?;
+        let (memory_limit, temp_dir) = self.memory_limit.take().unwrap();
+        let mut sum_names = self.summed_names.clone();
+        if split_by_orientation {
+            sum_names.extend(ORIENTATION_COLUMNS[..2].iter().map(|name| name.to_string()));
+        }
+
+        // Combine the partial aggregates of each key, then restore the first-seen order
+        let mut streams: Vec<RowStream> = Vec::with_capacity(self.spills.len());
+        for spill in &self.spills {
+            streams.push(Box::new(spill.read_rows()?));
+        }
+        let merged = MergedRows::new(move |a: &[String], b: &[String]| a[..key_len].cmp(&b[..key_len]), streams, self.spills)?;
+        let by_first_seen = |a: &[String], b: &[String]| -> Ordering {
+            a[0].parse::<u64>().unwrap_or(0).cmp(&b[0].parse::<u64>().unwrap_or(0))
+        };
+        let mut sorter = ExternalSorter::new(by_first_seen, memory_limit, &temp_dir);
+
+        // Combined rows are (first_seen, key..., alignments, sums...)
+        let mut current: Option<Vec<String>> = None;
+        for row in merged {
+            let row = row?;
+            match current.as_mut() {
+                Some(combined) if combined[1..=key_len] == row[..key_len] => {
+                    let first_seen = combined[0].parse::<u64>().unwrap_or(0).min(row[key_len].parse::<u64>().unwrap_or(0));
+                    combined[0] = first_seen.to_string();
+                    let alignments = combined[key_len + 1].parse::<u64>().unwrap_or(0) + row[key_len + 1].parse::<u64>().unwrap_or(0);
+                    combined[key_len + 1] = alignments.to_string();
+                    for i in key_len + 2..row.len() {
+                        let mut sum = parse_sum(&combined[i]);
+                        if let Some(value) = parse_sum(&row[i]) {
+                            add_to_sum(&mut sum, value, &sum_names[i - key_len - 2]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                        } else {
+                            sum = None;
+                        }
+                        combined[i] = format_sum(&sum);
+                    }
+                }
+                _ => {
+                    if let Some(combined) = current.take() {
+                        sorter.push(combined)?;
+                    }
+                    let mut combined = vec![row[key_len].clone()];
+                    combined.extend(row[..key_len].iter().cloned());
+                    combined.extend(row[key_len + 1..].iter().cloned());
+                    current = Some(combined);
+                }
+            }
+        }
+        if let Some(combined) = current {
+            sorter.push(combined)?;
+        }
+
+        Ok(Box::new(sorter.finish()?.map(move |row| row.map(|mut row| {
+            row.remove(0);
+            if split_by_orientation {
+                add_orientation(&mut row);
+            }
+            row
+        }))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Vec<String> {
+        ["feature.name", "query", "target", "aligned.bp", "mismatches.bp"].iter().map(|name| name.to_string()).collect()
+    }
+
+    const ROWS: [[&str; 5]; 6] = [
+        ["gene1", "q1", "t1", "10", "1"],
+        ["gene2", "q1", "t1", "5", "NA"],
+        ["gene1", "q1", "t1", "7", "2"],
+        ["gene3", "q2", "t1", "3", "0"],
+        ["gene2", "q1", "t1", "1", "4"],
+        ["gene1", "q1", "t1", "2", "0"],
+    ];
+
+    fn summarize(summarizer: Summarizer, rows: &[[&str; 5]]) -> (Vec<Vec<String>>, usize) {
+        let mut summarizer = summarizer;
+        for row in rows {
+            assert_eq!(summarizer.add(row).unwrap(), None);
+        }
+        let spilled_chunks = summarizer.spilled_chunks();
+        (summarizer.finish().unwrap().collect::<io::Result<Vec<_>>>().unwrap(), spilled_chunks)
+    }
+
+    #[test]
+    fn spilled_aggregates_match_in_memory_ones() {
+        let (in_memory, spilled_chunks) = summarize(Summarizer::new(&header()).unwrap(), &ROWS);
+        assert_eq!(spilled_chunks, 0);
+        assert_eq!(in_memory, vec![
+            vec!["gene1", "q1", "t1", "3", "19", "3"],
+            vec!["gene2", "q1", "t1", "2", "6", "NA"],
+            vec!["gene3", "q2", "t1", "1", "3", "0"],
+        ]);
+
+        // Any row exceeds a zero memory limit, so that every row is spilled on its own
+        let temp_dir = std::env::temp_dir();
+        let (spilled, spilled_chunks) = summarize(Summarizer::new(&header()).unwrap().with_memory_limit(0, &temp_dir), &ROWS);
+        assert_eq!(spilled_chunks, ROWS.len());
+        assert_eq!(spilled, in_memory);
+    }
+
+    #[test]
+    fn spilled_sums_overflowing_fail() {
+        let temp_dir = std::env::temp_dir();
+        let max = i64::MAX.to_string();
+        let mut summarizer = Summarizer::new(&header()).unwrap().with_memory_limit(0, &temp_dir);
+        summarizer.add(&["gene1", "q1", "t1", &max, "0"]).unwrap();
+        summarizer.add(&["gene1", "q1", "t1", "1", "0"]).unwrap();
+        let error = summarizer.finish().err().unwrap();
+        assert!(error.to_string().contains("the sum of aligned.bp overflows a 64-bit integer"), "{}", error);
+    }
+
+    #[test]
+    fn sorted_groups_are_returned_as_they_end() {
+        let mut summarizer = Summarizer::new(&header()).unwrap().assume_sorted();
+        assert_eq!(summarizer.add(&ROWS[0]).unwrap(), None);
+        assert_eq!(summarizer.add(&ROWS[2]).unwrap(), None);
+        assert_eq!(summarizer.add(&ROWS[1]).unwrap(), Some(vec!["gene1", "q1", "t1", "2", "17", "3"].into_iter().map(String::from).collect()));
+        assert_eq!(summarizer.add(&ROWS[4]).unwrap(), None);
+        let rows: Vec<Vec<String>> = summarizer.finish().unwrap().collect::<io::Result<_>>().unwrap();
+        assert_eq!(rows, vec![vec!["gene2", "q1", "t1", "2", "6", "NA"]]);
+    }
+
+    #[test]
+    fn sorted_input_with_non_adjacent_rows_fails() {
+        let mut summarizer = Summarizer::new(&header()).unwrap().assume_sorted();
+        for row in &ROWS[..2] {
+            summarizer.add(row).unwrap();
+        }
+        // gene1 was finished when gene2 started
+        let error = summarizer.add(&ROWS[2]).unwrap_err();
+        assert_eq!(error, "the rows of gene1/q1/t1 are not adjacent, so the input is not sorted by feature");
+    }
+}
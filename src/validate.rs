@@ -0,0 +1,48 @@
+use clap::ArgMatches;
+use std::io::{self, BufRead};
+
+use crate::{features, open_file, parse_record, Record};
+
+// Reason why a line of the PAF + feature layout would not be counted, if any
+fn check_record(parts: &[&str], independent_strands: bool) -> Result<(), String> {
+    let Record { query_name, query_strand, target_name, query_name_2, feature_in_query_name, feature_in_query_strand, target_name_2, feature_in_target_name, feature_in_target_strand, .. } = parse_record(parts)?;
+    if query_name != query_name_2 || target_name != target_name_2 {
+        return Err(format!("query and/or target name do not match the feature sequences ({} vs {}, {} vs {})", query_name, query_name_2, target_name, target_name_2));
+    }
+    if feature_in_query_name != feature_in_target_name {
+        return Err(format!("feature names do not match ({} vs {})", feature_in_query_name, feature_in_target_name));
+    }
+    if feature_in_query_strand != feature_in_target_strand && query_strand == "+" && !independent_strands {
+        return Err(format!("feature {} is on different strands in query and target, but query and target are in the same orientation", feature_in_query_name));
+    }
+    Ok(())
+}
+
+pub fn run(matches: &ArgMatches) -> io::Result<()> {
+    let input_file = matches.value_of("input").unwrap();
+    let plain_paf = matches.value_of("input_layout") == Some("paf");
+    let independent_strands = matches.is_present("independent_strands");
+
+    let (mut lines, mut invalid) = (0u64, 0u64);
+    for (line_number, line) in open_file(input_file).lines().enumerate() {
+        let line = line?;
+        lines += 1;
+        let result = features::validate_layout(&line, plain_paf).and_then(|_| {
+            if plain_paf {
+                Ok(())
+            } else {
+                check_record(&line.split('\t').collect::<Vec<&str>>(), independent_strands)
+            }
+        });
+        if let Err(reason) = result {
+            eprintln!("WARNING: line {}: {}", line_number + 1, reason.replace("the first line", "the line"));
+            invalid += 1;
+        }
+    }
+
+    eprintln!("INFO: {} lines checked, {} valid, {} invalid", lines, lines - invalid, invalid);
+    if invalid > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
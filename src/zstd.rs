@@ -0,0 +1,221 @@
+use std::io::{self, Read};
+#[cfg(not(feature = "zstd"))]
+use std::process::{Child, ChildStdout, Command, Stdio};
+#[cfg(not(feature = "zstd"))]
+use std::thread::JoinHandle;
+
+// Decompresses a zstd stream through the zstd command line tool: the compressed bytes are written to its input from
+// a thread, and its error output is read from another one, so that neither pipe can fill up and block the
+// decompressed output. Errors of the tool or of the compressed input name the file, as those of gzip input do. With
+// the zstd feature the stream is decompressed in process by the zstd crate instead, and the tool is not needed
+#[cfg(not(feature = "zstd"))]
+pub struct ZstdReader {
+    file_path: String,
+    child: Child,
+    stdout: ChildStdout,
+    feeder: Option<JoinHandle<io::Result<u64>>>,
+    stderr: Option<JoinHandle<String>>,
+    decompressed: u64,
+}
+
+#[cfg(feature = "zstd")]
+pub struct ZstdReader {
+    file_path: String,
+    decoder: ::zstd::stream::read::Decoder<'static, io::BufReader<InputReader>>,
+    decompressed: u64,
+}
+
+// Compressed input of the zstd crate, recording whether an error came from it rather than from the decoder
+#[cfg(feature = "zstd")]
+struct InputReader {
+    inner: Box<dyn Read + Send>,
+    failed: bool,
+}
+
+#[cfg(feature = "zstd")]
+impl Read for InputReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf);
+        self.failed |= read.is_err();
+        read
+    }
+}
+
+// Error of the zstd tool failing to start, with the way around a missing tool
+#[cfg(not(feature = "zstd"))]
+fn start_error(file_path: &str, e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::NotFound {
+        return io::Error::new(e.kind(), format!(
+            "{}: zstd input is decompressed with the zstd command line tool, which was not found in PATH; install zstd or build with --features zstd",
+            file_path));
+    }
+    io::Error::new(e.kind(), format!("{}: zstd input is decompressed with the zstd command line tool, which failed to start: {}", file_path, e))
+}
+
+impl ZstdReader {
+    fn error(&self, message: impl std::fmt::Display) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!(
+            "{}: corrupt or truncated zstd data (after {} decompressed bytes): {}; check that the file is complete and not damaged (e.g. with zstd -t)",
+            self.file_path, self.decompressed, message))
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+impl ZstdReader {
+    pub fn new(mut inner: impl Read + Send + 'static, file_path: &str) -> io::Result<ZstdReader> {
+        let mut child = Command::new("zstd").args(["-d", "-c", "-q"])
+            .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| start_error(file_path, e))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let feeder = std::thread::spawn(move || {
+            let copied = io::copy(&mut inner, &mut stdin);
+            // The tool stopping early (e.g. on corrupt input) closes the pipe, which is reported from its own status
+            match copied {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(0),
+                copied => copied,
+            }
+        });
+        let mut stderr = child.stderr.take().unwrap();
+        let stderr = std::thread::spawn(move || {
+            let mut message = Vec::new();
+            let _ = stderr.read_to_end(&mut message);
+            String::from_utf8_lossy(&message).trim().to_string()
+        });
+        let stdout = child.stdout.take().unwrap();
+        Ok(ZstdReader { file_path: file_path.to_string(), child, stdout, feeder: Some(feeder), stderr: Some(stderr), decompressed: 0 })
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl ZstdReader {
+    pub fn new(inner: impl Read + Send + 'static, file_path: &str) -> io::Result<ZstdReader> {
+        let input = InputReader { inner: Box::new(inner), failed: false };
+        let decoder = ::zstd::stream::read::Decoder::new(input).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file_path, e)))?;
+        Ok(ZstdReader { file_path: file_path.to_string(), decoder, decompressed: 0 })
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Read for ZstdReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.decoder.read(buf) {
+            Ok(read) => {
+                self.decompressed += read as u64;
+                Ok(read)
+            }
+            Err(e) if self.decoder.get_ref().get_ref().failed => Err(io::Error::new(e.kind(), format!("{}: {}", self.file_path, e))),
+            Err(e) => Err(self.error(e)),
+        }
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+impl Read for ZstdReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        self.decompressed += read as u64;
+        if read > 0 || buf.is_empty() {
+            return Ok(read);
+        }
+
+        // End of the decompressed stream: it is complete only if both the input and the tool ended without error
+        let Some(feeder) = self.feeder.take() else {
+            return Ok(0);
+        };
+        let copied = feeder.join().unwrap_or_else(|_| Err(io::Error::other("the input thread panicked")));
+        let status = self.child.wait()?;
+        let message = self.stderr.take().map(|stderr| stderr.join().unwrap_or_default()).unwrap_or_default();
+        if let Err(e) = copied {
+            return Err(io::Error::new(e.kind(), format!("{}: {}", self.file_path, e)));
+        }
+        if !status.success() {
+            return Err(self.error(if message.is_empty() { status.to_string() } else { message }));
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+impl Drop for ZstdReader {
+    // The tool is stopped when the input is not read to its end (e.g. with --head)
+    fn drop(&mut self) {
+        if self.feeder.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+// Compresses `data` with the zstd tool, for the tests of zstd input
+#[cfg(all(test, not(feature = "zstd")))]
+pub fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    use std::io::Write;
+    let mut child = Command::new("zstd").args(["-c", "-q"]).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let data = data.to_vec();
+    let feeder = std::thread::spawn(move || stdin.write_all(&data));
+    let output = child.wait_with_output()?;
+    feeder.join().unwrap()?;
+    Ok(output.stdout)
+}
+
+#[cfg(all(test, feature = "zstd"))]
+pub fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    ::zstd::encode_all(data, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decompress(compressed: Vec<u8>) -> io::Result<String> {
+        let mut content = String::new();
+        ZstdReader::new(io::Cursor::new(compressed), "input.paf.zst")?.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    #[test]
+    fn decompresses_inputs_larger_than_the_pipes() {
+        let data: String = (0..200_000).map(|i| format!("q{}\t{}\n", i, i * 7)).collect();
+        assert_eq!(decompress(compress(data.as_bytes()).unwrap()).unwrap(), data);
+    }
+
+    #[test]
+    fn concatenated_frames_round_trip() {
+        // A file of several frames (e.g. appended to with zstd -c >>) decompresses to the data of all of them
+        let parts: Vec<String> = (0..3).map(|part| (0..10_000).map(|i| format!("q{}_{}\t{}\n", part, i, i * 7)).collect()).collect();
+        let compressed: Vec<u8> = parts.iter().flat_map(|part| compress(part.as_bytes()).unwrap()).collect();
+        assert_eq!(decompress(compressed).unwrap(), parts.concat());
+        assert_eq!(decompress(compress(b"").unwrap()).unwrap(), "");
+    }
+
+    #[test]
+    fn truncated_input_names_the_file() {
+        let data: String = (0..100_000).map(|i| format!("q{}\t{}\n", i, i * 7)).collect();
+        let mut compressed = compress(data.as_bytes()).unwrap();
+        compressed.truncate(compressed.len() / 2);
+        let error = decompress(compressed).unwrap_err().to_string();
+        assert!(error.starts_with("input.paf.zst: corrupt or truncated zstd data"), "{}", error);
+    }
+
+    #[test]
+    fn input_errors_are_reported() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("connection lost"))
+            }
+        }
+        let error = ZstdReader::new(Failing, "input.paf.zst").and_then(|mut reader| reader.read_to_end(&mut Vec::new())).unwrap_err().to_string();
+        assert_eq!(error, "input.paf.zst: connection lost");
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn missing_tool_is_reported_with_the_way_around_it() {
+        let error = start_error("input.paf.zst", io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(error.to_string(), "input.paf.zst: zstd input is decompressed with the zstd command line tool, which was not found in PATH; install zstd or build with --features zstd");
+    }
+}
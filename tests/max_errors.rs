@@ -0,0 +1,61 @@
+// Exit status and message of a report run aborted by --max-errors
+
+use std::process::Command;
+
+#[test]
+fn max_errors_exits_with_an_error_after_one_more_invalid_line() {
+    let input = std::env::temp_dir().join(format!("feature_level_report_max_errors_{}.tsv", std::process::id()));
+    let valid = "q1\t100\t0\t100\t+\tt1\t100\t0\t100\t100\t100\t60\tcg:Z:100M\tq1\t10\t20\tf1\t0\t+\tgene\tt1\t10\t20\tf1\t0\t+\tgene\n";
+    let invalid = valid.replacen("\t0\t100\t", "\tx\t100\t", 1);
+    std::fs::write(&input, [valid, &invalid, &invalid].concat()).unwrap();
+    let report_with = |max_errors: &str| {
+        Command::new(env!("CARGO_BIN_EXE_feature_level_report"))
+            .args(["-i", input.to_str().unwrap(), "--max-errors", max_errors])
+            .output()
+            .unwrap()
+    };
+
+    let passed = report_with("2");
+    assert!(passed.status.success());
+    assert_eq!(String::from_utf8_lossy(&passed.stdout).lines().count(), 2);
+
+    let aborted = report_with("1");
+    std::fs::remove_file(&input).unwrap();
+    assert_eq!(aborted.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&aborted.stderr);
+    assert!(stderr.contains("ERROR: 2 invalid lines:\nERROR:   line 2: "), "{}", stderr);
+    assert!(stderr.ends_with("ERROR: aborting after 2 invalid lines (--max-errors 1)\n"), "{}", stderr);
+    // The row of the valid line before the abort is written out
+    assert_eq!(String::from_utf8_lossy(&aborted.stdout).lines().count(), 2);
+}
+
+#[test]
+fn zero_length_cigar_operations_are_reported_as_invalid_lines() {
+    let input = std::env::temp_dir().join(format!("feature_level_report_zero_length_{}.tsv", std::process::id()));
+    let valid = "q1\t100\t0\t100\t+\tt1\t100\t0\t100\t100\t100\t60\tcg:Z:100M\tq1\t10\t20\tf1\t0\t+\tgene\tt1\t10\t20\tf1\t0\t+\tgene\n";
+    let cigars = [("0M100M", 1, 0), ("50M0I50M", 2, 3), ("0=100=", 1, 0), ("100=0I", 2, 4)];
+    let lines: Vec<String> = cigars.iter().map(|(cigar, _, _)| valid.replacen("cg:Z:100M", &format!("cg:Z:{}", cigar), 1)).collect();
+    std::fs::write(&input, [valid.to_string(), lines.concat()].concat()).unwrap();
+    let report_with = |max_errors: &str| {
+        Command::new(env!("CARGO_BIN_EXE_feature_level_report"))
+            .args(["-i", input.to_str().unwrap(), "--max-errors", max_errors])
+            .output()
+            .unwrap()
+    };
+
+    let passed = report_with("4");
+    let aborted = report_with("0");
+    std::fs::remove_file(&input).unwrap();
+    assert!(passed.status.success());
+    assert_eq!(String::from_utf8_lossy(&passed.stdout).lines().count(), 2);
+    let stderr = String::from_utf8_lossy(&passed.stderr);
+    for (line, (_, op, byte)) in (2..).zip(cigars) {
+        let warning = format!("WARNING: line {}: invalid CIGAR: operation {} (at byte {}) has a length of 0. Skip this line\n", line, op, byte);
+        assert!(stderr.contains(&warning), "{}", stderr);
+    }
+
+    assert_eq!(aborted.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&aborted.stderr);
+    assert!(stderr.contains("ERROR:   line 2: invalid CIGAR: operation 1 (at byte 0) has a length of 0\n"), "{}", stderr);
+    assert!(stderr.ends_with("ERROR: aborting after 1 invalid lines (--max-errors 0)\n"), "{}", stderr);
+}
@@ -0,0 +1,27 @@
+// Counters of a report run written on stderr only with --verbose
+
+use std::process::Command;
+
+#[test]
+fn counters_are_written_with_verbose_only() {
+    let input = std::env::temp_dir().join(format!("feature_level_report_verbose_{}.tsv", std::process::id()));
+    let line = "q1\t100\t0\t100\t+\tt1\t100\t0\t100\t100\t100\t60\tcg:Z:100M\tq1\t10\t20\tf1\t0\t+\tgene\tt1\t10\t20\tf1\t0\t+\tgene\n";
+    std::fs::write(&input, line.repeat(3)).unwrap();
+    let report_with = |arguments: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_feature_level_report"))
+            .args(["-i", input.to_str().unwrap()])
+            .args(arguments)
+            .output()
+            .unwrap()
+    };
+
+    let quiet = report_with(&[]);
+    let verbose = report_with(&["--verbose"]);
+    std::fs::remove_file(&input).unwrap();
+    assert!(quiet.status.success() && verbose.status.success());
+    assert_eq!(String::from_utf8_lossy(&quiet.stderr), "");
+    assert_eq!(quiet.stdout, verbose.stdout);
+    let stderr = String::from_utf8_lossy(&verbose.stderr);
+    assert!(stderr.starts_with("INFO: processed 3 lines: 3 reported, "), "{}", stderr);
+    assert_eq!(stderr.lines().count(), 1, "{}", stderr);
+}